@@ -0,0 +1,289 @@
+//! Working with devices spread across several Govee accounts.
+//!
+//! A [MultiClient] holds one [GoveeClient] per account, keyed by a profile
+//! name. Listing devices merges every account's devices, tagging each with the
+//! account it came from, and control calls are routed to whichever account
+//! owns the device.
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Deref,
+    sync::RwLock,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    client::{GoveeClient, GoveeError},
+    models::{Color, Device, DeviceId, DeviceState, Devices, PowerState},
+};
+
+#[derive(Debug, Error)]
+pub enum AccountError {
+    #[error("unknown account {:?}", account)]
+    UnknownAccount { account: String },
+    #[error(
+        "device {} does not belong to any known account; list devices first",
+        device
+    )]
+    UnknownDevice { device: DeviceId },
+    #[error("account {:?}: {}", account, source)]
+    Govee {
+        account: String,
+        #[source]
+        source: GoveeError,
+    },
+}
+
+/// A [Device] tagged with the account it belongs to.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AccountDevice {
+    pub account: String,
+    #[serde(flatten)]
+    pub device: Device,
+}
+
+/// The merged device listing of several accounts.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AccountDevices {
+    pub devices: Vec<AccountDevice>,
+}
+
+impl Deref for AccountDevices {
+    type Target = Vec<AccountDevice>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.devices
+    }
+}
+
+impl AccountDevices {
+    /// The account owning the given device.
+    pub fn account_of(&self, id: &DeviceId) -> Option<&str> {
+        self.iter()
+            .find(|d| &d.device.device == id)
+            .map(|d| d.account.as_str())
+    }
+
+    /// The devices belonging to a single account.
+    pub fn for_account(&self, account: &str) -> Devices {
+        self.iter()
+            .filter(|d| d.account == account)
+            .map(|d| d.device.clone())
+            .collect()
+    }
+
+    /// Every device, without account tags.
+    ///
+    /// This is useful for the lookup and query helpers on [Devices].
+    pub fn untagged(&self) -> Devices {
+        self.iter().map(|d| d.device.clone()).collect()
+    }
+}
+
+/// A client for several Govee accounts at once.
+///
+/// Control and state calls are routed using the device listings from the most
+/// recent call to [MultiClient::devices], so that must be called before a
+/// device can be controlled.
+///
+/// # Examples
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use govee_rs::{accounts::MultiClient, models::PowerState, GoveeClient, DEFAULT_API_URL};
+///
+/// let mut client = MultiClient::default();
+/// client.add("office", GoveeClient::new(DEFAULT_API_URL, "office-key")?);
+/// client.add("lab", GoveeClient::new(DEFAULT_API_URL, "lab-key")?);
+///
+/// let devices = client.devices().await?;
+/// for tagged in devices.iter() {
+///     println!("{}: {}", tagged.account, tagged.device.name);
+///     client.turn(&tagged.device, PowerState::Off).await?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct MultiClient {
+    clients: BTreeMap<String, GoveeClient>,
+    routes: RwLock<HashMap<DeviceId, String>>,
+}
+
+impl MultiClient {
+    /// Add (or replace) the client for an account.
+    pub fn add(&mut self, account: &str, client: GoveeClient) -> Option<GoveeClient> {
+        self.clients.insert(account.to_string(), client)
+    }
+
+    /// The client for a single account.
+    pub fn client(&self, account: &str) -> Result<&GoveeClient, AccountError> {
+        self.clients
+            .get(account)
+            .ok_or_else(|| AccountError::UnknownAccount {
+                account: account.to_string(),
+            })
+    }
+
+    /// The names of every account, in order.
+    pub fn accounts(&self) -> impl Iterator<Item = &str> {
+        self.clients.keys().map(String::as_str)
+    }
+
+    /// List the devices of every account, refreshing the routing table.
+    pub async fn devices(&self) -> Result<AccountDevices, AccountError> {
+        let mut merged = AccountDevices::default();
+        for (account, client) in self.clients.iter() {
+            let devices = client
+                .devices()
+                .await
+                .map_err(|source| AccountError::Govee {
+                    account: account.clone(),
+                    source,
+                })?;
+
+            merged
+                .devices
+                .extend(devices.devices.into_iter().map(|device| AccountDevice {
+                    account: account.clone(),
+                    device,
+                }));
+        }
+
+        let mut routes = self.routes.write().unwrap_or_else(|e| e.into_inner());
+        routes.clear();
+        for tagged in merged.iter() {
+            routes.insert(tagged.device.device.clone(), tagged.account.clone());
+        }
+
+        Ok(merged)
+    }
+
+    /// The account that owns the given device.
+    pub fn account_of(&self, id: &DeviceId) -> Result<String, AccountError> {
+        self.routes
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(id)
+            .cloned()
+            .ok_or_else(|| AccountError::UnknownDevice { device: id.clone() })
+    }
+
+    async fn routed<'a, F, Fut, T>(&'a self, device: &'a Device, call: F) -> Result<T, AccountError>
+    where
+        F: FnOnce(&'a GoveeClient) -> Fut,
+        Fut: std::future::Future<Output = Result<T, GoveeError>>,
+    {
+        let account = self.account_of(&device.device)?;
+        let client = self.client(&account)?;
+        call(client)
+            .await
+            .map_err(|source| AccountError::Govee { account, source })
+    }
+
+    /// Get the [DeviceState] of a device on any account.
+    pub async fn state(&self, device: &Device) -> Result<DeviceState, AccountError> {
+        self.routed(device, |c| c.state(device)).await
+    }
+
+    /// Set the power state of a device on any account.
+    pub async fn turn(&self, device: &Device, state: PowerState) -> Result<(), AccountError> {
+        self.routed(device, |c| c.turn(device, state)).await
+    }
+
+    /// Set the brightness of a device on any account.
+    pub async fn brightness(&self, device: &Device, brightness: u64) -> Result<(), AccountError> {
+        self.routed(device, |c| c.brightness(device, brightness))
+            .await
+    }
+
+    /// Set the color of a device on any account.
+    pub async fn color(&self, device: &Device, color: Color) -> Result<(), AccountError> {
+        self.routed(device, |c| c.color(device, color)).await
+    }
+
+    /// Set the color temperature of a device on any account.
+    pub async fn color_temp(&self, device: &Device, color_temp: u64) -> Result<(), AccountError> {
+        self.routed(device, |c| c.color_temp(device, color_temp))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::{Matcher, Server, ServerGuard};
+
+    use super::*;
+
+    async fn account(id: &str, name: &str) -> (ServerGuard, GoveeClient) {
+        let mut server = Server::new_async().await;
+        let client = GoveeClient::new(&server.url(), name).unwrap();
+        server
+            .mock("GET", "/v1/devices?")
+            .match_header("Govee-API-Key", name)
+            .with_status(200)
+            .with_body(format!(
+                r#"{{"data": {{"devices": [{{
+                    "device": "{}",
+                    "model": "H6159",
+                    "deviceName": "{} light",
+                    "controllable": true,
+                    "retrievable": true,
+                    "supportCmds": ["turn"]
+                }}]}}}}"#,
+                id, name
+            ))
+            .create_async()
+            .await;
+        (server, client)
+    }
+
+    #[tokio::test]
+    async fn merges_and_routes() {
+        let (_office_server, office) = account("34:20:03:2e:30:01", "office").await;
+        let (mut lab_server, lab) = account("34:20:03:2e:30:02", "lab").await;
+
+        let mut client = MultiClient::default();
+        client.add("office", office);
+        client.add("lab", lab);
+        assert_eq!(client.accounts().collect::<Vec<_>>(), vec!["lab", "office"]);
+
+        let lab_light = Device {
+            model: "H6159".into(),
+            ..Device::new(DeviceId::parse("34:20:03:2e:30:02").unwrap())
+        };
+
+        // nothing is routable until the devices have been listed
+        assert!(matches!(
+            client.turn(&lab_light, PowerState::On).await,
+            Err(AccountError::UnknownDevice { .. })
+        ));
+
+        let devices = client.devices().await.unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices.account_of(&lab_light.device), Some("lab"));
+        assert_eq!(devices.for_account("office")[0].name, "office light");
+        assert_eq!(
+            devices.untagged().find("lab light").unwrap().device,
+            lab_light.device
+        );
+
+        let control = lab_server
+            .mock("PUT", "/v1/devices/control?")
+            .match_header("Govee-API-Key", "lab")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "device": "34:20:03:2e:30:02"
+            })))
+            .with_status(200)
+            .with_body(r#"{"data": {}}"#)
+            .create_async()
+            .await;
+
+        client.turn(&lab_light, PowerState::On).await.unwrap();
+        control.assert_async().await;
+
+        let tagged = serde_json::to_value(&devices[0]).unwrap();
+        assert_eq!(tagged["account"], "lab");
+        assert_eq!(tagged["deviceName"], "lab light");
+    }
+}
@@ -0,0 +1,113 @@
+//! A Prometheus exporter for Govee devices and API usage.
+//!
+//! Devices are polled in the background at a quota-safe interval and the
+//! latest values are served on `/metrics`, along with metrics about every
+//! request the exporter made to the API.
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use clap::Parser;
+use govee_rs::{metrics::Metrics, monitor::Monitor, GoveeClient, DEFAULT_API_URL};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct Cli {
+    /// The address to serve metrics on.
+    #[arg(long, env = "GOVEE_EXPORTER_LISTEN", default_value = "127.0.0.1:9898")]
+    listen: SocketAddr,
+
+    /// The Govee API key.
+    #[arg(long, env = "GOVEE_KEY", hide_env_values = true)]
+    api_key: String,
+
+    /// The Govee API url.
+    #[arg(long, env = "GOVEE_API_URL", default_value = DEFAULT_API_URL)]
+    api_url: String,
+
+    /// Seconds between polls of every device [default: quota-safe].
+    #[arg(long)]
+    interval: Option<u64>,
+}
+
+async fn metrics(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    match metrics.encode() {
+        Ok(text) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            text,
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::warn!("failed to encode metrics: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+async fn poll_forever(monitor: Monitor, metrics: Arc<Metrics>) {
+    loop {
+        for device in monitor.devices().iter() {
+            let snapshot = monitor.poll_device(device).await;
+            if let Err(e) = &snapshot.state {
+                tracing::warn!("failed to poll {}: {}", device.name, e);
+            }
+            metrics.record_snapshot(&snapshot);
+        }
+        tokio::time::sleep(monitor.interval()).await;
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let metrics = Arc::new(Metrics::new()?);
+    let client = GoveeClient::new(&cli.api_url, &cli.api_key)?.with_observer(metrics.clone());
+
+    let devices = client.devices().await.context("failed to list devices")?;
+    let mut monitor = Monitor::new(client, &devices);
+    if let Some(interval) = cli.interval {
+        monitor = monitor.with_interval(Duration::from_secs(interval));
+    }
+    tracing::info!(
+        "polling {} devices every {:.1}s",
+        monitor.devices().len(),
+        monitor.interval().as_secs_f64()
+    );
+    let poller = tokio::spawn(poll_forever(monitor, metrics.clone()));
+
+    let app = Router::new()
+        .route("/metrics", get(self::metrics))
+        .with_state(metrics);
+    let listener = tokio::net::TcpListener::bind(cli.listen)
+        .await
+        .with_context(|| format!("failed to listen on {}", cli.listen))?;
+    tracing::info!("serving metrics on http://{}/metrics", cli.listen);
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+            tracing::info!("shutting down");
+        })
+        .await?;
+
+    poller.abort();
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
+        .init();
+
+    if let Err(e) = run(Cli::parse()).await {
+        tracing::error!("{:#}", e);
+        std::process::exit(1);
+    }
+}
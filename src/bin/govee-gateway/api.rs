@@ -0,0 +1,156 @@
+//! The gateway's routes.
+//!
+//! - `GET /devices` lists the devices on the account.
+//! - `GET /devices/{id}/state` returns a device's normalized state.
+//! - `PUT /devices/{id}` applies a JSON desired state, such as
+//!   `{"power": "on", "brightness": 40, "color": "orange"}`.
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, put},
+    Json, Router,
+};
+use govee_rs::{
+    cache::{CacheError, CachingClient},
+    models::{DesiredState, Devices, NormalizedState},
+    DeviceId,
+};
+use serde_json::json;
+
+pub fn router(client: CachingClient) -> Router {
+    Router::new()
+        .route("/devices", get(devices))
+        .route("/devices/:id", put(apply))
+        .route("/devices/:id/state", get(state))
+        .with_state(client)
+}
+
+/// A [CacheError] as an HTTP response.
+struct ApiError(CacheError);
+
+impl From<CacheError> for ApiError {
+    fn from(value: CacheError) -> Self {
+        Self(value)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match &self.0 {
+            CacheError::UnknownDevice { .. } => StatusCode::NOT_FOUND,
+            CacheError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            CacheError::DesiredState { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            CacheError::Govee { .. } => {
+                tracing::warn!("upstream request failed: {}", self.0);
+                StatusCode::BAD_GATEWAY
+            }
+        };
+
+        let body = Json(json!({ "error": self.0.to_string() }));
+        match self.0 {
+            CacheError::RateLimited { retry_after } => {
+                let secs = retry_after.as_secs_f64().ceil().to_string();
+                (status, [(header::RETRY_AFTER, secs)], body).into_response()
+            }
+            _ => (status, body).into_response(),
+        }
+    }
+}
+
+async fn devices(State(client): State<CachingClient>) -> Result<Json<Devices>, ApiError> {
+    Ok(Json(client.devices().await?))
+}
+
+async fn state(
+    State(client): State<CachingClient>,
+    Path(id): Path<DeviceId>,
+) -> Result<Json<NormalizedState>, ApiError> {
+    let device = client.device(&id).await?;
+    Ok(Json(client.state(&device).await?.normalized()))
+}
+
+async fn apply(
+    State(client): State<CachingClient>,
+    Path(id): Path<DeviceId>,
+    Json(desired): Json<DesiredState>,
+) -> Result<StatusCode, ApiError> {
+    let device = client.device(&id).await?;
+    client.apply(&device, &desired).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use govee_rs::{
+        cache::CacheOptions,
+        models::PowerState,
+        testing::{self, FakeServer},
+    };
+    use serde_json::Value;
+
+    use super::*;
+
+    /// Serve the gateway in front of `server`, returning its base url.
+    async fn gateway(server: &FakeServer) -> String {
+        let client = CachingClient::new(server.client(), CacheOptions::default());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(client)).await });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn routes() {
+        let server = FakeServer::start().await.unwrap();
+        let lamp = server.add_device(testing::light("34:20:03:2e:30:2b", "lamp"));
+        let url = gateway(&server).await;
+        let http = reqwest::Client::new();
+
+        let listed: Value = http
+            .get(format!("{}/devices", url))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(listed["devices"][0]["deviceName"], "lamp");
+
+        let applied = http
+            .put(format!("{}/devices/{}", url, lamp.device))
+            .json(&json!({"power": "on", "brightness": 40}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(applied.status(), reqwest::StatusCode::NO_CONTENT);
+        let state = server.state(&lamp.device).unwrap();
+        assert_eq!(state.power, Some(PowerState::On));
+        assert_eq!(state.brightness, Some(40));
+
+        let state: Value = http
+            .get(format!("{}/devices/{}/state", url, lamp.device))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(state["power"], "on");
+
+        let invalid = http
+            .put(format!("{}/devices/{}", url, lamp.device))
+            .json(&json!({"brightness": 200}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(invalid.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+
+        let unknown = http
+            .get(format!("{}/devices/34:20:03:2e:30:ff/state", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(unknown.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,99 @@
+//! A local REST gateway in front of the Govee API.
+//!
+//! The gateway holds the API key and a single [CachingClient], so services on
+//! the network share one cache and one rate limit instead of each spending
+//! the account's quota.
+use std::{net::SocketAddr, time::Duration};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use govee_rs::{
+    cache::{CacheOptions, CachingClient},
+    GoveeClient, DEFAULT_API_URL,
+};
+use tracing_subscriber::EnvFilter;
+
+mod api;
+
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct Cli {
+    /// The address to listen on.
+    #[arg(long, env = "GOVEE_GATEWAY_LISTEN", default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+
+    /// The Govee API key.
+    #[arg(long, env = "GOVEE_KEY", hide_env_values = true)]
+    api_key: String,
+
+    /// The Govee API url.
+    #[arg(long, env = "GOVEE_API_URL", default_value = DEFAULT_API_URL)]
+    api_url: String,
+
+    /// Seconds a device listing is cached for [default: 300].
+    #[arg(long)]
+    devices_ttl: Option<u64>,
+
+    /// Seconds a device state is cached for [default: 10].
+    #[arg(long)]
+    state_ttl: Option<u64>,
+
+    /// Upstream requests allowed per minute [default: the daily quota, spread evenly].
+    #[arg(long)]
+    rate: Option<f64>,
+
+    /// Upstream requests allowed back to back [default: 10].
+    #[arg(long)]
+    burst: Option<u32>,
+
+    /// Seconds a request may wait for the rate limit before getting a 429 [default: 10].
+    #[arg(long)]
+    max_wait: Option<u64>,
+}
+
+impl Cli {
+    fn options(&self) -> CacheOptions {
+        let defaults = CacheOptions::default();
+        CacheOptions {
+            devices_ttl: self
+                .devices_ttl
+                .map_or(defaults.devices_ttl, Duration::from_secs),
+            state_ttl: self
+                .state_ttl
+                .map_or(defaults.state_ttl, Duration::from_secs),
+            requests_per_minute: self.rate.unwrap_or(defaults.requests_per_minute),
+            burst: self.burst.unwrap_or(defaults.burst),
+            max_wait: self.max_wait.map_or(defaults.max_wait, Duration::from_secs),
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let client = CachingClient::new(GoveeClient::new(&cli.api_url, &cli.api_key)?, cli.options());
+
+    let listener = tokio::net::TcpListener::bind(cli.listen)
+        .await
+        .with_context(|| format!("failed to listen on {}", cli.listen))?;
+    tracing::info!("listening on {}", cli.listen);
+
+    axum::serve(listener, api::router(client))
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+            tracing::info!("shutting down");
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
+        .init();
+
+    if let Err(e) = run(Cli::parse()).await {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    }
+}
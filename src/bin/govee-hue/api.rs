@@ -0,0 +1,193 @@
+//! The subset of the Hue bridge API that apps use to find and control lights.
+//!
+//! Lights are numbered from 1 in device id order. Like a real bridge, errors
+//! are reported in the body of a `200 OK` response.
+use std::{collections::BTreeMap, sync::Arc, time::SystemTime};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    routing::{get, post, put},
+    Json, Router,
+};
+use futures_util::future::join_all;
+use govee_rs::{
+    cache::{CacheError, CachingClient},
+    hue::{self, error_type, Light, StateChange},
+    models::{Device, Devices},
+};
+use serde_json::{json, Value};
+
+struct Bridge {
+    client: CachingClient,
+    lights: Vec<Device>,
+    name: String,
+}
+
+type Shared = State<Arc<Bridge>>;
+
+pub fn router(client: CachingClient, devices: Devices, name: String) -> Router {
+    let mut sorted = devices.devices;
+    sorted.sort_by(|a, b| a.device.as_str().cmp(b.device.as_str()));
+
+    let bridge = Arc::new(Bridge {
+        client,
+        lights: sorted,
+        name,
+    });
+
+    Router::new()
+        .route("/api", post(create_user))
+        .route("/api/config", get(config))
+        .route("/api/:user", get(full_state))
+        .route("/api/:user/config", get(config))
+        .route("/api/:user/lights", get(lights))
+        .route("/api/:user/lights/:id", get(light))
+        .route("/api/:user/lights/:id/state", put(set_state))
+        .with_state(bridge)
+}
+
+impl Bridge {
+    fn light(&self, id: &str) -> Result<&Device, Value> {
+        id.parse::<usize>()
+            .ok()
+            .and_then(|n| n.checked_sub(1))
+            .and_then(|i| self.lights.get(i))
+            .ok_or_else(|| {
+                let address = format!("/lights/{}", id);
+                hue::error(
+                    error_type::RESOURCE_NOT_AVAILABLE,
+                    &address,
+                    &format!("resource, {}, not available", address),
+                )
+            })
+    }
+
+    /// Present a device, fetching its state if it can be retrieved.
+    async fn present(&self, device: &Device) -> Light {
+        if !device.retrievable {
+            return Light::new(device, None);
+        }
+
+        match self.client.state(device).await {
+            Ok(state) => Light::new(device, Some(&state.normalized())),
+            Err(e) => {
+                tracing::warn!("failed to get state of {}: {}", device.name, e);
+                let mut light = Light::new(device, None);
+                light.state.reachable = false;
+                light
+            }
+        }
+    }
+
+    async fn all_lights(&self) -> BTreeMap<String, Light> {
+        let lights = join_all(self.lights.iter().map(|d| self.present(d))).await;
+        lights
+            .into_iter()
+            .enumerate()
+            .map(|(i, light)| ((i + 1).to_string(), light))
+            .collect()
+    }
+
+    fn config(&self) -> Value {
+        json!({
+            "name": self.name,
+            "modelid": "BSB002",
+            "bridgeid": "001788FFFE000000",
+            "mac": "00:17:88:00:00:00",
+            "apiversion": "1.56.0",
+            "swversion": "1956000000",
+            "datastoreversion": "1",
+            "factorynew": false,
+        })
+    }
+}
+
+/// Pair with the bridge. Every request succeeds, as if the link button had
+/// just been pressed.
+async fn create_user() -> Json<Value> {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    Json(json!([{ "success": { "username": format!("govee{:x}", nanos) } }]))
+}
+
+async fn config(State(bridge): Shared) -> Json<Value> {
+    Json(bridge.config())
+}
+
+async fn full_state(State(bridge): Shared) -> Json<Value> {
+    Json(json!({
+        "lights": bridge.all_lights().await,
+        "config": bridge.config(),
+        "groups": {},
+        "scenes": {},
+        "schedules": {},
+        "sensors": {},
+        "rules": {},
+    }))
+}
+
+async fn lights(State(bridge): Shared) -> Json<Value> {
+    Json(json!(bridge.all_lights().await))
+}
+
+async fn light(State(bridge): Shared, Path((_user, id)): Path<(String, String)>) -> Json<Value> {
+    match bridge.light(&id) {
+        Ok(device) => Json(json!(bridge.present(device).await)),
+        Err(e) => Json(e),
+    }
+}
+
+async fn set_state(
+    State(bridge): Shared,
+    Path((_user, id)): Path<(String, String)>,
+    body: Bytes,
+) -> Json<Value> {
+    let address = format!("/lights/{}/state", id);
+    let device = match bridge.light(&id) {
+        Ok(device) => device,
+        Err(e) => return Json(e),
+    };
+
+    let change: StateChange = match serde_json::from_slice(&body) {
+        Ok(change) => change,
+        Err(e) => {
+            return Json(hue::error(
+                error_type::INVALID_JSON,
+                &address,
+                &format!("body contains invalid json: {}", e),
+            ))
+        }
+    };
+
+    // a lone hue or sat needs the other half of the current color
+    let partial_hs = change.hue.is_some() != change.sat.is_some();
+    let current = if partial_hs && change.xy.is_none() && change.ct.is_none() {
+        match bridge.client.state(device).await {
+            Ok(state) => state.normalized().color,
+            Err(_) => None,
+        }
+    } else {
+        None
+    };
+
+    let desired = change.desired(current);
+    match bridge.client.apply(device, &desired).await {
+        Ok(()) => Json(change.success(&id)),
+        Err(CacheError::DesiredState { source }) => Json(hue::error(
+            error_type::PARAMETER_NOT_AVAILABLE,
+            &address,
+            &source.to_string(),
+        )),
+        Err(e) => {
+            tracing::warn!("failed to set state of {}: {}", device.name, e);
+            Json(hue::error(
+                error_type::DEVICE_UNREACHABLE,
+                &address,
+                &e.to_string(),
+            ))
+        }
+    }
+}
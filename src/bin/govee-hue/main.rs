@@ -0,0 +1,81 @@
+//! A Philips Hue bridge emulator in front of the Govee API.
+//!
+//! Apps that speak the Hue REST API see every controllable Govee device as a
+//! Hue light. Any username is accepted, so only run this on a trusted
+//! network.
+use std::{net::SocketAddr, time::Duration};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use govee_rs::{
+    cache::{CacheOptions, CachingClient},
+    GoveeClient, DEFAULT_API_URL,
+};
+use tracing_subscriber::EnvFilter;
+
+mod api;
+
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct Cli {
+    /// The address to listen on. Most Hue apps expect port 80.
+    #[arg(long, env = "GOVEE_HUE_LISTEN", default_value = "0.0.0.0:80")]
+    listen: SocketAddr,
+
+    /// The bridge name shown to apps.
+    #[arg(long, default_value = "Govee")]
+    name: String,
+
+    /// The Govee API key.
+    #[arg(long, env = "GOVEE_KEY", hide_env_values = true)]
+    api_key: String,
+
+    /// The Govee API url.
+    #[arg(long, env = "GOVEE_API_URL", default_value = DEFAULT_API_URL)]
+    api_url: String,
+
+    /// Seconds a device state is cached for [default: 10].
+    #[arg(long)]
+    state_ttl: Option<u64>,
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let mut options = CacheOptions::default();
+    if let Some(ttl) = cli.state_ttl {
+        options.state_ttl = Duration::from_secs(ttl);
+    }
+    let client = CachingClient::new(GoveeClient::new(&cli.api_url, &cli.api_key)?, options);
+
+    let devices = client
+        .devices()
+        .await
+        .context("failed to list devices")?
+        .controllable();
+    tracing::info!("presenting {} devices as hue lights", devices.len());
+
+    let listener = tokio::net::TcpListener::bind(cli.listen)
+        .await
+        .with_context(|| format!("failed to listen on {}", cli.listen))?;
+    tracing::info!("listening on {}", cli.listen);
+
+    axum::serve(listener, api::router(client, devices, cli.name))
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+            tracing::info!("shutting down");
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
+        .init();
+
+    if let Err(e) = run(Cli::parse()).await {
+        tracing::error!("{:#}", e);
+        std::process::exit(1);
+    }
+}
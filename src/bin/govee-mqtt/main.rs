@@ -0,0 +1,533 @@
+//! An MQTT bridge exposing Govee lights to Home Assistant.
+//!
+//! Every controllable device is announced with a discovery message, its
+//! state is polled and published to a state topic, and JSON commands from
+//! Home Assistant are turned into API calls.
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use govee_rs::{
+    cache::{CacheOptions, CachingClient},
+    homeassistant::{self, LightCommand, LightState, Topics},
+    models::{Device, Devices, NormalizedState},
+    monitor::{quota_safe_interval, DEFAULT_QUOTA_SHARE},
+    DeviceId, GoveeClient, DEFAULT_API_URL,
+};
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, Publish, QoS};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct Cli {
+    /// The MQTT broker host.
+    #[arg(long, env = "GOVEE_MQTT_HOST", default_value = "localhost")]
+    host: String,
+
+    /// The MQTT broker port.
+    #[arg(long, env = "GOVEE_MQTT_PORT", default_value_t = 1883)]
+    port: u16,
+
+    /// The MQTT username.
+    #[arg(long, env = "GOVEE_MQTT_USERNAME")]
+    username: Option<String>,
+
+    /// The MQTT password.
+    #[arg(long, env = "GOVEE_MQTT_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+
+    /// The MQTT client id.
+    #[arg(long, default_value = "govee-mqtt")]
+    client_id: String,
+
+    /// The prefix of the bridge's state and command topics.
+    #[arg(long, default_value = "govee")]
+    prefix: String,
+
+    /// Home Assistant's discovery prefix.
+    #[arg(long, default_value = "homeassistant")]
+    discovery_prefix: String,
+
+    /// The Govee API key.
+    #[arg(long, env = "GOVEE_KEY", hide_env_values = true)]
+    api_key: String,
+
+    /// The Govee API url.
+    #[arg(long, env = "GOVEE_API_URL", default_value = DEFAULT_API_URL)]
+    api_url: String,
+
+    /// Seconds between polls of every device [default: quota-safe].
+    #[arg(long)]
+    interval: Option<u64>,
+}
+
+/// The state shared between the MQTT event loop, the poller and commands.
+struct Bridge {
+    client: CachingClient,
+    mqtt: AsyncClient,
+    topics: Topics,
+    devices: Devices,
+    states: Mutex<HashMap<DeviceId, NormalizedState>>,
+}
+
+impl Bridge {
+    /// The device a command topic is for.
+    fn device_for(&self, topic: &str) -> Option<&Device> {
+        let object_id = self.topics.command_object_id(topic)?;
+        self.devices
+            .iter()
+            .find(|d| homeassistant::object_id(&d.device) == object_id)
+    }
+
+    /// Announce the bridge and every device, and republish known states.
+    async fn announce(&self) -> Result<()> {
+        self.mqtt
+            .publish(self.topics.availability(), QoS::AtLeastOnce, true, "online")
+            .await?;
+
+        for device in self.devices.iter() {
+            if let Some((topic, config)) = homeassistant::discovery(device, &self.topics) {
+                let payload = serde_json::to_vec(&config)?;
+                self.mqtt
+                    .publish(topic, QoS::AtLeastOnce, true, payload)
+                    .await?;
+            }
+        }
+
+        let states: Vec<_> = self.lock_states().values().cloned().collect();
+        for state in states {
+            self.publish_state(&state).await?;
+        }
+        Ok(())
+    }
+
+    fn lock_states(&self) -> std::sync::MutexGuard<'_, HashMap<DeviceId, NormalizedState>> {
+        self.states.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    async fn publish_state(&self, state: &NormalizedState) -> Result<()> {
+        let payload = serde_json::to_vec(&LightState::from(state))?;
+        self.mqtt
+            .publish(
+                self.topics.state(&state.device),
+                QoS::AtLeastOnce,
+                true,
+                payload,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Poll every retrievable device and publish its state.
+    async fn poll(&self) {
+        for device in self.devices.iter().filter(|d| d.retrievable) {
+            let state = match self.client.state(device).await {
+                Ok(state) => state.normalized(),
+                Err(e) => {
+                    tracing::warn!("failed to poll {}: {}", device.name, e);
+                    continue;
+                }
+            };
+
+            self.lock_states()
+                .insert(device.device.clone(), state.clone());
+            if let Err(e) = self.publish_state(&state).await {
+                tracing::warn!("failed to publish state of {}: {}", device.name, e);
+            }
+        }
+    }
+
+    /// Apply a command and publish the resulting state straight away, since
+    /// the API takes a while to reflect changes.
+    async fn command(&self, device: &Device, payload: &[u8]) -> Result<()> {
+        let command: LightCommand =
+            serde_json::from_slice(payload).context("invalid command payload")?;
+        let desired = command.desired();
+        self.client.apply(device, &desired).await?;
+
+        let state = {
+            let mut states = self.lock_states();
+            let state = states
+                .entry(device.device.clone())
+                .or_insert_with(|| NormalizedState {
+                    model: device.model.clone(),
+                    ..NormalizedState::new(device.device.clone())
+                });
+            state.apply(&desired);
+            state.clone()
+        };
+        self.publish_state(&state).await
+    }
+
+    /// The topics to subscribe to on every connection: commands and Home
+    /// Assistant's status.
+    fn subscriptions(&self) -> Vec<String> {
+        vec![self.topics.commands(), self.topics.homeassistant_status()]
+    }
+
+    /// Subscribe to the `pending` topics without waiting, removing those
+    /// that were queued.
+    ///
+    /// Awaiting here could deadlock, since the request queue only drains
+    /// while the event loop is polled. A full queue, such as after messages
+    /// piled up during an outage, is logged and the rest are left for the
+    /// next try.
+    fn try_subscribe(&self, pending: &mut Vec<String>) {
+        while let Some(topic) = pending.first() {
+            if let Err(e) = self.mqtt.try_subscribe(topic.as_str(), QoS::AtLeastOnce) {
+                tracing::warn!("failed to subscribe to {}: {}, retrying", topic, e);
+                return;
+            }
+            pending.remove(0);
+        }
+    }
+
+    /// Announce in the background, since publishing blocks once the client's
+    /// queue is full and the queue only drains while the event loop is polled.
+    fn spawn_announce(self: &Arc<Self>) {
+        let bridge = Arc::clone(self);
+        tokio::spawn(async move {
+            if let Err(e) = bridge.announce().await {
+                tracing::warn!("failed to announce devices: {}", e);
+            }
+        });
+    }
+
+    fn handle(self: &Arc<Self>, publish: Publish) {
+        if publish.topic == self.topics.homeassistant_status() {
+            // home assistant forgets discovered entities when it restarts
+            if publish.payload.as_ref() == b"online" {
+                self.spawn_announce();
+            }
+            return;
+        }
+
+        let Some(device) = self.device_for(&publish.topic).cloned() else {
+            tracing::debug!("ignoring message on {}", publish.topic);
+            return;
+        };
+
+        let bridge = Arc::clone(self);
+        tokio::spawn(async move {
+            tracing::info!("command for {}", device.name);
+            if let Err(e) = bridge.command(&device, &publish.payload).await {
+                tracing::warn!("command for {} failed: {:#}", device.name, e);
+            }
+        });
+    }
+}
+
+async fn poll_forever(bridge: Arc<Bridge>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        bridge.poll().await;
+    }
+}
+
+/// Drive the MQTT connection until `shutdown` completes, then go offline.
+async fn run_loop(
+    bridge: Arc<Bridge>,
+    mut events: EventLoop,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    tokio::pin!(shutdown);
+    let mut pending = Vec::new();
+    loop {
+        let event = tokio::select! {
+            event = events.poll() => event,
+            _ = &mut shutdown => break,
+        };
+
+        match event {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                tracing::info!("connected to broker");
+                pending = bridge.subscriptions();
+                bridge.spawn_announce();
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => bridge.handle(publish),
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!("mqtt connection error: {}, reconnecting", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+
+        bridge.try_subscribe(&mut pending);
+    }
+
+    tracing::info!("shutting down");
+    bridge
+        .mqtt
+        .publish(
+            bridge.topics.availability(),
+            QoS::AtLeastOnce,
+            true,
+            "offline",
+        )
+        .await?;
+    bridge.mqtt.disconnect().await?;
+
+    // flush the offline message and the disconnect
+    let flush = async {
+        while let Ok(event) = events.poll().await {
+            if let Event::Outgoing(rumqttc::Outgoing::Disconnect) = event {
+                break;
+            }
+        }
+    };
+    let _ = tokio::time::timeout(Duration::from_secs(2), flush).await;
+    Ok(())
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let client = CachingClient::new(
+        GoveeClient::new(&cli.api_url, &cli.api_key)?,
+        CacheOptions::default(),
+    );
+    let devices = client
+        .devices()
+        .await
+        .context("failed to list devices")?
+        .controllable();
+    tracing::info!("bridging {} devices", devices.len());
+
+    let topics = Topics {
+        prefix: cli.prefix,
+        discovery_prefix: cli.discovery_prefix,
+    };
+
+    let mut options = MqttOptions::new(cli.client_id, cli.host, cli.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(
+        topics.availability(),
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some(username) = cli.username {
+        options.set_credentials(username, cli.password.unwrap_or_default());
+    }
+    let (mqtt, events) = AsyncClient::new(options, 64);
+
+    let interval = cli
+        .interval
+        .map(Duration::from_secs)
+        .unwrap_or_else(|| quota_safe_interval(devices.retrievable().len(), DEFAULT_QUOTA_SHARE));
+
+    let bridge = Arc::new(Bridge {
+        client,
+        mqtt,
+        topics,
+        devices,
+        states: Mutex::default(),
+    });
+
+    let poller = tokio::spawn(poll_forever(Arc::clone(&bridge), interval));
+    let shutdown = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    let result = run_loop(bridge, events, shutdown).await;
+    poller.abort();
+    result
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
+        .init();
+
+    if let Err(e) = run(Cli::parse()).await {
+        tracing::error!("{:#}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::oneshot,
+    };
+
+    use super::*;
+
+    const CONNECT: u8 = 1;
+    const PUBLISH: u8 = 3;
+    const SUBSCRIBE: u8 = 8;
+    const DISCONNECT: u8 = 14;
+
+    fn bridge(mqtt: AsyncClient, devices: Vec<Device>) -> Arc<Bridge> {
+        let client = GoveeClient::new("http://127.0.0.1:9", "key").unwrap();
+        Arc::new(Bridge {
+            client: CachingClient::new(client, CacheOptions::default()),
+            mqtt,
+            topics: Topics {
+                prefix: "govee".into(),
+                discovery_prefix: "homeassistant".into(),
+            },
+            devices: Devices { devices },
+            states: Mutex::default(),
+        })
+    }
+
+    /// Read one MQTT packet, returning its type and body.
+    async fn packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let kind = stream.read_u8().await.unwrap() >> 4;
+        let (mut len, mut shift) = (0, 0);
+        loop {
+            let byte = stream.read_u8().await.unwrap();
+            len |= usize::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).await.unwrap();
+        (kind, body)
+    }
+
+    /// The length-prefixed string at the start of `body`, and what follows.
+    fn string(body: &[u8]) -> (String, &[u8]) {
+        let len = usize::from(u16::from_be_bytes([body[0], body[1]]));
+        let s = String::from_utf8(body[2..2 + len].to_vec()).unwrap();
+        (s, &body[2 + len..])
+    }
+
+    #[test]
+    fn device_for_command_topics() {
+        let (mqtt, _events) = AsyncClient::new(MqttOptions::new("test", "localhost", 1883), 8);
+        let desk = Device {
+            name: "desk".into(),
+            ..Device::new(DeviceId::parse("34:20:03:2E:30:2B").unwrap())
+        };
+        let bridge = bridge(mqtt, vec![desk]);
+
+        let found = bridge.device_for("govee/3420032e302b/set").unwrap();
+        assert_eq!(found.name, "desk");
+        assert!(bridge.device_for("govee/3420032e302c/set").is_none());
+        assert!(bridge.device_for("govee/3420032e302b/state").is_none());
+    }
+
+    #[tokio::test]
+    async fn subscribes_on_connect_and_goes_offline_on_shutdown() {
+        let broker = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = broker.local_addr().unwrap().port();
+        let (mqtt, events) = AsyncClient::new(MqttOptions::new("test", "127.0.0.1", port), 8);
+        let bridge = bridge(mqtt, Vec::new());
+
+        let (stop, stopped) = oneshot::channel::<()>();
+        let run = tokio::spawn(run_loop(bridge, events, async {
+            let _ = stopped.await;
+        }));
+
+        let (mut stream, _) = broker.accept().await.unwrap();
+        assert_eq!(packet(&mut stream).await.0, CONNECT);
+        stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap();
+
+        let mut subscribed = Vec::new();
+        let mut published = Vec::new();
+        let connected = async {
+            while subscribed.len() < 2 || published.is_empty() {
+                match packet(&mut stream).await {
+                    (SUBSCRIBE, body) => subscribed.push(string(&body[2..]).0),
+                    (PUBLISH, body) => published.push(string(&body).0),
+                    _ => {}
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), connected)
+            .await
+            .unwrap();
+        assert_eq!(subscribed, ["govee/+/set", "homeassistant/status"]);
+        assert_eq!(published, ["govee/bridge/availability"]);
+
+        stop.send(()).unwrap();
+        let mut last = Vec::new();
+        let disconnected = async {
+            loop {
+                match packet(&mut stream).await {
+                    (DISCONNECT, _) => break,
+                    (PUBLISH, body) => {
+                        let (topic, rest) = string(&body);
+                        // skip the packet id
+                        last.push((topic, String::from_utf8_lossy(&rest[2..]).into_owned()));
+                    }
+                    _ => {}
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), disconnected)
+            .await
+            .unwrap();
+        assert_eq!(
+            last,
+            [(
+                "govee/bridge/availability".to_string(),
+                "offline".to_string()
+            )]
+        );
+        run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn subscribes_after_reconnecting_with_a_full_queue() {
+        let broker = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = broker.local_addr().unwrap().port();
+        let (mqtt, events) = AsyncClient::new(MqttOptions::new("test", "127.0.0.1", port), 8);
+        let bridge = bridge(mqtt.clone(), Vec::new());
+
+        let (stop, stopped) = oneshot::channel::<()>();
+        let run = tokio::spawn(run_loop(bridge, events, async {
+            let _ = stopped.await;
+        }));
+
+        let connect = |mut stream: TcpStream| async move {
+            assert_eq!(packet(&mut stream).await.0, CONNECT);
+            stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap();
+            let mut subscribed = Vec::new();
+            let subscribing = async {
+                while subscribed.len() < 2 {
+                    if let (SUBSCRIBE, body) = packet(&mut stream).await {
+                        subscribed.push(string(&body[2..]).0);
+                    }
+                }
+            };
+            // the first reconnect comes after a 5s pause
+            tokio::time::timeout(Duration::from_secs(10), subscribing)
+                .await
+                .unwrap();
+            assert_eq!(subscribed, ["govee/+/set", "homeassistant/status"]);
+            stream
+        };
+
+        let (stream, _) = broker.accept().await.unwrap();
+        let stream = connect(stream).await;
+
+        // drop the connection, and fill the queue while it is down
+        drop(stream);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        while mqtt
+            .try_publish("govee/bridge/test", QoS::AtLeastOnce, false, "queued")
+            .is_ok()
+        {}
+
+        let (stream, _) = broker.accept().await.unwrap();
+        let _stream = connect(stream).await;
+
+        stop.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), run)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,80 @@
+//! A WebSocket server pushing Govee device state changes.
+//!
+//! Devices are polled in the background at a quota-safe interval. Clients
+//! connect to `/ws`, subscribe to devices, and are sent their state whenever
+//! it changes. They can also control devices over the same connection.
+use std::{net::SocketAddr, time::Duration};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use govee_rs::{events::Watcher, monitor::Monitor, websocket, GoveeClient, DEFAULT_API_URL};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct Cli {
+    /// The address to listen on.
+    #[arg(long, env = "GOVEE_WS_LISTEN", default_value = "127.0.0.1:8090")]
+    listen: SocketAddr,
+
+    /// The Govee API key.
+    #[arg(long, env = "GOVEE_KEY", hide_env_values = true)]
+    api_key: String,
+
+    /// The Govee API url.
+    #[arg(long, env = "GOVEE_API_URL", default_value = DEFAULT_API_URL)]
+    api_url: String,
+
+    /// Seconds between polls of every device [default: quota-safe].
+    #[arg(long)]
+    interval: Option<u64>,
+
+    /// State changes buffered for each slow client before it misses some.
+    #[arg(long, default_value_t = 256)]
+    buffer: usize,
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let client = GoveeClient::new(&cli.api_url, &cli.api_key)?;
+    let devices = client.devices().await.context("failed to list devices")?;
+
+    let mut monitor = Monitor::new(client.clone(), &devices);
+    if let Some(interval) = cli.interval {
+        monitor = monitor.with_interval(Duration::from_secs(interval));
+    }
+    tracing::info!(
+        "polling {} devices every {:.1}s",
+        monitor.devices().len(),
+        monitor.interval().as_secs_f64()
+    );
+
+    let watcher = Watcher::new(cli.buffer);
+    let poller = watcher.clone();
+    tokio::spawn(async move { poller.run(&monitor).await });
+
+    let listener = tokio::net::TcpListener::bind(cli.listen)
+        .await
+        .with_context(|| format!("failed to listen on {}", cli.listen))?;
+    tracing::info!("listening on {}", cli.listen);
+
+    axum::serve(listener, websocket::router(client, devices, watcher))
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+            tracing::info!("shutting down");
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
+        .init();
+
+    if let Err(e) = run(Cli::parse()).await {
+        tracing::error!("{:#}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+/// The environment variable holding the API key.
+pub const KEY_VAR: &str = "GOVEE_KEY";
+
+/// The environment variable overriding the config file location.
+pub const CONFIG_VAR: &str = "GOVEE_CONFIG";
+
+/// The environment variable selecting a profile.
+pub const PROFILE_VAR: &str = "GOVEE_PROFILE";
+
+/// Settings for a single account.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub api_key: Option<String>,
+    pub api_url: Option<String>,
+    pub layout: Option<PathBuf>,
+}
+
+/// The contents of the CLI config file.
+///
+/// The top level settings are the default profile. Additional accounts go in
+/// named profiles, which fall back to the top level `api_url` and `layout`
+/// but need their own `api_key`:
+///
+/// ```toml
+/// api_key = "..."
+/// layout = "~/.config/govee/layout.toml"
+///
+/// [profiles.lab]
+/// api_key = "..."
+/// layout = "lab.toml"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub api_key: Option<String>,
+    pub api_url: Option<String>,
+    pub layout: Option<PathBuf>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ProfileConfig>,
+}
+
+/// The settings to use for this invocation.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub api_key: String,
+    pub api_url: String,
+    pub layout: Option<PathBuf>,
+}
+
+impl Config {
+    /// Load the config from `path`, `$GOVEE_CONFIG` or the default location.
+    ///
+    /// A missing file at the default location is not an error.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let explicit = path
+            .map(Path::to_path_buf)
+            .or_else(|| env::var_os(CONFIG_VAR).map(PathBuf::from));
+
+        let path = match explicit {
+            Some(path) => path,
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+
+        let raw = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+        let mut config: Self =
+            toml::from_str(&raw).with_context(|| format!("invalid config {}", path.display()))?;
+
+        // relative layout paths are relative to the config file
+        if let Some(dir) = path.parent() {
+            let layouts = std::iter::once(&mut config.layout)
+                .chain(config.profiles.values_mut().map(|p| &mut p.layout));
+            for layout in layouts.flatten() {
+                *layout = dir.join(expand_home(layout));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Resolve the settings for the named profile, or the default profile.
+    ///
+    /// `$GOVEE_KEY` overrides the key of the default profile only.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile> {
+        let api_url = |p: Option<&String>| {
+            p.or(self.api_url.as_ref())
+                .cloned()
+                .unwrap_or_else(|| govee_rs::DEFAULT_API_URL.to_string())
+        };
+
+        match name {
+            None => {
+                let api_key = env::var(KEY_VAR)
+                    .ok()
+                    .filter(|k| !k.is_empty())
+                    .or_else(|| self.api_key.clone())
+                    .ok_or_else(|| {
+                        anyhow!("no api key: set {} or api_key in the config file", KEY_VAR)
+                    })?;
+
+                Ok(Profile {
+                    api_key,
+                    api_url: api_url(None),
+                    layout: self.layout.clone(),
+                })
+            }
+            Some(name) => {
+                let profile = self.profiles.get(name).ok_or_else(|| {
+                    let known: Vec<_> = self.profiles.keys().map(String::as_str).collect();
+                    anyhow!(
+                        "unknown profile {:?}, known profiles: {}",
+                        name,
+                        known.join(", ")
+                    )
+                })?;
+
+                let api_key = profile
+                    .api_key
+                    .clone()
+                    .ok_or_else(|| anyhow!("profile {:?} has no api_key", name))?;
+
+                Ok(Profile {
+                    api_key,
+                    api_url: api_url(profile.api_url.as_ref()),
+                    layout: profile.layout.clone().or_else(|| self.layout.clone()),
+                })
+            }
+        }
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("govee").join("config.toml"))
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
@@ -0,0 +1,783 @@
+//! A command line interface to Govee devices.
+#[cfg(feature = "effects")]
+use std::net::IpAddr;
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::Result;
+#[cfg(feature = "effects")]
+use clap::ValueEnum;
+use clap::{Args, Parser, Subcommand};
+#[cfg(feature = "circadian")]
+use govee_rs::circadian::{Circadian, Curve};
+#[cfg(feature = "schedule")]
+use govee_rs::schedule::Schedule;
+#[cfg(feature = "script")]
+use govee_rs::script::{Limits, ScriptRunner};
+use govee_rs::{
+    accounts::MultiClient,
+    layout::{Home, Layout},
+    lookup::{LookupError, Resolve},
+    models::{Devices, PowerState, COLOR_TEMP_RANGE},
+    sequence::{Outcome, Sequence},
+    Color, GoveeClient,
+};
+#[cfg(feature = "lightshow")]
+use govee_rs::{
+    effects::LAN_INTERVAL,
+    lightshow::{Analysis, Audio, Choreography, ShowPlayer},
+    models::ControlCommand,
+    monitor,
+};
+#[cfg(feature = "effects")]
+use govee_rs::{
+    effects::{Effect, EffectPlayer, LanTransport, Transport},
+    models::DeviceId,
+};
+#[cfg(feature = "rules")]
+use govee_rs::{events::Watcher, monitor::Monitor, rules::RuleEngine};
+use tokio_util::sync::CancellationToken;
+
+mod config;
+mod output;
+mod watch;
+
+use config::Config;
+use output::{Format, StateRecord};
+
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct Cli {
+    /// Path to the config file [default: ~/.config/govee/config.toml].
+    #[arg(long, global = true, env = config::CONFIG_VAR)]
+    config: Option<PathBuf>,
+
+    /// The config file profile (account) to use [default: the top level settings].
+    #[arg(short, long, global = true, env = config::PROFILE_VAR)]
+    profile: Option<String>,
+
+    /// Path to a layout file, overriding the config file.
+    #[arg(long, global = true)]
+    layout: Option<PathBuf>,
+
+    /// The output format for `devices` and `state`.
+    #[arg(short, long, global = true, value_enum, default_value_t)]
+    output: Format,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List the devices on the account.
+    Devices {
+        /// List the devices of every configured profile instead.
+        #[arg(long, conflicts_with = "profile")]
+        all_profiles: bool,
+    },
+
+    /// Show the state of devices.
+    State(Targets),
+
+    /// Turn devices on.
+    On(Targets),
+
+    /// Turn devices off.
+    Off(Targets),
+
+    /// Set the brightness of devices.
+    Brightness {
+        /// Brightness from 0 to 100.
+        #[arg(value_parser = clap::value_parser!(u64).range(0..=100))]
+        value: u64,
+
+        #[command(flatten)]
+        targets: Targets,
+    },
+
+    /// Set the color of devices.
+    Color {
+        /// A hex color like "#ff8800" or a name like "orange".
+        #[arg(value_parser = parse_color)]
+        color: Color,
+
+        #[command(flatten)]
+        targets: Targets,
+    },
+
+    /// Set the color temperature of devices.
+    Temp {
+        /// Color temperature in kelvin, from 2000 to 9000.
+        #[arg(value_parser = clap::value_parser!(u64).range(COLOR_TEMP_RANGE.0..=COLOR_TEMP_RANGE.1))]
+        kelvin: u64,
+
+        #[command(flatten)]
+        targets: Targets,
+    },
+
+    /// Show a live dashboard of device state.
+    Watch {
+        /// Seconds between polls of every device [default: quota-safe].
+        #[arg(long)]
+        interval: Option<u64>,
+
+        /// Devices to watch [default: all].
+        targets: Vec<String>,
+    },
+
+    /// Run a light sequence from a TOML or YAML file.
+    Run {
+        /// Print what would be done without doing it.
+        #[arg(long)]
+        dry_run: bool,
+
+        file: PathBuf,
+    },
+
+    /// Run jobs from a TOML or YAML schedule file until interrupted.
+    #[cfg(feature = "schedule")]
+    Schedule {
+        /// Print the next this many times jobs are due instead.
+        #[arg(long, value_name = "COUNT")]
+        list: Option<usize>,
+
+        /// Where to remember when jobs last ran, so missed jobs can catch up
+        /// after a restart.
+        #[arg(long)]
+        state: Option<PathBuf>,
+
+        file: PathBuf,
+    },
+
+    /// Run rules from a TOML or YAML file until interrupted, reloading them
+    /// when the file changes.
+    #[cfg(feature = "rules")]
+    Rules {
+        /// Seconds between polls of every device [default: quota-safe].
+        #[arg(long)]
+        interval: Option<u64>,
+
+        file: PathBuf,
+    },
+
+    /// Run a Rhai automation script.
+    #[cfg(feature = "script")]
+    Script {
+        /// How long the script may run.
+        #[arg(long, value_parser = humantime_serde::re::humantime::parse_duration, default_value = "1m")]
+        timeout: Duration,
+
+        file: PathBuf,
+    },
+
+    /// Keep devices on a color temperature and brightness curve through the
+    /// day until interrupted.
+    #[cfg(feature = "circadian")]
+    Circadian {
+        /// A TOML or YAML curve file [default: warm mornings and evenings,
+        /// cool middays].
+        #[arg(long)]
+        curve: Option<PathBuf>,
+
+        /// The IANA time zone to read the curve in, like "Europe/Amsterdam"
+        /// [default: local time].
+        #[arg(long, value_parser = parse_timezone)]
+        timezone: Option<chrono_tz::Tz>,
+
+        /// How long to leave a device alone after it is changed by hand.
+        #[arg(long, value_parser = humantime_serde::re::humantime::parse_duration)]
+        pause: Option<Duration>,
+
+        #[command(flatten)]
+        targets: Targets,
+    },
+
+    /// Play an animated effect on devices until interrupted.
+    #[cfg(feature = "effects")]
+    Effect {
+        #[arg(value_enum)]
+        effect: EffectKind,
+
+        /// A color to use, or for `loop` one of the palette [default: depends
+        /// on the effect].
+        #[arg(long = "color", value_parser = parse_color)]
+        colors: Vec<Color>,
+
+        /// How long one cycle of the effect takes [default: depends on the
+        /// effect].
+        #[arg(long, value_parser = humantime_serde::re::humantime::parse_duration)]
+        period: Option<Duration>,
+
+        /// Brightness from 0 to 100.
+        #[arg(long, value_parser = clap::value_parser!(u64).range(0..=100), default_value_t = 100)]
+        brightness: u64,
+
+        /// Stop after this long.
+        #[arg(long, value_parser = humantime_serde::re::humantime::parse_duration)]
+        duration: Option<Duration>,
+
+        /// Time between frames [default: quota-safe over the cloud, 100ms over
+        /// the LAN].
+        #[arg(long, value_parser = humantime_serde::re::humantime::parse_duration)]
+        interval: Option<Duration>,
+
+        /// Send frames over the LAN instead, to a device at an address given
+        /// as DEVICE_ID=IP. Repeat for each device.
+        #[arg(long, value_name = "DEVICE_ID=IP", value_parser = parse_lan_addr)]
+        lan: Vec<(DeviceId, IpAddr)>,
+
+        #[command(flatten)]
+        targets: Targets,
+    },
+
+    /// Play a light show synced to a WAV or FLAC file, started with the
+    /// music after a countdown.
+    #[cfg(feature = "lightshow")]
+    Lightshow {
+        /// Print the cues instead of playing them.
+        #[arg(long)]
+        cues: bool,
+
+        /// A color of the palette stepped through on each beat [default: a
+        /// rainbow].
+        #[arg(long = "color", value_parser = parse_color)]
+        colors: Vec<Color>,
+
+        /// The shortest time between cues for a device [default: a
+        /// quota-safe interval over the cloud, 100ms over the LAN].
+        #[arg(long, value_parser = humantime_serde::re::humantime::parse_duration)]
+        interval: Option<Duration>,
+
+        /// Send each cue this much early, to make up for network delay.
+        #[arg(long, value_parser = humantime_serde::re::humantime::parse_duration)]
+        latency: Option<Duration>,
+
+        /// Seconds to count down before the show starts.
+        #[arg(long, default_value_t = 3)]
+        countdown: u64,
+
+        /// Send cues over the LAN instead, to a device at an address given
+        /// as DEVICE_ID=IP. Repeat for each device.
+        #[arg(long, value_name = "DEVICE_ID=IP", value_parser = parse_lan_addr)]
+        lan: Vec<(DeviceId, IpAddr)>,
+
+        file: PathBuf,
+
+        #[command(flatten)]
+        targets: Targets,
+    },
+}
+
+#[cfg(feature = "effects")]
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum EffectKind {
+    Breathe,
+    Rainbow,
+    Loop,
+    Candle,
+    Strobe,
+}
+
+#[cfg(feature = "effects")]
+impl EffectKind {
+    fn effect(self, colors: &[Color], period: Option<Duration>, brightness: u64) -> Effect {
+        let color = |default| colors.first().copied().unwrap_or(default);
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        match self {
+            Self::Breathe => Effect::Breathe {
+                color: color(white),
+                period: period.unwrap_or(Duration::from_secs(4)),
+                low: brightness / 10,
+                high: brightness,
+            },
+            Self::Rainbow => Effect::Rainbow {
+                period: period.unwrap_or(Duration::from_secs(60)),
+                brightness,
+                spread: 30.0,
+            },
+            Self::Loop => {
+                let period = period.unwrap_or(Duration::from_secs(10));
+                Effect::ColorLoop {
+                    palette: if colors.is_empty() {
+                        ["red", "lime", "blue"]
+                            .into_iter()
+                            .filter_map(Color::named)
+                            .collect()
+                    } else {
+                        colors.to_vec()
+                    },
+                    hold: period / 2,
+                    fade: period / 2,
+                    brightness,
+                }
+            }
+            Self::Candle => Effect::Candle {
+                color: color(Color {
+                    r: 255,
+                    g: 147,
+                    b: 41,
+                }),
+                brightness,
+                flicker: brightness / 2,
+                seed: 1,
+            },
+            Self::Strobe => Effect::Strobe {
+                color: color(white),
+                period: period.unwrap_or(Duration::from_millis(200)),
+                duty: 0.5,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+struct Targets {
+    /// Device ids, names, aliases, rooms or groups.
+    #[arg(required = true)]
+    targets: Vec<String>,
+}
+
+#[cfg(feature = "circadian")]
+fn parse_timezone(s: &str) -> Result<chrono_tz::Tz, String> {
+    s.parse()
+        .map_err(|_| format!("{:?} is not an IANA time zone name", s))
+}
+
+#[cfg(feature = "effects")]
+fn parse_lan_addr(s: &str) -> Result<(DeviceId, IpAddr), String> {
+    let (device, ip) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("{:?} is not of the form DEVICE_ID=IP", s))?;
+    let device = device.parse().map_err(|e| format!("{}", e))?;
+    let ip = ip
+        .parse()
+        .map_err(|_| format!("{:?} is not an IP address", ip))?;
+    Ok((device, ip))
+}
+
+#[cfg(feature = "effects")]
+async fn play_effect<T: Transport>(
+    mut player: EffectPlayer<T>,
+    interval: Option<Duration>,
+    duration: Option<Duration>,
+) -> Result<()> {
+    if let Some(interval) = interval {
+        player = player.with_interval(interval);
+    }
+    if let Some(duration) = duration {
+        player = player.with_duration(duration);
+    }
+    if player.devices().is_empty() {
+        anyhow::bail!("none of the devices can change color");
+    }
+    eprintln!(
+        "playing on {} devices every {}",
+        player.devices().len(),
+        humantime_serde::re::humantime::format_duration(player.interval())
+    );
+
+    let cancel = cancel_on_ctrl_c();
+    player.run(&cancel, |event| eprintln!("{}", event)).await;
+    Ok(())
+}
+
+#[cfg(feature = "lightshow")]
+async fn play_show<T: Transport>(
+    mut player: ShowPlayer<T>,
+    latency: Option<Duration>,
+    countdown: u64,
+) -> Result<()> {
+    if player.devices().is_empty() {
+        anyhow::bail!("none of the devices can change color");
+    }
+    if let Some(latency) = latency {
+        player = player.with_latency(latency);
+    }
+
+    let cancel = cancel_on_ctrl_c();
+    for n in (1..=countdown).rev() {
+        eprintln!("{}...", n);
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+            _ = cancel.cancelled() => return Ok(()),
+        }
+    }
+    eprintln!("go");
+    player
+        .run(tokio::time::Instant::now(), &cancel, |event| {
+            eprintln!("{}", event)
+        })
+        .await;
+    Ok(())
+}
+
+/// A token cancelled when the user presses Ctrl-C.
+fn cancel_on_ctrl_c() -> CancellationToken {
+    let cancel = CancellationToken::new();
+    let on_interrupt = cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("cancelling...");
+            on_interrupt.cancel();
+        }
+    });
+    cancel
+}
+
+fn parse_color(s: &str) -> Result<Color, String> {
+    Color::parse(s)
+        .ok()
+        .or_else(|| Color::named(s))
+        .ok_or_else(|| format!("{:?} is not a hex color or a known color name", s))
+}
+
+/// Maps what the user typed to devices, using the layout if there is one.
+#[derive(Clone)]
+enum Registry {
+    Home(Home),
+    Plain(Devices),
+}
+
+impl Registry {
+    fn devices(&self) -> &Devices {
+        match self {
+            Self::Home(home) => home.devices(),
+            Self::Plain(devices) => devices,
+        }
+    }
+
+    fn select(&self, targets: &[String]) -> Result<Devices> {
+        let mut selected = Devices::default();
+        for target in targets.iter() {
+            selected = selected.union(&self.resolve(target)?);
+        }
+        Ok(selected)
+    }
+}
+
+impl Resolve for Registry {
+    fn resolve(&self, name: &str) -> Result<Devices, LookupError> {
+        match self {
+            Self::Home(home) => home.resolve(name),
+            Self::Plain(devices) => devices.resolve(name),
+        }
+    }
+}
+
+struct App {
+    client: GoveeClient,
+    registry: Registry,
+}
+
+impl App {
+    async fn new(cli: &Cli, config: &Config) -> Result<Self> {
+        let profile = config.profile(cli.profile.as_deref())?;
+        let client = GoveeClient::new(&profile.api_url, &profile.api_key)?;
+        let devices = client.devices().await?;
+
+        let registry = match cli.layout.as_ref().or(profile.layout.as_ref()) {
+            Some(path) => Registry::Home(Layout::load(path)?.resolve(devices)?),
+            None => Registry::Plain(devices),
+        };
+
+        Ok(Self { client, registry })
+    }
+
+    async fn run(&self, command: &Command, format: Format) -> Result<()> {
+        match command {
+            Command::Devices { .. } => output::print(format, self.registry.devices())?,
+            Command::State(targets) => {
+                let mut records = Vec::new();
+                for device in self.registry.select(&targets.targets)?.iter() {
+                    let state = self.client.state(device).await?;
+                    records.push(StateRecord::new(device, state));
+                }
+                output::print(format, &records)?;
+            }
+            Command::On(targets) => {
+                for device in self.registry.select(&targets.targets)?.iter() {
+                    self.client.turn(device, PowerState::On).await?;
+                }
+            }
+            Command::Off(targets) => {
+                for device in self.registry.select(&targets.targets)?.iter() {
+                    self.client.turn(device, PowerState::Off).await?;
+                }
+            }
+            Command::Brightness { value, targets } => {
+                for device in self.registry.select(&targets.targets)?.iter() {
+                    self.client.brightness(device, *value).await?;
+                }
+            }
+            Command::Color { color, targets } => {
+                for device in self.registry.select(&targets.targets)?.iter() {
+                    self.client.color(device, *color).await?;
+                }
+            }
+            Command::Temp { kelvin, targets } => {
+                for device in self.registry.select(&targets.targets)?.iter() {
+                    self.client.color_temp(device, *kelvin).await?;
+                }
+            }
+            Command::Watch { interval, targets } => {
+                let devices = if targets.is_empty() {
+                    self.registry.devices().clone()
+                } else {
+                    self.registry.select(targets)?
+                };
+                let interval = interval.map(Duration::from_secs);
+                watch::run(self.client.clone(), &devices, interval).await?;
+            }
+            Command::Run { dry_run, file } => {
+                let plan = Sequence::load(file)?.plan(&self.registry)?;
+
+                if *dry_run {
+                    for action in plan.dry_run() {
+                        println!("{}", action);
+                    }
+                    return Ok(());
+                }
+
+                let cancel = cancel_on_ctrl_c();
+
+                if plan.run(&self.client, &cancel).await? == Outcome::Cancelled {
+                    eprintln!("sequence cancelled");
+                }
+            }
+            #[cfg(feature = "rules")]
+            Command::Rules { interval, file } => {
+                let watcher = Watcher::new(256);
+                let mut engine = RuleEngine::from_file(
+                    self.client.clone(),
+                    &self.registry,
+                    watcher.clone(),
+                    file,
+                )?;
+
+                let mut monitor = Monitor::new(self.client.clone(), self.registry.devices());
+                if let Some(interval) = interval {
+                    monitor = monitor.with_interval(Duration::from_secs(*interval));
+                }
+                let polling = tokio::spawn(async move { watcher.run(&monitor).await });
+
+                let cancel = cancel_on_ctrl_c();
+                engine.run(&cancel, |event| eprintln!("{}", event)).await;
+                polling.abort();
+            }
+            #[cfg(feature = "script")]
+            Command::Script { timeout, file } => {
+                let runner = ScriptRunner::new(
+                    self.client.clone(),
+                    self.registry.devices().clone(),
+                    self.registry.clone(),
+                )
+                .with_limits(Limits {
+                    timeout: *timeout,
+                    ..Default::default()
+                })
+                .with_printer(|line| println!("{}", line));
+
+                let cancel = cancel_on_ctrl_c();
+                let output = runner.run_file(file, &cancel).await?;
+                if let Some(value) = output.value {
+                    println!("{}", value);
+                }
+            }
+            #[cfg(feature = "circadian")]
+            Command::Circadian {
+                curve,
+                timezone,
+                pause,
+                targets,
+            } => {
+                let curve = match curve {
+                    Some(path) => Curve::load(path)?,
+                    None => Curve::default(),
+                };
+                let devices = self.registry.select(&targets.targets)?;
+                let mut circadian = Circadian::new(self.client.clone(), &devices, curve);
+                if let Some(tz) = timezone {
+                    circadian = circadian.with_timezone(*tz);
+                }
+                if let Some(pause) = pause {
+                    circadian = circadian.with_pause(*pause);
+                }
+                if circadian.devices().is_empty() {
+                    anyhow::bail!("none of the devices can change color temperature or brightness");
+                }
+
+                let cancel = cancel_on_ctrl_c();
+                circadian.run(&cancel, |event| eprintln!("{}", event)).await;
+            }
+            #[cfg(feature = "effects")]
+            Command::Effect {
+                effect,
+                colors,
+                period,
+                brightness,
+                duration,
+                interval,
+                lan,
+                targets,
+            } => {
+                let devices = self.registry.select(&targets.targets)?;
+                let effect = effect.effect(colors, *period, *brightness);
+
+                if lan.is_empty() {
+                    let player = EffectPlayer::new(self.client.clone(), &devices, effect);
+                    play_effect(player, *interval, *duration).await?;
+                } else {
+                    let mut transport = LanTransport::bind().await?;
+                    for (device, ip) in lan {
+                        transport = transport.with_ip(device.clone(), *ip);
+                    }
+                    let player = EffectPlayer::new(transport, &devices, effect);
+                    play_effect(player, *interval, *duration).await?;
+                }
+            }
+            #[cfg(feature = "lightshow")]
+            Command::Lightshow {
+                cues,
+                colors,
+                interval,
+                latency,
+                countdown,
+                lan,
+                file,
+                targets,
+            } => {
+                let devices = self.registry.select(&targets.targets)?;
+                let analysis = Analysis::new(&Audio::load(file)?);
+                eprintln!(
+                    "{}: {} beats{}",
+                    humantime_serde::re::humantime::format_duration(analysis.duration()),
+                    analysis.beats().len(),
+                    analysis
+                        .tempo()
+                        .map(|bpm| format!(", {:.0} a minute", bpm))
+                        .unwrap_or_default()
+                );
+
+                let mut choreography = Choreography {
+                    interval: interval.unwrap_or(if lan.is_empty() {
+                        let colored = devices.controllable().supporting(ControlCommand::Color);
+                        self.client.interval(colored.len())
+                    } else {
+                        LAN_INTERVAL
+                    }),
+                    ..Default::default()
+                };
+                if !colors.is_empty() {
+                    choreography.palette = colors.clone();
+                }
+                let show = choreography.cues(&analysis, &devices);
+
+                if *cues {
+                    for cue in &show {
+                        let name = devices
+                            .iter()
+                            .find(|d| d.device == cue.device)
+                            .map_or(cue.device.as_str(), |d| d.name.as_str());
+                        println!(
+                            "{:>9.2}s  {}  {} {}%",
+                            cue.at.as_secs_f64(),
+                            name,
+                            cue.frame.color,
+                            cue.frame.brightness
+                        );
+                    }
+                    return Ok(());
+                }
+
+                if lan.is_empty() {
+                    let player = ShowPlayer::new(self.client.clone(), &devices, show);
+                    eprintln!(
+                        "about {} requests of the daily {}",
+                        player.requests(),
+                        monitor::DAILY_REQUEST_LIMIT
+                    );
+                    play_show(player, *latency, *countdown).await?;
+                } else {
+                    let mut transport = LanTransport::bind().await?;
+                    for (device, ip) in lan {
+                        transport = transport.with_ip(device.clone(), *ip);
+                    }
+                    let player = ShowPlayer::new(transport, &devices, show);
+                    play_show(player, *latency, *countdown).await?;
+                }
+            }
+            #[cfg(feature = "schedule")]
+            Command::Schedule { list, state, file } => {
+                let timetable = Schedule::load(file)?.plan(&self.registry)?;
+
+                if let Some(count) = list {
+                    for firing in timetable.upcoming(chrono::Utc::now(), *count) {
+                        println!(
+                            "{}  {}",
+                            firing.at.format("%a %Y-%m-%d %H:%M %Z"),
+                            firing.job
+                        );
+                    }
+                    return Ok(());
+                }
+
+                let cancel = cancel_on_ctrl_c();
+                timetable
+                    .run(&self.client, &cancel, state.as_deref(), |report| {
+                        eprintln!("{}", report)
+                    })
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// List the devices of every profile, tagged with the profile name.
+async fn all_devices(config: &Config, format: Format) -> Result<()> {
+    let mut client = MultiClient::default();
+
+    // the top level settings are only a profile if they have a key
+    if let Ok(profile) = config.profile(None) {
+        client.add(
+            "default",
+            GoveeClient::new(&profile.api_url, &profile.api_key)?,
+        );
+    }
+
+    for name in config.profiles.keys() {
+        let profile = config.profile(Some(name))?;
+        client.add(name, GoveeClient::new(&profile.api_url, &profile.api_key)?);
+    }
+
+    output::print(format, &client.devices().await?)
+}
+
+async fn run(cli: &Cli) -> Result<()> {
+    let config = Config::load(cli.config.as_deref())?;
+
+    if let Command::Devices { all_profiles: true } = cli.command {
+        return all_devices(&config, cli.output).await;
+    }
+
+    App::new(cli, &config)
+        .await?
+        .run(&cli.command, cli.output)
+        .await
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let result = run(&cli).await;
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
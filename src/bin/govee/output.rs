@@ -0,0 +1,446 @@
+use std::io::{self, Write};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use govee_rs::{
+    accounts::AccountDevice,
+    models::{Color, Device, DeviceId, DeviceState, Model, NormalizedState, PowerState},
+};
+use serde::{Serialize, Serializer};
+
+/// How results are printed.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, ValueEnum)]
+pub enum Format {
+    /// Aligned columns for humans.
+    #[default]
+    Table,
+    /// A single JSON array.
+    Json,
+    /// One JSON object per line.
+    Ndjson,
+    Yaml,
+    Csv,
+}
+
+/// Something that can be printed as a row in a table or CSV file.
+pub trait Row {
+    fn headers() -> &'static [&'static str];
+
+    fn cells(&self) -> Vec<String>;
+}
+
+impl Row for Device {
+    fn headers() -> &'static [&'static str] {
+        &[
+            "name",
+            "device",
+            "model",
+            "controllable",
+            "retrievable",
+            "commands",
+        ]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        let mut commands: Vec<_> = self.supported_commands.iter().collect();
+        commands.sort();
+        let commands: Vec<_> = commands
+            .into_iter()
+            .map(|c| {
+                serde_json::to_value(c)
+                    .ok()
+                    .and_then(|v| v.as_str().map(String::from))
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        vec![
+            self.name.clone(),
+            self.device.to_string(),
+            self.model.to_string(),
+            self.controllable.to_string(),
+            self.retrievable.to_string(),
+            commands.join(","),
+        ]
+    }
+}
+
+impl Row for AccountDevice {
+    fn headers() -> &'static [&'static str] {
+        &[
+            "account",
+            "name",
+            "device",
+            "model",
+            "controllable",
+            "retrievable",
+            "commands",
+        ]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        std::iter::once(self.account.clone())
+            .chain(self.device.cells())
+            .collect()
+    }
+}
+
+/// The state of a single device, as printed by `govee state`.
+#[derive(Debug, Clone, Serialize)]
+pub struct StateRecord {
+    pub name: String,
+    pub state: DeviceState,
+    #[serde(serialize_with = "camel_case")]
+    pub normalized: NormalizedState,
+}
+
+/// Serialize `state` with camelCase fields, like devices and raw states.
+fn camel_case<S: Serializer>(state: &NormalizedState, serializer: S) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Normalized<'a> {
+        device: &'a DeviceId,
+        model: &'a Model,
+        online: &'a Option<bool>,
+        power: &'a Option<PowerState>,
+        brightness: &'a Option<u64>,
+        color: &'a Option<Color>,
+        color_temp: &'a Option<u64>,
+    }
+
+    Normalized {
+        device: &state.device,
+        model: &state.model,
+        online: &state.online,
+        power: &state.power,
+        brightness: &state.brightness,
+        color: &state.color,
+        color_temp: &state.color_temp,
+    }
+    .serialize(serializer)
+}
+
+impl StateRecord {
+    pub fn new(device: &Device, state: DeviceState) -> Self {
+        Self {
+            name: device.name.clone(),
+            normalized: state.normalized(),
+            state,
+        }
+    }
+}
+
+impl Row for StateRecord {
+    fn headers() -> &'static [&'static str] {
+        &[
+            "name",
+            "device",
+            "model",
+            "online",
+            "power",
+            "brightness",
+            "color",
+            "color_temp",
+        ]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        let n = &self.normalized;
+        vec![
+            self.name.clone(),
+            n.device.to_string(),
+            n.model.to_string(),
+            opt(n.online),
+            opt(n.power),
+            opt(n.brightness),
+            opt(n.color),
+            opt(n.color_temp),
+        ]
+    }
+}
+
+fn opt<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// Print `records` to stdout in the given format.
+pub fn print<T: Serialize + Row>(format: Format, records: &[T]) -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    write(&mut out, format, records)?;
+    out.flush()?;
+    Ok(())
+}
+
+pub fn write<W: Write, T: Serialize + Row>(
+    out: &mut W,
+    format: Format,
+    records: &[T],
+) -> Result<()> {
+    match format {
+        Format::Table => write_table(out, records)?,
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, records)?;
+            writeln!(out)?;
+        }
+        Format::Ndjson => {
+            for record in records {
+                serde_json::to_writer(&mut *out, record)?;
+                writeln!(out)?;
+            }
+        }
+        Format::Yaml => serde_yaml::to_writer(&mut *out, records)?,
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record(T::headers())?;
+            for record in records {
+                writer.write_record(record.cells())?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_table<W: Write, T: Row>(out: &mut W, records: &[T]) -> io::Result<()> {
+    let headers: Vec<String> = T::headers().iter().map(|h| h.to_uppercase()).collect();
+    let rows: Vec<Vec<String>> = records.iter().map(Row::cells).collect();
+
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    for row in std::iter::once(&headers).chain(rows.iter()) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        writeln!(out, "{}", line.join("  ").trim_end())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use govee_rs::models::{ControlCommand, DeviceId, DeviceProperty, PowerState};
+
+    use super::*;
+
+    fn devices() -> Vec<Device> {
+        vec![
+            Device {
+                model: "H6159".into(),
+                name: "Desk Strip".into(),
+                controllable: true,
+                retrievable: true,
+                // listed out of order to check they are sorted
+                supported_commands: [
+                    ControlCommand::Turn,
+                    ControlCommand::Color,
+                    ControlCommand::Brightness,
+                    ControlCommand::ColorTem,
+                ]
+                .into(),
+                ..Device::new(DeviceId::parse("34:20:03:2e:30:01").unwrap())
+            },
+            Device {
+                model: "H5081".into(),
+                name: "Plug, Hallway".into(),
+                controllable: true,
+                supported_commands: [ControlCommand::Turn].into(),
+                ..Device::new(DeviceId::parse("34:20:03:2e:30:02").unwrap())
+            },
+        ]
+    }
+
+    fn state() -> StateRecord {
+        let devices = devices();
+        StateRecord::new(
+            &devices[0],
+            DeviceState {
+                device: devices[0].device.clone(),
+                model: devices[0].model.clone(),
+                properties: vec![
+                    DeviceProperty::Online { online: true },
+                    DeviceProperty::PowerState {
+                        power_state: PowerState::On,
+                    },
+                    DeviceProperty::Brightness { brightness: 40 },
+                ],
+            },
+        )
+    }
+
+    fn written<T: Serialize + Row>(format: Format, records: &[T]) -> String {
+        let mut out = Vec::new();
+        write(&mut out, format, records).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn devices_as_table_and_csv() {
+        assert_eq!(
+            written(Format::Table, &devices()),
+            concat!(
+                "NAME           DEVICE             MODEL  CONTROLLABLE  RETRIEVABLE  COMMANDS\n",
+                "Desk Strip     34:20:03:2e:30:01  H6159  true          true         turn,brightness,color,colorTem\n",
+                "Plug, Hallway  34:20:03:2e:30:02  H5081  true          false        turn\n",
+            )
+        );
+        assert_eq!(
+            written(Format::Csv, &devices()),
+            concat!(
+                "name,device,model,controllable,retrievable,commands\n",
+                "Desk Strip,34:20:03:2e:30:01,H6159,true,true,\"turn,brightness,color,colorTem\"\n",
+                "\"Plug, Hallway\",34:20:03:2e:30:02,H5081,true,false,turn\n",
+            )
+        );
+    }
+
+    #[test]
+    fn devices_as_json_ndjson_and_yaml() {
+        assert_eq!(
+            written(Format::Json, &devices()),
+            concat!(
+                "[\n",
+                "  {\n",
+                "    \"model\": \"H6159\",\n",
+                "    \"device\": \"34:20:03:2e:30:01\",\n",
+                "    \"deviceName\": \"Desk Strip\",\n",
+                "    \"controllable\": true,\n",
+                "    \"retrievable\": true,\n",
+                "    \"supportCmds\": [\n",
+                "      \"turn\",\n",
+                "      \"brightness\",\n",
+                "      \"color\",\n",
+                "      \"colorTem\"\n",
+                "    ]\n",
+                "  },\n",
+                "  {\n",
+                "    \"model\": \"H5081\",\n",
+                "    \"device\": \"34:20:03:2e:30:02\",\n",
+                "    \"deviceName\": \"Plug, Hallway\",\n",
+                "    \"controllable\": true,\n",
+                "    \"retrievable\": false,\n",
+                "    \"supportCmds\": [\n",
+                "      \"turn\"\n",
+                "    ]\n",
+                "  }\n",
+                "]\n",
+            )
+        );
+        assert_eq!(
+            written(Format::Ndjson, &devices()),
+            concat!(
+                "{\"model\":\"H6159\",\"device\":\"34:20:03:2e:30:01\",\"deviceName\":\"Desk Strip\",\"controllable\":true,\"retrievable\":true,\"supportCmds\":[\"turn\",\"brightness\",\"color\",\"colorTem\"]}\n",
+                "{\"model\":\"H5081\",\"device\":\"34:20:03:2e:30:02\",\"deviceName\":\"Plug, Hallway\",\"controllable\":true,\"retrievable\":false,\"supportCmds\":[\"turn\"]}\n",
+            )
+        );
+        assert_eq!(
+            written(Format::Yaml, &devices()),
+            concat!(
+                "- model: H6159\n",
+                "  device: 34:20:03:2e:30:01\n",
+                "  deviceName: Desk Strip\n",
+                "  controllable: true\n",
+                "  retrievable: true\n",
+                "  supportCmds:\n",
+                "  - turn\n",
+                "  - brightness\n",
+                "  - color\n",
+                "  - colorTem\n",
+                "- model: H5081\n",
+                "  device: 34:20:03:2e:30:02\n",
+                "  deviceName: Plug, Hallway\n",
+                "  controllable: true\n",
+                "  retrievable: false\n",
+                "  supportCmds:\n",
+                "  - turn\n",
+            )
+        );
+    }
+
+    #[test]
+    fn states_in_every_format() {
+        let states = [state()];
+        assert_eq!(
+            written(Format::Table, &states),
+            concat!(
+                "NAME        DEVICE             MODEL  ONLINE  POWER  BRIGHTNESS  COLOR  COLOR_TEMP\n",
+                "Desk Strip  34:20:03:2e:30:01  H6159  true    on     40\n",
+            )
+        );
+        assert_eq!(
+            written(Format::Csv, &states),
+            concat!(
+                "name,device,model,online,power,brightness,color,color_temp\n",
+                "Desk Strip,34:20:03:2e:30:01,H6159,true,on,40,,\n",
+            )
+        );
+        assert_eq!(
+            written(Format::Ndjson, &states),
+            "{\"name\":\"Desk Strip\",\"state\":{\"device\":\"34:20:03:2e:30:01\",\"model\":\"H6159\",\"properties\":[{\"online\":true},{\"powerState\":\"on\"},{\"brightness\":40}]},\"normalized\":{\"device\":\"34:20:03:2e:30:01\",\"model\":\"H6159\",\"online\":true,\"power\":\"on\",\"brightness\":40,\"color\":null,\"colorTemp\":null}}\n"
+        );
+        assert_eq!(
+            written(Format::Json, &states),
+            concat!(
+                "[\n",
+                "  {\n",
+                "    \"name\": \"Desk Strip\",\n",
+                "    \"state\": {\n",
+                "      \"device\": \"34:20:03:2e:30:01\",\n",
+                "      \"model\": \"H6159\",\n",
+                "      \"properties\": [\n",
+                "        {\n",
+                "          \"online\": true\n",
+                "        },\n",
+                "        {\n",
+                "          \"powerState\": \"on\"\n",
+                "        },\n",
+                "        {\n",
+                "          \"brightness\": 40\n",
+                "        }\n",
+                "      ]\n",
+                "    },\n",
+                "    \"normalized\": {\n",
+                "      \"device\": \"34:20:03:2e:30:01\",\n",
+                "      \"model\": \"H6159\",\n",
+                "      \"online\": true,\n",
+                "      \"power\": \"on\",\n",
+                "      \"brightness\": 40,\n",
+                "      \"color\": null,\n",
+                "      \"colorTemp\": null\n",
+                "    }\n",
+                "  }\n",
+                "]\n",
+            )
+        );
+        assert_eq!(
+            written(Format::Yaml, &states),
+            concat!(
+                "- name: Desk Strip\n",
+                "  state:\n",
+                "    device: 34:20:03:2e:30:01\n",
+                "    model: H6159\n",
+                "    properties:\n",
+                "    - online: true\n",
+                "    - powerState: on\n",
+                "    - brightness: 40\n",
+                "  normalized:\n",
+                "    device: 34:20:03:2e:30:01\n",
+                "    model: H6159\n",
+                "    online: true\n",
+                "    power: on\n",
+                "    brightness: 40\n",
+                "    color: null\n",
+                "    colorTemp: null\n",
+            )
+        );
+    }
+}
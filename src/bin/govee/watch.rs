@@ -0,0 +1,355 @@
+//! `govee watch`: a live dashboard of device state.
+use std::{
+    io::{self, Stdout},
+    time::Duration,
+};
+
+use anyhow::Result;
+use crossterm::{
+    event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use futures_util::StreamExt;
+use govee_rs::{
+    models::{Device, Devices, NormalizedState, PowerState},
+    monitor::{Monitor, Snapshot},
+    GoveeClient,
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Layout},
+    style::{Color as TermColor, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState},
+    Frame, Terminal,
+};
+use tokio::sync::mpsc;
+
+/// How much the brightness keys change brightness by.
+const BRIGHTNESS_STEP: u64 = 10;
+
+enum Update {
+    Snapshot(Snapshot),
+    Controlled {
+        index: usize,
+        result: Result<Change, String>,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum Change {
+    Power(PowerState),
+    Brightness(u64),
+}
+
+struct Entry {
+    device: Device,
+    state: Option<NormalizedState>,
+    error: Option<String>,
+}
+
+struct Dashboard {
+    entries: Vec<Entry>,
+    table: TableState,
+    interval: Duration,
+    status: String,
+}
+
+impl Dashboard {
+    fn new(devices: &Devices, interval: Duration) -> Self {
+        let entries = devices
+            .iter()
+            .map(|d| Entry {
+                device: d.clone(),
+                state: None,
+                error: None,
+            })
+            .collect();
+
+        let mut table = TableState::default();
+        table.select(Some(0));
+
+        Self {
+            entries,
+            table,
+            interval,
+            status: "waiting for first poll...".into(),
+        }
+    }
+
+    fn selected(&self) -> Option<usize> {
+        self.table.selected().filter(|i| *i < self.entries.len())
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let len = self.entries.len() as isize;
+        let current = self.selected().unwrap_or(0) as isize;
+        self.table
+            .select(Some((current + delta).rem_euclid(len) as usize));
+    }
+
+    fn apply(&mut self, update: Update) {
+        match update {
+            Update::Snapshot(snapshot) => {
+                let Some(entry) = self
+                    .entries
+                    .iter_mut()
+                    .find(|e| e.device.device == snapshot.device.device)
+                else {
+                    return;
+                };
+
+                match snapshot.state {
+                    Ok(state) => {
+                        entry.state = Some(state);
+                        entry.error = None;
+                    }
+                    Err(e) => entry.error = Some(e.to_string()),
+                }
+                self.status = format!(
+                    "last update: {}, polling every {}s",
+                    snapshot.device.name,
+                    self.interval.as_secs()
+                );
+            }
+            Update::Controlled { index, result } => {
+                let Some(entry) = self.entries.get_mut(index) else {
+                    return;
+                };
+
+                match result {
+                    Ok(change) => {
+                        let state = entry.state.get_or_insert_with(|| NormalizedState {
+                            model: entry.device.model.clone(),
+                            ..NormalizedState::new(entry.device.device.clone())
+                        });
+                        match change {
+                            Change::Power(power) => state.power = Some(power),
+                            Change::Brightness(b) => state.brightness = Some(b),
+                        }
+                        self.status = format!("updated {}", entry.device.name);
+                    }
+                    Err(e) => self.status = format!("{}: {}", entry.device.name, e),
+                }
+            }
+        }
+    }
+
+    /// The change a key press asks for on the selected device, if any.
+    fn change_for(&self, key: KeyCode) -> Option<(usize, Change)> {
+        let index = self.selected()?;
+        let state = self.entries[index].state.as_ref();
+
+        match key {
+            KeyCode::Char(' ') | KeyCode::Enter => {
+                let power = match state.and_then(|s| s.power) {
+                    Some(PowerState::On) => PowerState::Off,
+                    _ => PowerState::On,
+                };
+                Some((index, Change::Power(power)))
+            }
+            KeyCode::Char('+') | KeyCode::Char('=') | KeyCode::Right => {
+                let current = state.and_then(|s| s.brightness).unwrap_or(0);
+                Some((
+                    index,
+                    Change::Brightness((current + BRIGHTNESS_STEP).min(100)),
+                ))
+            }
+            KeyCode::Char('-') | KeyCode::Left => {
+                let current = state.and_then(|s| s.brightness).unwrap_or(0);
+                Some((
+                    index,
+                    Change::Brightness(current.saturating_sub(BRIGHTNESS_STEP).max(1)),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [body, status, help] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let header = Row::new(["Name", "Online", "Power", "Brightness", "Color", "Temp"])
+            .style(Style::default().add_modifier(Modifier::BOLD));
+
+        let rows = self.entries.iter().map(|entry| {
+            let state = entry.state.as_ref();
+            let online = match (state.and_then(|s| s.online), &entry.error) {
+                (_, Some(_)) => "error".to_string(),
+                (Some(true), None) => "yes".to_string(),
+                (Some(false), None) => "no".to_string(),
+                (None, None) => "?".to_string(),
+            };
+
+            let color = match state.and_then(|s| s.color) {
+                Some(c) => Line::from(vec![
+                    Span::styled("    ", Style::default().bg(TermColor::Rgb(c.r, c.g, c.b))),
+                    Span::raw(format!(" {}", c)),
+                ]),
+                None => Line::from(""),
+            };
+
+            Row::new(vec![
+                Cell::from(entry.device.name.clone()),
+                Cell::from(online),
+                Cell::from(opt(state.and_then(|s| s.power))),
+                Cell::from(opt(state.and_then(|s| s.brightness))),
+                Cell::from(color),
+                Cell::from(opt(state.and_then(|s| s.color_temp))),
+            ])
+        });
+
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Min(16),
+                Constraint::Length(7),
+                Constraint::Length(6),
+                Constraint::Length(11),
+                Constraint::Length(13),
+                Constraint::Length(6),
+            ],
+        )
+        .header(header)
+        .block(Block::default().borders(Borders::ALL).title("govee watch"))
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+        frame.render_stateful_widget(table, body, &mut self.table);
+        frame.render_widget(Paragraph::new(self.status.as_str()), status);
+        frame.render_widget(
+            Paragraph::new("q quit  up/down select  space toggle power  +/- brightness  r refresh")
+                .style(Style::default().add_modifier(Modifier::DIM)),
+            help,
+        );
+    }
+}
+
+fn opt<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// Restores the terminal when dropped, even on error.
+struct TerminalGuard {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+}
+
+impl TerminalGuard {
+    fn new() -> Result<Self> {
+        enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        if let Err(e) = execute!(stdout, EnterAlternateScreen) {
+            let _ = disable_raw_mode();
+            return Err(e.into());
+        }
+        let terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+        Ok(Self { terminal })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(self.terminal.backend_mut(), LeaveAlternateScreen);
+        let _ = self.terminal.show_cursor();
+    }
+}
+
+/// Poll `monitor` forever, sending each snapshot as soon as it arrives.
+async fn poll(monitor: Monitor, tx: mpsc::Sender<Update>, mut refresh: mpsc::Receiver<()>) {
+    loop {
+        for device in monitor.devices().iter() {
+            let snapshot = monitor.poll_device(device).await;
+            if tx.send(Update::Snapshot(snapshot)).await.is_err() {
+                return;
+            }
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(monitor.interval()) => {}
+            msg = refresh.recv() => {
+                if msg.is_none() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Run the dashboard until the user quits.
+pub async fn run(client: GoveeClient, devices: &Devices, interval: Option<Duration>) -> Result<()> {
+    let mut monitor = Monitor::new(client.clone(), devices);
+    if let Some(interval) = interval {
+        monitor = monitor.with_interval(interval);
+    }
+
+    let mut dashboard = Dashboard::new(monitor.devices(), monitor.interval());
+    let (tx, mut rx) = mpsc::channel(64);
+    let (refresh_tx, refresh_rx) = mpsc::channel(1);
+    let poller = tokio::spawn(poll(monitor, tx.clone(), refresh_rx));
+
+    let mut guard = TerminalGuard::new()?;
+    let mut events = EventStream::new();
+
+    let result = loop {
+        if let Err(e) = guard.terminal.draw(|f| dashboard.draw(f)) {
+            break Err(e.into());
+        }
+
+        tokio::select! {
+            Some(update) = rx.recv() => dashboard.apply(update),
+            event = events.next() => {
+                let key = match event {
+                    Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => key,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => break Err(e.into()),
+                    None => break Ok(()),
+                };
+
+                if is_quit(&key) {
+                    break Ok(());
+                }
+
+                match key.code {
+                    KeyCode::Up | KeyCode::Char('k') => dashboard.move_selection(-1),
+                    KeyCode::Down | KeyCode::Char('j') => dashboard.move_selection(1),
+                    KeyCode::Char('r') => {
+                        let _ = refresh_tx.try_send(());
+                    }
+                    code => {
+                        if let Some((index, change)) = dashboard.change_for(code) {
+                            let device = dashboard.entries[index].device.clone();
+                            let client = client.clone();
+                            let tx = tx.clone();
+                            tokio::spawn(async move {
+                                let result = match change {
+                                    Change::Power(p) => client.turn(&device, p).await,
+                                    Change::Brightness(b) => client.brightness(&device, b).await,
+                                };
+                                let result = result.map(|_| change).map_err(|e| e.to_string());
+                                let _ = tx.send(Update::Controlled { index, result }).await;
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    poller.abort();
+    drop(guard);
+    result
+}
+
+fn is_quit(key: &KeyEvent) -> bool {
+    matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+        || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
+}
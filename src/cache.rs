@@ -0,0 +1,416 @@
+//! A caching, rate limited wrapper around [GoveeClient].
+//!
+//! Services that share one API key share one daily quota. A [CachingClient]
+//! answers repeated reads from a short-lived cache, makes concurrent reads of
+//! the same thing wait for a single upstream request rather than each making
+//! their own, and spaces upstream requests out with a [RateLimiter].
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::{
+    client::{GoveeClient, GoveeError},
+    models::{DesiredState, DesiredStateError, Device, DeviceId, DeviceState, Devices},
+    monitor::DAILY_REQUEST_LIMIT,
+};
+
+#[derive(Debug, Error)]
+pub enum CacheError {
+    #[error("unknown device {}", device)]
+    UnknownDevice { device: DeviceId },
+    #[error("rate limited, retry in {:.1}s", retry_after.as_secs_f64())]
+    RateLimited { retry_after: Duration },
+    #[error("invalid desired state: {}", source)]
+    DesiredState {
+        #[from]
+        source: DesiredStateError,
+    },
+    #[error("{}", source)]
+    Govee {
+        #[from]
+        source: GoveeError,
+    },
+}
+
+/// A token bucket limiting how often upstream requests are made.
+///
+/// Requests beyond the burst are delayed until a token is available, up to
+/// `max_wait`; anything that would wait longer is refused instead.
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+/// use govee_rs::cache::RateLimiter;
+///
+/// let limiter = RateLimiter::new(60.0, 2, Duration::ZERO);
+/// assert!(limiter.reserve().is_ok());
+/// assert!(limiter.reserve().is_ok());
+///
+/// // the burst is spent and waiting is not allowed
+/// let retry_after = limiter.reserve().unwrap_err();
+/// assert!(retry_after <= Duration::from_secs(1));
+/// ```
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    max_wait: Duration,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Allow `per_minute` requests per minute on average, with bursts of up to
+    /// `burst` requests.
+    pub fn new(per_minute: f64, burst: u32, max_wait: Duration) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            per_second: (per_minute / 60.0).max(f64::EPSILON),
+            burst,
+            max_wait,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Reserve a request, returning how long to wait before making it, or how
+    /// long until a retry could succeed if that would exceed `max_wait`.
+    pub fn reserve(&self) -> Result<Duration, Duration> {
+        self.reserve_many(1)
+    }
+
+    /// Reserve `n` requests to be made back to back.
+    pub fn reserve_many(&self, n: usize) -> Result<Duration, Duration> {
+        let n = n as f64;
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+        bucket.updated = now;
+
+        // tokens may go negative: that is a queue of reserved requests
+        let wait = Duration::from_secs_f64((n - bucket.tokens).max(0.0) / self.per_second);
+        if wait > self.max_wait {
+            return Err(wait - self.max_wait);
+        }
+
+        bucket.tokens -= n;
+        Ok(wait)
+    }
+
+    /// Wait for a request slot.
+    pub async fn acquire(&self) -> Result<(), CacheError> {
+        self.acquire_many(1).await
+    }
+
+    /// Wait until `n` requests can be made back to back.
+    pub async fn acquire_many(&self, n: usize) -> Result<(), CacheError> {
+        let wait = self
+            .reserve_many(n)
+            .map_err(|retry_after| CacheError::RateLimited { retry_after })?;
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+}
+
+/// Settings for a [CachingClient].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheOptions {
+    /// How long a device listing is reused.
+    pub devices_ttl: Duration,
+
+    /// How long a device state is reused.
+    pub state_ttl: Duration,
+
+    /// The average number of upstream requests allowed per minute.
+    pub requests_per_minute: f64,
+
+    /// How many upstream requests may be made back to back.
+    pub burst: u32,
+
+    /// How long a request may wait for the rate limiter before it is refused.
+    pub max_wait: Duration,
+}
+
+impl Default for CacheOptions {
+    /// Stay inside the daily quota, with some room for bursts of control.
+    fn default() -> Self {
+        Self {
+            devices_ttl: Duration::from_secs(300),
+            state_ttl: Duration::from_secs(10),
+            requests_per_minute: f64::from(DAILY_REQUEST_LIMIT) / (24.0 * 60.0),
+            burst: 10,
+            max_wait: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Cached<T> {
+    value: T,
+    at: Instant,
+}
+
+type Slot<T> = Arc<AsyncMutex<Option<Cached<T>>>>;
+
+struct Inner {
+    client: GoveeClient,
+    options: CacheOptions,
+    limiter: RateLimiter,
+    devices: Slot<Devices>,
+    states: Mutex<HashMap<DeviceId, Slot<DeviceState>>>,
+}
+
+/// A [GoveeClient] with caching, request coalescing and rate limiting.
+///
+/// Cloning is cheap and clones share the cache and the rate limit.
+///
+/// # Examples
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use govee_rs::{
+///     cache::{CacheOptions, CachingClient},
+///     models::DesiredState,
+///     GoveeClient, DEFAULT_API_URL,
+/// };
+///
+/// let client = CachingClient::new(
+///     GoveeClient::new(DEFAULT_API_URL, "my-api-key")?,
+///     CacheOptions::default(),
+/// );
+///
+/// let devices = client.devices().await?;
+/// let lamp = devices.find("desk lamp")?;
+///
+/// // a second read within the ttl is answered from the cache
+/// let state = client.state(lamp).await?;
+/// let again = client.state(lamp).await?;
+/// assert_eq!(state, again);
+///
+/// let desired: DesiredState = serde_json::from_str(r#"{"power": "off"}"#)?;
+/// client.apply(lamp, &desired).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct CachingClient {
+    inner: Arc<Inner>,
+}
+
+impl CachingClient {
+    pub fn new(client: GoveeClient, options: CacheOptions) -> Self {
+        let limiter =
+            RateLimiter::new(options.requests_per_minute, options.burst, options.max_wait);
+        Self {
+            inner: Arc::new(Inner {
+                client,
+                options,
+                limiter,
+                devices: Slot::default(),
+                states: Mutex::default(),
+            }),
+        }
+    }
+
+    /// The wrapped client, bypassing the cache and the rate limit.
+    pub fn client(&self) -> &GoveeClient {
+        &self.inner.client
+    }
+
+    pub fn options(&self) -> &CacheOptions {
+        &self.inner.options
+    }
+
+    /// Return `slot`'s value if it is younger than `ttl`, otherwise fetch it.
+    ///
+    /// Callers asking for the same slot queue on its lock, so only the first
+    /// makes a request and the rest see its result.
+    async fn cached<T, F, Fut>(
+        &self,
+        slot: &Slot<T>,
+        ttl: Duration,
+        fetch: F,
+    ) -> Result<T, CacheError>
+    where
+        T: Clone,
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<T, GoveeError>>,
+    {
+        let mut cached = slot.lock().await;
+        if let Some(hit) = cached.as_ref().filter(|c| c.at.elapsed() < ttl) {
+            return Ok(hit.value.clone());
+        }
+
+        self.inner.limiter.acquire().await?;
+        let value = fetch().await?;
+        *cached = Some(Cached {
+            value: value.clone(),
+            at: Instant::now(),
+        });
+        Ok(value)
+    }
+
+    fn state_slot(&self, id: &DeviceId) -> Slot<DeviceState> {
+        let mut states = self.inner.states.lock().unwrap_or_else(|e| e.into_inner());
+        states.entry(id.clone()).or_default().clone()
+    }
+
+    /// The devices on the account.
+    pub async fn devices(&self) -> Result<Devices, CacheError> {
+        let client = &self.inner.client;
+        self.cached(&self.inner.devices, self.inner.options.devices_ttl, || {
+            client.devices()
+        })
+        .await
+    }
+
+    /// Look up a device on the account by id.
+    pub async fn device(&self, id: &DeviceId) -> Result<Device, CacheError> {
+        self.devices()
+            .await?
+            .by_id(id)
+            .cloned()
+            .ok_or_else(|| CacheError::UnknownDevice { device: id.clone() })
+    }
+
+    /// The state of a device.
+    pub async fn state(&self, device: &Device) -> Result<DeviceState, CacheError> {
+        let slot = self.state_slot(&device.device);
+        let client = &self.inner.client;
+        self.cached(&slot, self.inner.options.state_ttl, || client.state(device))
+            .await
+    }
+
+    /// Bring a device into the desired state.
+    ///
+    /// Each command is one upstream request, and they are rate limited as a
+    /// whole so a state is never left half applied by the limiter. The cached
+    /// state of the device is dropped, so the next read sees the change.
+    pub async fn apply(&self, device: &Device, desired: &DesiredState) -> Result<(), CacheError> {
+        let cmds = desired.commands_for(device)?;
+        let slot = self.state_slot(&device.device);
+
+        // holding the slot keeps reads from caching a half applied state
+        let mut cached = slot.lock().await;
+        *cached = None;
+        self.inner.limiter.acquire_many(cmds.len()).await?;
+        for cmd in cmds {
+            self.inner.client.control(device, cmd).await?;
+        }
+        Ok(())
+    }
+
+    /// Forget everything cached.
+    pub async fn invalidate(&self) {
+        *self.inner.devices.lock().await = None;
+        self.inner
+            .states
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Server;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn coalesces_and_caches() {
+        let mut server = Server::new_async().await;
+        let devices = server
+            .mock("GET", "/v1/devices?")
+            .with_status(200)
+            .with_body(
+                r#"{"data": {"devices": [{
+                    "device": "34:20:03:2e:30:2b",
+                    "model": "H6159",
+                    "deviceName": "lamp",
+                    "controllable": true,
+                    "retrievable": true,
+                    "supportCmds": ["turn"]
+                }]}}"#,
+            )
+            .expect(1)
+            .create_async()
+            .await;
+        let control = server
+            .mock("PUT", "/v1/devices/control?")
+            .with_status(200)
+            .with_body(r#"{"data": {}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let client = CachingClient::new(
+            GoveeClient::new(&server.url(), "key").unwrap(),
+            CacheOptions::default(),
+        );
+
+        let (a, b, c) = tokio::join!(client.devices(), client.devices(), client.devices());
+        assert_eq!(a.unwrap(), b.unwrap());
+        assert_eq!(c.unwrap().len(), 1);
+        devices.assert_async().await;
+
+        let id = DeviceId::parse("34:20:03:2E:30:2B").unwrap();
+        let lamp = client.device(&id).await.unwrap();
+        assert_eq!(lamp.name, "lamp");
+
+        let missing = DeviceId::parse("00:00:00:00:00:00").unwrap();
+        assert!(matches!(
+            client.device(&missing).await,
+            Err(CacheError::UnknownDevice { .. })
+        ));
+
+        // unsupported commands never reach the api
+        let brightness = DesiredState {
+            brightness: Some(50),
+            ..Default::default()
+        };
+        assert!(matches!(
+            client.apply(&lamp, &brightness).await,
+            Err(CacheError::DesiredState { .. })
+        ));
+
+        let off = DesiredState {
+            power: Some(crate::models::PowerState::Off),
+            ..Default::default()
+        };
+        client.apply(&lamp, &off).await.unwrap();
+        control.assert_async().await;
+    }
+
+    #[test]
+    fn limiter_refuses_long_waits() {
+        let limiter = RateLimiter::new(6.0, 1, Duration::from_secs(15));
+        assert_eq!(limiter.reserve(), Ok(Duration::ZERO));
+
+        // one request every 10s: the next waits ~10s, the one after ~20s
+        let wait = limiter.reserve().unwrap();
+        assert!(wait > Duration::from_secs(9) && wait <= Duration::from_secs(10));
+        let retry = limiter.reserve().unwrap_err();
+        assert!(retry > Duration::from_secs(4) && retry <= Duration::from_secs(5));
+
+        // several requests are reserved together or not at all
+        let limiter = RateLimiter::new(6.0, 4, Duration::ZERO);
+        assert_eq!(limiter.reserve_many(3), Ok(Duration::ZERO));
+        assert!(limiter.reserve_many(2).is_err());
+        assert_eq!(limiter.reserve(), Ok(Duration::ZERO));
+    }
+}
@@ -0,0 +1,320 @@
+//! Recording and replaying the HTTP interactions of a [GoveeClient].
+//!
+//! A client made with [GoveeClient::with_recording] writes every request and
+//! response it makes to a cassette file, with the `Govee-API-Key` header
+//! redacted. A client made with [GoveeClient::with_replay] answers requests
+//! from a cassette without touching the network, which makes regression tests
+//! against real account behavior deterministic and offline.
+//!
+//! Replayed requests are matched on their method, path, query and body. Each
+//! recorded interaction is used once, in order, so polling the same endpoint
+//! twice replays two different responses.
+//!
+//! # Examples
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use govee_rs::{cassette::Cassette, GoveeClient, DEFAULT_API_URL};
+//!
+//! // once, against the real API
+//! let client = GoveeClient::new(DEFAULT_API_URL, "my-api-key")?
+//!     .with_recording("tests/cassettes/devices.json");
+//! client.devices().await?;
+//!
+//! // from then on, offline
+//! let cassette = Cassette::load("tests/cassettes/devices.json")?;
+//! let client = GoveeClient::new(DEFAULT_API_URL, "any key")?.with_replay(cassette);
+//! let devices = client.devices().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [GoveeClient]: crate::GoveeClient
+//! [GoveeClient::with_recording]: crate::GoveeClient::with_recording
+//! [GoveeClient::with_replay]: crate::GoveeClient::with_replay
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use bytes::Bytes;
+use http::{HeaderMap, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// What recorded secret headers are replaced with.
+pub const REDACTED: &str = "<redacted>";
+
+/// Headers never written to a cassette as they are.
+const SECRET_HEADERS: &[&str] = &["govee-api-key"];
+
+#[derive(Debug, Error)]
+pub enum CassetteError {
+    #[error("failed to read cassette {}: {}", path.display(), source)]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("failed to write cassette {}: {}", path.display(), source)]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("invalid cassette: {}", source)]
+    Json {
+        #[from]
+        source: serde_json::Error,
+    },
+    #[error("invalid recorded response: {}", source)]
+    Http {
+        #[from]
+        source: http::Error,
+    },
+    #[error("no recorded interaction left for {} {}", method, path)]
+    NoMatch { method: String, path: String },
+}
+
+/// A recorded request.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CassetteRequest {
+    pub method: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub body: String,
+}
+
+impl CassetteRequest {
+    pub(crate) fn from_http(request: &Request<Vec<u8>>) -> Self {
+        Self {
+            method: request.method().to_string(),
+            path: request.uri().path().to_string(),
+            query: request.uri().query().map(String::from),
+            headers: header_map(request.headers()),
+            body: String::from_utf8_lossy(request.body()).into_owned(),
+        }
+    }
+
+    /// If this is the same request as `other`, ignoring headers.
+    fn matches(&self, other: &Self) -> bool {
+        self.method == other.method
+            && self.path == other.path
+            && self.query == other.query
+            && self.body == other.body
+    }
+}
+
+/// A recorded response.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CassetteResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub body: String,
+}
+
+impl CassetteResponse {
+    pub(crate) fn from_http(response: &Response<Bytes>) -> Self {
+        Self {
+            status: response.status().as_u16(),
+            headers: header_map(response.headers()),
+            body: String::from_utf8_lossy(response.body()).into_owned(),
+        }
+    }
+
+    fn to_http(&self) -> Result<Response<Bytes>, CassetteError> {
+        let mut response = Response::builder()
+            .status(StatusCode::from_u16(self.status).map_err(http::Error::from)?);
+        for (name, value) in &self.headers {
+            response = response.header(name, value);
+        }
+        Ok(response.body(Bytes::from(self.body.clone()))?)
+    }
+}
+
+fn header_map(headers: &HeaderMap) -> BTreeMap<String, String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if SECRET_HEADERS.contains(&name.as_str()) {
+                REDACTED.to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// A request and the response it got.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: CassetteRequest,
+    pub response: CassetteResponse,
+}
+
+/// A list of recorded interactions, stored as JSON.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CassetteError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|source| CassetteError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    /// Write the cassette to `path`, replacing any file there in one step so
+    /// a reader never sees it half written.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CassetteError> {
+        write_atomically(path.as_ref(), &serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Write `text` to a temporary file beside `path`, then rename it over `path`.
+fn write_atomically(path: &Path, text: &str) -> Result<(), CassetteError> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    std::fs::write(&temp, text)
+        .and_then(|_| std::fs::rename(&temp, path))
+        .map_err(|source| CassetteError::Write {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+/// Appends interactions to a cassette file, rewriting it after each one so
+/// nothing is lost if the process stops.
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    path: PathBuf,
+    // held across the write so files are written in the order recorded
+    cassette: tokio::sync::Mutex<Cassette>,
+}
+
+impl Recorder {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            cassette: tokio::sync::Mutex::default(),
+        }
+    }
+
+    /// Add `interaction` and rewrite the file off the async runtime.
+    pub(crate) async fn record(&self, interaction: Interaction) -> Result<(), CassetteError> {
+        let mut cassette = self.cassette.lock().await;
+        cassette.interactions.push(interaction);
+        let text = serde_json::to_string_pretty(&*cassette)?;
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || write_atomically(&path, &text))
+            .await
+            .map_err(|e| CassetteError::Write {
+                path: self.path.clone(),
+                source: std::io::Error::other(e),
+            })?
+    }
+}
+
+/// Answers requests from a cassette, using each interaction once.
+#[derive(Debug)]
+pub(crate) struct Player {
+    interactions: Mutex<Vec<Option<Interaction>>>,
+}
+
+impl Player {
+    pub(crate) fn new(cassette: Cassette) -> Self {
+        Self {
+            interactions: Mutex::new(cassette.interactions.into_iter().map(Some).collect()),
+        }
+    }
+
+    pub(crate) fn respond(
+        &self,
+        request: &CassetteRequest,
+    ) -> Result<Response<Bytes>, CassetteError> {
+        let mut interactions = self.interactions.lock().unwrap_or_else(|e| e.into_inner());
+        interactions
+            .iter_mut()
+            .find(|i| i.as_ref().is_some_and(|i| i.request.matches(request)))
+            .and_then(Option::take)
+            .ok_or_else(|| CassetteError::NoMatch {
+                method: request.method.clone(),
+                path: request.path.clone(),
+            })?
+            .response
+            .to_http()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Server;
+
+    use super::*;
+    use crate::GoveeClient;
+
+    #[tokio::test]
+    async fn record_then_replay() {
+        let mut server = Server::new_async().await;
+        let devices = server
+            .mock("GET", "/v1/devices?")
+            .with_status(200)
+            .with_body(r#"{"data": {"devices": []}, "message": "Success", "code": 200}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let path = std::env::temp_dir().join(format!("govee-cassette-{}.json", std::process::id()));
+        let client = GoveeClient::new(&server.url(), "secret")
+            .unwrap()
+            .with_recording(&path);
+        assert!(client.devices().await.unwrap().is_empty());
+        devices.assert_async().await;
+
+        let cassette = Cassette::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(cassette.interactions.len(), 1);
+        let request = &cassette.interactions[0].request;
+        assert_eq!(request.path, "/v1/devices");
+        assert_eq!(request.headers["govee-api-key"], REDACTED);
+        assert!(!serde_json::to_string(&cassette).unwrap().contains("secret"));
+
+        // nothing listens here, so this only works from the cassette
+        let client = GoveeClient::new("http://127.0.0.1:9", "other")
+            .unwrap()
+            .with_replay(cassette);
+        assert!(client.devices().await.unwrap().is_empty());
+        assert!(client.devices().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_writes_keep_the_response() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("GET", "/v1/devices?")
+            .with_status(200)
+            .with_body(r#"{"data": {"devices": []}, "message": "Success", "code": 200}"#)
+            .create_async()
+            .await;
+
+        let path = std::env::temp_dir()
+            .join(format!("govee-missing-{}", std::process::id()))
+            .join("cassette.json");
+        let client = GoveeClient::new(&server.url(), "secret")
+            .unwrap()
+            .with_recording(&path);
+        assert!(client.devices().await.unwrap().is_empty());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,301 @@
+//! Static metadata about known Govee models.
+//!
+//! The developer API only reports a model (SKU) for each device, which says
+//! nothing about what kind of device it is or what it can do beyond the
+//! supported control commands. This module ships a small built-in catalog of
+//! known SKUs, and a [Catalog] value that extends or corrects it with local
+//! overrides.
+//!
+//! The built-in data is best-effort; if your device is missing or described
+//! incorrectly, an override is the intended fix.
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Device, Model};
+
+/// The broad category of a Govee device.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DeviceKind {
+    Bulb,
+    Strip,
+    Lamp,
+    Panel,
+    TvBacklight,
+    OutdoorLight,
+    Plug,
+    Appliance,
+}
+
+/// The commands a model accepts over the Govee LAN protocol.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct LanFeatures {
+    /// Responds to multicast discovery.
+    pub scan: bool,
+
+    /// Accepts the `turn` command.
+    pub turn: bool,
+
+    /// Accepts the `brightness` command.
+    pub brightness: bool,
+
+    /// Accepts color via the `colorwc` command.
+    pub color: bool,
+
+    /// Accepts color temperature via the `colorwc` command.
+    pub color_temp: bool,
+
+    /// Responds to `devStatus` queries.
+    pub status: bool,
+}
+
+impl LanFeatures {
+    /// No LAN control at all.
+    pub const NONE: Self = Self {
+        scan: false,
+        turn: false,
+        brightness: false,
+        color: false,
+        color_temp: false,
+        status: false,
+    };
+
+    /// Every LAN command.
+    pub const ALL: Self = Self {
+        scan: true,
+        turn: true,
+        brightness: true,
+        color: true,
+        color_temp: true,
+        status: true,
+    };
+
+    /// If any LAN command is available.
+    pub fn any(&self) -> bool {
+        *self != Self::NONE
+    }
+}
+
+/// A known oddity in how a model behaves through the API.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Quirk {
+    /// The state endpoint keeps reporting the old state for a while after a
+    /// control request.
+    StaleState,
+    /// Changes to brightness or color are ignored while the device is off.
+    IgnoredWhileOff,
+    /// A color temperature that was set is reported back as an RGB color.
+    ColorTempAsColor,
+}
+
+/// Metadata about a particular model.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CatalogInfo {
+    /// The SKU this entry describes, like `H6159`.
+    pub model: Cow<'static, str>,
+
+    pub kind: DeviceKind,
+
+    /// The name Govee sells this model under.
+    pub product_name: Cow<'static, str>,
+
+    /// The number of individually addressable segments, if any.
+    #[serde(default)]
+    pub segments: Option<u8>,
+
+    /// The largest brightness value the model accepts. Every built-in entry
+    /// uses the developer API's documented 0 to 100 scale.
+    #[serde(default = "default_max_brightness")]
+    pub max_brightness: u64,
+
+    #[serde(default)]
+    pub lan: LanFeatures,
+
+    /// Known oddities of the model. The built-in entries list none, since
+    /// they vary with firmware; record the ones you see with an override.
+    #[serde(default)]
+    pub quirks: Cow<'static, [Quirk]>,
+}
+
+fn default_max_brightness() -> u64 {
+    100
+}
+
+impl CatalogInfo {
+    /// Check if this entry describes the given model.
+    ///
+    /// SKUs are compared case-insensitively.
+    pub fn describes(&self, model: &Model) -> bool {
+        self.model.eq_ignore_ascii_case(model.as_str())
+    }
+}
+
+macro_rules! entry {
+    ($model:literal, $kind:ident, $name:literal, $segments:expr, $lan:ident) => {
+        CatalogInfo {
+            model: Cow::Borrowed($model),
+            kind: DeviceKind::$kind,
+            product_name: Cow::Borrowed($name),
+            segments: $segments,
+            max_brightness: 100,
+            lan: LanFeatures::$lan,
+            quirks: Cow::Borrowed(&[]),
+        }
+    };
+}
+
+/// The built-in catalog.
+#[rustfmt::skip]
+pub static BUILTIN: &[CatalogInfo] = &[
+    entry!("H6003", Bulb, "Wi-Fi LED Bulb", None, NONE),
+    entry!("H6008", Bulb, "Wi-Fi LED Bulb", None, NONE),
+    entry!("H6110", Strip, "Multicolor LED Strip Light", None, NONE),
+    entry!("H6141", Strip, "LED Strip Light", None, NONE),
+    entry!("H6159", Strip, "RGB LED Strip Light", None, ALL),
+    entry!("H6163", Strip, "RGBIC LED Strip Light", Some(15), ALL),
+    entry!("H6188", Strip, "RGBIC LED Strip Light", Some(15), NONE),
+    entry!("H6199", TvBacklight, "DreamView T1 TV Backlight", Some(15), ALL),
+    entry!("H6061", Panel, "Glide Hexa Light Panels", None, ALL),
+    entry!("H6072", Lamp, "Lyra RGBICWW Floor Lamp", Some(7), ALL),
+    entry!("H6076", Lamp, "RGBICW Floor Lamp Basic", Some(7), ALL),
+    entry!("H7021", OutdoorLight, "Outdoor RGBIC String Lights", None, ALL),
+    entry!("H7060", OutdoorLight, "Outdoor LED Flood Lights", Some(4), ALL),
+    entry!("H5080", Plug, "Smart Plug", None, NONE),
+    entry!("H5081", Plug, "Smart Plug", None, NONE),
+    entry!("H5083", Plug, "Smart Plug", None, NONE),
+    entry!("H7101", Appliance, "Smart Tower Fan", None, NONE),
+    entry!("H7121", Appliance, "Smart Air Purifier", None, NONE),
+    entry!("H7131", Appliance, "Smart Space Heater", None, NONE),
+];
+
+/// The built-in catalog together with local overrides.
+///
+/// Overrides take precedence over the built-in entries, so they can both add
+/// models and correct ones that are described wrongly.
+///
+/// # Examples
+/// ```
+/// use std::borrow::Cow;
+/// use govee_rs::catalog::{Catalog, CatalogInfo, DeviceKind, LanFeatures, Quirk};
+///
+/// let catalog = Catalog::builtin().with_overrides([CatalogInfo {
+///     model: Cow::Borrowed("H9999"),
+///     kind: DeviceKind::Lamp,
+///     product_name: Cow::Borrowed("Prototype Lamp"),
+///     segments: None,
+///     max_brightness: 100,
+///     lan: LanFeatures::NONE,
+///     quirks: Cow::Borrowed(&[Quirk::StaleState]),
+/// }]);
+///
+/// let info = catalog.lookup(&"h9999".into()).unwrap();
+/// assert_eq!(info.kind, DeviceKind::Lamp);
+/// assert!(Catalog::builtin().lookup(&"H9999".into()).is_none());
+/// ```
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Catalog {
+    overrides: Vec<CatalogInfo>,
+}
+
+impl Catalog {
+    /// The built-in catalog with no overrides.
+    pub fn builtin() -> Self {
+        Self::default()
+    }
+
+    /// Add (or replace) entries for the models in `overrides`. A later
+    /// entry for the same model replaces an earlier one.
+    pub fn with_overrides(mut self, overrides: impl IntoIterator<Item = CatalogInfo>) -> Self {
+        for info in overrides {
+            self.overrides
+                .retain(|o| !o.model.eq_ignore_ascii_case(&info.model));
+            self.overrides.push(info);
+        }
+        self
+    }
+
+    /// Look up the entry for a model, preferring overrides.
+    pub fn lookup(&self, model: &Model) -> Option<&CatalogInfo> {
+        self.overrides
+            .iter()
+            .chain(BUILTIN.iter())
+            .find(|info| info.describes(model))
+    }
+}
+
+impl Device {
+    /// Get the built-in catalog metadata for this device's model, if it is
+    /// known. Use [Catalog::lookup] to take overrides into account.
+    pub fn catalog_info(&self) -> Option<CatalogInfo> {
+        Catalog::builtin().lookup(&self.model).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::models::DeviceId;
+
+    #[test]
+    fn builtin_is_unique() {
+        let models: HashSet<_> = BUILTIN.iter().map(|i| i.model.to_uppercase()).collect();
+        assert_eq!(models.len(), BUILTIN.len());
+    }
+
+    #[test]
+    fn device_catalog_info() {
+        let plug = Device {
+            model: "H5081".into(),
+            ..Device::new(DeviceId::parse("34:20:03:2e:30:01").unwrap())
+        };
+        let info = plug.catalog_info().unwrap();
+        assert_eq!(info.kind, DeviceKind::Plug);
+        assert!(!info.lan.any());
+
+        let unknown = Device {
+            model: "H0000".into(),
+            ..Device::new(DeviceId::parse("34:20:03:2e:30:02").unwrap())
+        };
+        assert!(unknown.catalog_info().is_none());
+    }
+
+    #[test]
+    fn overrides_take_precedence() {
+        let builtin = Catalog::builtin();
+        let info: CatalogInfo = serde_json::from_str(
+            r#"{
+                "model": "H7131",
+                "kind": "appliance",
+                "productName": "Office Heater",
+                "segments": 3,
+                "lan": {"turn": true}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(info.max_brightness, 100);
+        assert!(info.quirks.is_empty());
+
+        let catalog = builtin.clone().with_overrides([info.clone()]);
+        assert_eq!(catalog.lookup(&"h7131".into()), Some(&info));
+        // the built-in catalog is untouched
+        assert_eq!(
+            builtin.lookup(&"H7131".into()).unwrap().product_name,
+            "Smart Space Heater"
+        );
+
+        // overriding again replaces the earlier override
+        let mut replacement = info;
+        replacement.segments = Some(4);
+        replacement.quirks = Cow::Owned(vec![Quirk::IgnoredWhileOff]);
+        let catalog = catalog.with_overrides([replacement]);
+        let info = catalog.lookup(&"H7131".into()).unwrap();
+        assert_eq!(info.segments, Some(4));
+        assert_eq!(info.quirks[..], [Quirk::IgnoredWhileOff]);
+    }
+}
@@ -0,0 +1,665 @@
+//! Following the sun's color through the day.
+//!
+//! A [Circadian] controller keeps devices on a [Curve] of color temperature
+//! and brightness: warm and dim in the morning and evening, cool and bright
+//! at midday. Curves are a list of points, usually written as a TOML or YAML
+//! file, and are interpolated between them, wrapping around midnight:
+//!
+//! ```toml
+//! points = [
+//!     { at = "07:00", color_temp = 2700, brightness = 40 },
+//!     { at = "13:00", color_temp = 5500, brightness = 100 },
+//!     { at = "22:00", color_temp = 2200, brightness = 20 },
+//! ]
+//! ```
+//!
+//! To save quota, a device is only sent a new color temperature or
+//! brightness once it differs perceptibly from what it was last sent. When
+//! someone changes a device by hand, the controller leaves it alone for a
+//! while (two hours by default) before taking it back. Devices that are off
+//! are never turned on.
+use std::{collections::HashMap, fmt, time::Duration};
+
+use chrono::{DateTime, Local, NaiveTime, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+
+#[cfg(feature = "config")]
+use crate::config::{self, ConfigError};
+use crate::{
+    client::{GoveeClient, GoveeError},
+    models::{
+        ControlCommand, Device, DeviceId, Devices, NormalizedState, PowerState, COLOR_TEMP_RANGE,
+    },
+    monitor::{quota_safe_interval, DEFAULT_QUOTA_SHARE},
+};
+
+/// The smallest color temperature change sent by default, in mireds.
+///
+/// Mireds (a million over the temperature in kelvin) are used because equal
+/// steps in them look about equal, where equal steps in kelvin do not.
+pub const DEFAULT_MIRED_STEP: u64 = 10;
+
+/// The smallest brightness change sent by default, in percentage points.
+pub const DEFAULT_BRIGHTNESS_STEP: u64 = 5;
+
+/// How long a device is left alone after a manual change by default.
+pub const DEFAULT_PAUSE: Duration = Duration::from_secs(2 * 60 * 60);
+
+/// The shortest interval between updates, however few devices there are.
+pub const MIN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How far a reported color temperature may be from the one sent, in
+/// mireds, before it counts as changed by someone else. Devices round what
+/// they are sent.
+const MIRED_TOLERANCE: f64 = 3.0;
+
+#[derive(Debug, Error)]
+pub enum CircadianError {
+    #[cfg(feature = "config")]
+    #[error("failed to load curve: {}", source)]
+    Config {
+        #[from]
+        source: ConfigError,
+    },
+    #[error("a curve needs at least one point")]
+    Empty,
+    #[error("more than one curve point at {}", at)]
+    DuplicateTime { at: NaiveTime },
+    #[error(
+        "color temperature {}K at {} is outside {}-{}K",
+        color_temp,
+        at,
+        COLOR_TEMP_RANGE.0,
+        COLOR_TEMP_RANGE.1
+    )]
+    ColorTemp { at: NaiveTime, color_temp: u64 },
+    #[error("brightness {} at {} is above 100", brightness, at)]
+    Brightness { at: NaiveTime, brightness: u64 },
+}
+
+fn mireds(kelvin: u64) -> f64 {
+    1_000_000.0 / kelvin.max(1) as f64
+}
+
+/// A color temperature and brightness.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Level {
+    /// In kelvin.
+    pub color_temp: u64,
+    /// From 0 to 100.
+    pub brightness: u64,
+}
+
+/// Where a [Curve] passes at a time of day.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CurvePoint {
+    #[serde(with = "crate::time_of_day")]
+    pub at: NaiveTime,
+    /// In kelvin.
+    pub color_temp: u64,
+    /// From 0 to 100.
+    pub brightness: u64,
+}
+
+impl CurvePoint {
+    pub fn level(&self) -> Level {
+        Level {
+            color_temp: self.color_temp,
+            brightness: self.brightness,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawCurve {
+    points: Vec<CurvePoint>,
+}
+
+/// How color temperature and brightness change over a day.
+///
+/// # Examples
+/// ```
+/// use chrono::NaiveTime;
+/// use govee_rs::circadian::Curve;
+///
+/// let curve = Curve::default();
+/// let noon = curve.at(NaiveTime::from_hms_opt(13, 0, 0).unwrap());
+/// let night = curve.at(NaiveTime::from_hms_opt(23, 30, 0).unwrap());
+/// assert!(noon.color_temp > night.color_temp);
+/// assert!(noon.brightness > night.brightness);
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "RawCurve")]
+pub struct Curve {
+    points: Vec<CurvePoint>,
+}
+
+impl TryFrom<RawCurve> for Curve {
+    type Error = CircadianError;
+
+    fn try_from(raw: RawCurve) -> Result<Self, Self::Error> {
+        Self::new(raw.points)
+    }
+}
+
+impl Default for Curve {
+    fn default() -> Self {
+        let point = |hour, color_temp, brightness| CurvePoint {
+            at: NaiveTime::from_hms_opt(hour, 0, 0).unwrap_or_default(),
+            color_temp,
+            brightness,
+        };
+        Self {
+            points: vec![
+                point(6, 2700, 30),
+                point(9, 4500, 80),
+                point(13, 5500, 100),
+                point(18, 4000, 80),
+                point(21, 2700, 40),
+                point(23, 2200, 10),
+            ],
+        }
+    }
+}
+
+impl Curve {
+    /// Make a curve through `points`, which may be in any order.
+    pub fn new(mut points: Vec<CurvePoint>) -> Result<Self, CircadianError> {
+        if points.is_empty() {
+            return Err(CircadianError::Empty);
+        }
+        points.sort_by_key(|p| p.at);
+        if let Some(pair) = points.windows(2).find(|w| w[0].at == w[1].at) {
+            return Err(CircadianError::DuplicateTime { at: pair[0].at });
+        }
+        for &CurvePoint {
+            at,
+            color_temp,
+            brightness,
+        } in &points
+        {
+            let (min, max) = COLOR_TEMP_RANGE;
+            if !(min..=max).contains(&color_temp) {
+                return Err(CircadianError::ColorTemp { at, color_temp });
+            }
+            if brightness > 100 {
+                return Err(CircadianError::Brightness { at, brightness });
+            }
+        }
+        Ok(Self { points })
+    }
+
+    /// Load a curve from a `.toml`, `.yaml` or `.yml` file.
+    #[cfg(feature = "config")]
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, CircadianError> {
+        Ok(config::load(path.as_ref())?)
+    }
+
+    pub fn points(&self) -> &[CurvePoint] {
+        &self.points
+    }
+
+    /// The level at `time`, between the points either side of it.
+    ///
+    /// Color temperature is interpolated in mireds, so the change looks even.
+    pub fn at(&self, time: NaiveTime) -> Level {
+        const DAY: i64 = 86_400;
+        let seconds = |t: NaiveTime| i64::from(t.num_seconds_from_midnight());
+        let now = seconds(time);
+
+        // the last point at or before now and the first after, wrapping
+        let next = self.points.partition_point(|p| seconds(p.at) <= now);
+        let before = self.points[(next + self.points.len() - 1) % self.points.len()];
+        let after = self.points[next % self.points.len()];
+
+        let span = (seconds(after.at) - seconds(before.at)).rem_euclid(DAY);
+        if span == 0 {
+            return before.level();
+        }
+        let f = (now - seconds(before.at)).rem_euclid(DAY) as f64 / span as f64;
+
+        let from = mireds(before.color_temp);
+        let to = mireds(after.color_temp);
+        let brightness =
+            before.brightness as f64 + (after.brightness as f64 - before.brightness as f64) * f;
+        Level {
+            color_temp: (1_000_000.0 / (from + (to - from) * f)).round() as u64,
+            brightness: brightness.round() as u64,
+        }
+    }
+}
+
+/// Something a [Circadian] controller did.
+#[derive(Debug)]
+pub enum CircadianEvent {
+    /// Sent a device a new color temperature, brightness or both.
+    Adjusted {
+        device: String,
+        color_temp: Option<u64>,
+        brightness: Option<u64>,
+    },
+    /// Noticed someone else changed a device, and left it alone until
+    /// `until`.
+    Paused {
+        device: String,
+        until: DateTime<Utc>,
+    },
+    /// Took a paused device back.
+    Resumed {
+        device: String,
+    },
+    Failed {
+        device: String,
+        error: GoveeError,
+    },
+}
+
+impl fmt::Display for CircadianEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Adjusted {
+                device,
+                color_temp,
+                brightness,
+            } => {
+                write!(f, "{}:", device)?;
+                if let Some(temp) = color_temp {
+                    write!(f, " {}K", temp)?;
+                }
+                if let Some(brightness) = brightness {
+                    write!(f, " {}%", brightness)?;
+                }
+                Ok(())
+            }
+            Self::Paused { device, until } => {
+                write!(f, "{}: changed by hand, paused until {}", device, until)
+            }
+            Self::Resumed { device } => write!(f, "{}: resumed", device),
+            Self::Failed { device, error } => write!(f, "{}: {}", device, error),
+        }
+    }
+}
+
+/// What to send a device.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+struct Update {
+    color_temp: Option<u64>,
+    brightness: Option<u64>,
+}
+
+/// What the controller decided to do with a device.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Step {
+    Hold,
+    Pause { until: DateTime<Utc> },
+    Update(Update),
+}
+
+/// What the controller knows about a device.
+#[derive(Debug, Clone, Default)]
+struct Track {
+    /// What the device was last sent.
+    sent: Update,
+    paused_until: Option<DateTime<Utc>>,
+}
+
+impl Track {
+    /// If the device is no longer as it was left.
+    fn changed_by_hand(&self, observed: &NormalizedState) -> bool {
+        let temp_changed = self.sent.color_temp.is_some_and(|sent| {
+            match observed.color_temp.filter(|t| *t > 0) {
+                Some(temp) => (mireds(temp) - mireds(sent)).abs() > MIRED_TOLERANCE,
+                // switched to a color
+                None => observed.color.is_some(),
+            }
+        });
+        let brightness_changed = self
+            .sent
+            .brightness
+            .zip(observed.brightness)
+            .is_some_and(|(sent, seen)| sent.abs_diff(seen) > 1);
+        temp_changed || brightness_changed
+    }
+
+    fn step(
+        &mut self,
+        device: &Device,
+        observed: &NormalizedState,
+        target: Level,
+        settings: &Settings,
+        now: DateTime<Utc>,
+    ) -> Step {
+        if self.paused_until.is_some()
+            || observed.online == Some(false)
+            || observed.power == Some(PowerState::Off)
+        {
+            return Step::Hold;
+        }
+        if self.changed_by_hand(observed) {
+            let until = now + chrono::Duration::from_std(settings.pause).unwrap_or_default();
+            self.paused_until = Some(until);
+            return Step::Pause { until };
+        }
+
+        // what it is now, as far as we know
+        let temp = self.sent.color_temp.or(observed
+            .color_temp
+            .filter(|t| *t > 0 && observed.color.is_none()));
+        let brightness = self.sent.brightness.or(observed.brightness);
+
+        let update = Update {
+            color_temp: Some(target.color_temp).filter(|target| {
+                device.supports(&ControlCommand::ColorTem)
+                    && temp.map_or(true, |temp| {
+                        (mireds(temp) - mireds(*target)).abs() >= settings.mired_step as f64
+                    })
+            }),
+            brightness: Some(target.brightness).filter(|target| {
+                device.supports(&ControlCommand::Brightness)
+                    && brightness.map_or(true, |b| b.abs_diff(*target) >= settings.brightness_step)
+            }),
+        };
+        if update == Update::default() {
+            Step::Hold
+        } else {
+            Step::Update(update)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Settings {
+    mired_step: u64,
+    brightness_step: u64,
+    pause: Duration,
+}
+
+/// Keeps devices on a [Curve].
+///
+/// # Examples
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use govee_rs::{circadian::{Circadian, Curve}, GoveeClient, DEFAULT_API_URL};
+/// use tokio_util::sync::CancellationToken;
+///
+/// let client = GoveeClient::new(DEFAULT_API_URL, "my-api-key")?;
+/// let devices = client.devices().await?;
+/// let circadian = Circadian::new(client, &devices, Curve::default())
+///     .with_timezone(chrono_tz::Europe::Amsterdam);
+///
+/// circadian
+///     .run(&CancellationToken::new(), |event| println!("{}", event))
+///     .await;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Circadian {
+    client: GoveeClient,
+    devices: Devices,
+    curve: Curve,
+    timezone: Option<Tz>,
+    interval: Duration,
+    settings: Settings,
+}
+
+impl Circadian {
+    /// Make a controller for the devices in `devices` whose color temperature
+    /// or brightness can be set and whose state can be retrieved.
+    pub fn new(client: GoveeClient, devices: &Devices, curve: Curve) -> Self {
+        let devices = devices.retrievable().filtered(|d| {
+            d.supports(&ControlCommand::ColorTem) || d.supports(&ControlCommand::Brightness)
+        });
+        let interval = quota_safe_interval(devices.len(), DEFAULT_QUOTA_SHARE).max(MIN_INTERVAL);
+        Self {
+            client,
+            devices,
+            curve,
+            timezone: None,
+            interval,
+            settings: Settings {
+                mired_step: DEFAULT_MIRED_STEP,
+                brightness_step: DEFAULT_BRIGHTNESS_STEP,
+                pause: DEFAULT_PAUSE,
+            },
+        }
+    }
+
+    /// Read the curve in `timezone` rather than the system's local time.
+    pub fn with_timezone(mut self, timezone: Tz) -> Self {
+        self.timezone = Some(timezone);
+        self
+    }
+
+    /// Override how often devices are checked and updated.
+    ///
+    /// Intervals below [MIN_INTERVAL] are raised to it.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval.max(MIN_INTERVAL);
+        self
+    }
+
+    /// Override the smallest changes sent, in mireds and percentage points.
+    pub fn with_min_change(mut self, mireds: u64, brightness: u64) -> Self {
+        self.settings.mired_step = mireds;
+        self.settings.brightness_step = brightness;
+        self
+    }
+
+    /// Override how long a device is left alone after a manual change.
+    pub fn with_pause(mut self, pause: Duration) -> Self {
+        self.settings.pause = pause;
+        self
+    }
+
+    pub fn devices(&self) -> &Devices {
+        &self.devices
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Where the curve is at `now`.
+    pub fn target(&self, now: DateTime<Utc>) -> Level {
+        let time = match self.timezone {
+            Some(tz) => now.with_timezone(&tz).time(),
+            None => now.with_timezone(&Local).time(),
+        };
+        self.curve.at(time)
+    }
+
+    /// Keep the devices on the curve until `cancel` is triggered, telling
+    /// `report` what was done.
+    pub async fn run(&self, cancel: &CancellationToken, mut report: impl FnMut(CircadianEvent)) {
+        let mut tracks: HashMap<DeviceId, Track> = HashMap::new();
+
+        loop {
+            let now = Utc::now();
+            let target = self.target(now);
+
+            for device in self.devices.iter() {
+                let track = tracks.entry(device.device.clone()).or_default();
+                let name = || device.name.clone();
+
+                if track.paused_until.is_some_and(|until| now >= until) {
+                    // start over from whatever it was left at
+                    *track = Track::default();
+                    report(CircadianEvent::Resumed { device: name() });
+                }
+
+                let observed = match self.client.state(device).await {
+                    Ok(state) => state.normalized(),
+                    Err(error) => {
+                        report(CircadianEvent::Failed {
+                            device: name(),
+                            error,
+                        });
+                        continue;
+                    }
+                };
+
+                match track.step(device, &observed, target, &self.settings, now) {
+                    Step::Hold => {}
+                    Step::Pause { until } => report(CircadianEvent::Paused {
+                        device: name(),
+                        until,
+                    }),
+                    Step::Update(update) => {
+                        let mut result = Ok(());
+                        if let Some(temp) = update.color_temp {
+                            result = self.client.color_temp(device, temp).await;
+                            if result.is_ok() {
+                                track.sent.color_temp = Some(temp);
+                            }
+                        }
+                        if let (Ok(()), Some(brightness)) = (&result, update.brightness) {
+                            result = self.client.brightness(device, brightness).await;
+                            if result.is_ok() {
+                                track.sent.brightness = Some(brightness);
+                            }
+                        }
+                        report(match result {
+                            Ok(()) => CircadianEvent::Adjusted {
+                                device: name(),
+                                color_temp: update.color_temp,
+                                brightness: update.brightness,
+                            },
+                            Err(error) => CircadianEvent::Failed {
+                                device: name(),
+                                error,
+                            },
+                        });
+                    }
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(self.interval) => {}
+                _ = cancel.cancelled() => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn time(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    #[test]
+    fn curve_interpolates_and_wraps() {
+        let curve: Curve = serde_json::from_value(serde_json::json!({
+            "points": [
+                { "at": "20:00", "color_temp": 2500, "brightness": 20 },
+                { "at": "08:00", "color_temp": 5000, "brightness": 100 },
+            ],
+        }))
+        .unwrap();
+
+        assert_eq!(curve.at(time(8, 0)).color_temp, 5000);
+        // halfway in mireds, 300, not halfway in kelvin
+        assert_eq!(
+            curve.at(time(14, 0)),
+            Level {
+                color_temp: 3333,
+                brightness: 60
+            }
+        );
+        // the night runs from 20:00 back around to 08:00
+        assert_eq!(curve.at(time(2, 0)).brightness, 60);
+
+        let point = |at, color_temp| CurvePoint {
+            at,
+            color_temp,
+            brightness: 50,
+        };
+        assert!(matches!(
+            Curve::new(vec![point(time(1, 0), 2700), point(time(1, 0), 3000)]),
+            Err(CircadianError::DuplicateTime { .. })
+        ));
+        assert!(matches!(
+            Curve::new(vec![point(time(1, 0), 100_000)]),
+            Err(CircadianError::ColorTemp { .. })
+        ));
+    }
+
+    #[test]
+    fn steps_only_when_perceptible_and_pauses_for_manual_changes() {
+        let device = Device {
+            name: "desk".into(),
+            supported_commands: [ControlCommand::ColorTem, ControlCommand::Brightness].into(),
+            ..Device::new(DeviceId::parse("34:20:03:2e:30:2b").unwrap())
+        };
+        let settings = Settings {
+            mired_step: DEFAULT_MIRED_STEP,
+            brightness_step: DEFAULT_BRIGHTNESS_STEP,
+            pause: DEFAULT_PAUSE,
+        };
+        let now = Utc.with_ymd_and_hms(2024, 6, 21, 12, 0, 0).unwrap();
+        let on = NormalizedState {
+            power: Some(PowerState::On),
+            brightness: Some(40),
+            color_temp: Some(4000),
+            ..NormalizedState::new(device.device.clone())
+        };
+        let level = |color_temp, brightness| Level {
+            color_temp,
+            brightness,
+        };
+
+        let mut track = Track::default();
+        // 4000K to 4100K is only 6 mireds
+        assert_eq!(
+            track.step(&device, &on, level(4100, 60), &settings, now),
+            Step::Update(Update {
+                color_temp: None,
+                brightness: Some(60)
+            })
+        );
+        track.sent.brightness = Some(60);
+
+        let seen = NormalizedState {
+            brightness: Some(60),
+            ..on.clone()
+        };
+        assert_eq!(
+            track.step(&device, &seen, level(4100, 62), &settings, now),
+            Step::Hold
+        );
+
+        let off = NormalizedState {
+            power: Some(PowerState::Off),
+            ..seen.clone()
+        };
+        assert_eq!(
+            track.step(&device, &off, level(2700, 20), &settings, now),
+            Step::Hold
+        );
+
+        // someone turned it down
+        let dimmed = NormalizedState {
+            brightness: Some(10),
+            ..seen
+        };
+        let Step::Pause { until } = track.step(&device, &dimmed, level(4100, 60), &settings, now)
+        else {
+            panic!("expected a pause");
+        };
+        assert_eq!(until - now, chrono::Duration::hours(2));
+        assert_eq!(
+            track.step(&device, &dimmed, level(2700, 20), &settings, now),
+            Step::Hold
+        );
+    }
+}
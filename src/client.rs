@@ -1,3 +1,10 @@
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
 use async_trait::async_trait;
 use bytes::Bytes;
 use gen_api_wrapper::{
@@ -5,13 +12,17 @@ use gen_api_wrapper::{
     error::ApiError,
     query::AsyncQuery,
 };
-use http::{HeaderMap, HeaderValue, Response};
+use http::{HeaderMap, HeaderValue, Method, Response, StatusCode};
 use reqwest::Client;
 use thiserror::Error;
 use url::Url;
 
 use crate::{
+    cassette::{
+        Cassette, CassetteError, CassetteRequest, CassetteResponse, Interaction, Player, Recorder,
+    },
     endpoints::{DeviceControlEndpoint, DeviceStateEndpoint, DevicesEndpoint},
+    journal::{Journal, JournalEntry},
     models::{
         AnySuccessResponse, BaseResponse, Color, ControlCmd, Device, DeviceState, Devices,
         PowerState,
@@ -67,6 +78,11 @@ pub enum RestError {
         #[from]
         source: http::Error,
     },
+    #[error("cassette error: {}", source)]
+    Cassette {
+        #[from]
+        source: CassetteError,
+    },
 }
 
 #[derive(Debug, Error)]
@@ -95,6 +111,93 @@ impl Auth {
     }
 }
 
+/// A rate limit as reported in the headers of an API response.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Quota {
+    pub limit: Option<u64>,
+    pub remaining: Option<u64>,
+
+    /// When the limit resets, in seconds since the unix epoch.
+    pub reset: Option<u64>,
+}
+
+impl Quota {
+    /// Read the `<prefix>-Limit`, `<prefix>-Remaining` and `<prefix>-Reset`
+    /// headers.
+    fn from_headers(headers: &HeaderMap, prefix: &str) -> Self {
+        let get = |suffix: &str| {
+            headers
+                .get(format!("{}-{}", prefix, suffix))
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse().ok())
+        };
+        Self {
+            limit: get("Limit"),
+            remaining: get("Remaining"),
+            reset: get("Reset"),
+        }
+    }
+}
+
+/// A single request made by a [GoveeClient], as seen by a [RequestObserver].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RequestRecord {
+    pub method: Method,
+
+    /// The path of the endpoint, without the query string.
+    pub path: String,
+
+    /// The response status, or `None` if no response was received.
+    pub status: Option<StatusCode>,
+    pub latency: Duration,
+
+    /// The account's daily request quota.
+    pub daily_quota: Quota,
+
+    /// The per minute quota of the endpoint.
+    pub endpoint_quota: Quota,
+}
+
+/// Something told about every request a [GoveeClient] makes.
+///
+/// This is implemented for closures, and is how metrics are collected.
+/// Requests a dry-run client writes to its journal are not observed, since
+/// they never reach the API or use any quota.
+///
+/// # Examples
+/// ```
+/// use std::sync::Arc;
+/// use govee_rs::{client::RequestRecord, GoveeClient, DEFAULT_API_URL};
+///
+/// let client = GoveeClient::new(DEFAULT_API_URL, "my-api-key")
+///     .unwrap()
+///     .with_observer(Arc::new(|record: &RequestRecord| {
+///         println!("{} {} took {:?}", record.method, record.path, record.latency);
+///     }));
+/// ```
+pub trait RequestObserver: Send + Sync {
+    fn observe(&self, record: &RequestRecord);
+}
+
+impl<F> RequestObserver for F
+where
+    F: Fn(&RequestRecord) + Send + Sync,
+{
+    fn observe(&self, record: &RequestRecord) {
+        self(record)
+    }
+}
+
+/// What a dry-run client answers requests it does not send with.
+const DRY_RUN_RESPONSE: &[u8] = br#"{"code": 200, "message": "Success", "data": {}}"#;
+
+/// Where a [GoveeClient]'s requests go besides, or instead of, the network.
+#[derive(Clone)]
+enum CassetteMode {
+    Record(Arc<Recorder>),
+    Replay(Arc<Player>),
+}
+
 /// A client for interacting with the GoveeApi.
 ///
 /// Can either be used directly or as an argument to the endpoint structs.
@@ -103,6 +206,26 @@ pub struct GoveeClient {
     client: Client,
     api_url: Url,
     auth: Auth,
+    observer: Option<Arc<dyn RequestObserver>>,
+    cassette: Option<CassetteMode>,
+    journal: Option<Journal>,
+}
+
+impl fmt::Debug for GoveeClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GoveeClient")
+            .field("api_url", &self.api_url.as_str())
+            .field("observed", &self.observer.is_some())
+            .field(
+                "cassette",
+                &self.cassette.as_ref().map(|c| match c {
+                    CassetteMode::Record(_) => "recording",
+                    CassetteMode::Replay(_) => "replaying",
+                }),
+            )
+            .field("dry_run", &self.journal.is_some())
+            .finish_non_exhaustive()
+    }
 }
 
 impl GoveeClient {
@@ -119,9 +242,42 @@ impl GoveeClient {
             auth: Auth {
                 api_key: api_key.into(),
             },
+            observer: None,
+            cassette: None,
+            journal: None,
         })
     }
 
+    /// Tell `observer` about every request this client makes.
+    pub fn with_observer(mut self, observer: Arc<dyn RequestObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Write every request and response to a [Cassette] at `path`, replacing
+    /// any file there. See [crate::cassette].
+    pub fn with_recording(mut self, path: impl Into<PathBuf>) -> Self {
+        self.cassette = Some(CassetteMode::Record(Arc::new(Recorder::new(path.into()))));
+        self
+    }
+
+    /// Answer requests from `cassette` instead of the network. See
+    /// [crate::cassette].
+    pub fn with_replay(mut self, cassette: Cassette) -> Self {
+        self.cassette = Some(CassetteMode::Replay(Arc::new(Player::new(cassette))));
+        self
+    }
+
+    /// Write anything but reads to `journal` instead of sending it. See
+    /// [crate::journal].
+    ///
+    /// Journaled requests are logged at info level with the `tracing`
+    /// feature, and are not passed to the [RequestObserver].
+    pub fn with_dry_run(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
     /// Gets the [Devices] associated with the account specified by the key.
     pub async fn devices(&self) -> Result<Devices, GoveeError> {
         let endpoint = DevicesEndpoint::new();
@@ -161,7 +317,8 @@ impl GoveeClient {
         self.control(device, ControlCmd::ColorTem(color_temp)).await
     }
 
-    async fn control(&self, device: &Device, cmd: ControlCmd) -> Result<(), GoveeError> {
+    /// Send a single [ControlCmd] to a particular [Device].
+    pub async fn control(&self, device: &Device, cmd: ControlCmd) -> Result<(), GoveeError> {
         let endpoint = DeviceControlEndpoint::builder()
             .device(&device.device)
             .model(&device.model)
@@ -190,10 +347,43 @@ impl AsyncClient for GoveeClient {
         mut request: http::request::Builder,
         body: Vec<u8>,
     ) -> Result<Response<Bytes>, ApiError<<Self as RestClient>::Error>> {
-        use futures_util::TryFutureExt;
+        let method = request.method_ref().cloned().unwrap_or_default();
+        let path = request
+            .uri_ref()
+            .map(|uri| uri.path().to_string())
+            .unwrap_or_default();
+        let started = Instant::now();
+
+        if let Some(journal) = self.journal.as_ref().filter(|_| method != Method::GET) {
+            let entry = JournalEntry {
+                method,
+                path,
+                body: serde_json::from_slice(&body).unwrap_or_default(),
+                at: SystemTime::now(),
+            };
+            #[cfg(feature = "tracing")]
+            tracing::info!("dry run, not sending {}", entry);
+            journal.push(entry);
+            return Response::builder()
+                .status(StatusCode::OK)
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(Bytes::from_static(DRY_RUN_RESPONSE))
+                .map_err(|e| ApiError::client(e.into()));
+        }
+
         let call = || async {
             self.auth.set_header(request.headers_mut().unwrap())?;
             let http_request = request.body(body)?;
+            let recorded = self
+                .cassette
+                .as_ref()
+                .map(|_| CassetteRequest::from_http(&http_request));
+            if let (Some(CassetteMode::Replay(player)), Some(recorded)) =
+                (&self.cassette, &recorded)
+            {
+                return Ok(player.respond(recorded)?);
+            }
+
             let request = http_request.try_into()?;
             let rsp = self.client.execute(request).await?;
 
@@ -204,9 +394,45 @@ impl AsyncClient for GoveeClient {
             for (key, value) in rsp.headers() {
                 headers.insert(key, value.clone());
             }
-            Ok(http_rsp.body(rsp.bytes().await?)?)
+            let http_rsp = http_rsp.body(rsp.bytes().await?)?;
+
+            if let (Some(CassetteMode::Record(recorder)), Some(request)) =
+                (&self.cassette, recorded)
+            {
+                let interaction = Interaction {
+                    request,
+                    response: CassetteResponse::from_http(&http_rsp),
+                };
+                // the request already happened, so its response is still good
+                if let Err(e) = recorder.record(interaction).await {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!("{}", e);
+                    #[cfg(not(feature = "tracing"))]
+                    let _ = e;
+                }
+            }
+            Ok(http_rsp)
         };
-        call().map_err(ApiError::client).await
+        let result: Result<Response<Bytes>, RestError> = call().await;
+
+        if let Some(observer) = self.observer.as_ref() {
+            let headers = result.as_ref().ok().map(Response::headers);
+            let quota = |prefix| {
+                headers
+                    .map(|h| Quota::from_headers(h, prefix))
+                    .unwrap_or_default()
+            };
+            observer.observe(&RequestRecord {
+                method,
+                path,
+                status: result.as_ref().ok().map(Response::status),
+                latency: started.elapsed(),
+                daily_quota: quota("X-RateLimit"),
+                endpoint_quota: quota("API-RateLimit"),
+            });
+        }
+
+        result.map_err(ApiError::client)
     }
 }
 
@@ -214,15 +440,16 @@ impl AsyncClient for GoveeClient {
 mod tests {
     use std::collections::HashSet;
 
-    use crate::models::{ControlCommand, ControlRequest};
+    use crate::models::{ControlCommand, ControlRequest, DeviceId};
 
     use super::*;
     use mockito::Server;
 
+    #[allow(clippy::useless_conversion)]
     fn fake_device() -> Device {
         Device {
-            device: "34:20:03:15:82:ae".to_string(),
-            model: "H6089".to_string(),
+            device: DeviceId::parse("34:20:03:15:82:ae").unwrap(),
+            model: "H6089".into(),
             name: "fake device".to_string(),
             controllable: true,
             retrievable: true,
@@ -238,6 +465,51 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn devices_skips_invalid_ids() {
+        let mut server = Server::new_async().await;
+        let client = GoveeClient::new(&server.url(), "foobarbaz").unwrap();
+
+        let fake_response = r#"
+            {
+                "data": {
+                    "devices": [
+                        {
+                            "device": "not a mac",
+                            "model": "H6159",
+                            "deviceName": "odd light",
+                            "controllable": true,
+                            "retrievable": true,
+                            "supportCmds": ["turn"]
+                        },
+                        {
+                            "device": "99:A5:A4:C1:38:29:DA:7B",
+                            "model": "H6159",
+                            "deviceName": "test light",
+                            "controllable": true,
+                            "retrievable": true,
+                            "supportCmds": ["turn"]
+                        }
+                    ]
+                },
+                "message": "Success",
+                "code": 200
+            }"#;
+        let mock = server
+            .mock("GET", "/v1/devices?")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(fake_response)
+            .create_async()
+            .await;
+
+        let devices = client.devices().await.unwrap();
+        mock.assert_async().await;
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, "test light");
+    }
+
+    #[allow(clippy::useless_conversion)]
     #[tokio::test]
     async fn devices() {
         let mut server = Server::new_async().await;
@@ -304,7 +576,7 @@ mod tests {
         assert_eq!(devices.len(), 3);
 
         let expected = Device {
-            device: "99:A5:A4:C1:38:29:DA:7B".into(),
+            device: DeviceId::parse("99:A5:A4:C1:38:29:DA:7B").unwrap(),
             model: "H6159".into(),
             name: "test light".into(),
             controllable: true,
@@ -355,8 +627,8 @@ mod tests {
             .mock("GET", "/v1/devices/state")
             .match_header("Govee-API-Key", fake_api_key)
             .match_query(mockito::Matcher::AllOf(vec![
-                mockito::Matcher::UrlEncoded("device".into(), device.device.clone()),
-                mockito::Matcher::UrlEncoded("model".into(), device.model.clone()),
+                mockito::Matcher::UrlEncoded("device".into(), device.device.to_string()),
+                mockito::Matcher::UrlEncoded("model".into(), device.model.to_string()),
             ]))
             .with_status(200)
             .with_body(fake_response)
@@ -377,8 +649,8 @@ mod tests {
         let device = fake_device();
 
         let control_request = ControlRequest {
-            device: device.device.clone().into(),
-            model: device.model.clone().into(),
+            device: (&device.device).into(),
+            model: (&device.model).into(),
             cmd: ControlCmd::Turn(PowerState::On),
         };
 
@@ -415,8 +687,8 @@ mod tests {
         let device = fake_device();
 
         let control_request = ControlRequest {
-            device: device.device.clone().into(),
-            model: device.model.clone().into(),
+            device: (&device.device).into(),
+            model: (&device.model).into(),
             cmd: ControlCmd::Brightness(25),
         };
 
@@ -459,8 +731,8 @@ mod tests {
         };
 
         let control_request = ControlRequest {
-            device: device.device.clone().into(),
-            model: device.model.clone().into(),
+            device: (&device.device).into(),
+            model: (&device.model).into(),
             cmd: ControlCmd::Color(color),
         };
 
@@ -497,8 +769,8 @@ mod tests {
         let device = fake_device();
 
         let control_request = ControlRequest {
-            device: device.device.clone().into(),
-            model: device.model.clone().into(),
+            device: (&device.device).into(),
+            model: (&device.model).into(),
             cmd: ControlCmd::ColorTem(1000),
         };
 
@@ -525,4 +797,54 @@ mod tests {
 
         control_mock.assert_async().await;
     }
+
+    #[tokio::test]
+    async fn observer() {
+        use std::sync::Mutex;
+
+        let mut server = Server::new_async().await;
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let seen = records.clone();
+        let client = GoveeClient::new(&server.url(), "foobarbaz")
+            .unwrap()
+            .with_observer(Arc::new(move |r: &RequestRecord| {
+                seen.lock().unwrap().push(r.clone())
+            }));
+
+        let state_mock = server
+            .mock("GET", "/v1/devices/state")
+            .match_query(mockito::Matcher::Any)
+            .with_status(429)
+            .with_header("X-RateLimit-Limit", "10000")
+            .with_header("X-RateLimit-Remaining", "0")
+            .with_header("API-RateLimit-Remaining", "7")
+            .with_body(r#"{"message": "Too Many Requests"}"#)
+            .create_async()
+            .await;
+
+        assert!(client.state(&fake_device()).await.is_err());
+        state_mock.assert_async().await;
+
+        let recorded = records.lock().unwrap().clone();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].method, Method::GET);
+        assert_eq!(recorded[0].path, "/v1/devices/state");
+        assert_eq!(recorded[0].status, Some(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(
+            recorded[0].daily_quota,
+            Quota {
+                limit: Some(10000),
+                remaining: Some(0),
+                reset: None
+            }
+        );
+        assert_eq!(recorded[0].endpoint_quota.remaining, Some(7));
+
+        // requests a dry run does not send are not observed
+        let journal = Journal::default();
+        let client = client.with_dry_run(journal.clone());
+        client.turn(&fake_device(), PowerState::On).await.unwrap();
+        assert_eq!(journal.len(), 1);
+        assert_eq!(records.lock().unwrap().len(), 1);
+    }
 }
@@ -0,0 +1,88 @@
+//! Reading configuration files.
+//!
+//! Layouts, sequences, schedules, curves and rules are all written as TOML
+//! or YAML, and loaded from files picked by their extension.
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("cannot read {}: {}", path.display(), source)]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("unsupported format for {}: expected .toml, .yaml or .yml", path.display())]
+    UnsupportedFormat { path: PathBuf },
+    #[error("invalid toml: {}", source)]
+    Toml {
+        #[from]
+        source: toml::de::Error,
+    },
+    #[error("invalid yaml: {}", source)]
+    Yaml {
+        #[from]
+        source: serde_yaml::Error,
+    },
+}
+
+/// Read a `.toml`, `.yaml` or `.yml` file.
+pub fn load<T: DeserializeOwned>(path: &Path) -> Result<T, ConfigError> {
+    let raw = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => from_toml(&raw),
+        Some("yaml") | Some("yml") => from_yaml(&raw),
+        _ => Err(ConfigError::UnsupportedFormat {
+            path: path.to_path_buf(),
+        }),
+    }
+}
+
+pub fn from_toml<T: DeserializeOwned>(raw: &str) -> Result<T, ConfigError> {
+    Ok(toml::from_str(raw)?)
+}
+
+pub fn from_yaml<T: DeserializeOwned>(raw: &str) -> Result<T, ConfigError> {
+    Ok(serde_yaml::from_str(raw)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn picks_the_format_by_extension() {
+        let dir = std::env::temp_dir().join(format!("govee-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, raw: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, raw).unwrap();
+            path
+        };
+
+        let toml = write("a.toml", "answer = 42\n");
+        let yml = write("a.yml", "answer: 42\n");
+        let json = write("a.json", r#"{"answer": 42}"#);
+        let expected = BTreeMap::from([("answer".to_string(), 42)]);
+        assert_eq!(load::<BTreeMap<String, u32>>(&toml).unwrap(), expected);
+        assert_eq!(load::<BTreeMap<String, u32>>(&yml).unwrap(), expected);
+        assert!(matches!(
+            load::<BTreeMap<String, u32>>(&json),
+            Err(ConfigError::UnsupportedFormat { .. })
+        ));
+        assert!(matches!(
+            load::<BTreeMap<String, u32>>(&dir.join("missing.toml")),
+            Err(ConfigError::Io { .. })
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
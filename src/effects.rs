@@ -0,0 +1,704 @@
+//! Animated effects played from the client side.
+//!
+//! The developer API only sets a device's color and brightness, so effects
+//! like a breathing glow or a rainbow cycle have to be animated by sending
+//! one frame after another. An [Effect] is a pure function from time to a
+//! [Frame], which makes it easy to preview and test; an [EffectPlayer] sends
+//! those frames to one device or a group through a [Transport].
+//!
+//! How fast frames are sent depends on the transport. Over the cloud API
+//! every frame costs requests from the daily quota, so frames come slowly,
+//! at a quota-safe interval. Over the LAN there is no quota and frames come
+//! several times a second. Either way, only what changed since the last
+//! frame is sent.
+//!
+//! Devices only take a brightness from 1 to 100, so a frame with no
+//! brightness, like the dark half of a strobe, turns the device off instead,
+//! and the next frame with some turns it back on.
+//!
+//! # Examples
+//! ```
+//! use std::time::Duration;
+//! use govee_rs::{effects::Effect, Color};
+//!
+//! let strobe = Effect::Strobe {
+//!     color: Color { r: 255, g: 255, b: 255 },
+//!     period: Duration::from_millis(200),
+//!     duty: 0.5,
+//! };
+//! assert_eq!(strobe.frame(Duration::from_millis(50), 0).brightness, 100);
+//! assert_eq!(strobe.frame(Duration::from_millis(150), 0).brightness, 0);
+//! ```
+use std::{
+    collections::HashMap,
+    f64::consts::TAU,
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use thiserror::Error;
+use tokio::{net::UdpSocket, time::MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    client::{GoveeClient, GoveeError},
+    lan::{LanMessage, COMMAND_PORT},
+    models::{Color, ControlCmd, ControlCommand, Device, DeviceId, Devices, PowerState},
+    monitor::{quota_safe_interval, DEFAULT_QUOTA_SHARE},
+};
+
+/// How long to wait between frames over the LAN by default.
+pub const LAN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The shortest interval between frames over the LAN. Devices drop commands
+/// that come faster.
+pub const MIN_LAN_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How often a candle picks a new flicker to move towards.
+const CANDLE_STEP: Duration = Duration::from_millis(150);
+
+/// The deep orange a candle dips towards as it flickers.
+const EMBER: Color = Color {
+    r: 255,
+    g: 90,
+    b: 0,
+};
+
+#[derive(Debug, Error)]
+pub enum EffectError {
+    #[error("failed to control device: {}", source)]
+    Cloud {
+        #[from]
+        source: GoveeError,
+    },
+    #[error("failed to send to {} over the LAN: {}", device, source)]
+    Lan {
+        device: DeviceId,
+        #[source]
+        source: io::Error,
+    },
+    #[error("no LAN address is known for {}", device)]
+    NoAddress { device: DeviceId },
+}
+
+/// What a device shows at one moment of an [Effect].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Frame {
+    pub color: Color,
+    /// From 0 to 100.
+    pub brightness: u64,
+}
+
+/// An animation of color and brightness.
+///
+/// Frames depend only on the time since the effect started and the device's
+/// position in the group, so the same effect always produces the same frames.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Effect {
+    /// Fade `color` from `high` brightness down to `low` and back up again
+    /// every `period`.
+    Breathe {
+        color: Color,
+        period: Duration,
+        low: u64,
+        high: u64,
+    },
+    /// Cycle through every hue every `period`. Each device in a group is
+    /// `spread` degrees further around the color wheel than the one before.
+    Rainbow {
+        period: Duration,
+        brightness: u64,
+        spread: f64,
+    },
+    /// Show each color in `palette` for `hold`, then fade to the next over
+    /// `fade`. Each device in a group starts one color further along. An
+    /// empty palette shows white.
+    ColorLoop {
+        palette: Vec<Color>,
+        hold: Duration,
+        fade: Duration,
+        brightness: u64,
+    },
+    /// Flicker around `color` and `brightness`, dimming by up to `flicker`
+    /// points but never going out. Devices in a group flicker independently, and the same
+    /// `seed` always flickers the same way.
+    Candle {
+        color: Color,
+        brightness: u64,
+        flicker: u64,
+        seed: u64,
+    },
+    /// Flash `color` at full brightness for `duty` of each `period`, and
+    /// show nothing for the rest.
+    Strobe {
+        color: Color,
+        period: Duration,
+        duty: f64,
+    },
+}
+
+impl Effect {
+    /// The frame the device at `index` in the group shows `at` after the
+    /// effect started.
+    pub fn frame(&self, at: Duration, index: usize) -> Frame {
+        match self {
+            Self::Breathe {
+                color,
+                period,
+                low,
+                high,
+            } => {
+                let (low, high) = ((*low).min(100) as f64, (*high).min(100) as f64);
+                let level = 0.5 + 0.5 * (TAU * phase(at, *period)).cos();
+                Frame {
+                    color: *color,
+                    brightness: (low + (high - low) * level).round() as u64,
+                }
+            }
+            Self::Rainbow {
+                period,
+                brightness,
+                spread,
+            } => Frame {
+                color: Color::from_hsv(
+                    360.0 * phase(at, *period) + spread * index as f64,
+                    1.0,
+                    1.0,
+                ),
+                brightness: (*brightness).min(100),
+            },
+            Self::ColorLoop {
+                palette,
+                hold,
+                fade,
+                brightness,
+            } => {
+                let color = if palette.is_empty() {
+                    Color {
+                        r: 255,
+                        g: 255,
+                        b: 255,
+                    }
+                } else {
+                    let step = (*hold + *fade).as_secs_f64();
+                    let (count, within) = if step > 0.0 {
+                        let steps = at.as_secs_f64() / step;
+                        (steps as usize, steps.fract() * step)
+                    } else {
+                        (0, 0.0)
+                    };
+                    let from = palette[(count + index) % palette.len()];
+                    let to = palette[(count + index + 1) % palette.len()];
+                    let faded = within - hold.as_secs_f64();
+                    if faded > 0.0 {
+                        from.mix(to, faded / fade.as_secs_f64())
+                    } else {
+                        from
+                    }
+                };
+                Frame {
+                    color,
+                    brightness: (*brightness).min(100),
+                }
+            }
+            Self::Candle {
+                color,
+                brightness,
+                flicker,
+                seed,
+            } => {
+                // ease between random targets so the flame wavers rather
+                // than jumps
+                let steps = at.as_secs_f64() / CANDLE_STEP.as_secs_f64();
+                let tick = steps as u64;
+                let t = steps.fract();
+                let t = t * t * (3.0 - 2.0 * t);
+                let dip = noise(*seed, index, tick) * (1.0 - t) + noise(*seed, index, tick + 1) * t;
+                let brightness = (*brightness).min(100) as f64;
+                Frame {
+                    color: color.mix(EMBER, dip * 0.4),
+                    brightness: (brightness - *flicker as f64 * dip).round().max(1.0) as u64,
+                }
+            }
+            Self::Strobe {
+                color,
+                period,
+                duty,
+            } => Frame {
+                color: *color,
+                brightness: if phase(at, *period) < *duty { 100 } else { 0 },
+            },
+        }
+    }
+}
+
+/// How far through a `period` the time `at` is, from 0 up to 1.
+fn phase(at: Duration, period: Duration) -> f64 {
+    if period.is_zero() {
+        return 0.0;
+    }
+    (at.as_secs_f64() / period.as_secs_f64()).fract()
+}
+
+/// A number from 0 up to 1 that only depends on its arguments, using
+/// splitmix64.
+fn noise(seed: u64, index: usize, tick: u64) -> f64 {
+    let mut z = seed
+        ^ (index as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ tick.wrapping_mul(0xd1b5_4a32_d192_ed03);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A way of sending frames to devices.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// How long to wait between frames when playing to `devices` devices,
+    /// unless overridden.
+    fn interval(&self, devices: usize) -> Duration;
+
+    /// The shortest interval between frames allowed when playing to
+    /// `devices` devices.
+    fn min_interval(&self, devices: usize) -> Duration;
+
+    async fn send(&self, device: &Device, cmd: ControlCmd) -> Result<(), EffectError>;
+}
+
+/// Frames over the cloud API cost up to two requests per device, usually a
+/// color and a brightness, so they are spaced to spend no more than the
+/// default share of the daily quota. Frames are never sent faster, since
+/// that would run out the quota before the day is over.
+#[async_trait]
+impl Transport for GoveeClient {
+    fn interval(&self, devices: usize) -> Duration {
+        quota_safe_interval(devices.max(1) * 2, DEFAULT_QUOTA_SHARE)
+    }
+
+    fn min_interval(&self, devices: usize) -> Duration {
+        self.interval(devices)
+    }
+
+    async fn send(&self, device: &Device, cmd: ControlCmd) -> Result<(), EffectError> {
+        Ok(self.control(device, cmd).await?)
+    }
+}
+
+/// Sends frames straight to devices on the local network.
+///
+/// Devices need the LAN API turned on in the Govee Home app, and their
+/// addresses must be added with [LanTransport::with_ip].
+///
+/// # Examples
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use govee_rs::effects::LanTransport;
+///
+/// let lan = LanTransport::bind()
+///     .await?
+///     .with_ip("34:20:03:2e:30:2b".parse()?, "192.168.1.40".parse()?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct LanTransport {
+    socket: UdpSocket,
+    addrs: HashMap<DeviceId, SocketAddr>,
+}
+
+impl LanTransport {
+    /// Bind a socket on a free local port to send from.
+    pub async fn bind() -> io::Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind("0.0.0.0:0").await?,
+            addrs: HashMap::new(),
+        })
+    }
+
+    /// Send `device` its frames at `ip`, on the usual command port.
+    pub fn with_ip(self, device: DeviceId, ip: IpAddr) -> Self {
+        self.with_addr(device, SocketAddr::new(ip, COMMAND_PORT))
+    }
+
+    /// Send `device` its frames at `addr`, for devices that are not on the
+    /// usual command port, like simulated ones.
+    pub fn with_addr(mut self, device: DeviceId, addr: SocketAddr) -> Self {
+        self.addrs.insert(device, addr);
+        self
+    }
+}
+
+#[async_trait]
+impl Transport for LanTransport {
+    fn interval(&self, _devices: usize) -> Duration {
+        LAN_INTERVAL
+    }
+
+    fn min_interval(&self, _devices: usize) -> Duration {
+        MIN_LAN_INTERVAL
+    }
+
+    async fn send(&self, device: &Device, cmd: ControlCmd) -> Result<(), EffectError> {
+        let addr = self
+            .addrs
+            .get(&device.device)
+            .ok_or_else(|| EffectError::NoAddress {
+                device: device.device.clone(),
+            })?;
+        self.socket
+            .send_to(&LanMessage::from(cmd).encode(), addr)
+            .await
+            .map_err(|source| EffectError::Lan {
+                device: device.device.clone(),
+                source,
+            })?;
+        Ok(())
+    }
+}
+
+/// The commands that show `frame` on `device`, given the `last` frame shown
+/// on it, leaving out what did not change.
+///
+/// With no `last` frame the device is turned on, in case it was off. A
+/// frame with no brightness turns the device off, or dims it as far as it
+/// goes if it cannot be turned off, and the next frame with some turns it
+/// back on.
+pub(crate) fn frame_commands(
+    device: &Device,
+    last: Option<Frame>,
+    frame: Frame,
+) -> Vec<ControlCmd> {
+    let mut cmds = Vec::new();
+    if last.map_or(true, |last| last.color != frame.color) {
+        cmds.push(ControlCmd::Color(frame.color));
+    }
+    if last.is_some_and(|last| last.brightness == frame.brightness) {
+        return cmds;
+    }
+
+    let can_turn = device.supports(&ControlCommand::Turn);
+    if frame.brightness == 0 && can_turn {
+        cmds.push(ControlCmd::Turn(PowerState::Off));
+        return cmds;
+    }
+    if can_turn && last.map_or(true, |last| last.brightness == 0) {
+        cmds.push(ControlCmd::Turn(PowerState::On));
+    }
+    if device.supports(&ControlCommand::Brightness) {
+        cmds.push(ControlCmd::Brightness(frame.brightness.clamp(1, 100)));
+    }
+    cmds
+}
+
+/// Send the [frame_commands] for `frame`, stopping at the first failure.
+pub(crate) async fn send_frame<T: Transport>(
+    transport: &T,
+    device: &Device,
+    last: Option<Frame>,
+    frame: Frame,
+) -> Result<(), EffectError> {
+    for cmd in frame_commands(device, last, frame) {
+        transport.send(device, cmd).await?;
+    }
+    Ok(())
+}
+
+/// What happened while playing an effect.
+#[derive(Debug)]
+pub enum EffectEvent {
+    Failed { device: String, error: EffectError },
+    Finished { frames: u64 },
+}
+
+impl fmt::Display for EffectEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Failed { device, error } => write!(f, "{}: {}", device, error),
+            Self::Finished { frames } => write!(f, "finished after {} frames", frames),
+        }
+    }
+}
+
+/// Plays an [Effect] on a group of devices.
+///
+/// # Examples
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::time::Duration;
+/// use govee_rs::{effects::{Effect, EffectPlayer}, GoveeClient, DEFAULT_API_URL};
+/// use tokio_util::sync::CancellationToken;
+///
+/// let client = GoveeClient::new(DEFAULT_API_URL, "my-api-key")?;
+/// let devices = client.devices().await?;
+/// let rainbow = Effect::Rainbow {
+///     period: Duration::from_secs(600),
+///     brightness: 80,
+///     spread: 30.0,
+/// };
+///
+/// EffectPlayer::new(client, &devices, rainbow)
+///     .with_duration(Duration::from_secs(3600))
+///     .run(&CancellationToken::new(), |event| println!("{}", event))
+///     .await;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct EffectPlayer<T> {
+    transport: T,
+    devices: Devices,
+    effect: Effect,
+    interval: Duration,
+    duration: Option<Duration>,
+}
+
+impl<T: Transport> EffectPlayer<T> {
+    /// Make a player for the devices in `devices` that can be controlled and
+    /// whose color can be set, sending frames at the transport's interval.
+    pub fn new(transport: T, devices: &Devices, effect: Effect) -> Self {
+        let devices = devices.controllable().supporting(ControlCommand::Color);
+        let interval = transport.interval(devices.len());
+        Self {
+            transport,
+            devices,
+            effect,
+            interval,
+            duration: None,
+        }
+    }
+
+    /// Override how long to wait between frames.
+    ///
+    /// Intervals below the transport's minimum are raised to it.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval.max(self.transport.min_interval(self.devices.len()));
+        self
+    }
+
+    /// Stop after `duration` rather than playing until cancelled.
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    pub fn devices(&self) -> &Devices {
+        &self.devices
+    }
+
+    pub fn effect(&self) -> &Effect {
+        &self.effect
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Play the effect until `cancel` is triggered or the duration is up,
+    /// telling `report` about failures.
+    ///
+    /// When a frame is late, frames are skipped rather than played quickly
+    /// to catch up, so the effect keeps time.
+    pub async fn run(&self, cancel: &CancellationToken, mut report: impl FnMut(EffectEvent)) {
+        let mut shown: Vec<Option<Frame>> = vec![None; self.devices.len()];
+        let mut frames = 0;
+        let mut ticks = tokio::time::interval(self.interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let start = tokio::time::Instant::now();
+
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => {}
+                _ = cancel.cancelled() => break,
+            }
+            let at = start.elapsed();
+            if self.duration.is_some_and(|duration| at > duration) {
+                break;
+            }
+
+            for (index, device) in self.devices.iter().enumerate() {
+                let frame = self.effect.frame(at, index);
+                let result = send_frame(&self.transport, device, shown[index], frame).await;
+                match result {
+                    Ok(()) => shown[index] = Some(frame),
+                    Err(error) => {
+                        // send the whole frame next time
+                        shown[index] = None;
+                        report(EffectEvent::Failed {
+                            device: device.name.clone(),
+                            error,
+                        });
+                    }
+                }
+            }
+            frames += 1;
+        }
+
+        report(EffectEvent::Finished { frames });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Color = Color { r: 255, g: 0, b: 0 };
+    const BLUE: Color = Color { r: 0, g: 0, b: 255 };
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn frames_are_deterministic() {
+        let breathe = Effect::Breathe {
+            color: RED,
+            period: ms(4000),
+            low: 10,
+            high: 90,
+        };
+        assert_eq!(breathe.frame(ms(0), 0).brightness, 90);
+        assert_eq!(breathe.frame(ms(1000), 0).brightness, 50);
+        assert_eq!(breathe.frame(ms(2000), 3).brightness, 10);
+
+        let rainbow = Effect::Rainbow {
+            period: ms(3000),
+            brightness: 100,
+            spread: 120.0,
+        };
+        assert_eq!(rainbow.frame(ms(0), 0).color, RED);
+        assert_eq!(rainbow.frame(ms(2000), 0).color, BLUE);
+        assert_eq!(rainbow.frame(ms(0), 2).color, BLUE);
+
+        let palette = Effect::ColorLoop {
+            palette: vec![RED, BLUE],
+            hold: ms(1000),
+            fade: ms(1000),
+            brightness: 50,
+        };
+        assert_eq!(palette.frame(ms(500), 0).color, RED);
+        assert_eq!(palette.frame(ms(1500), 0).color, RED.mix(BLUE, 0.5));
+        assert_eq!(palette.frame(ms(2500), 0).color, BLUE);
+        assert_eq!(palette.frame(ms(500), 1).color, BLUE);
+
+        let candle = |seed| Effect::Candle {
+            color: Color::named("orange").unwrap(),
+            brightness: 60,
+            flicker: 30,
+            seed,
+        };
+        let flames = |effect: &Effect, index| {
+            (0..100)
+                .map(|i| effect.frame(ms(i * 50), index))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(flames(&candle(1), 0), flames(&candle(1), 0));
+        assert_ne!(flames(&candle(1), 0), flames(&candle(2), 0));
+        assert_ne!(flames(&candle(1), 0), flames(&candle(1), 1));
+        assert!(flames(&candle(1), 0)
+            .iter()
+            .all(|frame| (30..=60).contains(&frame.brightness)));
+
+        let blown = Effect::Candle {
+            color: RED,
+            brightness: 40,
+            flicker: 100,
+            seed: 1,
+        };
+        assert!(flames(&blown, 0).iter().all(|frame| frame.brightness >= 1));
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn plays_only_changes_over_the_lan() {
+        use crate::testing::lan::{LanSimulator, SimDevice};
+
+        let lamp = Device {
+            model: "H6159".into(),
+            name: "lamp".into(),
+            controllable: true,
+            supported_commands: [
+                ControlCommand::Turn,
+                ControlCommand::Color,
+                ControlCommand::Brightness,
+            ]
+            .into(),
+            ..Device::new("34:20:03:2e:30:2b".parse().unwrap())
+        };
+        let sim = LanSimulator::start([SimDevice::new(lamp.device.clone(), lamp.model.clone())])
+            .await
+            .unwrap();
+        let lan = LanTransport::bind()
+            .await
+            .unwrap()
+            .with_addr(lamp.device.clone(), sim.device_addr(&lamp.device).unwrap());
+        let strobe = Effect::Strobe {
+            color: RED,
+            period: ms(100),
+            duty: 0.5,
+        };
+        let player = EffectPlayer::new(
+            lan,
+            &Devices {
+                devices: vec![lamp.clone()],
+            },
+            strobe,
+        )
+        .with_interval(ms(50))
+        .with_duration(ms(220));
+
+        let mut events = Vec::new();
+        player
+            .run(&CancellationToken::new(), |event| events.push(event))
+            .await;
+        let [EffectEvent::Finished { frames }] = events[..] else {
+            panic!("expected only a finish, got {:?}", events);
+        };
+        assert!(frames >= 4, "{} frames", frames);
+        tokio::time::sleep(ms(50)).await;
+
+        let received: Vec<LanMessage> = sim.received().into_iter().map(|(_, m)| m).collect();
+        // the color never changes, so it is only sent with the first frame
+        assert_eq!(
+            received
+                .iter()
+                .filter(|m| matches!(m, LanMessage::Colorwc { .. }))
+                .count(),
+            1
+        );
+        // the dark half turns the lamp off, which devices ignore brightness
+        // 0 for
+        assert!(received.contains(&LanMessage::Turn { value: 0 }));
+        assert!(!received.contains(&LanMessage::Brightness { value: 0 }));
+
+        // the last frame, at 200ms, is lit
+        let state = sim.state(&lamp.device).unwrap();
+        assert_eq!(state.power, Some(PowerState::On));
+        assert_eq!(state.brightness, Some(100));
+        assert_eq!(state.color, Some(RED));
+    }
+
+    #[tokio::test]
+    async fn cloud_frames_are_never_faster_than_quota_safe() {
+        let client = GoveeClient::new("http://127.0.0.1:9", "key").unwrap();
+        let devices = Devices {
+            devices: vec![Device {
+                controllable: true,
+                supported_commands: [ControlCommand::Color].into(),
+                ..Device::new("34:20:03:2e:30:2b".parse().unwrap())
+            }],
+        };
+        let rainbow = Effect::Rainbow {
+            period: ms(60_000),
+            brightness: 100,
+            spread: 0.0,
+        };
+        let player = EffectPlayer::new(client, &devices, rainbow).with_interval(ms(1000));
+        assert_eq!(
+            player.interval(),
+            quota_safe_interval(2, DEFAULT_QUOTA_SHARE)
+        );
+    }
+}
@@ -4,7 +4,7 @@ use derive_builder::Builder;
 use gen_api_wrapper::{endpoint_prelude::Endpoint, params::QueryParams};
 use http::Method;
 
-use crate::models::{ControlCmd, ControlRequest};
+use crate::models::{ControlCmd, ControlRequest, DeviceId, Model};
 
 /// An endpoint for getting the list of devices.
 #[derive(Debug, Clone, Default)]
@@ -30,10 +30,10 @@ impl DevicesEndpoint {
 #[derive(Debug, Clone, Builder)]
 pub struct DeviceControlEndpoint<'a> {
     #[builder(setter(into))]
-    device: Cow<'a, str>,
+    device: Cow<'a, DeviceId>,
 
     #[builder(setter(into))]
-    model: Cow<'a, str>,
+    model: Cow<'a, Model>,
 
     control_cmd: ControlCmd,
 }
@@ -71,10 +71,10 @@ impl<'a> DeviceControlEndpoint<'a> {
 #[derive(Debug, Clone, Builder)]
 pub struct DeviceStateEndpoint<'a> {
     #[builder(setter(into))]
-    device: Cow<'a, str>,
+    device: Cow<'a, DeviceId>,
 
     #[builder(setter(into))]
-    model: Cow<'a, str>,
+    model: Cow<'a, Model>,
 }
 
 impl<'a> Endpoint for DeviceStateEndpoint<'a> {
@@ -86,10 +86,10 @@ impl<'a> Endpoint for DeviceStateEndpoint<'a> {
         "v1/devices/state".into()
     }
 
-    fn parameters(&self) -> QueryParams {
+    fn parameters(&self) -> QueryParams<'_> {
         let mut params = QueryParams::default();
-        params.push("device", &self.device);
-        params.push("model", &self.model);
+        params.push("device", self.device.as_str());
+        params.push("model", self.model.as_str());
         params
     }
 }
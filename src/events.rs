@@ -0,0 +1,264 @@
+//! Detecting device state changes.
+//!
+//! A [Watcher] remembers the last state seen for each device and broadcasts a
+//! [StateEvent] whenever a new state differs from it. States can come from
+//! polling with [Watcher::run], or be recorded directly, such as right after
+//! a control request, so subscribers hear about a change before the API
+//! reflects it.
+//!
+//! # Examples
+//! ```
+//! # #[tokio::main]
+//! # async fn main() {
+//! use govee_rs::{
+//!     events::{Field, Watcher},
+//!     models::{Device, NormalizedState, PowerState},
+//! };
+//!
+//! let watcher = Watcher::new(16);
+//! let mut events = watcher.subscribe();
+//!
+//! let lamp = Device {
+//!     name: "lamp".into(),
+//!     ..Device::new("34:20:03:2e:30:2b".parse().unwrap())
+//! };
+//! let mut state = NormalizedState {
+//!     power: Some(PowerState::Off),
+//!     ..NormalizedState::new(lamp.device.clone())
+//! };
+//! watcher.record(&lamp, state.clone());
+//!
+//! // recording the same state again is not a change
+//! watcher.record(&lamp, state.clone());
+//!
+//! state.power = Some(PowerState::On);
+//! watcher.record(&lamp, state);
+//!
+//! assert_eq!(events.recv().await.unwrap().changed, vec![Field::Power]);
+//! assert_eq!(events.recv().await.unwrap().changed, vec![Field::Power]);
+//! assert!(events.try_recv().is_err());
+//! # }
+//! ```
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::{
+    models::{Device, DeviceId, NormalizedState},
+    monitor::Monitor,
+};
+
+/// A property of a [NormalizedState].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Online,
+    Power,
+    Brightness,
+    Color,
+    ColorTemp,
+}
+
+/// The properties that differ between two states of a device.
+///
+/// Properties that `new` does not report are not counted as changes.
+pub fn diff(old: &NormalizedState, new: &NormalizedState) -> Vec<Field> {
+    fn changed<T: PartialEq>(old: &Option<T>, new: &Option<T>) -> bool {
+        new.is_some() && old != new
+    }
+
+    let mut fields = Vec::new();
+    if changed(&old.online, &new.online) {
+        fields.push(Field::Online);
+    }
+    if changed(&old.power, &new.power) {
+        fields.push(Field::Power);
+    }
+    if changed(&old.brightness, &new.brightness) {
+        fields.push(Field::Brightness);
+    }
+    if changed(&old.color, &new.color) {
+        fields.push(Field::Color);
+    }
+    if changed(&old.color_temp, &new.color_temp) {
+        fields.push(Field::ColorTemp);
+    }
+    fields
+}
+
+/// A change in the state of a device.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct StateEvent {
+    pub device: DeviceId,
+    pub name: String,
+
+    /// What changed. The first state seen for a device reports every
+    /// property it has.
+    pub changed: Vec<Field>,
+
+    /// The device's whole state after the change.
+    pub state: NormalizedState,
+
+    #[serde(with = "unix_millis")]
+    pub at: SystemTime,
+}
+
+mod unix_millis {
+    use std::time::{Duration, SystemTime};
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(at: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        let millis = at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        serializer.serialize_u64(u64::try_from(millis).unwrap_or(u64::MAX))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let millis = u64::deserialize(deserializer)?;
+        Ok(SystemTime::UNIX_EPOCH + Duration::from_millis(millis))
+    }
+}
+
+struct Inner {
+    sender: broadcast::Sender<StateEvent>,
+    last: Mutex<HashMap<DeviceId, NormalizedState>>,
+}
+
+/// Broadcasts [StateEvent]s when device states change.
+///
+/// Cloning is cheap and clones share their state and subscribers.
+#[derive(Clone)]
+pub struct Watcher {
+    inner: Arc<Inner>,
+}
+
+impl Watcher {
+    /// Make a [Watcher] buffering up to `capacity` events for slow
+    /// subscribers, who miss the oldest events beyond that.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        Self {
+            inner: Arc::new(Inner {
+                sender,
+                last: Mutex::default(),
+            }),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<StateEvent> {
+        self.inner.sender.subscribe()
+    }
+
+    /// The last state seen for every device.
+    pub fn states(&self) -> HashMap<DeviceId, NormalizedState> {
+        self.lock().clone()
+    }
+
+    /// The last state seen for `device`.
+    pub fn state(&self, device: &DeviceId) -> Option<NormalizedState> {
+        self.lock().get(device).cloned()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<DeviceId, NormalizedState>> {
+        self.inner.last.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record a state for `device`, broadcasting an event if it changed.
+    ///
+    /// Properties missing from `state` keep their last known value.
+    pub fn record(&self, device: &Device, state: NormalizedState) -> Option<StateEvent> {
+        let mut last = self.lock();
+        let (changed, merged) = match last.get(&device.device) {
+            Some(old) => {
+                let changed = diff(old, &state);
+                let merged = NormalizedState {
+                    online: state.online.or(old.online),
+                    power: state.power.or(old.power),
+                    brightness: state.brightness.or(old.brightness),
+                    color: state.color.or(old.color),
+                    color_temp: state.color_temp.or(old.color_temp),
+                    ..state
+                };
+                (changed, merged)
+            }
+            None => (
+                diff(&NormalizedState::new(state.device.clone()), &state),
+                state,
+            ),
+        };
+
+        if changed.is_empty() {
+            return None;
+        }
+
+        last.insert(device.device.clone(), merged.clone());
+        drop(last);
+
+        let event = StateEvent {
+            device: device.device.clone(),
+            name: device.name.clone(),
+            changed,
+            state: merged,
+            at: SystemTime::now(),
+        };
+        // no subscribers is not an error
+        let _ = self.inner.sender.send(event.clone());
+        Some(event)
+    }
+
+    /// Poll the monitor's devices forever, recording every state.
+    ///
+    /// Failed polls are skipped; the device keeps its last known state.
+    pub async fn run(&self, monitor: &Monitor) {
+        loop {
+            for snapshot in monitor.poll().await {
+                if let Ok(state) = snapshot.state {
+                    self.record(&snapshot.device, state);
+                }
+            }
+            tokio::time::sleep(monitor.interval()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Color, PowerState};
+
+    #[test]
+    fn diff_ignores_missing_properties() {
+        let lamp = Device::new(DeviceId::parse("34:20:03:2e:30:2b").unwrap());
+        let old = NormalizedState {
+            power: Some(PowerState::On),
+            brightness: Some(50),
+            color: Some(Color { r: 1, g: 2, b: 3 }),
+            ..NormalizedState::new(lamp.device.clone())
+        };
+        let new = NormalizedState {
+            power: Some(PowerState::On),
+            brightness: Some(60),
+            ..NormalizedState::new(lamp.device.clone())
+        };
+        assert_eq!(diff(&old, &new), vec![Field::Brightness]);
+
+        let watcher = Watcher::new(4);
+        let first = watcher.record(&lamp, old).unwrap();
+        assert_eq!(
+            first.changed,
+            vec![Field::Power, Field::Brightness, Field::Color]
+        );
+
+        let second = watcher.record(&lamp, new).unwrap();
+        assert_eq!(second.state.color, Some(Color { r: 1, g: 2, b: 3 }));
+        assert_eq!(watcher.states()[&lamp.device].brightness, Some(60));
+    }
+}
@@ -0,0 +1,350 @@
+//! Home Assistant MQTT payloads for Govee lights.
+//!
+//! Each controllable device is announced as an MQTT `light` entity using the
+//! [JSON schema](https://www.home-assistant.io/integrations/light.mqtt/#json-schema).
+//! This module only builds and parses the payloads, leaving the MQTT
+//! connection to the caller.
+//!
+//! # Examples
+//! ```
+//! use govee_rs::{
+//!     homeassistant::{discovery, LightCommand, Topics},
+//!     models::{ControlCommand, Device, PowerState},
+//! };
+//!
+//! let lamp = Device {
+//!     device: "34:20:03:2e:30:2b".parse().unwrap(),
+//!     model: "H6159".into(),
+//!     name: "Desk Lamp".into(),
+//!     controllable: true,
+//!     retrievable: true,
+//!     supported_commands: [ControlCommand::Turn, ControlCommand::Brightness].into(),
+//! };
+//!
+//! let topics = Topics::default();
+//! let (topic, config) = discovery(&lamp, &topics).unwrap();
+//! assert_eq!(topic, "homeassistant/light/govee/3420032e302b/config");
+//! assert_eq!(config.command_topic, "govee/3420032e302b/set");
+//!
+//! let command: LightCommand =
+//!     serde_json::from_str(r#"{"state": "ON", "brightness": 30}"#).unwrap();
+//! let desired = command.desired();
+//! assert_eq!(desired.power, Some(PowerState::On));
+//! assert_eq!(desired.brightness, Some(30));
+//! ```
+use serde::{Deserialize, Serialize};
+
+use crate::models::{
+    Color, ControlCommand, DesiredState, Device, DeviceId, NormalizedState, PowerState,
+    COLOR_TEMP_RANGE,
+};
+
+/// The topic layout used for discovery, state and commands.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Topics {
+    /// The prefix of the bridge's own topics.
+    pub prefix: String,
+
+    /// Home Assistant's discovery prefix.
+    pub discovery_prefix: String,
+}
+
+impl Default for Topics {
+    fn default() -> Self {
+        Self {
+            prefix: "govee".into(),
+            discovery_prefix: "homeassistant".into(),
+        }
+    }
+}
+
+impl Topics {
+    /// Where the bridge reports whether it is online.
+    pub fn availability(&self) -> String {
+        format!("{}/bridge/availability", self.prefix)
+    }
+
+    /// Where a device's state is published.
+    pub fn state(&self, id: &DeviceId) -> String {
+        format!("{}/{}/state", self.prefix, object_id(id))
+    }
+
+    /// Where Home Assistant sends a device's commands.
+    pub fn command(&self, id: &DeviceId) -> String {
+        format!("{}/{}/set", self.prefix, object_id(id))
+    }
+
+    /// A wildcard matching every command topic.
+    pub fn commands(&self) -> String {
+        format!("{}/+/set", self.prefix)
+    }
+
+    /// Where a device's discovery config is published.
+    pub fn discovery(&self, id: &DeviceId) -> String {
+        format!(
+            "{}/light/govee/{}/config",
+            self.discovery_prefix,
+            object_id(id)
+        )
+    }
+
+    /// Where Home Assistant announces that it has (re)started.
+    pub fn homeassistant_status(&self) -> String {
+        format!("{}/status", self.discovery_prefix)
+    }
+
+    /// The object id in a command topic, if `topic` is one.
+    pub fn command_object_id<'a>(&self, topic: &'a str) -> Option<&'a str> {
+        topic
+            .strip_prefix(self.prefix.as_str())?
+            .strip_prefix('/')?
+            .strip_suffix("/set")
+            .filter(|id| !id.is_empty() && !id.contains('/'))
+    }
+}
+
+/// A device id in the form Home Assistant accepts in ids and topics.
+pub fn object_id(id: &DeviceId) -> String {
+    id.as_str().replace(':', "").to_ascii_lowercase()
+}
+
+/// The device registry entry shared by a device's entities.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct DiscoveryDevice {
+    pub identifiers: Vec<String>,
+    pub name: String,
+    pub model: String,
+    pub manufacturer: &'static str,
+}
+
+/// The discovery config for a `light` entity.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct LightConfig {
+    /// Always `None`, so the entity takes the device's name.
+    pub name: Option<String>,
+    pub unique_id: String,
+    pub schema: &'static str,
+    pub command_topic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_topic: Option<String>,
+    pub availability_topic: String,
+    pub brightness: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brightness_scale: Option<u64>,
+    pub supported_color_modes: Vec<&'static str>,
+    pub color_temp_kelvin: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_kelvin: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_kelvin: Option<u64>,
+    pub device: DiscoveryDevice,
+}
+
+/// The discovery topic and config for a device, or `None` if it cannot be
+/// controlled.
+pub fn discovery(device: &Device, topics: &Topics) -> Option<(String, LightConfig)> {
+    if !device.controllable || !device.supports(&ControlCommand::Turn) {
+        return None;
+    }
+
+    let id = object_id(&device.device);
+    let brightness = device.supports(&ControlCommand::Brightness);
+    let color_temp = device.supports(&ControlCommand::ColorTem);
+
+    let mut modes = Vec::new();
+    if device.supports(&ControlCommand::Color) {
+        modes.push("rgb");
+    }
+    if color_temp {
+        modes.push("color_temp");
+    }
+    if modes.is_empty() {
+        modes.push(if brightness { "brightness" } else { "onoff" });
+    }
+
+    let config = LightConfig {
+        name: None,
+        unique_id: format!("govee_{}", id),
+        schema: "json",
+        command_topic: topics.command(&device.device),
+        state_topic: device.retrievable.then(|| topics.state(&device.device)),
+        availability_topic: topics.availability(),
+        brightness,
+        brightness_scale: brightness.then_some(100),
+        supported_color_modes: modes,
+        color_temp_kelvin: true,
+        min_kelvin: color_temp.then_some(COLOR_TEMP_RANGE.0),
+        max_kelvin: color_temp.then_some(COLOR_TEMP_RANGE.1),
+        device: DiscoveryDevice {
+            identifiers: vec![format!("govee_{}", id)],
+            name: device.name.clone(),
+            model: device.model.to_string(),
+            manufacturer: "Govee",
+        },
+    };
+
+    Some((topics.discovery(&device.device), config))
+}
+
+/// `ON` or `OFF`, as Home Assistant spells them.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Switch {
+    On,
+    Off,
+}
+
+impl From<PowerState> for Switch {
+    fn from(value: PowerState) -> Self {
+        match value {
+            PowerState::On => Self::On,
+            PowerState::Off => Self::Off,
+        }
+    }
+}
+
+impl From<Switch> for PowerState {
+    fn from(value: Switch) -> Self {
+        match value {
+            Switch::On => Self::On,
+            Switch::Off => Self::Off,
+        }
+    }
+}
+
+/// A state topic payload.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LightState {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<Switch>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<Color>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_temp: Option<u64>,
+}
+
+impl From<&NormalizedState> for LightState {
+    fn from(value: &NormalizedState) -> Self {
+        // devices report a zero color temperature while showing a color
+        let color_temp = value.color_temp.filter(|t| *t > 0);
+        let color_mode = match (color_temp, value.color) {
+            (Some(_), _) => Some("color_temp"),
+            (None, Some(_)) => Some("rgb"),
+            (None, None) => None,
+        };
+
+        Self {
+            state: value.power.map(Switch::from),
+            brightness: value.brightness,
+            color_mode: color_mode.map(String::from),
+            color: value.color.filter(|_| color_temp.is_none()),
+            color_temp,
+        }
+    }
+}
+
+/// A command topic payload.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightCommand {
+    pub state: Option<Switch>,
+    pub brightness: Option<u64>,
+    pub color: Option<Color>,
+    pub color_temp: Option<u64>,
+}
+
+impl LightCommand {
+    /// The state this command asks for.
+    ///
+    /// Turning off ignores any other fields, since Home Assistant only sends
+    /// them alongside `ON`.
+    pub fn desired(&self) -> DesiredState {
+        if self.state == Some(Switch::Off) {
+            return DesiredState {
+                power: Some(PowerState::Off),
+                ..Default::default()
+            };
+        }
+
+        DesiredState {
+            power: self.state.map(PowerState::from),
+            brightness: self.brightness,
+            color: self.color,
+            color_temp: self.color_temp,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plug_is_onoff_without_kelvin_range() {
+        let plug = Device {
+            device: DeviceId::parse("34:20:03:2E:30:2B").unwrap(),
+            model: "H5081".into(),
+            name: "Plug".into(),
+            controllable: true,
+            retrievable: false,
+            supported_commands: [ControlCommand::Turn].into(),
+        };
+
+        let (_, config) = discovery(&plug, &Topics::default()).unwrap();
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["supported_color_modes"], serde_json::json!(["onoff"]));
+        assert_eq!(json["unique_id"], "govee_3420032e302b");
+        assert_eq!(json["name"], serde_json::Value::Null);
+        assert!(json.get("state_topic").is_none());
+        assert!(json.get("min_kelvin").is_none());
+
+        let uncontrollable = Device {
+            controllable: false,
+            ..plug
+        };
+        assert!(discovery(&uncontrollable, &Topics::default()).is_none());
+    }
+
+    #[test]
+    fn state_and_commands() {
+        let topics = Topics::default();
+        assert_eq!(
+            topics.command_object_id("govee/3420032e302b/set"),
+            Some("3420032e302b")
+        );
+        assert_eq!(topics.command_object_id("govee/bridge/availability"), None);
+        assert_eq!(topics.command_object_id("govee//set"), None);
+        assert_eq!(topics.command_object_id("other/3420032e302b/set"), None);
+
+        let state = LightState::from(&NormalizedState {
+            power: Some(PowerState::On),
+            brightness: Some(40),
+            color: Some(Color { r: 1, g: 2, b: 3 }),
+            color_temp: Some(0),
+            ..NormalizedState::new(DeviceId::parse("34:20:03:2e:30:2b").unwrap())
+        });
+        assert_eq!(
+            serde_json::to_value(&state).unwrap(),
+            serde_json::json!({
+                "state": "ON",
+                "brightness": 40,
+                "color_mode": "rgb",
+                "color": {"r": 1, "g": 2, "b": 3}
+            })
+        );
+
+        let off: LightCommand =
+            serde_json::from_str(r#"{"state": "OFF", "brightness": 10}"#).unwrap();
+        assert_eq!(
+            off.desired(),
+            DesiredState {
+                power: Some(PowerState::Off),
+                ..Default::default()
+            }
+        );
+    }
+}
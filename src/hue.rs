@@ -0,0 +1,459 @@
+//! Presenting Govee devices as Philips Hue lights.
+//!
+//! This module holds the Hue API's data model and the conversions between
+//! Hue and Govee units, leaving the HTTP server to the caller:
+//!
+//! - brightness: Hue's `bri` runs from 1 to 254, Govee's from 0 to 100.
+//! - color temperature: Hue's `ct` is in mireds, Govee's in kelvin.
+//! - color: Hue sends `hue`/`sat` or CIE `xy` coordinates, Govee takes RGB.
+//!
+//! # Examples
+//! ```
+//! use govee_rs::{hue::{self, StateChange}, models::PowerState, Color};
+//!
+//! assert_eq!(hue::bri_to_brightness(254), 100);
+//! assert_eq!(hue::brightness_to_bri(50), 127);
+//! assert_eq!(hue::mired_to_kelvin(250), 4000);
+//!
+//! let change: StateChange = serde_json::from_str(r#"{"on": true, "hue": 0, "sat": 254}"#).unwrap();
+//! let desired = change.desired(None);
+//! assert_eq!(desired.power, Some(PowerState::On));
+//! assert_eq!(desired.color, Some(Color { r: 255, g: 0, b: 0 }));
+//! ```
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::models::{
+    Color, ControlCommand, DesiredState, Device, NormalizedState, PowerState, COLOR_TEMP_RANGE,
+};
+
+/// The largest Hue brightness.
+pub const MAX_BRI: u8 = 254;
+
+/// The largest Hue saturation.
+pub const MAX_SAT: u8 = 254;
+
+/// The coolest color temperature advertised, in mireds.
+pub const MIN_MIRED: u16 = (1_000_000 / COLOR_TEMP_RANGE.1) as u16;
+
+/// The warmest color temperature advertised, in mireds.
+pub const MAX_MIRED: u16 = (1_000_000 / COLOR_TEMP_RANGE.0) as u16;
+
+/// Hue brightness (1-254) to Govee brightness (1-100).
+pub fn bri_to_brightness(bri: u8) -> u64 {
+    let scaled = (f64::from(bri) * 100.0 / f64::from(MAX_BRI)).round() as u64;
+    scaled.clamp(1, 100)
+}
+
+/// Govee brightness (0-100) to Hue brightness (1-254).
+pub fn brightness_to_bri(brightness: u64) -> u8 {
+    let scaled = (brightness.min(100) as f64 * f64::from(MAX_BRI) / 100.0).round() as u8;
+    scaled.max(1)
+}
+
+/// A color temperature in mireds to kelvin, within [COLOR_TEMP_RANGE].
+pub fn mired_to_kelvin(mired: u16) -> u64 {
+    let kelvin = (1_000_000.0 / f64::from(mired.max(1))).round() as u64;
+    kelvin.clamp(COLOR_TEMP_RANGE.0, COLOR_TEMP_RANGE.1)
+}
+
+/// A color temperature in kelvin to mireds, within the advertised range.
+pub fn kelvin_to_mired(kelvin: u64) -> u16 {
+    let mired = (1_000_000.0 / kelvin.max(1) as f64).round() as u64;
+    mired.clamp(u64::from(MIN_MIRED), u64::from(MAX_MIRED)) as u16
+}
+
+/// A Hue hue (0-65535) and saturation (0-254) to a fully bright color.
+pub fn hs_to_color(hue: u16, sat: u8) -> Color {
+    let h = f64::from(hue) / 65535.0 * 6.0;
+    let s = f64::from(sat.min(MAX_SAT)) / f64::from(MAX_SAT);
+
+    let sector = h.floor();
+    let f = h - sector;
+    let (p, q, t) = (1.0 - s, 1.0 - s * f, 1.0 - s * (1.0 - f));
+    let (r, g, b) = match sector as u8 % 6 {
+        0 => (1.0, t, p),
+        1 => (q, 1.0, p),
+        2 => (p, 1.0, t),
+        3 => (p, q, 1.0),
+        4 => (t, p, 1.0),
+        _ => (1.0, p, q),
+    };
+    Color {
+        r: to_u8(r),
+        g: to_u8(g),
+        b: to_u8(b),
+    }
+}
+
+/// A color to a Hue hue (0-65535) and saturation (0-254), ignoring its
+/// brightness.
+pub fn color_to_hs(color: Color) -> (u16, u8) {
+    let (r, g, b) = unit(color);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    if max == 0.0 || delta == 0.0 {
+        return (0, 0);
+    }
+
+    let h = if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    let hue = (h / 6.0 * 65535.0).round() as u16;
+    let sat = (delta / max * f64::from(MAX_SAT)).round() as u8;
+    (hue, sat)
+}
+
+/// CIE xy coordinates to a fully bright color, using the wide gamut
+/// conversion from the Hue documentation.
+pub fn xy_to_color(x: f64, y: f64) -> Color {
+    let y = y.max(f64::EPSILON);
+    let (cx, cy, cz) = (x / y, 1.0, (1.0 - x - y) / y);
+
+    let r = cx * 1.656492 - cy * 0.354851 - cz * 0.255038;
+    let g = -cx * 0.707196 + cy * 1.655397 + cz * 0.036152;
+    let b = cx * 0.051713 - cy * 0.121364 + cz * 1.011530;
+
+    let gamma = |v: f64| {
+        let v = v.max(0.0);
+        if v <= 0.0031308 {
+            12.92 * v
+        } else {
+            1.055 * v.powf(1.0 / 2.4) - 0.055
+        }
+    };
+    let (r, g, b) = (gamma(r), gamma(g), gamma(b));
+
+    // brightness is set separately, so scale to the brightest color
+    let max = r.max(g).max(b);
+    if max <= 0.0 {
+        return Color::default();
+    }
+    Color {
+        r: to_u8(r / max),
+        g: to_u8(g / max),
+        b: to_u8(b / max),
+    }
+}
+
+/// A color to CIE xy coordinates.
+pub fn color_to_xy(color: Color) -> [f64; 2] {
+    let linear = |v: f64| {
+        if v > 0.04045 {
+            ((v + 0.055) / 1.055).powf(2.4)
+        } else {
+            v / 12.92
+        }
+    };
+    let (r, g, b) = unit(color);
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+
+    let x = r * 0.664511 + g * 0.154324 + b * 0.162028;
+    let y = r * 0.283881 + g * 0.668433 + b * 0.047685;
+    let z = r * 0.000088 + g * 0.072310 + b * 0.986039;
+    let sum = x + y + z;
+    if sum == 0.0 {
+        // white point for black
+        return [0.3127, 0.329];
+    }
+
+    let round = |v: f64| (v * 10_000.0).round() / 10_000.0;
+    [round(x / sum), round(y / sum)]
+}
+
+fn unit(color: Color) -> (f64, f64, f64) {
+    (
+        f64::from(color.r) / 255.0,
+        f64::from(color.g) / 255.0,
+        f64::from(color.b) / 255.0,
+    )
+}
+
+fn to_u8(v: f64) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// How a Hue light's color was last set.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    Hs,
+    Xy,
+    Ct,
+}
+
+/// A Hue light's `state` object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LightState {
+    pub on: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bri: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hue: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sat: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xy: Option<[f64; 2]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ct: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub colormode: Option<ColorMode>,
+    pub alert: String,
+    pub reachable: bool,
+}
+
+/// A Hue light, as returned by `GET /api/<user>/lights/<id>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Light {
+    pub state: LightState,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub name: String,
+    pub modelid: String,
+    pub manufacturername: String,
+    pub productname: String,
+    pub uniqueid: String,
+    pub swversion: String,
+}
+
+impl Light {
+    /// Present `device` as a Hue light, in `state` if it is known.
+    pub fn new(device: &Device, state: Option<&NormalizedState>) -> Self {
+        let color = device.supports(&ControlCommand::Color);
+        let color_temp = device.supports(&ControlCommand::ColorTem);
+        let dimmable = device.supports(&ControlCommand::Brightness);
+
+        let kind = match (color, color_temp, dimmable) {
+            (true, true, _) => "Extended color light",
+            (true, false, _) => "Color light",
+            (false, true, _) => "Color temperature light",
+            (false, false, true) => "Dimmable light",
+            (false, false, false) => "On/Off plug-in unit",
+        };
+
+        // a zero color temperature means the device is showing a color
+        let kelvin = state.and_then(|s| s.color_temp).filter(|t| *t > 0);
+        let rgb = state.and_then(|s| s.color);
+        let (hs, xy) = match rgb.filter(|_| color) {
+            Some(rgb) => (Some(color_to_hs(rgb)), Some(color_to_xy(rgb))),
+            None if color => (Some((0, 0)), Some(color_to_xy(Color::default()))),
+            None => (None, None),
+        };
+        let colormode = match (kelvin, color, color_temp) {
+            (Some(_), _, true) => Some(ColorMode::Ct),
+            (_, true, _) => Some(ColorMode::Xy),
+            (_, false, true) => Some(ColorMode::Ct),
+            _ => None,
+        };
+
+        let state = LightState {
+            on: state.and_then(|s| s.power) == Some(PowerState::On),
+            bri: dimmable.then(|| {
+                state
+                    .and_then(|s| s.brightness)
+                    .map_or(MAX_BRI, brightness_to_bri)
+            }),
+            hue: hs.map(|(h, _)| h),
+            sat: hs.map(|(_, s)| s),
+            xy,
+            ct: color_temp.then(|| kelvin.map_or(MAX_MIRED, kelvin_to_mired)),
+            colormode,
+            alert: "none".into(),
+            reachable: state.and_then(|s| s.online).unwrap_or(true),
+        };
+
+        Self {
+            state,
+            kind: kind.into(),
+            name: device.name.clone(),
+            modelid: device.model.to_string(),
+            manufacturername: "Govee".into(),
+            productname: device.model.to_string(),
+            uniqueid: format!("{}-0b", device.device),
+            swversion: "1.0".into(),
+        }
+    }
+}
+
+/// The body of `PUT /api/<user>/lights/<id>/state`.
+///
+/// When several color fields are given, `xy` wins over `ct`, which wins over
+/// `hue`/`sat`, as on a real bridge. Fields the emulator cannot act on, like
+/// `transitiontime`, are accepted and ignored.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StateChange {
+    pub on: Option<bool>,
+    pub bri: Option<u8>,
+    pub hue: Option<u16>,
+    pub sat: Option<u8>,
+    pub xy: Option<[f64; 2]>,
+    pub ct: Option<u16>,
+    pub transitiontime: Option<u16>,
+}
+
+impl StateChange {
+    /// The Govee state this change asks for.
+    ///
+    /// A `hue` without a `sat` (or the other way around) takes the missing
+    /// half from `current`, the color the light is showing.
+    pub fn desired(&self, current: Option<Color>) -> DesiredState {
+        let mut desired = DesiredState {
+            power: self
+                .on
+                .map(|on| if on { PowerState::On } else { PowerState::Off }),
+            brightness: self.bri.map(bri_to_brightness),
+            ..Default::default()
+        };
+
+        if let Some([x, y]) = self.xy {
+            desired.color = Some(xy_to_color(x, y));
+        } else if let Some(ct) = self.ct {
+            desired.color_temp = Some(mired_to_kelvin(ct));
+        } else if self.hue.is_some() || self.sat.is_some() {
+            let (hue, sat) = current.map(color_to_hs).unwrap_or((0, MAX_SAT));
+            desired.color = Some(hs_to_color(
+                self.hue.unwrap_or(hue),
+                self.sat.unwrap_or(sat),
+            ));
+        }
+
+        desired
+    }
+
+    /// The Hue response to a successful change of light `id`.
+    pub fn success(&self, id: &str) -> Value {
+        let address = |field: &str| format!("/lights/{}/state/{}", id, field);
+        let mut results = Vec::new();
+        let mut push = |field: &str, value: Value| {
+            results.push(json!({ "success": { address(field): value } }));
+        };
+
+        if let Some(on) = self.on {
+            push("on", json!(on));
+        }
+        if let Some(bri) = self.bri {
+            push("bri", json!(bri));
+        }
+        if let Some(hue) = self.hue {
+            push("hue", json!(hue));
+        }
+        if let Some(sat) = self.sat {
+            push("sat", json!(sat));
+        }
+        if let Some(xy) = self.xy {
+            push("xy", json!(xy));
+        }
+        if let Some(ct) = self.ct {
+            push("ct", json!(ct));
+        }
+        if let Some(t) = self.transitiontime {
+            push("transitiontime", json!(t));
+        }
+
+        Value::Array(results)
+    }
+}
+
+/// Hue API error types.
+pub mod error_type {
+    pub const UNAUTHORIZED_USER: u16 = 1;
+    pub const INVALID_JSON: u16 = 2;
+    pub const RESOURCE_NOT_AVAILABLE: u16 = 3;
+    pub const PARAMETER_NOT_AVAILABLE: u16 = 6;
+    pub const DEVICE_UNREACHABLE: u16 = 201;
+    pub const INTERNAL_ERROR: u16 = 901;
+}
+
+/// A Hue error response. Hue reports errors with a `200 OK` status.
+pub fn error(kind: u16, address: &str, description: &str) -> Value {
+    json!([{
+        "error": {
+            "type": kind,
+            "address": address,
+            "description": description,
+        }
+    }])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions_round_trip() {
+        for brightness in [1, 25, 50, 99, 100] {
+            assert_eq!(bri_to_brightness(brightness_to_bri(brightness)), brightness);
+        }
+        assert_eq!(bri_to_brightness(0), 1);
+        assert_eq!(brightness_to_bri(0), 1);
+
+        assert_eq!(mired_to_kelvin(153), 6536);
+        assert_eq!(mired_to_kelvin(1000), COLOR_TEMP_RANGE.0);
+        assert_eq!(kelvin_to_mired(2700), 370);
+        assert_eq!(kelvin_to_mired(20000), MIN_MIRED);
+
+        for color in [
+            Color { r: 255, g: 0, b: 0 },
+            Color { r: 0, g: 255, b: 0 },
+            Color { r: 0, g: 0, b: 255 },
+            Color {
+                r: 255,
+                g: 165,
+                b: 0,
+            },
+        ] {
+            let (h, s) = color_to_hs(color);
+            assert_eq!(hs_to_color(h, s), color);
+
+            let [x, y] = color_to_xy(color);
+            let back = xy_to_color(x, y);
+            for (a, b) in [(back.r, color.r), (back.g, color.g), (back.b, color.b)] {
+                assert!(a.abs_diff(b) <= 2, "{:?} became {:?}", color, back);
+            }
+        }
+    }
+
+    #[test]
+    fn state_change_priority() {
+        let change = StateChange {
+            xy: Some([0.7, 0.3]),
+            ct: Some(300),
+            hue: Some(20000),
+            ..Default::default()
+        };
+        let desired = change.desired(None);
+        assert!(desired.color.is_some());
+        assert_eq!(desired.color_temp, None);
+
+        // a lone sat keeps the current hue
+        let change = StateChange {
+            sat: Some(0),
+            ..Default::default()
+        };
+        let desired = change.desired(Some(Color { r: 0, g: 0, b: 255 }));
+        assert_eq!(
+            desired.color,
+            Some(Color {
+                r: 255,
+                g: 255,
+                b: 255
+            })
+        );
+
+        let success = StateChange {
+            on: Some(false),
+            ..Default::default()
+        }
+        .success("3");
+        assert_eq!(
+            success,
+            json!([{ "success": { "/lights/3/state/on": false } }])
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Collecting the control requests a dry-run client would have sent.
+//!
+//! A client made with [GoveeClient::with_dry_run] sends reads as usual, to
+//! the API or a [cassette](crate::cassette), but anything else, like a
+//! [DeviceControlEndpoint] request, is answered with a made-up success and
+//! written to a [Journal] instead. This shows exactly what an automation
+//! would do before it is let loose on real devices.
+//!
+//! # Examples
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use govee_rs::{journal::Journal, models::PowerState, GoveeClient, DEFAULT_API_URL};
+//!
+//! let journal = Journal::new();
+//! let client = GoveeClient::new(DEFAULT_API_URL, "my-api-key")?.with_dry_run(journal.clone());
+//!
+//! for device in client.devices().await?.iter() {
+//!     client.turn(device, PowerState::Off).await?;
+//! }
+//!
+//! for entry in journal.entries() {
+//!     println!("{}", entry);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [GoveeClient::with_dry_run]: crate::GoveeClient::with_dry_run
+//! [DeviceControlEndpoint]: crate::endpoints::DeviceControlEndpoint
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+use http::Method;
+use serde_json::Value;
+
+use crate::models::ControlRequest;
+
+/// A request a dry-run client did not send.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    pub method: Method,
+
+    /// The path of the endpoint, without the query string.
+    pub path: String,
+
+    /// The request body, or `null` if it was not JSON.
+    pub body: Value,
+    pub at: SystemTime,
+}
+
+impl JournalEntry {
+    /// The body as a control request, if it is one.
+    pub fn control(&self) -> Option<ControlRequest<'static>> {
+        serde_json::from_value(self.body.clone()).ok()
+    }
+}
+
+impl fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.method, self.path, self.body)
+    }
+}
+
+/// The requests a dry-run client did not send, in order.
+///
+/// Clones share their entries, so keep one to inspect what a client given
+/// another did.
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    entries: Arc<Mutex<Vec<JournalEntry>>>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<JournalEntry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn push(&self, entry: JournalEntry) {
+        self.lock().push(entry);
+    }
+
+    pub fn entries(&self) -> Vec<JournalEntry> {
+        self.lock().clone()
+    }
+
+    /// Every entry that is a control request.
+    pub fn controls(&self) -> Vec<ControlRequest<'static>> {
+        self.lock()
+            .iter()
+            .filter_map(JournalEntry::control)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Remove and return every entry.
+    pub fn take(&self) -> Vec<JournalEntry> {
+        std::mem::take(&mut *self.lock())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use mockito::Server;
+
+    use super::*;
+    use crate::{
+        models::{ControlCmd, ControlCommand, Device, DeviceId, PowerState},
+        GoveeClient,
+    };
+
+    #[tokio::test]
+    async fn controls_are_journaled_not_sent() {
+        let mut server = Server::new_async().await;
+        let control = server
+            .mock("PUT", "/v1/devices/control?")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let journal = Journal::new();
+        let client = GoveeClient::new(&server.url(), "key")
+            .unwrap()
+            .with_dry_run(journal.clone());
+        let lamp = Device {
+            model: "H6159".into(),
+            supported_commands: HashSet::from([ControlCommand::Turn]),
+            ..Device::new(DeviceId::parse("34:20:03:2e:30:2b").unwrap())
+        };
+
+        client.turn(&lamp, PowerState::On).await.unwrap();
+        client.brightness(&lamp, 30).await.unwrap();
+        control.assert_async().await;
+
+        let controls = journal.controls();
+        assert_eq!(controls.len(), 2);
+        assert_eq!(controls[0].cmd, ControlCmd::Turn(PowerState::On));
+        assert_eq!(*controls[1].device, lamp.device);
+
+        let entries = journal.take();
+        assert_eq!(entries[1].path, "/v1/devices/control");
+        assert!(entries[1]
+            .to_string()
+            .starts_with("PUT /v1/devices/control {"));
+        assert!(journal.is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+//! Messages in the Govee LAN protocol.
+//!
+//! Devices with the LAN API enabled in the Govee Home app answer multicast
+//! `scan` requests sent to [MULTICAST_ADDR] on [SCAN_PORT], take commands on
+//! [COMMAND_PORT], and send their replies to [REPLY_PORT]. Every message is a
+//! small JSON document; [LanMessage] encodes and decodes them.
+//!
+//! # Examples
+//! ```
+//! use govee_rs::{lan::LanMessage, Color};
+//!
+//! let message = LanMessage::Colorwc {
+//!     color: Color { r: 255, g: 0, b: 0 },
+//!     color_temp: 0,
+//! };
+//! let bytes = message.encode();
+//! assert_eq!(
+//!     std::str::from_utf8(&bytes).unwrap(),
+//!     r#"{"msg":{"cmd":"colorwc","data":{"color":{"r":255,"g":0,"b":0},"colorTemInKelvin":0}}}"#
+//! );
+//! assert_eq!(LanMessage::decode(&bytes).unwrap(), message);
+//! ```
+use std::net::Ipv4Addr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Color, ControlCmd, DeviceId, Model, PowerState};
+
+/// The multicast group devices listen on for `scan` requests.
+pub const MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+
+/// The port devices listen on for `scan` requests.
+pub const SCAN_PORT: u16 = 4001;
+
+/// The port devices send their replies to.
+pub const REPLY_PORT: u16 = 4002;
+
+/// The port devices listen on for commands.
+pub const COMMAND_PORT: u16 = 4003;
+
+/// A message in the Govee LAN protocol.
+///
+/// On the wire this is wrapped as `{"msg": {"cmd": ..., "data": ...}}`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "cmd", content = "data", rename_all = "camelCase")]
+pub enum LanMessage {
+    /// A discovery request, or a device's reply to one.
+    Scan(#[serde(default)] ScanData),
+    /// Power off with `0` or on with `1`.
+    Turn { value: u8 },
+    /// Brightness from 1 to 100.
+    Brightness { value: u64 },
+    /// A color, or a color temperature if `color_temp` is not zero.
+    Colorwc {
+        #[serde(default)]
+        color: Color,
+        #[serde(default, rename = "colorTemInKelvin")]
+        color_temp: u64,
+    },
+    /// A status request, or a device's reply to one.
+    DevStatus(#[serde(default)] DevStatus),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    msg: LanMessage,
+}
+
+impl LanMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(&Envelope { msg: self.clone() }).expect("messages always serialize")
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_slice::<Envelope>(bytes).map(|e| e.msg)
+    }
+}
+
+/// The data of a `scan` message. Requests only set `account_topic`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ScanData {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_topic: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+
+    /// Where the simulated device listens. Real devices always use 4003.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<DeviceId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sku: Option<Model>,
+}
+
+/// The data of a `devStatus` message. Requests leave every field unset.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DevStatus {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_off: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<Color>,
+
+    /// Zero while the device shows a color.
+    #[serde(
+        default,
+        rename = "colorTemInKelvin",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub color_temp: Option<u64>,
+}
+
+impl From<ControlCmd> for LanMessage {
+    fn from(cmd: ControlCmd) -> Self {
+        match cmd {
+            ControlCmd::Turn(state) => Self::Turn {
+                value: u8::from(state == PowerState::On),
+            },
+            ControlCmd::Brightness(value) => Self::Brightness { value },
+            ControlCmd::Color(color) => Self::Colorwc {
+                color,
+                color_temp: 0,
+            },
+            ControlCmd::ColorTem(color_temp) => Self::Colorwc {
+                color: Color::default(),
+                color_temp,
+            },
+        }
+    }
+}
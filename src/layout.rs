@@ -0,0 +1,648 @@
+//! A shared description of a home (or office): rooms, groups, aliases and
+//! per-device defaults.
+//!
+//! A [Layout] is usually written once as a TOML or YAML file and loaded with
+//! [Layout::load]. Resolving it against the [Devices] reported by
+//! [GoveeClient::devices](crate::GoveeClient::devices) produces a [Home],
+//! which turns human names into devices.
+//!
+//! ```toml
+//! [devices.desk-lamp]
+//! id = "34:20:03:2e:30:2b"
+//! model = "H6159"
+//! aliases = ["desk"]
+//! room = "office"
+//!
+//! [devices.desk-lamp.defaults]
+//! brightness = 80
+//! color = "#ff8800"
+//!
+//! [rooms.office]
+//! name = "Main Office"
+//!
+//! [groups.evening]
+//! members = ["desk", "kitchen"]
+//! ```
+//!
+//! Group members may name devices (by key or alias) or rooms.
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[cfg(feature = "config")]
+use crate::config::{self, ConfigError};
+use crate::{
+    lookup::{normalize, Aliases, LookupError, Resolve},
+    models::{color_or_hex, Color, Device, DeviceId, Devices, Model, PowerState},
+};
+
+#[derive(Debug, Error)]
+pub enum LayoutError {
+    #[cfg(feature = "config")]
+    #[error("failed to load layout: {}", source)]
+    Config {
+        #[from]
+        source: ConfigError,
+    },
+    #[error("invalid layout: {}", ProblemList(problems))]
+    Invalid { problems: Vec<LayoutProblem> },
+}
+
+/// A single inconsistency found while validating a [Layout].
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+pub enum LayoutProblem {
+    #[error("device {:?} ({}) is not in the device listing", name, id)]
+    UnknownDevice { name: String, id: DeviceId },
+    #[error(
+        "device {:?} is configured as model {} but reports {}",
+        name,
+        expected,
+        actual
+    )]
+    ModelMismatch {
+        name: String,
+        expected: Model,
+        actual: Model,
+    },
+    #[error("{:?} is used by both {:?} and {:?}", alias, first, second)]
+    DuplicateAlias {
+        alias: String,
+        first: String,
+        second: String,
+    },
+    #[error("devices {:?} and {:?} share the id {}", first, second, id)]
+    DuplicateDevice {
+        id: DeviceId,
+        first: String,
+        second: String,
+    },
+    #[error("device {:?} is in unknown room {:?}", device, room)]
+    UnknownRoom { device: String, room: String },
+    #[error("group {:?} has unknown member {:?}", group, member)]
+    UnknownMember { group: String, member: String },
+}
+
+struct ProblemList<'a>(&'a [LayoutProblem]);
+
+impl<'a> fmt::Display for ProblemList<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, p) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}", p)?;
+        }
+        Ok(())
+    }
+}
+
+/// The settings a device should have when it is "reset" by a tool.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceDefaults {
+    pub power: Option<PowerState>,
+    pub brightness: Option<u64>,
+    #[serde(deserialize_with = "color_or_hex")]
+    pub color: Option<Color>,
+    pub color_temp: Option<u64>,
+}
+
+/// A device entry in a [Layout].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceEntry {
+    pub id: DeviceId,
+
+    /// If set, the model the device must report.
+    #[serde(default)]
+    pub model: Option<Model>,
+
+    #[serde(default)]
+    pub aliases: Vec<String>,
+
+    #[serde(default)]
+    pub room: Option<String>,
+
+    #[serde(default)]
+    pub defaults: DeviceDefaults,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Room {
+    /// A display name, defaulting to the room's key.
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Group {
+    /// Device keys, device aliases or room keys.
+    pub members: Vec<String>,
+}
+
+/// The contents of a layout file.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Layout {
+    pub devices: BTreeMap<String, DeviceEntry>,
+    pub rooms: BTreeMap<String, Room>,
+    pub groups: BTreeMap<String, Group>,
+}
+
+impl Layout {
+    /// Load a layout from a `.toml`, `.yaml` or `.yml` file.
+    #[cfg(feature = "config")]
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, LayoutError> {
+        let layout: Self = config::load(path.as_ref())?;
+        layout.validate()?;
+        Ok(layout)
+    }
+
+    /// Parse and validate a TOML layout.
+    #[cfg(feature = "config")]
+    pub fn from_toml(raw: &str) -> Result<Self, LayoutError> {
+        let layout: Self = config::from_toml(raw)?;
+        layout.validate()?;
+        Ok(layout)
+    }
+
+    /// Parse and validate a YAML layout.
+    #[cfg(feature = "config")]
+    pub fn from_yaml(raw: &str) -> Result<Self, LayoutError> {
+        let layout: Self = config::from_yaml(raw)?;
+        layout.validate()?;
+        Ok(layout)
+    }
+
+    /// Check the layout for internal consistency.
+    ///
+    /// This catches names that are used more than once, devices in undefined
+    /// rooms and group members that don't refer to anything. It does not need
+    /// a device listing; see [Layout::resolve] for that.
+    pub fn validate(&self) -> Result<(), LayoutError> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(LayoutError::Invalid { problems })
+        }
+    }
+
+    /// Validate the layout against a device listing and build a [Home].
+    pub fn resolve(self, devices: Devices) -> Result<Home, LayoutError> {
+        let mut problems = self.problems();
+
+        for (name, entry) in self.devices.iter() {
+            match devices.by_id(&entry.id) {
+                None => problems.push(LayoutProblem::UnknownDevice {
+                    name: name.clone(),
+                    id: entry.id.clone(),
+                }),
+                Some(device) => {
+                    if let Some(ref expected) = entry.model {
+                        if !expected
+                            .as_str()
+                            .eq_ignore_ascii_case(device.model.as_str())
+                        {
+                            problems.push(LayoutProblem::ModelMismatch {
+                                name: name.clone(),
+                                expected: expected.clone(),
+                                actual: device.model.clone(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(LayoutError::Invalid { problems });
+        }
+
+        Ok(Home::new(self, devices))
+    }
+
+    /// The aliases defined by this layout, including each device's key.
+    pub fn aliases(&self) -> Aliases {
+        let mut aliases = Aliases::default();
+        for (name, entry) in self.devices.iter() {
+            aliases.insert(name, entry.id.clone());
+            for alias in entry.aliases.iter() {
+                aliases.insert(alias, entry.id.clone());
+            }
+        }
+        aliases
+    }
+
+    fn problems(&self) -> Vec<LayoutProblem> {
+        let mut problems = Vec::new();
+
+        // every name a human might type, and who owns it
+        let mut owners: HashMap<String, String> = HashMap::new();
+        let mut claim = |alias: &str, owner: String, problems: &mut Vec<LayoutProblem>| {
+            let key = normalize(alias);
+            if let Some(first) = owners.get(&key) {
+                if *first != owner {
+                    problems.push(LayoutProblem::DuplicateAlias {
+                        alias: alias.to_string(),
+                        first: first.clone(),
+                        second: owner,
+                    });
+                }
+            } else {
+                owners.insert(key, owner);
+            }
+        };
+
+        for (name, entry) in self.devices.iter() {
+            let owner = format!("device {}", name);
+            claim(name, owner.clone(), &mut problems);
+            for alias in entry.aliases.iter() {
+                claim(alias, owner.clone(), &mut problems);
+            }
+        }
+
+        for name in self.rooms.keys() {
+            claim(name, format!("room {}", name), &mut problems);
+        }
+
+        for name in self.groups.keys() {
+            claim(name, format!("group {}", name), &mut problems);
+        }
+
+        let mut ids: HashMap<&DeviceId, &String> = HashMap::new();
+        for (name, entry) in self.devices.iter() {
+            if let Some(first) = ids.insert(&entry.id, name) {
+                problems.push(LayoutProblem::DuplicateDevice {
+                    id: entry.id.clone(),
+                    first: first.clone(),
+                    second: name.clone(),
+                });
+            }
+
+            if let Some(ref room) = entry.room {
+                if !self.rooms.contains_key(room) {
+                    problems.push(LayoutProblem::UnknownRoom {
+                        device: name.clone(),
+                        room: room.clone(),
+                    });
+                }
+            }
+        }
+
+        let aliases = self.aliases();
+        for (name, group) in self.groups.iter() {
+            for member in group.members.iter() {
+                if aliases.get(member).is_none() && !self.rooms.contains_key(member) {
+                    problems.push(LayoutProblem::UnknownMember {
+                        group: name.clone(),
+                        member: member.clone(),
+                    });
+                }
+            }
+        }
+
+        problems
+    }
+}
+
+/// A [Layout] resolved against a device listing.
+///
+/// # Examples
+/// ```
+/// use govee_rs::{
+///     layout::{DeviceEntry, Layout, Room},
+///     models::{Device, Devices},
+/// };
+///
+/// let id = "34:20:03:2e:30:2b".parse().unwrap();
+/// let devices = Devices {
+///     devices: vec![Device {
+///         name: "H6159_302B".into(),
+///         ..Device::new("34:20:03:2e:30:2b".parse().unwrap())
+///     }],
+/// };
+///
+/// let mut layout = Layout::default();
+/// layout.rooms.insert("office".into(), Room::default());
+/// layout.devices.insert(
+///     "desk-lamp".into(),
+///     DeviceEntry {
+///         id,
+///         model: None,
+///         aliases: vec!["desk".into()],
+///         room: Some("office".into()),
+///         defaults: Default::default(),
+///     },
+/// );
+///
+/// let home = layout.resolve(devices).unwrap();
+/// assert_eq!(home.device("desk").unwrap().name, "H6159_302B");
+/// assert_eq!(home.resolve("office").unwrap().len(), 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Home {
+    layout: Layout,
+    devices: Devices,
+    aliases: Aliases,
+}
+
+impl Home {
+    fn new(layout: Layout, devices: Devices) -> Self {
+        let aliases = layout.aliases();
+        Self {
+            layout,
+            devices,
+            aliases,
+        }
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    /// Every device in the listing, including those not in the layout.
+    pub fn devices(&self) -> &Devices {
+        &self.devices
+    }
+
+    pub fn aliases(&self) -> &Aliases {
+        &self.aliases
+    }
+
+    /// Find a single device by layout key, alias, id or name.
+    pub fn device(&self, name: &str) -> Result<&Device, LookupError> {
+        self.devices.find_with_aliases(name, &self.aliases)
+    }
+
+    /// The devices in the given room, if the room exists.
+    pub fn room(&self, name: &str) -> Option<Devices> {
+        let key = self.key_of(self.layout.rooms.keys(), name)?;
+        let devices = self
+            .layout
+            .devices
+            .values()
+            .filter(|e| e.room.as_deref() == Some(key))
+            .filter_map(|e| self.devices.by_id(&e.id))
+            .cloned()
+            .collect();
+
+        Some(Devices { devices })
+    }
+
+    /// The devices in the given group, if the group exists.
+    pub fn group(&self, name: &str) -> Option<Devices> {
+        let key = self.key_of(self.layout.groups.keys(), name)?;
+        let mut devices: Vec<Device> = Vec::new();
+        for member in self.layout.groups[key].members.iter() {
+            let found = match self.aliases.get(member) {
+                Some(id) => self.devices.by_id(id).cloned().into_iter().collect(),
+                None => self.room(member).map(|r| r.devices).unwrap_or_default(),
+            };
+
+            for device in found {
+                if !devices.iter().any(|d| d.device == device.device) {
+                    devices.push(device);
+                }
+            }
+        }
+
+        Some(Devices { devices })
+    }
+
+    /// Resolve a name that may refer to a group, a room or a single device.
+    ///
+    /// Groups are checked first, then rooms, then devices.
+    pub fn resolve(&self, name: &str) -> Result<Devices, LookupError> {
+        if let Some(devices) = self.group(name) {
+            return Ok(devices);
+        }
+
+        if let Some(devices) = self.room(name) {
+            return Ok(devices);
+        }
+
+        Ok(Devices {
+            devices: vec![self.device(name)?.clone()],
+        })
+    }
+
+    /// The layout key for the given device, if it is in the layout.
+    pub fn name_of(&self, id: &DeviceId) -> Option<&str> {
+        self.layout
+            .devices
+            .iter()
+            .find(|(_, e)| &e.id == id)
+            .map(|(k, _)| k.as_str())
+    }
+
+    /// The configured defaults for the given device.
+    pub fn defaults(&self, id: &DeviceId) -> Option<&DeviceDefaults> {
+        self.layout
+            .devices
+            .values()
+            .find(|e| &e.id == id)
+            .map(|e| &e.defaults)
+    }
+
+    fn key_of<'a, I>(&self, mut keys: I, name: &str) -> Option<&'a str>
+    where
+        I: Iterator<Item = &'a String>,
+    {
+        let name = normalize(name);
+        keys.find(|k| normalize(k) == name).map(|k| k.as_str())
+    }
+}
+
+impl Resolve for Home {
+    fn resolve(&self, name: &str) -> Result<Devices, LookupError> {
+        Home::resolve(self, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(id: &str, model: &str, name: &str) -> Device {
+        Device {
+            model: model.into(),
+            name: name.into(),
+            ..Device::new(DeviceId::parse(id).unwrap())
+        }
+    }
+
+    fn devices() -> Devices {
+        Devices {
+            devices: vec![
+                device("34:20:03:2e:30:01", "H6159", "H6159_3001"),
+                device("34:20:03:2e:30:02", "H6163", "Kitchen Strip"),
+                device("34:20:03:2e:30:03", "H5081", "Smart Plug"),
+            ],
+        }
+    }
+
+    fn layout() -> Layout {
+        serde_json::from_str(
+            r##"{
+                "devices": {
+                    "desk-lamp": {
+                        "id": "34:20:03:2E:30:01",
+                        "model": "H6159",
+                        "aliases": ["desk"],
+                        "room": "office",
+                        "defaults": {"brightness": 80, "color": "#ff8800"}
+                    },
+                    "kitchen": {
+                        "id": "34:20:03:2e:30:02",
+                        "room": "kitchen-room"
+                    },
+                    "coffee": {
+                        "id": "34:20:03:2e:30:03",
+                        "room": "kitchen-room",
+                        "defaults": {"power": "off"}
+                    }
+                },
+                "rooms": {
+                    "office": {"name": "Main Office"},
+                    "kitchen-room": {}
+                },
+                "groups": {
+                    "evening": {"members": ["desk", "kitchen-room"]}
+                }
+            }"##,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn resolution() {
+        let home = layout().resolve(devices()).unwrap();
+
+        assert_eq!(home.device("desk").unwrap().model.as_str(), "H6159");
+        assert_eq!(home.device("Desk Lamp").unwrap().model.as_str(), "H6159");
+        assert_eq!(
+            home.device("kitchen strip").unwrap().model.as_str(),
+            "H6163"
+        );
+
+        assert_eq!(home.room("office").unwrap().len(), 1);
+        assert_eq!(home.room("kitchen room").unwrap().len(), 2);
+        assert!(home.room("garage").is_none());
+
+        assert_eq!(home.group("evening").unwrap().len(), 3);
+        assert_eq!(home.resolve("evening").unwrap().len(), 3);
+        assert_eq!(home.resolve("coffee").unwrap().len(), 1);
+
+        let id = DeviceId::parse("34:20:03:2e:30:01").unwrap();
+        assert_eq!(home.name_of(&id), Some("desk-lamp"));
+        assert_eq!(
+            home.defaults(&id).unwrap().color,
+            Some(Color {
+                r: 255,
+                g: 136,
+                b: 0
+            })
+        );
+    }
+
+    #[test]
+    fn validation() {
+        let mut layout = layout();
+        layout
+            .devices
+            .get_mut("kitchen")
+            .unwrap()
+            .aliases
+            .push("DESK".into());
+        layout.devices.get_mut("coffee").unwrap().room = Some("garage".into());
+        layout
+            .groups
+            .get_mut("evening")
+            .unwrap()
+            .members
+            .push("porch".into());
+
+        match layout.validate() {
+            Err(LayoutError::Invalid { problems }) => {
+                assert_eq!(problems.len(), 3);
+                assert!(problems.contains(&LayoutProblem::UnknownRoom {
+                    device: "coffee".into(),
+                    room: "garage".into(),
+                }));
+                assert!(problems.contains(&LayoutProblem::UnknownMember {
+                    group: "evening".into(),
+                    member: "porch".into(),
+                }));
+                assert!(problems.contains(&LayoutProblem::DuplicateAlias {
+                    alias: "DESK".into(),
+                    first: "device desk-lamp".into(),
+                    second: "device kitchen".into(),
+                }));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_devices() {
+        let mut layout = layout();
+        layout.devices.get_mut("coffee").unwrap().id =
+            DeviceId::parse("34:20:03:2e:30:99").unwrap();
+        layout.devices.get_mut("desk-lamp").unwrap().model = Some("H6163".into());
+
+        match layout.resolve(devices()) {
+            Err(LayoutError::Invalid { problems }) => {
+                assert_eq!(problems.len(), 2);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn formats() {
+        let toml = r##"
+            [devices.desk-lamp]
+            id = "34:20:03:2e:30:01"
+            aliases = ["desk"]
+            room = "office"
+
+            [devices.desk-lamp.defaults]
+            color = "#ff8800"
+            color_temp = 4000
+
+            [rooms.office]
+
+            [groups.all]
+            members = ["office"]
+        "##;
+
+        let yaml = r##"
+            devices:
+              desk-lamp:
+                id: "34:20:03:2e:30:01"
+                aliases: [desk]
+                room: office
+                defaults:
+                  color: "#ff8800"
+                  color_temp: 4000
+            rooms:
+              office: {}
+            groups:
+              all:
+                members: [office]
+        "##;
+
+        let from_toml = Layout::from_toml(toml).unwrap();
+        let from_yaml = Layout::from_yaml(yaml).unwrap();
+        assert_eq!(from_toml, from_yaml);
+
+        assert!(Layout::from_toml("[devices.bad]\nid = \"nope\"").is_err());
+    }
+}
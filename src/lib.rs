@@ -1,8 +1,47 @@
+pub mod accounts;
+#[cfg(feature = "cache")]
+pub mod cache;
+pub mod cassette;
+pub mod catalog;
+#[cfg(feature = "circadian")]
+pub mod circadian;
 pub mod client;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "effects")]
+pub mod effects;
 pub mod endpoints;
+#[cfg(feature = "events")]
+pub mod events;
+pub mod homeassistant;
+pub mod hue;
+pub mod journal;
+pub mod lan;
+pub mod layout;
+#[cfg(feature = "lightshow")]
+pub mod lightshow;
+pub mod lookup;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod models;
+pub mod monitor;
+pub mod query;
+#[cfg(feature = "rules")]
+pub mod rules;
+#[cfg(feature = "schedule")]
+pub mod schedule;
+#[cfg(feature = "script")]
+pub mod script;
+#[cfg(feature = "sequence")]
+pub mod sequence;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(any(feature = "circadian", feature = "rules"))]
+mod time_of_day;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
 pub use client::GoveeClient;
-pub use models::Color;
+pub use models::{Color, DeviceId, Model};
 
 pub const DEFAULT_API_URL: &str = "https://developer-api.govee.com";
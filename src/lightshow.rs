@@ -0,0 +1,585 @@
+//! Light shows synced to music.
+//!
+//! A show is made in three steps. The music is decoded from a WAV or FLAC
+//! file into [Audio], which is measured into an [Analysis] of its loudness,
+//! bass, mid and treble, and beats. A [Choreography] then turns the analysis
+//! into a list of [Cue]s, each a [Frame] for one device at one moment, and a
+//! [ShowPlayer] sends the cues through an effects [Transport] in time with a
+//! clock started alongside the music.
+//!
+//! Each device in a show follows one band in turn (bass, then mid, then
+//! treble), growing brighter as the music and its band get louder and
+//! flashing on every beat. Colors step through a palette on each beat, with
+//! every device one color further along than the one before.
+//!
+//! Everything up to the cues happens offline, so a show can be checked
+//! before it is played.
+//!
+//! # Examples
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use govee_rs::{
+//!     lightshow::{Analysis, Audio, Choreography, ShowPlayer},
+//!     GoveeClient, DEFAULT_API_URL,
+//! };
+//! use tokio_util::sync::CancellationToken;
+//!
+//! let client = GoveeClient::new(DEFAULT_API_URL, "my-api-key")?;
+//! let devices = client.devices().await?;
+//!
+//! let analysis = Analysis::new(&Audio::load("song.flac")?);
+//! println!("{:?} beats a minute", analysis.tempo());
+//! let cues = Choreography::default().cues(&analysis, &devices);
+//!
+//! // start the music now, then
+//! ShowPlayer::new(client, &devices, cues)
+//!     .run(tokio::time::Instant::now(), &CancellationToken::new(), |event| {
+//!         println!("{}", event)
+//!     })
+//!     .await;
+//! # Ok(())
+//! # }
+//! ```
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use thiserror::Error;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    effects::{self, EffectError, Frame, Transport, LAN_INTERVAL},
+    models::{Color, ControlCommand, DeviceId, Devices},
+};
+
+mod analysis;
+
+pub use analysis::{Analysis, Bands, HOP};
+
+/// How long the flash on a beat takes to fade.
+const FLASH: Duration = Duration::from_millis(150);
+
+#[derive(Debug, Error)]
+pub enum LightShowError {
+    #[error("failed to read audio {}: {}", path.display(), source)]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("unsupported audio format for {}: expected .wav or .flac", path.display())]
+    UnsupportedFormat { path: PathBuf },
+    #[error("invalid wav audio: {}", source)]
+    Wav {
+        #[from]
+        source: hound::Error,
+    },
+    #[error("invalid flac audio: {}", source)]
+    Flac {
+        #[from]
+        source: claxon::Error,
+    },
+}
+
+/// Decoded audio, mixed down to one channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Audio {
+    sample_rate: u32,
+    samples: Vec<f32>,
+}
+
+impl Audio {
+    /// Audio from samples between -1 and 1.
+    pub fn new(sample_rate: u32, samples: Vec<f32>) -> Self {
+        Self {
+            sample_rate: sample_rate.max(1),
+            samples,
+        }
+    }
+
+    /// Decode a `.wav` or `.flac` file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LightShowError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let open = || {
+            std::fs::File::open(path)
+                .map(BufReader::new)
+                .map_err(|source| LightShowError::Io {
+                    path: path.to_owned(),
+                    source,
+                })
+        };
+        match extension.as_deref() {
+            Some("wav") => Self::from_wav(open()?),
+            Some("flac") => Self::from_flac(open()?),
+            _ => Err(LightShowError::UnsupportedFormat {
+                path: path.to_owned(),
+            }),
+        }
+    }
+
+    /// Decode WAV audio, in integer or float samples.
+    pub fn from_wav(reader: impl io::Read) -> Result<Self, LightShowError> {
+        let reader = hound::WavReader::new(reader)?;
+        let spec = reader.spec();
+        let samples = match spec.sample_format {
+            hound::SampleFormat::Float => reader.into_samples::<f32>().collect::<Result<_, _>>()?,
+            hound::SampleFormat::Int => {
+                let scale = full_scale(spec.bits_per_sample);
+                reader
+                    .into_samples::<i32>()
+                    .map(|s| s.map(|s| s as f32 / scale))
+                    .collect::<Result<_, _>>()?
+            }
+        };
+        Ok(Self::mixed(spec.sample_rate, spec.channels, samples))
+    }
+
+    /// Decode FLAC audio.
+    pub fn from_flac(reader: impl io::Read) -> Result<Self, LightShowError> {
+        let mut reader = claxon::FlacReader::new(reader)?;
+        let info = reader.streaminfo();
+        let scale = full_scale(info.bits_per_sample as u16);
+        let samples = reader
+            .samples()
+            .map(|s| s.map(|s| s as f32 / scale))
+            .collect::<Result<_, _>>()?;
+        Ok(Self::mixed(info.sample_rate, info.channels as u16, samples))
+    }
+
+    /// Average interleaved `channels` into one.
+    fn mixed(sample_rate: u32, channels: u16, samples: Vec<f32>) -> Self {
+        let channels = usize::from(channels.max(1));
+        let samples = if channels == 1 {
+            samples
+        } else {
+            samples
+                .chunks(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                .collect()
+        };
+        Self::new(sample_rate, samples)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.samples.len() as f64 / f64::from(self.sample_rate))
+    }
+}
+
+/// The largest magnitude of a `bits` bit integer sample.
+fn full_scale(bits: u16) -> f32 {
+    (1u64 << (bits.clamp(1, 32) - 1)) as f32
+}
+
+/// What one device should show from a moment in a show.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cue {
+    pub at: Duration,
+    pub device: DeviceId,
+    pub frame: Frame,
+}
+
+/// How an [Analysis] is turned into [Cue]s.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Choreography {
+    /// The colors stepped through on each beat. An empty palette shows white.
+    pub palette: Vec<Color>,
+    /// The brightness in silence, from 0 to 100.
+    pub min_brightness: u64,
+    /// The shortest time between cues for one device. Match it to the
+    /// transport the show will be played through.
+    pub interval: Duration,
+    /// The smallest brightness change worth a cue, in percentage points.
+    pub brightness_step: u64,
+}
+
+impl Default for Choreography {
+    fn default() -> Self {
+        Self {
+            palette: ["red", "orange", "magenta", "blue", "cyan", "lime"]
+                .into_iter()
+                .filter_map(Color::named)
+                .collect(),
+            min_brightness: 5,
+            interval: LAN_INTERVAL,
+            brightness_step: 5,
+        }
+    }
+}
+
+impl Choreography {
+    /// The cues for `devices`, in order of time and then of device.
+    ///
+    /// A cue is only made when a device's color changes or its brightness
+    /// changes by at least the step, so quiet stretches need few requests.
+    pub fn cues(&self, analysis: &Analysis, devices: &Devices) -> Vec<Cue> {
+        let interval = self.interval.max(HOP);
+        let min = self.min_brightness.min(100) as f32;
+        let mut shown: Vec<Option<Frame>> = vec![None; devices.len()];
+        let mut cues = Vec::new();
+        let mut beats = analysis.beats().iter().peekable();
+        let mut counted = 0;
+        let mut last_beat = None;
+
+        let mut at = Duration::ZERO;
+        while at < analysis.duration() {
+            while let Some(beat) = beats.next_if(|beat| **beat <= at) {
+                counted += 1;
+                last_beat = Some(*beat);
+            }
+            let flash = last_beat.map_or(0.0, |beat| {
+                (-(at - beat).as_secs_f32() / FLASH.as_secs_f32()).exp()
+            });
+            let loudness = analysis.loudness_at(at);
+            let bands = analysis.bands_at(at);
+
+            for (index, device) in devices.iter().enumerate() {
+                let band = match index % 3 {
+                    0 => bands.bass,
+                    1 => bands.mid,
+                    _ => bands.treble,
+                };
+                let level = (0.5 * loudness + 0.5 * band).max(flash).clamp(0.0, 1.0);
+                let frame = Frame {
+                    color: self.color(counted + index),
+                    brightness: (min + (100.0 - min) * level).round() as u64,
+                };
+                let changed = shown[index].map_or(true, |last| {
+                    last.color != frame.color
+                        || last.brightness.abs_diff(frame.brightness) >= self.brightness_step.max(1)
+                });
+                if changed {
+                    shown[index] = Some(frame);
+                    cues.push(Cue {
+                        at,
+                        device: device.device.clone(),
+                        frame,
+                    });
+                }
+            }
+            at += interval;
+        }
+        cues
+    }
+
+    fn color(&self, step: usize) -> Color {
+        if self.palette.is_empty() {
+            return Color {
+                r: 255,
+                g: 255,
+                b: 255,
+            };
+        }
+        self.palette[step % self.palette.len()]
+    }
+}
+
+/// What happened while playing a show.
+#[derive(Debug)]
+pub enum ShowEvent {
+    Failed { device: String, error: EffectError },
+    Finished { played: usize, skipped: usize },
+}
+
+impl fmt::Display for ShowEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Failed { device, error } => write!(f, "{}: {}", device, error),
+            Self::Finished { played, skipped } => {
+                write!(f, "finished: {} cues played, {} skipped", played, skipped)
+            }
+        }
+    }
+}
+
+/// Plays [Cue]s in time with a clock.
+#[derive(Debug, Clone)]
+pub struct ShowPlayer<T> {
+    transport: T,
+    devices: Devices,
+    cues: Vec<Cue>,
+    latency: Duration,
+}
+
+impl<T: Transport> ShowPlayer<T> {
+    /// Make a player for the `cues` of the devices in `devices` that can be
+    /// controlled and whose color can be set. Cues for other devices are
+    /// dropped.
+    pub fn new(transport: T, devices: &Devices, mut cues: Vec<Cue>) -> Self {
+        let devices = devices.controllable().supporting(ControlCommand::Color);
+        cues.retain(|cue| devices.iter().any(|d| d.device == cue.device));
+        cues.sort_by_key(|cue| cue.at);
+        Self {
+            transport,
+            devices,
+            cues,
+            latency: Duration::ZERO,
+        }
+    }
+
+    /// Send every cue this much early, to make up for the time a command
+    /// takes to reach a device.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn devices(&self) -> &Devices {
+        &self.devices
+    }
+
+    pub fn cues(&self) -> &[Cue] {
+        &self.cues
+    }
+
+    /// How many commands playing every cue sends, if none are skipped or
+    /// fail. Over the cloud API each is a request from the daily quota.
+    pub fn requests(&self) -> usize {
+        let mut shown: HashMap<&DeviceId, Frame> = HashMap::new();
+        let mut requests = 0;
+        for cue in &self.cues {
+            let Some(device) = self.devices.iter().find(|d| d.device == cue.device) else {
+                continue;
+            };
+            requests +=
+                effects::frame_commands(device, shown.get(&cue.device).copied(), cue.frame).len();
+            shown.insert(&cue.device, cue.frame);
+        }
+        requests
+    }
+
+    /// Play the cues against a clock started at `start`, when the music
+    /// starts, until they run out or `cancel` is triggered.
+    ///
+    /// A cue that is already overtaken by the device's next one when its
+    /// time comes is skipped, so a slow transport falls behind by at most a
+    /// cue.
+    pub async fn run(
+        &self,
+        start: Instant,
+        cancel: &CancellationToken,
+        mut report: impl FnMut(ShowEvent),
+    ) {
+        let devices: HashMap<&DeviceId, usize> = self
+            .devices
+            .iter()
+            .enumerate()
+            .map(|(i, d)| (&d.device, i))
+            .collect();
+        let mut next: Vec<Option<usize>> = vec![None; self.cues.len()];
+        let mut later: HashMap<&DeviceId, usize> = HashMap::new();
+        for (i, cue) in self.cues.iter().enumerate().rev() {
+            next[i] = later.insert(&cue.device, i);
+        }
+        let due = |cue: &Cue| (start + cue.at).checked_sub(self.latency).unwrap_or(start);
+
+        let mut shown: Vec<Option<Frame>> = vec![None; self.devices.len()];
+        let (mut played, mut skipped) = (0, 0);
+        for (i, cue) in self.cues.iter().enumerate() {
+            tokio::select! {
+                _ = tokio::time::sleep_until(due(cue)) => {}
+                _ = cancel.cancelled() => break,
+            }
+            if next[i].is_some_and(|next| due(&self.cues[next]) <= Instant::now()) {
+                skipped += 1;
+                continue;
+            }
+
+            let index = devices[&cue.device];
+            let device = &self.devices[index];
+            let result =
+                effects::send_frame(&self.transport, device, shown[index], cue.frame).await;
+            match result {
+                Ok(()) => {
+                    shown[index] = Some(cue.frame);
+                    played += 1;
+                }
+                Err(error) => {
+                    shown[index] = None;
+                    report(ShowEvent::Failed {
+                        device: device.name.clone(),
+                        error,
+                    });
+                }
+            }
+        }
+
+        report(ShowEvent::Finished { played, skipped });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::models::Device;
+
+    /// A 4 second, 8 kHz mono recording of a decaying 200 Hz thump every
+    /// half second from 0.25s, or 120 beats a minute.
+    const BEATS: &[u8] = include_bytes!("lightshow/testdata/beats.flac");
+
+    fn beats_as_wav(audio: &Audio) -> Vec<u8> {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: audio.sample_rate(),
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut wav = Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut wav, spec).unwrap();
+        for sample in audio.samples() {
+            let sample = (sample * 32768.0).round() as i16;
+            writer.write_sample(sample).unwrap();
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        wav.into_inner()
+    }
+
+    #[test]
+    fn analyzes_beats_from_flac_and_wav() {
+        let flac = Audio::from_flac(BEATS).unwrap();
+        assert_eq!(flac.sample_rate(), 8000);
+        assert_eq!(flac.duration(), Duration::from_secs(4));
+
+        let analysis = Analysis::new(&flac);
+        assert_eq!(analysis.beats().len(), 8);
+        for (n, beat) in analysis.beats().iter().enumerate() {
+            // each beat is found in the hop it starts in
+            let thump = Duration::from_millis(250 + 500 * n as u64);
+            assert!(*beat <= thump && thump - *beat < HOP, "{:?}", beat);
+        }
+        assert_eq!(analysis.tempo(), Some(120.0));
+
+        let thump = Duration::from_millis(2250);
+        let silence = Duration::from_millis(2600);
+        assert!(analysis.loudness_at(thump) > 0.5);
+        assert_eq!(analysis.loudness_at(silence), 0.0);
+        assert!(analysis.bands_at(thump).bass > 0.5);
+        assert_eq!(analysis.bands_at(silence), Bands::default());
+
+        // the same audio as stereo wav measures the same
+        let wav = Audio::from_wav(Cursor::new(beats_as_wav(&flac))).unwrap();
+        assert_eq!(wav, flac);
+        assert_eq!(Analysis::new(&wav), analysis);
+    }
+
+    #[test]
+    fn choreographs_cues_on_the_beat() {
+        let analysis = Analysis::new(&Audio::from_flac(BEATS).unwrap());
+        let device = |id: &str, name: &str| Device {
+            name: name.into(),
+            controllable: true,
+            supported_commands: [ControlCommand::Color, ControlCommand::Brightness].into(),
+            ..Device::new(id.parse().unwrap())
+        };
+        let devices = Devices {
+            devices: vec![
+                device("34:20:03:2e:30:01", "left"),
+                device("34:20:03:2e:30:02", "right"),
+            ],
+        };
+        let red = Color::named("red").unwrap();
+        let blue = Color::named("blue").unwrap();
+        let choreography = Choreography {
+            palette: vec![red, blue],
+            min_brightness: 10,
+            ..Default::default()
+        };
+
+        let cues = choreography.cues(&analysis, &devices);
+        assert_eq!(cues, choreography.cues(&analysis, &devices));
+        assert_eq!(
+            cues[..2],
+            [
+                Cue {
+                    at: Duration::ZERO,
+                    device: devices[0].device.clone(),
+                    frame: Frame {
+                        color: red,
+                        brightness: 10
+                    },
+                },
+                Cue {
+                    at: Duration::ZERO,
+                    device: devices[1].device.clone(),
+                    frame: Frame {
+                        color: blue,
+                        brightness: 10
+                    },
+                },
+            ]
+        );
+
+        // the first beat swaps the colors and flashes both devices
+        let first = analysis.beats()[0];
+        let flashes: Vec<&Cue> = cues
+            .iter()
+            .filter(|cue| cue.at >= first && cue.at < first + choreography.interval)
+            .collect();
+        assert_eq!(flashes.len(), 2);
+        assert_eq!(flashes[0].frame.color, blue);
+        assert_eq!(flashes[1].frame.color, red);
+        assert!(flashes.iter().all(|cue| cue.frame.brightness > 60));
+    }
+
+    #[tokio::test]
+    async fn estimates_requests_from_changes() {
+        let lamp = Device {
+            controllable: true,
+            supported_commands: [
+                ControlCommand::Turn,
+                ControlCommand::Color,
+                ControlCommand::Brightness,
+            ]
+            .into(),
+            ..Device::new("34:20:03:2e:30:01".parse().unwrap())
+        };
+        let red = Color::named("red").unwrap();
+        let blue = Color::named("blue").unwrap();
+        let cue = |secs, color, brightness| Cue {
+            at: Duration::from_secs(secs),
+            device: lamp.device.clone(),
+            frame: Frame { color, brightness },
+        };
+        let cues = vec![
+            // color, on and brightness
+            cue(0, red, 50),
+            // brightness
+            cue(1, red, 60),
+            // color
+            cue(2, blue, 60),
+            // off
+            cue(3, blue, 0),
+            // on and brightness
+            cue(4, blue, 60),
+        ];
+
+        let lan = crate::effects::LanTransport::bind().await.unwrap();
+        let player = ShowPlayer::new(
+            lan,
+            &Devices {
+                devices: vec![lamp],
+            },
+            cues,
+        );
+        assert_eq!(player.requests(), 8);
+    }
+}
@@ -0,0 +1,195 @@
+//! Loudness, spectral bands and beats of decoded audio.
+use std::time::Duration;
+
+use realfft::RealFftPlanner;
+
+use super::Audio;
+
+/// How often the audio is measured.
+pub const HOP: Duration = Duration::from_millis(20);
+
+/// Where the bass band ends and the mid band starts, in hertz.
+const BASS_TOP: f32 = 250.0;
+
+/// Where the mid band ends and the treble band starts, in hertz.
+const MID_TOP: f32 = 2000.0;
+
+/// How far back the average a beat must stand out from reaches.
+const HISTORY: Duration = Duration::from_secs(1);
+
+/// How much louder than the recent average a beat must be.
+const BEAT_RISE: f32 = 1.5;
+
+/// How loud a beat must be, relative to the loudest part of the audio.
+const BEAT_FLOOR: f32 = 0.05;
+
+/// The shortest time between beats, which caps tempo at 240 beats a minute.
+const MIN_BEAT_GAP: Duration = Duration::from_millis(250);
+
+/// The energy of one measurement in three frequency bands, each from 0 to
+/// 1 relative to the band's loudest measurement.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Bands {
+    pub bass: f32,
+    pub mid: f32,
+    pub treble: f32,
+}
+
+/// What was measured in some [Audio], one measurement every [HOP].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    duration: Duration,
+    loudness: Vec<f32>,
+    bands: Vec<Bands>,
+    beats: Vec<Duration>,
+}
+
+impl Analysis {
+    /// Measure `audio`.
+    ///
+    /// Loudness is the RMS level of each [HOP], relative to the loudest.
+    /// Bands come from a Hann-windowed FFT centered on each hop. Beats are
+    /// hops whose energy jumps well above the average of the second before.
+    pub fn new(audio: &Audio) -> Self {
+        let rate = audio.sample_rate() as usize;
+        let hop = (rate * HOP.as_millis() as usize / 1000).max(1);
+        let samples = audio.samples();
+        let count = samples.len().div_ceil(hop);
+
+        let energy: Vec<f32> = (0..count)
+            .map(|k| {
+                let chunk = &samples[k * hop..((k + 1) * hop).min(samples.len())];
+                chunk.iter().map(|s| s * s).sum::<f32>() / chunk.len() as f32
+            })
+            .collect();
+        let loudness = normalized(energy.iter().map(|e| e.sqrt()).collect());
+
+        let window = (hop * 2).next_power_of_two().max(256);
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(window);
+        let hann: Vec<f32> = (0..window)
+            .map(|i| {
+                let x = std::f32::consts::PI * i as f32 / window as f32;
+                x.sin().powi(2)
+            })
+            .collect();
+        let bin_width = rate as f32 / window as f32;
+        let mut input = fft.make_input_vec();
+        let mut spectrum = fft.make_output_vec();
+        let mut bass = Vec::with_capacity(count);
+        let mut mid = Vec::with_capacity(count);
+        let mut treble = Vec::with_capacity(count);
+        for k in 0..count {
+            let start = (k * hop + hop / 2) as isize - (window / 2) as isize;
+            for (i, x) in input.iter_mut().enumerate() {
+                let at = start + i as isize;
+                let sample = usize::try_from(at)
+                    .ok()
+                    .and_then(|at| samples.get(at))
+                    .copied()
+                    .unwrap_or(0.0);
+                *x = sample * hann[i];
+            }
+            fft.process(&mut input, &mut spectrum)
+                .expect("buffers come from the plan");
+
+            let mut sums = [0.0f32; 3];
+            // skip the DC bin
+            for (i, bin) in spectrum.iter().enumerate().skip(1) {
+                let freq = i as f32 * bin_width;
+                let band = if freq < BASS_TOP {
+                    0
+                } else if freq < MID_TOP {
+                    1
+                } else {
+                    2
+                };
+                sums[band] += bin.norm_sqr();
+            }
+            bass.push(sums[0].sqrt());
+            mid.push(sums[1].sqrt());
+            treble.push(sums[2].sqrt());
+        }
+        let bands = normalized(bass)
+            .into_iter()
+            .zip(normalized(mid))
+            .zip(normalized(treble))
+            .map(|((bass, mid), treble)| Bands { bass, mid, treble })
+            .collect();
+
+        Self {
+            duration: audio.duration(),
+            loudness,
+            bands,
+            beats: beats(&energy),
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// When each beat starts, in order.
+    pub fn beats(&self) -> &[Duration] {
+        &self.beats
+    }
+
+    /// The loudness at `at`, from 0 to 1, or 0 past the end.
+    pub fn loudness_at(&self, at: Duration) -> f32 {
+        self.loudness.get(index(at)).copied().unwrap_or_default()
+    }
+
+    /// The bands at `at`, or silence past the end.
+    pub fn bands_at(&self, at: Duration) -> Bands {
+        self.bands.get(index(at)).copied().unwrap_or_default()
+    }
+
+    /// The tempo in beats a minute, from the median time between beats, or
+    /// `None` with fewer than two beats.
+    pub fn tempo(&self) -> Option<f64> {
+        let mut gaps: Vec<Duration> = self.beats.windows(2).map(|w| w[1] - w[0]).collect();
+        if gaps.is_empty() {
+            return None;
+        }
+        gaps.sort();
+        Some(60.0 / gaps[gaps.len() / 2].as_secs_f64())
+    }
+}
+
+/// The measurement `at` falls in.
+fn index(at: Duration) -> usize {
+    (at.as_millis() / HOP.as_millis()) as usize
+}
+
+/// Scale `values` so the largest is 1, leaving silence at 0.
+fn normalized(mut values: Vec<f32>) -> Vec<f32> {
+    let max = values.iter().copied().fold(0.0, f32::max);
+    if max > 0.0 {
+        for value in &mut values {
+            *value /= max;
+        }
+    }
+    values
+}
+
+/// The starts of the hops in `energy` that are beats.
+fn beats(energy: &[f32]) -> Vec<Duration> {
+    let history = (HISTORY.as_millis() / HOP.as_millis()) as usize;
+    let gap = (MIN_BEAT_GAP.as_millis() / HOP.as_millis()) as usize;
+    let floor = energy.iter().copied().fold(0.0, f32::max) * BEAT_FLOOR * BEAT_FLOOR;
+
+    let mut beats = Vec::new();
+    let mut last: Option<usize> = None;
+    for (k, &e) in energy.iter().enumerate() {
+        let recent = &energy[k.saturating_sub(history)..k];
+        let average = if recent.is_empty() {
+            0.0
+        } else {
+            recent.iter().sum::<f32>() / recent.len() as f32
+        };
+        if e > floor && e > average * BEAT_RISE && last.map_or(true, |last| k - last >= gap) {
+            beats.push(HOP * k as u32);
+            last = Some(k);
+        }
+    }
+    beats
+}
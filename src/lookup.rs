@@ -0,0 +1,467 @@
+//! Helpers for finding a particular [Device] in a [Devices] listing.
+//!
+//! The most convenient entry point is [Devices::find], which accepts whatever
+//! a human typed (an id, a name, an alias or something close to a name) and
+//! either resolves it to exactly one device or explains why it could not.
+use std::{collections::HashMap, fmt};
+
+use thiserror::Error;
+
+use crate::models::{Device, DeviceId, Devices};
+
+/// Fuzzy matches scoring at least this much are considered a match.
+const MATCH_THRESHOLD: f64 = 0.75;
+
+/// Fuzzy matches scoring at least this much are offered as suggestions.
+const SUGGESTION_THRESHOLD: f64 = 0.5;
+
+/// How far ahead of the runner up the best fuzzy match must be to win outright.
+const MATCH_MARGIN: f64 = 0.1;
+
+/// The maximum number of suggestions reported by [LookupError::NotFound].
+const MAX_SUGGESTIONS: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum LookupError {
+    #[error("no device matches {:?}{}", query, SuggestionList(suggestions))]
+    NotFound {
+        query: String,
+        suggestions: Vec<DeviceMatch>,
+    },
+    #[error("{:?} is ambiguous, it matches: {}", query, MatchList(matches))]
+    Ambiguous {
+        query: String,
+        matches: Vec<DeviceMatch>,
+    },
+}
+
+/// A device that matched a lookup, along with how well it matched.
+///
+/// Scores range from `0.0` (nothing in common) to `1.0` (an exact match).
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceMatch {
+    pub device: DeviceId,
+    pub name: String,
+    pub score: f64,
+}
+
+impl DeviceMatch {
+    fn new(device: &Device, score: f64) -> Self {
+        Self {
+            device: device.device.clone(),
+            name: device.name.clone(),
+            score,
+        }
+    }
+}
+
+struct MatchList<'a>(&'a [DeviceMatch]);
+
+impl<'a> fmt::Display for MatchList<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, m) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{:?} ({})", m.name, m.device)?;
+        }
+        Ok(())
+    }
+}
+
+struct SuggestionList<'a>(&'a [DeviceMatch]);
+
+impl<'a> fmt::Display for SuggestionList<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        write!(f, ", did you mean: {}", MatchList(self.0))
+    }
+}
+
+/// Anything that can turn a name into one or more devices.
+///
+/// A plain [Devices] listing resolves names to single devices with
+/// [Devices::find], while a [Home](crate::layout::Home) also understands rooms
+/// and groups.
+pub trait Resolve {
+    fn resolve(&self, name: &str) -> Result<Devices, LookupError>;
+}
+
+impl<R: Resolve + ?Sized> Resolve for &R {
+    fn resolve(&self, name: &str) -> Result<Devices, LookupError> {
+        (**self).resolve(name)
+    }
+}
+
+impl Resolve for Devices {
+    fn resolve(&self, name: &str) -> Result<Devices, LookupError> {
+        Ok(Devices {
+            devices: vec![self.find(name)?.clone()],
+        })
+    }
+}
+
+/// User-defined alternate names for devices.
+///
+/// Aliases are compared case-insensitively and with surrounding and repeated
+/// whitespace ignored.
+///
+/// # Examples
+/// ```
+/// use govee_rs::{lookup::Aliases, DeviceId};
+///
+/// let mut aliases = Aliases::default();
+/// aliases.insert("Kitchen Strip", DeviceId::parse("34:20:03:2e:30:2b").unwrap());
+///
+/// assert!(aliases.get("kitchen  strip").is_some());
+/// assert!(aliases.get("kitchen").is_none());
+/// ```
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Aliases {
+    aliases: HashMap<String, DeviceId>,
+}
+
+impl Aliases {
+    /// Add an alias, returning the id it previously referred to, if any.
+    pub fn insert(&mut self, alias: &str, device: DeviceId) -> Option<DeviceId> {
+        self.aliases.insert(normalize(alias), device)
+    }
+
+    /// Get the id referred to by the given alias.
+    pub fn get(&self, alias: &str) -> Option<&DeviceId> {
+        self.aliases.get(&normalize(alias))
+    }
+
+    pub fn len(&self) -> usize {
+        self.aliases.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+
+    /// Iterate over the (normalized) aliases and the ids they refer to.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &DeviceId)> {
+        self.aliases.iter().map(|(k, v)| (k.as_str(), v))
+    }
+}
+
+impl Devices {
+    /// Get the device with the given id.
+    pub fn by_id(&self, id: &DeviceId) -> Option<&Device> {
+        self.iter().find(|d| &d.device == id)
+    }
+
+    /// Get the device with exactly the given name.
+    ///
+    /// Fails if more than one device has that name.
+    pub fn by_name(&self, name: &str) -> Result<Option<&Device>, LookupError> {
+        self.unique(name, |d| d.name == name)
+    }
+
+    /// Get the device with the given name, ignoring case and extra whitespace.
+    ///
+    /// Fails if more than one device has that name.
+    pub fn by_name_ignore_case(&self, name: &str) -> Result<Option<&Device>, LookupError> {
+        let name = normalize(name);
+        self.unique(&name, |d| normalize(&d.name) == name)
+    }
+
+    /// Get the device referred to by the given alias.
+    pub fn by_alias(&self, aliases: &Aliases, alias: &str) -> Option<&Device> {
+        aliases.get(alias).and_then(|id| self.by_id(id))
+    }
+
+    /// Rank devices by how closely their names resemble `query`.
+    ///
+    /// Only reasonably close matches are returned, best first.
+    pub fn fuzzy(&self, query: &str) -> Vec<DeviceMatch> {
+        let query = normalize(query);
+        let mut matches: Vec<_> = self
+            .iter()
+            .map(|d| DeviceMatch::new(d, score(&query, &normalize(&d.name))))
+            .filter(|m| m.score >= SUGGESTION_THRESHOLD)
+            .collect();
+
+        matches.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+
+        matches
+    }
+
+    /// Find exactly one device described by `query`.
+    ///
+    /// This is equivalent to [Devices::find_with_aliases] with no aliases.
+    pub fn find(&self, query: &str) -> Result<&Device, LookupError> {
+        self.find_with_aliases(query, &Aliases::default())
+    }
+
+    /// Find exactly one device described by `query`.
+    ///
+    /// The following are tried in order, stopping at the first that matches
+    /// anything:
+    ///
+    /// 1. the device id
+    /// 2. the exact device name
+    /// 3. the device name, ignoring case
+    /// 4. the given aliases
+    /// 5. a fuzzy match against device names
+    ///
+    /// # Examples
+    /// ```
+    /// use govee_rs::models::{Device, Devices};
+    ///
+    /// let devices = Devices {
+    ///     devices: vec![
+    ///         Device {
+    ///             name: "Kitchen Strip".into(),
+    ///             ..Device::new("34:20:03:2e:30:2b".parse().unwrap())
+    ///         },
+    ///         Device {
+    ///             name: "Office Lamp".into(),
+    ///             ..Device::new("34:20:03:2e:30:2c".parse().unwrap())
+    ///         },
+    ///     ],
+    /// };
+    ///
+    /// assert_eq!(devices.find("kitchen strip").unwrap().name, "Kitchen Strip");
+    /// assert_eq!(devices.find("ofice lamp").unwrap().name, "Office Lamp");
+    /// assert!(devices.find("garage").is_err());
+    /// ```
+    pub fn find_with_aliases(
+        &self,
+        query: &str,
+        aliases: &Aliases,
+    ) -> Result<&Device, LookupError> {
+        if let Ok(id) = DeviceId::parse(query.trim()) {
+            if let Some(device) = self.by_id(&id) {
+                return Ok(device);
+            }
+        }
+
+        if let Some(device) = self.by_name(query)? {
+            return Ok(device);
+        }
+
+        if let Some(device) = self.by_name_ignore_case(query)? {
+            return Ok(device);
+        }
+
+        if let Some(device) = self.by_alias(aliases, query) {
+            return Ok(device);
+        }
+
+        let suggestions = self.fuzzy(query);
+        let mut strong = suggestions
+            .iter()
+            .take_while(|m| m.score >= MATCH_THRESHOLD)
+            .peekable();
+
+        if let Some(best) = strong.next() {
+            let runner_up = strong.peek();
+            if runner_up.map_or(true, |r| best.score - r.score >= MATCH_MARGIN) {
+                // the id came from this listing, so this can't fail
+                return Ok(self.by_id(&best.device).expect("matched device exists"));
+            }
+
+            return Err(LookupError::Ambiguous {
+                query: query.to_string(),
+                matches: suggestions
+                    .iter()
+                    .take_while(|m| best.score - m.score < MATCH_MARGIN)
+                    .cloned()
+                    .collect(),
+            });
+        }
+
+        Err(LookupError::NotFound {
+            query: query.to_string(),
+            suggestions: suggestions.into_iter().take(MAX_SUGGESTIONS).collect(),
+        })
+    }
+
+    fn unique<F>(&self, query: &str, pred: F) -> Result<Option<&Device>, LookupError>
+    where
+        F: Fn(&Device) -> bool,
+    {
+        let mut found = self.iter().filter(|d| pred(d));
+        let first = found.next();
+        let rest: Vec<_> = found.collect();
+
+        if rest.is_empty() {
+            return Ok(first);
+        }
+
+        Err(LookupError::Ambiguous {
+            query: query.to_string(),
+            matches: first
+                .into_iter()
+                .chain(rest)
+                .map(|d| DeviceMatch::new(d, 1.0))
+                .collect(),
+        })
+    }
+}
+
+/// Lowercase, treat `-` and `_` as spaces and collapse whitespace.
+pub(crate) fn normalize(s: &str) -> String {
+    s.split(|c: char| c.is_whitespace() || c == '-' || c == '_')
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Score how well a normalized `query` describes a normalized `name`.
+fn score(query: &str, name: &str) -> f64 {
+    if query.is_empty() || name.is_empty() {
+        return 0.0;
+    }
+
+    if query == name {
+        return 1.0;
+    }
+
+    let mut best = strsim::jaro_winkler(query, name) * 0.85;
+
+    if name.contains(query) {
+        best = best.max(0.9);
+    }
+
+    let name_tokens: Vec<_> = name.split(' ').collect();
+    let all_tokens_match = query
+        .split(' ')
+        .all(|q| name_tokens.iter().any(|n| n.starts_with(q)));
+    if all_tokens_match {
+        best = best.max(0.85);
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(id: &str, name: &str) -> Device {
+        Device {
+            name: name.into(),
+            ..Device::new(DeviceId::parse(id).unwrap())
+        }
+    }
+
+    fn devices() -> Devices {
+        Devices {
+            devices: vec![
+                device("34:20:03:2e:30:01", "Kitchen Strip Left"),
+                device("34:20:03:2e:30:02", "Kitchen Strip Right"),
+                device("34:20:03:2e:30:03", "Office Lamp"),
+                device("34:20:03:2e:30:04", "Smart Plug"),
+                device("34:20:03:2e:30:05", "Smart Plug"),
+            ],
+        }
+    }
+
+    #[test]
+    fn by_id() {
+        let devices = devices();
+        let id = DeviceId::parse("34:20:03:2E:30:03").unwrap();
+        assert_eq!(devices.by_id(&id).unwrap().name, "Office Lamp");
+        assert_eq!(
+            devices.find("34:20:03:2E:30:03").unwrap().name,
+            "Office Lamp"
+        );
+    }
+
+    #[test]
+    fn by_name() {
+        let devices = devices();
+        assert_eq!(
+            devices.by_name("Office Lamp").unwrap().unwrap().name,
+            "Office Lamp"
+        );
+        assert!(devices.by_name("office lamp").unwrap().is_none());
+        assert_eq!(
+            devices
+                .by_name_ignore_case("office  LAMP")
+                .unwrap()
+                .unwrap()
+                .name,
+            "Office Lamp"
+        );
+    }
+
+    #[test]
+    fn duplicate_names_are_ambiguous() {
+        let devices = devices();
+        match devices.by_name("Smart Plug") {
+            Err(LookupError::Ambiguous { matches, .. }) => assert_eq!(matches.len(), 2),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(devices.find("smart plug").is_err());
+    }
+
+    #[test]
+    fn aliases() {
+        let devices = devices();
+        let mut aliases = Aliases::default();
+        aliases.insert("desk", DeviceId::parse("34:20:03:2e:30:03").unwrap());
+        aliases.insert("coffee", DeviceId::parse("34:20:03:2e:30:04").unwrap());
+
+        assert_eq!(
+            devices.find_with_aliases("Desk", &aliases).unwrap().name,
+            "Office Lamp"
+        );
+        assert_eq!(
+            devices
+                .find_with_aliases("coffee", &aliases)
+                .unwrap()
+                .device,
+            DeviceId::parse("34:20:03:2e:30:04").unwrap()
+        );
+        assert!(devices.find("desk").is_err());
+    }
+
+    #[test]
+    fn fuzzy() {
+        let devices = devices();
+        assert_eq!(devices.find("office").unwrap().name, "Office Lamp");
+        assert_eq!(devices.find("ofice lamp").unwrap().name, "Office Lamp");
+        assert_eq!(
+            devices.find("kitchen strip right").unwrap().name,
+            "Kitchen Strip Right"
+        );
+        assert_eq!(
+            devices.find("kit str left").unwrap().name,
+            "Kitchen Strip Left"
+        );
+
+        match devices.find("kitchen strip") {
+            Err(LookupError::Ambiguous { matches, .. }) => {
+                let names: Vec<_> = matches.iter().map(|m| m.name.as_str()).collect();
+                assert_eq!(names, vec!["Kitchen Strip Left", "Kitchen Strip Right"]);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn not_found_suggestions() {
+        let devices = devices();
+        match devices.find("garage door") {
+            Err(LookupError::NotFound { suggestions, .. }) => {
+                assert!(suggestions.len() <= MAX_SUGGESTIONS)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let ranked = devices.fuzzy("lamp");
+        assert_eq!(ranked[0].name, "Office Lamp");
+    }
+}
@@ -0,0 +1,351 @@
+//! Prometheus metrics for devices and API usage.
+//!
+//! [Metrics] collects two kinds of metric: per device gauges fed from polled
+//! state, and API usage fed by passing it to [GoveeClient::with_observer].
+//!
+//! # Examples
+//! ```
+//! use std::sync::Arc;
+//! use govee_rs::{metrics::Metrics, GoveeClient, DEFAULT_API_URL};
+//!
+//! let metrics = Arc::new(Metrics::new().unwrap());
+//! let client = GoveeClient::new(DEFAULT_API_URL, "my-api-key")
+//!     .unwrap()
+//!     .with_observer(metrics.clone());
+//!
+//! // after polling with `client`, serve this on /metrics
+//! println!("{}", metrics.encode().unwrap());
+//! ```
+//!
+//! [GoveeClient::with_observer]: crate::GoveeClient::with_observer
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use thiserror::Error;
+
+use crate::{
+    client::{Quota, RequestObserver, RequestRecord},
+    models::{Device, NormalizedState, PowerState},
+    monitor::Snapshot,
+};
+
+#[derive(Debug, Error)]
+pub enum MetricsError {
+    #[error("prometheus error: {}", source)]
+    Prometheus {
+        #[from]
+        source: prometheus::Error,
+    },
+    #[error("metrics were not valid utf-8: {}", source)]
+    Encoding {
+        #[from]
+        source: std::string::FromUtf8Error,
+    },
+}
+
+const DEVICE_LABELS: &[&str] = &["device", "name", "model"];
+
+/// Latency buckets in seconds, from a fast response to a timeout.
+const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Device and API usage metrics in their own [Registry].
+pub struct Metrics {
+    registry: Registry,
+
+    requests: IntCounterVec,
+    latency: HistogramVec,
+    // vecs without labels, so they are absent until the API reports them
+    daily_limit: IntGaugeVec,
+    daily_remaining: IntGaugeVec,
+    endpoint_remaining: IntGaugeVec,
+
+    online: IntGaugeVec,
+    power: IntGaugeVec,
+    brightness: IntGaugeVec,
+    color_temp: IntGaugeVec,
+    color: IntGaugeVec,
+    poll_errors: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, MetricsError> {
+        let registry = Registry::new();
+
+        let requests = IntCounterVec::new(
+            Opts::new(
+                "govee_api_requests_total",
+                "Requests made to the Govee API.",
+            ),
+            &["method", "endpoint", "status"],
+        )?;
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "govee_api_request_duration_seconds",
+                "Latency of requests to the Govee API.",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["method", "endpoint"],
+        )?;
+        let daily_limit = IntGaugeVec::new(
+            Opts::new(
+                "govee_api_quota_limit",
+                "The daily request quota of the account.",
+            ),
+            &[],
+        )?;
+        let daily_remaining = IntGaugeVec::new(
+            Opts::new(
+                "govee_api_quota_remaining",
+                "Requests left in the account's daily quota.",
+            ),
+            &[],
+        )?;
+        let endpoint_remaining = IntGaugeVec::new(
+            Opts::new(
+                "govee_api_endpoint_quota_remaining",
+                "Requests left in an endpoint's per minute quota.",
+            ),
+            &["endpoint"],
+        )?;
+
+        let device_gauge =
+            |name: &str, help: &str| IntGaugeVec::new(Opts::new(name, help), DEVICE_LABELS);
+        let online = device_gauge("govee_device_online", "Whether the device is online.")?;
+        let power = device_gauge("govee_device_power", "Whether the device is on.")?;
+        let brightness = device_gauge("govee_device_brightness", "Brightness from 0 to 100.")?;
+        let color_temp = device_gauge(
+            "govee_device_color_temp_kelvin",
+            "Color temperature in kelvin.",
+        )?;
+        let color = IntGaugeVec::new(
+            Opts::new(
+                "govee_device_color",
+                "A component of the device's RGB color.",
+            ),
+            &["device", "name", "model", "component"],
+        )?;
+        let poll_errors = IntCounterVec::new(
+            Opts::new(
+                "govee_device_poll_errors_total",
+                "Failed attempts to get the device's state.",
+            ),
+            DEVICE_LABELS,
+        )?;
+
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(latency.clone()))?;
+        registry.register(Box::new(daily_limit.clone()))?;
+        registry.register(Box::new(daily_remaining.clone()))?;
+        registry.register(Box::new(endpoint_remaining.clone()))?;
+        registry.register(Box::new(online.clone()))?;
+        registry.register(Box::new(power.clone()))?;
+        registry.register(Box::new(brightness.clone()))?;
+        registry.register(Box::new(color_temp.clone()))?;
+        registry.register(Box::new(color.clone()))?;
+        registry.register(Box::new(poll_errors.clone()))?;
+
+        Ok(Self {
+            registry,
+            requests,
+            latency,
+            daily_limit,
+            daily_remaining,
+            endpoint_remaining,
+            online,
+            power,
+            brightness,
+            color_temp,
+            color,
+            poll_errors,
+        })
+    }
+
+    /// The registry holding every metric, for adding metrics of your own.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Record a request made by a client.
+    pub fn record_request(&self, record: &RequestRecord) {
+        let method = record.method.as_str();
+        let status = record
+            .status
+            .map(|s| s.as_u16().to_string())
+            .unwrap_or_else(|| "error".into());
+
+        self.requests
+            .with_label_values(&[method, &record.path, &status])
+            .inc();
+        self.latency
+            .with_label_values(&[method, &record.path])
+            .observe(record.latency.as_secs_f64());
+
+        let Quota {
+            limit, remaining, ..
+        } = record.daily_quota;
+        if let Some(limit) = limit {
+            self.daily_limit
+                .with_label_values(&[])
+                .set(saturating_i64(limit));
+        }
+        if let Some(remaining) = remaining {
+            self.daily_remaining
+                .with_label_values(&[])
+                .set(saturating_i64(remaining));
+        }
+        if let Some(remaining) = record.endpoint_quota.remaining {
+            self.endpoint_remaining
+                .with_label_values(&[&record.path])
+                .set(saturating_i64(remaining));
+        }
+    }
+
+    /// Record the state of a device.
+    ///
+    /// Properties the device did not report are removed rather than left at
+    /// their last value.
+    pub fn record_state(&self, device: &Device, state: &NormalizedState) {
+        let labels = [
+            device.device.as_str(),
+            device.name.as_str(),
+            device.model.as_str(),
+        ];
+
+        let set = |gauge: &IntGaugeVec, value: Option<i64>| match value {
+            Some(value) => gauge.with_label_values(&labels).set(value),
+            None => {
+                let _ = gauge.remove_label_values(&labels);
+            }
+        };
+        set(&self.online, state.online.map(i64::from));
+        set(
+            &self.power,
+            state.power.map(|p| i64::from(p == PowerState::On)),
+        );
+        set(&self.brightness, state.brightness.map(saturating_i64));
+        set(
+            &self.color_temp,
+            state.color_temp.filter(|t| *t > 0).map(saturating_i64),
+        );
+
+        for (component, value) in [
+            ("r", state.color.map(|c| c.r)),
+            ("g", state.color.map(|c| c.g)),
+            ("b", state.color.map(|c| c.b)),
+        ] {
+            let labels = [labels[0], labels[1], labels[2], component];
+            match value {
+                Some(value) => self.color.with_label_values(&labels).set(i64::from(value)),
+                None => {
+                    let _ = self.color.remove_label_values(&labels);
+                }
+            }
+        }
+    }
+
+    /// Record the result of polling a device with a [Monitor].
+    ///
+    /// [Monitor]: crate::monitor::Monitor
+    pub fn record_snapshot(&self, snapshot: &Snapshot) {
+        match &snapshot.state {
+            Ok(state) => self.record_state(&snapshot.device, state),
+            Err(_) => {
+                let device = &snapshot.device;
+                self.poll_errors
+                    .with_label_values(&[
+                        device.device.as_str(),
+                        device.name.as_str(),
+                        device.model.as_str(),
+                    ])
+                    .inc();
+            }
+        }
+    }
+
+    /// Every metric in the Prometheus text format.
+    pub fn encode(&self) -> Result<String, MetricsError> {
+        let mut buf = String::new();
+        TextEncoder::new().encode_utf8(&self.registry.gather(), &mut buf)?;
+        Ok(buf)
+    }
+}
+
+impl RequestObserver for Metrics {
+    fn observe(&self, record: &RequestRecord) {
+        self.record_request(record);
+    }
+}
+
+fn saturating_i64(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use http::{Method, StatusCode};
+
+    use super::*;
+    use crate::models::{Color, DeviceId};
+
+    #[test]
+    fn records_requests_and_state() {
+        let metrics = Metrics::new().unwrap();
+        assert!(!metrics
+            .encode()
+            .unwrap()
+            .contains("govee_api_quota_remaining 0"));
+
+        metrics.record_request(&RequestRecord {
+            method: Method::GET,
+            path: "/v1/devices".into(),
+            status: Some(StatusCode::OK),
+            latency: Duration::from_millis(120),
+            daily_quota: Quota {
+                limit: Some(10000),
+                remaining: Some(9876),
+                reset: None,
+            },
+            endpoint_quota: Quota::default(),
+        });
+
+        let lamp = Device {
+            model: "H6159".into(),
+            name: "lamp".into(),
+            ..Device::new(DeviceId::parse("34:20:03:2e:30:2b").unwrap())
+        };
+        let mut state = NormalizedState {
+            online: Some(true),
+            power: Some(PowerState::On),
+            brightness: Some(40),
+            color: Some(Color {
+                r: 255,
+                g: 128,
+                b: 0,
+            }),
+            ..NormalizedState::new(lamp.device.clone())
+        };
+        metrics.record_state(&lamp, &state);
+
+        let text = metrics.encode().unwrap();
+        assert!(text.contains(
+            r#"govee_api_requests_total{endpoint="/v1/devices",method="GET",status="200"} 1"#
+        ));
+        assert!(text.contains("govee_api_quota_remaining 9876"));
+        assert!(text.contains(
+            r#"govee_device_brightness{device="34:20:03:2e:30:2b",model="H6159",name="lamp"} 40"#
+        ));
+        assert!(text.contains(
+            r#"govee_device_color{component="g",device="34:20:03:2e:30:2b",model="H6159",name="lamp"} 128"#
+        ));
+
+        // switching to a color temperature drops the stale color
+        state.color = None;
+        state.color_temp = Some(3000);
+        metrics.record_state(&lamp, &state);
+        let text = metrics.encode().unwrap();
+        assert!(!text.contains("govee_device_color{"));
+        assert!(text.contains("govee_device_color_temp_kelvin{"));
+    }
+}
@@ -1,8 +1,16 @@
-use std::{borrow::Cow, collections::HashSet, ops::Deref, str::FromStr};
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+    str::FromStr,
+};
 
 use hex_color::HexColor;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use thiserror::Error;
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct BaseResponse<T>
@@ -14,8 +22,11 @@ where
 
 pub type AnySuccessResponse = BaseResponse<Value>;
 
+/// The supported color temperature range, in kelvin.
+pub const COLOR_TEMP_RANGE: (u64, u64) = (2000, 9000);
+
 /// Control commands that can be issued against govee devices.
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum ControlCommand {
     /// Toggling power state.
@@ -31,20 +42,230 @@ pub enum ControlCommand {
     ColorTem,
 }
 
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+#[error(
+    "invalid device id {:?}: expected 6 or 8 colon-separated hex octets",
+    value
+)]
+pub struct ParseDeviceIdError {
+    value: String,
+}
+
+/// The MAC-style identifier of a Govee device.
+///
+/// Govee reports ids as either 6 or 8 colon-separated hex octets. The original
+/// casing is preserved for display and serialization, but comparisons ignore
+/// case.
+///
+/// # Examples
+/// ```
+/// use govee_rs::models::DeviceId;
+///
+/// let id: DeviceId = "99:A5:A4:C1:38:29:DA:7B".parse().unwrap();
+/// let lower: DeviceId = "99:a5:a4:c1:38:29:da:7b".parse().unwrap();
+/// assert_eq!(id, lower);
+/// assert_eq!(id.to_string(), "99:A5:A4:C1:38:29:DA:7B");
+///
+/// assert!(DeviceId::parse("34:20:03:15:82:ae").is_ok());
+/// assert!(DeviceId::parse("34:20:03:15:82").is_err());
+/// assert!(DeviceId::parse("H6159").is_err());
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DeviceId(String);
+
+impl DeviceId {
+    /// Parse and validate a device id.
+    pub fn parse(s: &str) -> Result<Self, ParseDeviceIdError> {
+        s.parse()
+    }
+
+    /// The id as it was originally provided.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn is_valid(s: &str) -> bool {
+        let mut octets = 0;
+        for octet in s.split(':') {
+            if octet.len() != 2 || !octet.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return false;
+            }
+            octets += 1;
+        }
+
+        octets == 6 || octets == 8
+    }
+}
+
+impl PartialEq for DeviceId {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+}
+
+impl Eq for DeviceId {}
+
+impl Hash for DeviceId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for b in self.0.bytes() {
+            state.write_u8(b.to_ascii_lowercase());
+        }
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for DeviceId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for DeviceId {
+    type Err = ParseDeviceIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if Self::is_valid(s) {
+            Ok(Self(s.to_string()))
+        } else {
+            Err(ParseDeviceIdError {
+                value: s.to_string(),
+            })
+        }
+    }
+}
+
+impl TryFrom<String> for DeviceId {
+    type Error = ParseDeviceIdError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if Self::is_valid(&value) {
+            Ok(Self(value))
+        } else {
+            Err(ParseDeviceIdError { value })
+        }
+    }
+}
+
+impl From<DeviceId> for String {
+    fn from(value: DeviceId) -> Self {
+        value.0
+    }
+}
+
+impl<'a> From<DeviceId> for Cow<'a, DeviceId> {
+    fn from(value: DeviceId) -> Self {
+        Cow::Owned(value)
+    }
+}
+
+impl<'a> From<&'a DeviceId> for Cow<'a, DeviceId> {
+    fn from(value: &'a DeviceId) -> Self {
+        Cow::Borrowed(value)
+    }
+}
+
+/// The model (SKU) of a Govee device, like `H6159`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Model(String);
+
+impl Model {
+    pub fn new(model: impl Into<String>) -> Self {
+        Self(model.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for Model {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Model {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl From<&str> for Model {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl From<String> for Model {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl<'a> From<Model> for Cow<'a, Model> {
+    fn from(value: Model) -> Self {
+        Cow::Owned(value)
+    }
+}
+
+impl<'a> From<&'a Model> for Cow<'a, Model> {
+    fn from(value: &'a Model) -> Self {
+        Cow::Borrowed(value)
+    }
+}
+
 /// A representation of a Govee device.
-#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Device {
-    pub model: String,
-    pub device: String,
+    pub model: Model,
+    pub device: DeviceId,
     #[serde(rename = "deviceName")]
     pub name: String,
     pub controllable: bool,
     pub retrievable: bool,
-    #[serde(rename = "supportCmds")]
+    #[serde(rename = "supportCmds", serialize_with = "sorted_commands")]
     pub supported_commands: HashSet<ControlCommand>,
 }
 
+/// Serialize commands in a stable order.
+fn sorted_commands<S>(commands: &HashSet<ControlCommand>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    let mut sorted: Vec<_> = commands.iter().collect();
+    sorted.sort();
+    serializer.collect_seq(sorted)
+}
+
 impl Device {
+    /// A device with the given id and nothing else: no model or name, and no
+    /// supported commands.
+    pub fn new(device: DeviceId) -> Self {
+        Self {
+            model: Model::default(),
+            device,
+            name: String::new(),
+            controllable: false,
+            retrievable: false,
+            supported_commands: HashSet::new(),
+        }
+    }
+
     /// Check if this device supports the specified [ControlCommand].
     pub fn supports(&self, command: &ControlCommand) -> bool {
         self.supported_commands.contains(command)
@@ -53,9 +274,33 @@ impl Device {
 
 #[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Devices {
+    #[serde(deserialize_with = "skip_invalid_devices")]
     pub devices: Vec<Device>,
 }
 
+/// Deserialize a list of devices, skipping any that are invalid, like one
+/// with an id that is not shaped like a MAC address, so one odd device does
+/// not hide the rest.
+fn skip_invalid_devices<'de, D>(deserializer: D) -> Result<Vec<Device>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let raw = Vec::<Value>::deserialize(deserializer)?;
+    Ok(raw
+        .into_iter()
+        .filter_map(|value| match serde_json::from_value(value) {
+            Ok(device) => Some(device),
+            Err(e) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!("skipping invalid device: {}", e);
+                #[cfg(not(feature = "tracing"))]
+                let _ = e;
+                None
+            }
+        })
+        .collect())
+}
+
 impl Deref for Devices {
     type Target = Vec<Device>;
 
@@ -64,13 +309,224 @@ impl Deref for Devices {
     }
 }
 
-#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct DeviceState {
-    pub device: String,
-    pub model: String,
+    pub device: DeviceId,
+    pub model: Model,
     pub properties: Vec<DeviceProperty>,
 }
 
+impl DeviceState {
+    /// Flatten the reported properties into a [NormalizedState].
+    pub fn normalized(&self) -> NormalizedState {
+        NormalizedState::from(self)
+    }
+}
+
+/// A [DeviceState] with its properties flattened into optional fields.
+///
+/// Properties the device did not report are `None`. If a property is reported
+/// more than once, the last value wins.
+///
+/// # Examples
+/// ```
+/// use govee_rs::models::{DeviceProperty, DeviceState, PowerState};
+///
+/// let state = DeviceState {
+///     device: "34:20:03:2e:30:2b".parse().unwrap(),
+///     model: "H6159".into(),
+///     properties: vec![
+///         DeviceProperty::Online { online: true },
+///         DeviceProperty::PowerState { power_state: PowerState::On },
+///         DeviceProperty::Brightness { brightness: 40 },
+///     ],
+/// };
+///
+/// let normalized = state.normalized();
+/// assert_eq!(normalized.online, Some(true));
+/// assert_eq!(normalized.power, Some(PowerState::On));
+/// assert_eq!(normalized.brightness, Some(40));
+/// assert_eq!(normalized.color, None);
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct NormalizedState {
+    pub device: DeviceId,
+    pub model: Model,
+    pub online: Option<bool>,
+    pub power: Option<PowerState>,
+    pub brightness: Option<u64>,
+    pub color: Option<Color>,
+    pub color_temp: Option<u64>,
+}
+
+impl NormalizedState {
+    /// The state of a device about which nothing is known yet.
+    pub fn new(device: DeviceId) -> Self {
+        Self {
+            device,
+            model: Model::default(),
+            online: None,
+            power: None,
+            brightness: None,
+            color: None,
+            color_temp: None,
+        }
+    }
+
+    /// Update this state as though `desired` had been applied.
+    ///
+    /// This is useful for reporting a change before the API does, which can
+    /// take a few seconds to reflect it.
+    pub fn apply(&mut self, desired: &DesiredState) {
+        if let Some(power) = desired.power {
+            self.power = Some(power);
+        }
+        if let Some(brightness) = desired.brightness {
+            self.brightness = Some(brightness);
+        }
+        if let Some(color) = desired.color {
+            self.color = Some(color);
+            self.color_temp = None;
+        }
+        if let Some(temp) = desired.color_temp {
+            self.color_temp = Some(temp);
+        }
+    }
+}
+
+impl From<&DeviceState> for NormalizedState {
+    fn from(value: &DeviceState) -> Self {
+        let mut state = Self {
+            model: value.model.clone(),
+            ..Self::new(value.device.clone())
+        };
+
+        for prop in value.properties.iter() {
+            match prop {
+                DeviceProperty::Online { online } => state.online = Some(*online),
+                DeviceProperty::PowerState { power_state } => state.power = Some(*power_state),
+                DeviceProperty::Brightness { brightness } => state.brightness = Some(*brightness),
+                DeviceProperty::Color { color } => state.color = Some(*color),
+                DeviceProperty::ColorTem { color_tem } => state.color_temp = Some(*color_tem),
+            }
+        }
+
+        state
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+pub enum DesiredStateError {
+    #[error("a desired state needs at least one of power, brightness, color or color_temp")]
+    Empty,
+    #[error("color and color_temp cannot be set together")]
+    ColorConflict,
+    #[error(
+        "color_temp must be between {} and {}, got {}",
+        COLOR_TEMP_RANGE.0,
+        COLOR_TEMP_RANGE.1,
+        color_temp
+    )]
+    ColorTemp { color_temp: u64 },
+    #[error("brightness must be between 0 and 100, got {}", brightness)]
+    Brightness { brightness: u64 },
+    #[error("{} does not support {:?}", device, command)]
+    Unsupported {
+        device: String,
+        command: ControlCommand,
+    },
+}
+
+/// The state a device should be put in.
+///
+/// Unset fields are left alone. Colors may be given as a `{r, g, b}` table, a
+/// hex string or a color name.
+///
+/// # Examples
+/// ```
+/// use govee_rs::models::{ControlCmd, DesiredState, PowerState};
+///
+/// let desired: DesiredState =
+///     serde_json::from_str(r#"{"power": "on", "brightness": 40, "color": "orange"}"#).unwrap();
+///
+/// // power on comes first so the other commands are not lost
+/// assert_eq!(
+///     desired.commands().unwrap(),
+///     vec![
+///         ControlCmd::Turn(PowerState::On),
+///         ControlCmd::Color((255, 165, 0).into()),
+///         ControlCmd::Brightness(40),
+///     ]
+/// );
+/// ```
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DesiredState {
+    pub power: Option<PowerState>,
+    pub brightness: Option<u64>,
+    #[serde(deserialize_with = "color_or_hex")]
+    pub color: Option<Color>,
+    pub color_temp: Option<u64>,
+}
+
+impl DesiredState {
+    /// The commands that bring a device into this state, in the order they
+    /// should be sent.
+    pub fn commands(&self) -> Result<Vec<ControlCmd>, DesiredStateError> {
+        if self.color.is_some() && self.color_temp.is_some() {
+            return Err(DesiredStateError::ColorConflict);
+        }
+        if let Some(color_temp) = self
+            .color_temp
+            .filter(|t| *t < COLOR_TEMP_RANGE.0 || *t > COLOR_TEMP_RANGE.1)
+        {
+            return Err(DesiredStateError::ColorTemp { color_temp });
+        }
+        if let Some(brightness) = self.brightness.filter(|b| *b > 100) {
+            return Err(DesiredStateError::Brightness { brightness });
+        }
+
+        let mut cmds = Vec::new();
+        if self.power == Some(PowerState::On) {
+            cmds.push(ControlCmd::Turn(PowerState::On));
+        }
+        if let Some(color) = self.color {
+            cmds.push(ControlCmd::Color(color));
+        }
+        if let Some(temp) = self.color_temp {
+            cmds.push(ControlCmd::ColorTem(temp));
+        }
+        if let Some(brightness) = self.brightness {
+            cmds.push(ControlCmd::Brightness(brightness));
+        }
+        if self.power == Some(PowerState::Off) {
+            cmds.push(ControlCmd::Turn(PowerState::Off));
+        }
+
+        if cmds.is_empty() {
+            return Err(DesiredStateError::Empty);
+        }
+        Ok(cmds)
+    }
+
+    /// Like [DesiredState::commands], but also checks that `device` supports
+    /// every command.
+    pub fn commands_for(&self, device: &Device) -> Result<Vec<ControlCmd>, DesiredStateError> {
+        let cmds = self.commands()?;
+        if let Some(command) = cmds
+            .iter()
+            .map(ControlCmd::command)
+            .find(|c| !device.supports(c))
+        {
+            return Err(DesiredStateError::Unsupported {
+                device: device.name.clone(),
+                command,
+            });
+        }
+        Ok(cmds)
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum PowerState {
@@ -78,6 +534,15 @@ pub enum PowerState {
     On,
 }
 
+impl fmt::Display for PowerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Off => f.write_str("off"),
+            Self::On => f.write_str("on"),
+        }
+    }
+}
+
 /// A RGB color.
 ///
 /// # Examples
@@ -97,6 +562,9 @@ pub enum PowerState {
 ///
 /// let parsed = Color::parse("#12FF07").unwrap();
 /// assert_eq!(parsed, Color {r: 18, g: 255, b: 7});
+///
+/// // and displays as a hex string
+/// assert_eq!(parsed.to_string(), "#12ff07");
 /// ```
 #[derive(Debug, Clone, Copy, Eq, Default, PartialEq, Serialize, Deserialize)]
 pub struct Color {
@@ -110,8 +578,117 @@ impl Color {
     pub fn parse(s: &str) -> Result<Self, hex_color::ParseHexColorError> {
         s.parse()
     }
+
+    /// Look up a color by its CSS name, ignoring case.
+    ///
+    /// # Examples
+    /// ```
+    /// use govee_rs::Color;
+    ///
+    /// assert_eq!(Color::named("Orange"), Some(Color {r: 255, g: 165, b: 0}));
+    /// assert_eq!(Color::named("no such color"), None);
+    /// ```
+    pub fn named(name: &str) -> Option<Self> {
+        let name = name.trim().to_ascii_lowercase();
+        NAMED_COLORS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, rgb)| Self::from(*rgb))
+    }
+
+    /// Make a color from a hue in degrees and a saturation and value from 0
+    /// to 1.
+    ///
+    /// # Examples
+    /// ```
+    /// use govee_rs::Color;
+    ///
+    /// assert_eq!(Color::from_hsv(0.0, 1.0, 1.0), Color { r: 255, g: 0, b: 0 });
+    /// assert_eq!(Color::from_hsv(240.0, 1.0, 0.5), Color { r: 0, g: 0, b: 128 });
+    /// ```
+    pub fn from_hsv(hue: f64, saturation: f64, value: f64) -> Self {
+        let hue = hue.rem_euclid(360.0) / 60.0;
+        let s = saturation.clamp(0.0, 1.0);
+        let v = value.clamp(0.0, 1.0);
+
+        let c = v * s;
+        let x = c * (1.0 - (hue % 2.0 - 1.0).abs());
+        let (r, g, b) = match hue as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let m = v - c;
+        let channel = |n: f64| ((n + m) * 255.0).round() as u8;
+        Self {
+            r: channel(r),
+            g: channel(g),
+            b: channel(b),
+        }
+    }
+
+    /// The color `amount` of the way from this one to `other`, where 0 is
+    /// this color and 1 is `other`.
+    ///
+    /// # Examples
+    /// ```
+    /// use govee_rs::Color;
+    ///
+    /// let black = Color { r: 0, g: 0, b: 0 };
+    /// let white = Color { r: 255, g: 255, b: 255 };
+    /// assert_eq!(black.mix(white, 0.5), Color { r: 128, g: 128, b: 128 });
+    /// ```
+    pub fn mix(self, other: Self, amount: f64) -> Self {
+        let t = amount.clamp(0.0, 1.0);
+        let channel =
+            |a: u8, b: u8| (f64::from(a) + (f64::from(b) - f64::from(a)) * t).round() as u8;
+        Self {
+            r: channel(self.r, other.r),
+            g: channel(self.g, other.g),
+            b: channel(self.b, other.b),
+        }
+    }
 }
 
+const NAMED_COLORS: &[(&str, (u8, u8, u8))] = &[
+    ("black", (0, 0, 0)),
+    ("white", (255, 255, 255)),
+    ("red", (255, 0, 0)),
+    ("lime", (0, 255, 0)),
+    ("green", (0, 128, 0)),
+    ("blue", (0, 0, 255)),
+    ("yellow", (255, 255, 0)),
+    ("cyan", (0, 255, 255)),
+    ("aqua", (0, 255, 255)),
+    ("magenta", (255, 0, 255)),
+    ("fuchsia", (255, 0, 255)),
+    ("orange", (255, 165, 0)),
+    ("purple", (128, 0, 128)),
+    ("pink", (255, 192, 203)),
+    ("hotpink", (255, 105, 180)),
+    ("violet", (238, 130, 238)),
+    ("indigo", (75, 0, 130)),
+    ("teal", (0, 128, 128)),
+    ("navy", (0, 0, 128)),
+    ("maroon", (128, 0, 0)),
+    ("olive", (128, 128, 0)),
+    ("silver", (192, 192, 192)),
+    ("gray", (128, 128, 128)),
+    ("grey", (128, 128, 128)),
+    ("gold", (255, 215, 0)),
+    ("coral", (255, 127, 80)),
+    ("salmon", (250, 128, 114)),
+    ("crimson", (220, 20, 60)),
+    ("turquoise", (64, 224, 208)),
+    ("lavender", (230, 230, 250)),
+    ("chartreuse", (127, 255, 0)),
+    ("skyblue", (135, 206, 235)),
+    ("tomato", (255, 99, 71)),
+];
+
 impl From<(u8, u8, u8)> for Color {
     fn from(value: (u8, u8, u8)) -> Self {
         Self {
@@ -132,6 +709,13 @@ impl From<HexColor> for Color {
     }
 }
 
+impl fmt::Display for Color {
+    /// Formats as a lowercase hex string like `#0aff06`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
 impl FromStr for Color {
     type Err = hex_color::ParseHexColorError;
 
@@ -141,6 +725,31 @@ impl FromStr for Color {
     }
 }
 
+/// Accept either a `{r, g, b}` table, a hex string like `"#ff8800"` or a
+/// color name like `"orange"`.
+pub(crate) fn color_or_hex<'de, D>(deserializer: D) -> Result<Option<Color>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Hex(String),
+        Color(Color),
+    }
+
+    match Option::<Raw>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Raw::Color(c)) => Ok(Some(c)),
+        Some(Raw::Hex(s)) => match Color::parse(&s) {
+            Ok(c) => Ok(Some(c)),
+            Err(e) => Color::named(&s)
+                .map(Some)
+                .ok_or_else(|| serde::de::Error::custom(format!("invalid color {:?}: {}", s, e))),
+        },
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase", untagged)]
 pub enum DeviceProperty {
@@ -165,8 +774,8 @@ pub enum DeviceProperty {
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ControlRequest<'a> {
-    pub device: Cow<'a, str>,
-    pub model: Cow<'a, str>,
+    pub device: Cow<'a, DeviceId>,
+    pub model: Cow<'a, Model>,
     pub cmd: ControlCmd,
 }
 
@@ -179,8 +788,58 @@ pub enum ControlCmd {
     ColorTem(u64),
 }
 
+impl ControlCmd {
+    /// The [ControlCommand] a device must support to accept this command.
+    pub fn command(&self) -> ControlCommand {
+        match self {
+            Self::Turn(_) => ControlCommand::Turn,
+            Self::Brightness(_) => ControlCommand::Brightness,
+            Self::Color(_) => ControlCommand::Color,
+            Self::ColorTem(_) => ControlCommand::ColorTem,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    mod device_id {
+        use std::collections::HashSet;
+
+        use super::super::*;
+
+        #[test]
+        fn validation() {
+            assert!(DeviceId::parse("99:A5:A4:C1:38:29:DA:7B").is_ok());
+            assert!(DeviceId::parse("34:20:03:2e:30:2b").is_ok());
+
+            assert!(DeviceId::parse("").is_err());
+            assert!(DeviceId::parse("34:20:03:2e:30").is_err());
+            assert!(DeviceId::parse("34:20:03:2e:30:2b:00").is_err());
+            assert!(DeviceId::parse("34:20:03:2e:30:2g").is_err());
+            assert!(DeviceId::parse("34:20:03:2e:30:2b:").is_err());
+            assert!(DeviceId::parse("342003:2e:30:2b:aa:bb").is_err());
+        }
+
+        #[test]
+        fn case_insensitive() {
+            let upper = DeviceId::parse("34:20:03:2E:30:2B").unwrap();
+            let lower = DeviceId::parse("34:20:03:2e:30:2b").unwrap();
+            assert_eq!(upper, lower);
+
+            let set: HashSet<DeviceId> = [upper, lower].into_iter().collect();
+            assert_eq!(set.len(), 1);
+        }
+
+        #[test]
+        fn serde() {
+            let id: DeviceId = serde_json::from_str("\"34:20:03:2E:30:2B\"").unwrap();
+            assert_eq!(id.as_str(), "34:20:03:2E:30:2B");
+            assert_eq!(serde_json::to_string(&id).unwrap(), "\"34:20:03:2E:30:2B\"");
+
+            assert!(serde_json::from_str::<DeviceId>("\"H6159\"").is_err());
+        }
+    }
+
     mod device_property {
         use super::super::*;
 
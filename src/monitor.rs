@@ -0,0 +1,129 @@
+//! Polling device state without exhausting the API quota.
+//!
+//! The developer API allows a limited number of requests per account per day,
+//! so anything that polls continuously needs to space its requests out. The
+//! [Monitor] here takes care of that, leaving scheduling of the polls
+//! themselves to the caller's runtime.
+use std::time::{Duration, SystemTime};
+
+use crate::{
+    client::{GoveeClient, GoveeError},
+    models::{Device, Devices, NormalizedState},
+};
+
+/// The number of requests Govee allows per account per day.
+pub const DAILY_REQUEST_LIMIT: u32 = 10_000;
+
+/// The fraction of the daily quota a [Monitor] uses by default, leaving the
+/// rest for control requests and other tools.
+pub const DEFAULT_QUOTA_SHARE: f64 = 0.5;
+
+/// The shortest interval [quota_safe_interval] will ever return.
+pub const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often `devices` devices can each be polled while spending at most
+/// `share` of the daily quota.
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+/// use govee_rs::monitor::quota_safe_interval;
+///
+/// // 86400s / (10000 * 0.5) is ~17.3s per request
+/// let interval = quota_safe_interval(2, 0.5);
+/// assert!(interval > Duration::from_secs(34) && interval < Duration::from_secs(35));
+/// ```
+pub fn quota_safe_interval(devices: usize, share: f64) -> Duration {
+    let share = share.clamp(f64::EPSILON, 1.0);
+    let per_request = 86_400.0 / (f64::from(DAILY_REQUEST_LIMIT) * share);
+    Duration::from_secs_f64(per_request * devices.max(1) as f64).max(MIN_INTERVAL)
+}
+
+/// The result of polling a single device.
+#[derive(Debug)]
+pub struct Snapshot {
+    pub device: Device,
+    pub state: Result<NormalizedState, GoveeError>,
+    pub at: SystemTime,
+}
+
+/// Polls the state of a fixed set of devices.
+#[derive(Clone)]
+pub struct Monitor {
+    client: GoveeClient,
+    devices: Devices,
+    interval: Duration,
+}
+
+impl Monitor {
+    /// Make a [Monitor] for the retrievable devices in `devices`, polling at a
+    /// quota-safe interval.
+    pub fn new(client: GoveeClient, devices: &Devices) -> Self {
+        let devices = devices.retrievable();
+        let interval = quota_safe_interval(devices.len(), DEFAULT_QUOTA_SHARE);
+        Self {
+            client,
+            devices,
+            interval,
+        }
+    }
+
+    /// Override the polling interval.
+    ///
+    /// Intervals below [MIN_INTERVAL] are raised to it.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval.max(MIN_INTERVAL);
+        self
+    }
+
+    /// How long to wait between calls to [Monitor::poll].
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn devices(&self) -> &Devices {
+        &self.devices
+    }
+
+    pub fn client(&self) -> &GoveeClient {
+        &self.client
+    }
+
+    /// Poll a single device.
+    pub async fn poll_device(&self, device: &Device) -> Snapshot {
+        let state = self.client.state(device).await.map(|s| s.normalized());
+        Snapshot {
+            device: device.clone(),
+            state,
+            at: SystemTime::now(),
+        }
+    }
+
+    /// Poll every device once, in order.
+    pub async fn poll(&self) -> Vec<Snapshot> {
+        let mut snapshots = Vec::with_capacity(self.devices.len());
+        for device in self.devices.iter() {
+            snapshots.push(self.poll_device(device).await);
+        }
+        snapshots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interval_scales_with_devices() {
+        let one = quota_safe_interval(1, 1.0);
+        let ten = quota_safe_interval(10, 1.0);
+        assert_eq!(one, Duration::from_secs_f64(8.64));
+        assert_eq!(ten, Duration::from_secs_f64(86.4));
+
+        // zero devices is treated as one
+        assert_eq!(quota_safe_interval(0, 1.0), one);
+
+        // a smaller share means slower polling
+        assert!(quota_safe_interval(1, 0.1) > one);
+    }
+}
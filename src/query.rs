@@ -0,0 +1,256 @@
+//! Filtering and set operations over [Devices].
+//!
+//! Every helper returns a new [Devices], so they can be chained and the
+//! result handed to anything that accepts a device listing.
+//!
+//! # Examples
+//! ```
+//! use govee_rs::models::{ControlCommand, Device, Devices};
+//!
+//! let devices = Devices {
+//!     devices: vec![
+//!         Device {
+//!             model: "H6159".into(),
+//!             name: "Desk Strip".into(),
+//!             controllable: true,
+//!             supported_commands: [ControlCommand::Turn, ControlCommand::Color].into(),
+//!             ..Device::new("34:20:03:2e:30:01".parse().unwrap())
+//!         },
+//!         Device {
+//!             model: "H5081".into(),
+//!             name: "Smart Plug".into(),
+//!             controllable: true,
+//!             supported_commands: [ControlCommand::Turn].into(),
+//!             ..Device::new("34:20:03:2e:30:02".parse().unwrap())
+//!         },
+//!     ],
+//! };
+//!
+//! let lights = devices.controllable().supporting(ControlCommand::Color);
+//! assert_eq!(lights.len(), 1);
+//!
+//! let strips = devices.with_model_prefix("h61").union(&devices.matching_name("*plug"));
+//! assert_eq!(strips.len(), 2);
+//! ```
+use std::collections::HashSet;
+
+use crate::models::{ControlCommand, Device, Devices};
+
+impl Devices {
+    /// The devices for which `pred` returns true.
+    pub fn filtered<F>(&self, pred: F) -> Devices
+    where
+        F: Fn(&Device) -> bool,
+    {
+        self.iter().filter(|d| pred(d)).cloned().collect()
+    }
+
+    /// The devices that support the given command.
+    pub fn supporting(&self, command: ControlCommand) -> Devices {
+        self.filtered(|d| d.supports(&command))
+    }
+
+    /// The devices that can be controlled.
+    pub fn controllable(&self) -> Devices {
+        self.filtered(|d| d.controllable)
+    }
+
+    /// The devices whose state can be retrieved.
+    pub fn retrievable(&self) -> Devices {
+        self.filtered(|d| d.retrievable)
+    }
+
+    /// The devices whose model starts with `prefix`, ignoring case.
+    pub fn with_model_prefix(&self, prefix: &str) -> Devices {
+        let prefix = prefix.to_ascii_lowercase();
+        self.filtered(|d| d.model.as_str().to_ascii_lowercase().starts_with(&prefix))
+    }
+
+    /// The devices whose name matches a glob-style `pattern`, ignoring case.
+    ///
+    /// `*` matches any run of characters and `?` matches a single character.
+    pub fn matching_name(&self, pattern: &str) -> Devices {
+        let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+        self.filtered(|d| {
+            let name: Vec<char> = d.name.to_lowercase().chars().collect();
+            glob_match(&pattern, &name)
+        })
+    }
+
+    /// The devices in either listing, in order of first appearance. Each
+    /// device appears once, even if it is repeated in either listing.
+    pub fn union(&self, other: &Devices) -> Devices {
+        let mut seen = HashSet::new();
+        self.iter()
+            .chain(other.iter())
+            .filter(|d| seen.insert(&d.device))
+            .cloned()
+            .collect()
+    }
+
+    /// The devices in both listings.
+    pub fn intersection(&self, other: &Devices) -> Devices {
+        self.filtered(|d| other.contains_device(d))
+    }
+
+    /// The devices in this listing but not in `other`.
+    pub fn difference(&self, other: &Devices) -> Devices {
+        self.filtered(|d| !other.contains_device(d))
+    }
+
+    fn contains_device(&self, device: &Device) -> bool {
+        self.iter().any(|d| d.device == device.device)
+    }
+}
+
+impl FromIterator<Device> for Devices {
+    fn from_iter<T: IntoIterator<Item = Device>>(iter: T) -> Self {
+        Self {
+            devices: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl IntoIterator for Devices {
+    type Item = Device;
+    type IntoIter = std::vec::IntoIter<Device>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.devices.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Devices {
+    type Item = &'a Device;
+    type IntoIter = std::slice::Iter<'a, Device>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.devices.iter()
+    }
+}
+
+fn glob_match(pattern: &[char], s: &[char]) -> bool {
+    let (mut p, mut i) = (0, 0);
+    // where to resume if the most recent `*` needs to swallow more input
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while i < s.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, i));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == s[i] => {
+                p += 1;
+                i += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bi)) => {
+                    p = bp + 1;
+                    i = bi + 1;
+                    backtrack = Some((bp, bi + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::DeviceId;
+
+    use super::*;
+
+    fn device(id: &str, model: &str, name: &str, commands: &[ControlCommand]) -> Device {
+        Device {
+            device: DeviceId::parse(id).unwrap(),
+            model: model.into(),
+            name: name.into(),
+            controllable: true,
+            retrievable: !commands.is_empty(),
+            supported_commands: commands.iter().copied().collect(),
+        }
+    }
+
+    fn devices() -> Devices {
+        use ControlCommand::*;
+        Devices {
+            devices: vec![
+                device("34:20:03:2e:30:01", "H6159", "Desk Strip", &[Turn, Color]),
+                device(
+                    "34:20:03:2e:30:02",
+                    "H6163",
+                    "Kitchen Strip",
+                    &[Turn, Brightness],
+                ),
+                device("34:20:03:2e:30:03", "H5081", "Smart Plug", &[Turn]),
+                device("34:20:03:2e:30:04", "H7131", "Heater", &[]),
+            ],
+        }
+    }
+
+    fn names(devices: &Devices) -> Vec<&str> {
+        devices.iter().map(|d| d.name.as_str()).collect()
+    }
+
+    #[test]
+    fn filters() {
+        let devices = devices();
+        assert_eq!(
+            names(&devices.supporting(ControlCommand::Turn)),
+            vec!["Desk Strip", "Kitchen Strip", "Smart Plug"]
+        );
+        assert_eq!(names(&devices.retrievable()).len(), 3);
+        assert_eq!(names(&devices.controllable()).len(), 4);
+        assert_eq!(
+            names(&devices.with_model_prefix("h61")),
+            vec!["Desk Strip", "Kitchen Strip"]
+        );
+    }
+
+    #[test]
+    fn name_patterns() {
+        let devices = devices();
+        assert_eq!(
+            names(&devices.matching_name("*strip")),
+            vec!["Desk Strip", "Kitchen Strip"]
+        );
+        assert_eq!(names(&devices.matching_name("k*")), vec!["Kitchen Strip"]);
+        assert_eq!(names(&devices.matching_name("he?ter")), vec!["Heater"]);
+        assert_eq!(names(&devices.matching_name("*e*r*")).len(), 3);
+        assert!(devices.matching_name("strip").is_empty());
+    }
+
+    #[test]
+    fn set_operations() {
+        let devices = devices();
+        let strips = devices.matching_name("*strip");
+        let colorful = devices.supporting(ControlCommand::Color);
+        let plugs = devices.with_model_prefix("H50");
+
+        assert_eq!(names(&strips.intersection(&colorful)), vec!["Desk Strip"]);
+        assert_eq!(names(&strips.difference(&colorful)), vec!["Kitchen Strip"]);
+        assert_eq!(
+            names(&colorful.union(&plugs).union(&strips)),
+            vec!["Desk Strip", "Smart Plug", "Kitchen Strip"]
+        );
+    }
+
+    #[test]
+    fn union_drops_repeats() {
+        let devices = devices();
+        let heater = devices.matching_name("heater");
+        let twice = Devices {
+            devices: vec![heater[0].clone(), devices[0].clone(), heater[0].clone()],
+        };
+
+        assert_eq!(names(&heater.union(&twice)), vec!["Heater", "Desk Strip"]);
+        assert_eq!(
+            names(&Devices::default().union(&twice)),
+            vec!["Heater", "Desk Strip"]
+        );
+    }
+}
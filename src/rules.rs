@@ -0,0 +1,1147 @@
+//! Reacting to device changes and the time of day.
+//!
+//! A [RuleSet] is a list of rules, usually written as a TOML or YAML file.
+//! Each rule has a trigger (`when`), optional conditions that must all hold
+//! when it fires (`if`), and [sequence](crate::sequence) steps to run
+//! (`then`):
+//!
+//! ```toml
+//! timezone = "Europe/London"
+//!
+//! [[rules]]
+//! name = "office off in the evening"
+//! when = { device = "desk lamp", turns = "off" }
+//! if = [{ after = "18:00" }]
+//! then = [{ set = "office", power = "off" }]
+//!
+//! [[rules]]
+//! name = "heater timeout"
+//! when = { device = "heater plug", on_for = "2h" }
+//! then = [{ set = "heater plug", power = "off" }]
+//!
+//! [[rules]]
+//! name = "porch at dusk"
+//! when = { at = "0 19 * * *" }
+//! if = [{ device = "porch", power = "off" }]
+//! then = [
+//!     { set = "porch", power = "on" },
+//!     { wait = "4h" },
+//!     { set = "porch", power = "off" },
+//! ]
+//! ```
+//!
+//! Triggers are one of:
+//!
+//! - `{ device = .., turns = "on" | "off" }`, when a device's power changes.
+//! - `{ device = .., changes = "brightness" }`, when any of `online`,
+//!   `power`, `brightness`, `color` or `color_temp` changes.
+//! - `{ device = .., on_for = "2h" }`, once each time a device has been on
+//!   that long.
+//! - `{ at = .. }`, a [schedule] trigger, either a cron expression or a solar
+//!   event like `sunset+15m`, which needs a `location`.
+//!
+//! Conditions are either `{ after = "18:00", before = "06:00" }`, where
+//! either end may be left out and the range may span midnight, or
+//! `{ device = .., power = .., online = .., brightness_above = ..,
+//! brightness_below = .. }` with any of the properties given.
+//!
+//! Devices are named as anywhere else, so a trigger or condition can name a
+//! room or group: a trigger fires for a change to any of its devices, and a
+//! condition holds only if it holds for all of them.
+//!
+//! A [RuleEngine] made with [RuleEngine::from_file] reloads its rules when
+//! the file changes, keeping the old rules if the new ones are invalid.
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::{broadcast, mpsc};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    client::GoveeClient,
+    config::{self, ConfigError},
+    events::{Field, StateEvent, Watcher},
+    lookup::Resolve,
+    models::{DeviceId, Devices, NormalizedState, PowerState},
+    schedule::{Location, Trigger, ON_TIME},
+    sequence::{OnCancel, Plan, Sequence, SequenceError, Step},
+};
+
+/// How often time triggers, `on_for` triggers and the rules file are checked.
+pub const TICK: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error)]
+pub enum RulesError {
+    #[error("failed to load rules: {}", source)]
+    Config {
+        #[from]
+        source: ConfigError,
+    },
+    #[error("invalid rules: {}", ProblemList(problems))]
+    Invalid { problems: Vec<RuleProblem> },
+}
+
+/// A problem with a particular rule, found by [RuleSet::plan].
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+#[error("{}: {}", path, message)]
+pub struct RuleProblem {
+    /// Where the problem is, like `rules[2].if[0]`.
+    pub path: String,
+    pub message: String,
+}
+
+struct ProblemList<'a>(&'a [RuleProblem]);
+
+impl<'a> fmt::Display for ProblemList<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, p) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}", p)?;
+        }
+        Ok(())
+    }
+}
+
+/// Deserialize one of several structs, picked by which identifying key is
+/// present, so mistakes are reported against the right kind.
+fn dispatch<'de, D, T>(
+    deserializer: D,
+    what: &str,
+    kinds: &[&str],
+    pick: impl FnOnce(&str, serde_json::Value) -> serde_json::Result<T>,
+) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    let value = serde_json::Value::deserialize(deserializer)?;
+    let Some(map) = value.as_object() else {
+        return Err(D::Error::custom(format!("a {} must be a table", what)));
+    };
+
+    let found: Vec<_> = kinds.iter().filter(|k| map.contains_key(**k)).collect();
+    match found.as_slice() {
+        [kind] => pick(kind, value).map_err(D::Error::custom),
+        [] => Err(D::Error::custom(format!(
+            "a {} needs one of: {}",
+            what,
+            kinds.join(", ")
+        ))),
+        _ => Err(D::Error::custom(format!(
+            "a {} can only be one of: {}",
+            what,
+            kinds.join(", ")
+        ))),
+    }
+}
+
+/// Fires when a device turns on or off.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TurnsTrigger {
+    pub device: String,
+    pub turns: PowerState,
+}
+
+/// Fires when a property of a device changes.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChangesTrigger {
+    pub device: String,
+    pub changes: Field,
+}
+
+/// Fires once each time a device has been on for a while.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OnForTrigger {
+    pub device: String,
+    #[serde(with = "humantime_serde")]
+    pub on_for: Duration,
+}
+
+/// Fires at a time.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AtTrigger {
+    pub at: Trigger,
+}
+
+/// What makes a rule fire, identified by which of `turns`, `changes`,
+/// `on_for` or `at` it contains.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum When {
+    Turns(TurnsTrigger),
+    Changes(ChangesTrigger),
+    OnFor(OnForTrigger),
+    At(AtTrigger),
+}
+
+impl<'de> Deserialize<'de> for When {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let kinds = ["turns", "changes", "on_for", "at"];
+        dispatch(deserializer, "trigger", &kinds, |kind, value| match kind {
+            "turns" => serde_json::from_value(value).map(When::Turns),
+            "changes" => serde_json::from_value(value).map(When::Changes),
+            "on_for" => serde_json::from_value(value).map(When::OnFor),
+            _ => serde_json::from_value(value).map(When::At),
+        })
+    }
+}
+
+/// Holds between two times of day.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeCondition {
+    #[serde(
+        default,
+        with = "crate::time_of_day::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub after: Option<NaiveTime>,
+    #[serde(
+        default,
+        with = "crate::time_of_day::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub before: Option<NaiveTime>,
+}
+
+impl TimeCondition {
+    /// If `time` is in the range, which wraps around midnight when `after`
+    /// is later than `before`.
+    pub fn holds(&self, time: NaiveTime) -> bool {
+        match (self.after, self.before) {
+            (Some(after), Some(before)) if after <= before => after <= time && time < before,
+            (Some(after), Some(before)) => after <= time || time < before,
+            (Some(after), None) => after <= time,
+            (None, Some(before)) => time < before,
+            (None, None) => true,
+        }
+    }
+}
+
+/// Holds when a device is in a state.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceCondition {
+    pub device: String,
+    #[serde(default)]
+    pub power: Option<PowerState>,
+    #[serde(default)]
+    pub online: Option<bool>,
+    #[serde(default)]
+    pub brightness_above: Option<u64>,
+    #[serde(default)]
+    pub brightness_below: Option<u64>,
+}
+
+impl DeviceCondition {
+    /// If `state` satisfies every property given. Properties the state does
+    /// not report do not.
+    pub fn holds(&self, state: &NormalizedState) -> bool {
+        let brightness = state.brightness;
+        self.power.map_or(true, |p| state.power == Some(p))
+            && self.online.map_or(true, |o| state.online == Some(o))
+            && self
+                .brightness_above
+                .map_or(true, |above| brightness.is_some_and(|b| b > above))
+            && self
+                .brightness_below
+                .map_or(true, |below| brightness.is_some_and(|b| b < below))
+    }
+}
+
+/// Something that must hold for a rule to run, identified by whether it
+/// names a `device`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Condition {
+    Time(TimeCondition),
+    Device(DeviceCondition),
+}
+
+impl<'de> Deserialize<'de> for Condition {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let condition = if value.get("device").is_some() {
+            serde_json::from_value(value).map(Condition::Device)
+        } else {
+            serde_json::from_value(value).map(Condition::Time)
+        };
+        condition.map_err(D::Error::custom)
+    }
+}
+
+/// A rule as written by a user.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub name: String,
+    pub when: When,
+    #[serde(default, rename = "if", skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<Condition>,
+    #[serde(rename = "then")]
+    pub steps: Vec<Step>,
+}
+
+/// A list of rules as written by a user.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleSet {
+    /// An IANA time zone name like `Europe/London`, defaulting to UTC.
+    #[serde(default)]
+    pub timezone: Option<String>,
+
+    /// Needed by rules that fire at solar events.
+    #[serde(default)]
+    pub location: Option<Location>,
+    pub rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    /// Load rules from a `.toml`, `.yaml` or `.yml` file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, RulesError> {
+        Ok(config::load(path.as_ref())?)
+    }
+
+    pub fn from_toml(raw: &str) -> Result<Self, RulesError> {
+        Ok(config::from_toml(raw)?)
+    }
+
+    pub fn from_yaml(raw: &str) -> Result<Self, RulesError> {
+        Ok(config::from_yaml(raw)?)
+    }
+
+    /// Validate the rules and resolve their devices.
+    ///
+    /// Every problem found is reported, not just the first.
+    pub fn plan<R: Resolve + ?Sized>(&self, resolver: &R) -> Result<Rules, RulesError> {
+        let mut problems = Vec::new();
+        let mut problem =
+            |path: String, message: String| problems.push(RuleProblem { path, message });
+
+        let timezone = match self.timezone.as_deref().map(str::parse::<Tz>) {
+            None => Tz::UTC,
+            Some(Ok(tz)) => tz,
+            Some(Err(_)) => {
+                problem(
+                    "timezone".into(),
+                    format!(
+                        "unknown time zone {:?}",
+                        self.timezone.as_deref().unwrap_or_default()
+                    ),
+                );
+                Tz::UTC
+            }
+        };
+
+        let mut resolve = |path: String, name: &str| match resolver.resolve(name) {
+            Ok(devices) => Some(devices),
+            Err(e) => {
+                problem(path, e.to_string());
+                None
+            }
+        };
+
+        let mut rules = Vec::new();
+        let mut names = HashMap::new();
+        let mut later = Vec::new();
+        for (i, rule) in self.rules.iter().enumerate() {
+            let path = format!("rules[{}]", i);
+            if let Some(first) = names.insert(rule.name.as_str(), i) {
+                later.push((
+                    path.clone(),
+                    format!("{:?} is also the name of rules[{}]", rule.name, first),
+                ));
+            }
+
+            let when_path = format!("{}.when", path);
+            let when = match &rule.when {
+                When::Turns(t) => resolve(when_path, &t.device).map(|devices| PlannedWhen::Turns {
+                    devices,
+                    power: t.turns,
+                }),
+                When::Changes(c) => {
+                    resolve(when_path, &c.device).map(|devices| PlannedWhen::Changes {
+                        devices,
+                        field: c.changes,
+                    })
+                }
+                When::OnFor(o) => resolve(when_path, &o.device).map(|devices| PlannedWhen::OnFor {
+                    devices,
+                    duration: o.on_for,
+                }),
+                When::At(a) => {
+                    if matches!(a.at, Trigger::Solar { .. }) && self.location.is_none() {
+                        later.push((when_path, format!("{} needs a location", a.at)));
+                    }
+                    Some(PlannedWhen::At(a.at.clone()))
+                }
+            };
+
+            let mut conditions = Vec::new();
+            for (j, condition) in rule.conditions.iter().enumerate() {
+                let path = format!("{}.if[{}]", path, j);
+                match condition {
+                    Condition::Time(t) if t.after.is_none() && t.before.is_none() => {
+                        later.push((path, "a time condition needs after, before or both".into()))
+                    }
+                    Condition::Time(t) => conditions.push(PlannedCondition::Time(t.clone())),
+                    Condition::Device(d) => {
+                        if let Some(devices) = resolve(path, &d.device) {
+                            conditions.push(PlannedCondition::Device {
+                                devices,
+                                condition: d.clone(),
+                            });
+                        }
+                    }
+                }
+            }
+
+            let sequence = Sequence {
+                name: Some(rule.name.clone()),
+                on_cancel: OnCancel::Leave,
+                steps: rule.steps.clone(),
+            };
+            let plan = match sequence.plan(resolver) {
+                Ok(plan) => Some(plan),
+                Err(SequenceError::Invalid { problems }) => {
+                    for p in problems {
+                        // sequence paths start with `steps`
+                        let within = p.path.strip_prefix("steps").unwrap_or(&p.path);
+                        later.push((format!("{}.then{}", path, within), p.message));
+                    }
+                    None
+                }
+                Err(e) => {
+                    later.push((format!("{}.then", path), e.to_string()));
+                    None
+                }
+            };
+
+            if let (Some(when), Some(plan)) = (when, plan) {
+                rules.push(PlannedRule {
+                    name: rule.name.clone(),
+                    when,
+                    conditions,
+                    plan,
+                });
+            }
+        }
+
+        for (path, message) in later {
+            problem(path, message);
+        }
+        if !problems.is_empty() {
+            return Err(RulesError::Invalid { problems });
+        }
+
+        Ok(Rules {
+            timezone,
+            location: self.location,
+            rules,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum PlannedWhen {
+    Turns {
+        devices: Devices,
+        power: PowerState,
+    },
+    Changes {
+        devices: Devices,
+        field: Field,
+    },
+    OnFor {
+        devices: Devices,
+        duration: Duration,
+    },
+    At(Trigger),
+}
+
+#[derive(Debug, Clone)]
+enum PlannedCondition {
+    Time(TimeCondition),
+    Device {
+        devices: Devices,
+        condition: DeviceCondition,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct PlannedRule {
+    name: String,
+    when: PlannedWhen,
+    conditions: Vec<PlannedCondition>,
+    plan: Plan,
+}
+
+fn field_name(field: Field) -> &'static str {
+    match field {
+        Field::Online => "online",
+        Field::Power => "power",
+        Field::Brightness => "brightness",
+        Field::Color => "color",
+        Field::ColorTemp => "color temperature",
+    }
+}
+
+impl PlannedRule {
+    /// Why `event` fires this rule, if it does.
+    fn fired_by(&self, event: &StateEvent) -> Option<String> {
+        match &self.when {
+            PlannedWhen::Turns { devices, power } => (devices.by_id(&event.device).is_some()
+                && event.changed.contains(&Field::Power)
+                && event.state.power == Some(*power))
+            .then(|| {
+                let power = match power {
+                    PowerState::On => "on",
+                    PowerState::Off => "off",
+                };
+                format!("{} turned {}", event.name, power)
+            }),
+            PlannedWhen::Changes { devices, field } => (devices.by_id(&event.device).is_some()
+                && event.changed.contains(field))
+            .then(|| format!("{} {} changed", event.name, field_name(*field))),
+            PlannedWhen::OnFor { .. } | PlannedWhen::At(_) => None,
+        }
+    }
+
+    fn conditions_hold(
+        &self,
+        states: &HashMap<DeviceId, NormalizedState>,
+        time: NaiveTime,
+    ) -> bool {
+        self.conditions.iter().all(|c| match c {
+            PlannedCondition::Time(t) => t.holds(time),
+            PlannedCondition::Device { devices, condition } => devices.iter().all(|d| {
+                states
+                    .get(&d.device)
+                    .is_some_and(|state| condition.holds(state))
+            }),
+        })
+    }
+}
+
+/// A validated [RuleSet] with its devices resolved.
+#[derive(Debug, Clone)]
+pub struct Rules {
+    timezone: Tz,
+    location: Option<Location>,
+    rules: Vec<PlannedRule>,
+}
+
+impl Rules {
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.rules.iter().map(|r| r.name.as_str())
+    }
+
+    /// When each time triggered rule is next due after `after`.
+    fn next_due(&self, after: DateTime<Utc>) -> HashMap<String, DateTime<Utc>> {
+        self.rules
+            .iter()
+            .filter_map(|rule| {
+                let PlannedWhen::At(trigger) = &rule.when else {
+                    return None;
+                };
+                let next = trigger
+                    .next_after(&after.with_timezone(&self.timezone), self.location.as_ref())?;
+                Some((rule.name.clone(), next.with_timezone(&Utc)))
+            })
+            .collect()
+    }
+}
+
+/// Something a [RuleEngine] did.
+#[derive(Debug)]
+pub enum RuleEvent {
+    /// A rule's conditions held when it was triggered, and its steps started.
+    Fired {
+        rule: String,
+        because: String,
+    },
+    /// A rule's steps completed.
+    Finished {
+        rule: String,
+    },
+    Failed {
+        rule: String,
+        error: SequenceError,
+    },
+    /// The rules file changed and its new rules are in use.
+    Reloaded {
+        rules: usize,
+    },
+    /// The rules file changed but could not be used, so the old rules are
+    /// still in use.
+    ReloadFailed {
+        error: RulesError,
+    },
+}
+
+impl fmt::Display for RuleEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fired { rule, because } => write!(f, "{}: fired because {}", rule, because),
+            Self::Finished { rule } => write!(f, "{}: finished", rule),
+            Self::Failed { rule, error } => write!(f, "{}: {}", rule, error),
+            Self::Reloaded { rules } => write!(f, "reloaded {} rules", rules),
+            Self::ReloadFailed { error } => write!(f, "kept the old rules: {}", error),
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Runs [Rules] against the states seen by a [Watcher].
+///
+/// The watcher is only listened to, so something else must feed it, like
+/// [Watcher::run].
+///
+/// # Examples
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use govee_rs::{
+///     events::Watcher, monitor::Monitor, rules::RuleEngine, GoveeClient, DEFAULT_API_URL,
+/// };
+/// use tokio_util::sync::CancellationToken;
+///
+/// let client = GoveeClient::new(DEFAULT_API_URL, "my-api-key")?;
+/// let devices = client.devices().await?;
+///
+/// let watcher = Watcher::new(64);
+/// let monitor = Monitor::new(client.clone(), &devices);
+/// let polling = watcher.clone();
+/// tokio::spawn(async move { polling.run(&monitor).await });
+///
+/// let mut engine = RuleEngine::from_file(client, devices, watcher, "rules.toml")?;
+/// engine
+///     .run(&CancellationToken::new(), |event| println!("{}", event))
+///     .await;
+/// # Ok(())
+/// # }
+/// ```
+pub struct RuleEngine<R> {
+    client: GoveeClient,
+    resolver: R,
+    watcher: Watcher,
+    rules: Rules,
+    source: Option<(PathBuf, Option<SystemTime>)>,
+}
+
+impl<R: Resolve> RuleEngine<R> {
+    pub fn new(client: GoveeClient, resolver: R, watcher: Watcher, rules: Rules) -> Self {
+        Self {
+            client,
+            resolver,
+            watcher,
+            rules,
+            source: None,
+        }
+    }
+
+    /// Make an engine for the rules in `path`, reloading them whenever the
+    /// file changes.
+    pub fn from_file(
+        client: GoveeClient,
+        resolver: R,
+        watcher: Watcher,
+        path: impl Into<PathBuf>,
+    ) -> Result<Self, RulesError> {
+        let path = path.into();
+        let seen = modified(&path);
+        let rules = RuleSet::load(&path)?.plan(&resolver)?;
+        Ok(Self {
+            source: Some((path, seen)),
+            ..Self::new(client, resolver, watcher, rules)
+        })
+    }
+
+    pub fn rules(&self) -> &Rules {
+        &self.rules
+    }
+
+    /// Reload the rules file if it changed since it was last read.
+    fn reload(&mut self) -> Option<RuleEvent> {
+        let (path, seen) = self.source.as_mut()?;
+        let now = modified(path);
+        if now == *seen {
+            return None;
+        }
+        *seen = now;
+
+        match RuleSet::load(&*path).and_then(|set| set.plan(&self.resolver)) {
+            Ok(rules) => {
+                self.rules = rules;
+                Some(RuleEvent::Reloaded {
+                    rules: self.rules.len(),
+                })
+            }
+            Err(error) => Some(RuleEvent::ReloadFailed { error }),
+        }
+    }
+
+    fn local_time(&self, now: DateTime<Utc>) -> NaiveTime {
+        now.with_timezone(&self.rules.timezone).time()
+    }
+
+    /// Start a rule's steps if its conditions hold.
+    fn fire(
+        &self,
+        rule: &PlannedRule,
+        because: String,
+        now: DateTime<Utc>,
+        cancel: &CancellationToken,
+        done: &mpsc::UnboundedSender<(String, Result<(), SequenceError>)>,
+    ) -> Option<RuleEvent> {
+        if !rule.conditions_hold(&self.watcher.states(), self.local_time(now)) {
+            return None;
+        }
+
+        let client = self.client.clone();
+        let plan = rule.plan.clone();
+        let name = rule.name.clone();
+        let cancel = cancel.child_token();
+        let done = done.clone();
+        tokio::spawn(async move {
+            let result = plan.run(&client, &cancel).await.map(|_| ());
+            let _ = done.send((name, result));
+        });
+
+        Some(RuleEvent::Fired {
+            rule: rule.name.clone(),
+            because,
+        })
+    }
+
+    /// Run rules as they are triggered until `cancel` is triggered, telling
+    /// `report` what was done.
+    ///
+    /// Steps run in the background, so a rule with waits does not hold up
+    /// the others. Cancelling stops them too.
+    pub async fn run(&mut self, cancel: &CancellationToken, mut report: impl FnMut(RuleEvent)) {
+        let mut events = self.watcher.subscribe();
+        let (done, mut finished) = mpsc::unbounded_channel();
+        let mut tick = tokio::time::interval(TICK);
+
+        let started = Utc::now();
+        // the first state of a device is where it starts, not a change
+        let mut known: HashSet<DeviceId> = HashSet::new();
+        let mut on_since: HashMap<DeviceId, DateTime<Utc>> = HashMap::new();
+        for (id, state) in self.watcher.states() {
+            if state.power == Some(PowerState::On) {
+                on_since.insert(id.clone(), started);
+            }
+            known.insert(id);
+        }
+        // on_for rules that already fired for a device in this on period
+        let mut fired: HashSet<(String, DeviceId)> = HashSet::new();
+        let mut due = self.rules.next_due(started);
+
+        loop {
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                event = events.recv() => {
+                    let event = match event {
+                        Ok(event) => event,
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => return,
+                    };
+                    let now = Utc::now();
+
+                    if event.changed.contains(&Field::Power) {
+                        if event.state.power == Some(PowerState::On) {
+                            on_since.insert(event.device.clone(), now);
+                        } else {
+                            on_since.remove(&event.device);
+                        }
+                        fired.retain(|(_, id)| *id != event.device);
+                    }
+                    if known.insert(event.device.clone()) {
+                        continue;
+                    }
+
+                    for rule in &self.rules.rules {
+                        if let Some(because) = rule.fired_by(&event) {
+                            if let Some(e) = self.fire(rule, because, now, cancel, &done) {
+                                report(e);
+                            }
+                        }
+                    }
+                }
+                Some((rule, result)) = finished.recv() => {
+                    report(match result {
+                        Ok(()) => RuleEvent::Finished { rule },
+                        Err(error) => RuleEvent::Failed { rule, error },
+                    });
+                }
+                _ = tick.tick() => {
+                    if let Some(e) = self.reload() {
+                        let reloaded = matches!(e, RuleEvent::Reloaded { .. });
+                        report(e);
+                        if reloaded {
+                            due = self.rules.next_due(Utc::now());
+                        }
+                    }
+
+                    let now = Utc::now();
+                    for rule in &self.rules.rules {
+                        match &rule.when {
+                            PlannedWhen::At(trigger) => {
+                                let Some(at) = due.get(&rule.name).copied() else {
+                                    continue;
+                                };
+                                if at > now {
+                                    continue;
+                                }
+                                // missed entirely, say while asleep, is not run
+                                let late = (now - at).to_std().unwrap_or_default();
+                                if late <= ON_TIME {
+                                    let because = format!("it is {}", trigger);
+                                    if let Some(e) = self.fire(rule, because, now, cancel, &done) {
+                                        report(e);
+                                    }
+                                }
+                                let local = now.with_timezone(&self.rules.timezone);
+                                match trigger.next_after(&local, self.rules.location.as_ref()) {
+                                    Some(next) => due.insert(rule.name.clone(), next.with_timezone(&Utc)),
+                                    None => due.remove(&rule.name),
+                                };
+                            }
+                            PlannedWhen::OnFor { devices, duration } => {
+                                for device in devices.iter() {
+                                    let Some(since) = on_since.get(&device.device) else {
+                                        continue;
+                                    };
+                                    let on_for = (now - *since).to_std().unwrap_or_default();
+                                    let key = (rule.name.clone(), device.device.clone());
+                                    if on_for < *duration || fired.contains(&key) {
+                                        continue;
+                                    }
+                                    fired.insert(key);
+                                    let because = format!(
+                                        "{} was on for {}",
+                                        device.name,
+                                        humantime_serde::re::humantime::format_duration(*duration)
+                                    );
+                                    if let Some(e) = self.fire(rule, because, now, cancel, &done) {
+                                        report(e);
+                                    }
+                                }
+                            }
+                            PlannedWhen::Turns { .. } | PlannedWhen::Changes { .. } => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use mockito::Server;
+
+    use super::*;
+    use crate::models::{ControlCommand, Device};
+
+    fn devices() -> Devices {
+        let device = |id: &str, name: &str| Device {
+            model: "H5081".into(),
+            name: name.into(),
+            controllable: true,
+            supported_commands: HashSet::from([ControlCommand::Turn]),
+            ..Device::new(DeviceId::parse(id).unwrap())
+        };
+        Devices {
+            devices: vec![
+                device("34:20:03:2e:30:01", "desk lamp"),
+                device("34:20:03:2e:30:02", "heater"),
+            ],
+        }
+    }
+
+    #[test]
+    fn parses_and_checks_rules() {
+        let set = RuleSet::from_toml(
+            r#"
+            [[rules]]
+            name = "off in the evening"
+            when = { device = "desk lamp", turns = "off" }
+            if = [{ after = "18:00", before = "02:00" }, { device = "heater", power = "on" }]
+            then = [{ set = "heater", power = "off" }]
+
+            [[rules]]
+            name = "porch"
+            when = { at = "sunset" }
+            then = [{ set = "porch", power = "on" }, { wait = "1h" }]
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(set.rules[0].when, When::Turns(_)));
+        let Condition::Time(evening) = &set.rules[0].conditions[0] else {
+            panic!("expected a time condition");
+        };
+        assert!(evening.holds(NaiveTime::from_hms_opt(23, 0, 0).unwrap()));
+        assert!(evening.holds(NaiveTime::from_hms_opt(1, 0, 0).unwrap()));
+        assert!(!evening.holds(NaiveTime::from_hms_opt(12, 0, 0).unwrap()));
+
+        let Err(RulesError::Invalid { problems }) = set.plan(&devices()) else {
+            panic!("expected problems");
+        };
+        let paths: Vec<_> = problems.iter().map(|p| p.path.as_str()).collect();
+        assert_eq!(paths, ["rules[1].when", "rules[1].then[0]"]);
+
+        let mixed = RuleSet::from_toml(
+            r#"
+            [[rules]]
+            name = "confused"
+            when = { device = "heater", turns = "on", on_for = "1h" }
+            then = []
+            "#,
+        );
+        assert!(mixed.is_err());
+    }
+
+    #[tokio::test]
+    async fn fires_on_changes() {
+        let mut server = Server::new_async().await;
+        let control = server
+            .mock("PUT", "/v1/devices/control?")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "device": "34:20:03:2e:30:02",
+                "cmd": { "name": "turn", "value": "off" },
+            })))
+            .with_body(r#"{"code": 200, "message": "Success", "data": {}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let client = GoveeClient::new(&server.url(), "key").unwrap();
+
+        let set = RuleSet::from_toml(
+            r#"
+            [[rules]]
+            name = "heater follows lamp"
+            when = { device = "desk lamp", turns = "off" }
+            if = [{ device = "heater", power = "on" }]
+            then = [{ set = "heater", power = "off" }]
+            "#,
+        )
+        .unwrap();
+        let devices = devices();
+        let rules = set.plan(&devices).unwrap();
+        let watcher = Watcher::new(16);
+        let mut engine = RuleEngine::new(client, devices.clone(), watcher.clone(), rules);
+
+        let cancel = CancellationToken::new();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let running = tokio::spawn({
+            let cancel = cancel.clone();
+            async move { engine.run(&cancel, |e| tx.send(e).unwrap()).await }
+        });
+        tokio::task::yield_now().await;
+
+        let state = |device: &Device, power| NormalizedState {
+            power: Some(power),
+            ..NormalizedState::new(device.device.clone())
+        };
+        let (lamp, heater) = (&devices.devices[0], &devices.devices[1]);
+        // first sightings are not changes, even to off
+        watcher.record(heater, state(heater, PowerState::On));
+        watcher.record(lamp, state(lamp, PowerState::Off));
+        watcher.record(lamp, state(lamp, PowerState::On));
+        watcher.record(lamp, state(lamp, PowerState::Off));
+
+        let fired = rx.recv().await.unwrap();
+        assert!(
+            matches!(&fired, RuleEvent::Fired { because, .. } if because == "desk lamp turned off"),
+            "{}",
+            fired
+        );
+        let finished = rx.recv().await.unwrap();
+        assert!(
+            matches!(finished, RuleEvent::Finished { .. }),
+            "{}",
+            finished
+        );
+        control.assert_async().await;
+
+        cancel.cancel();
+        running.await.unwrap();
+    }
+
+    fn power(device: &Device, power: PowerState) -> NormalizedState {
+        NormalizedState {
+            power: Some(power),
+            ..NormalizedState::new(device.device.clone())
+        }
+    }
+
+    async fn finished(rx: &mut mpsc::UnboundedReceiver<RuleEvent>) {
+        let event = rx.recv().await.unwrap();
+        assert!(matches!(event, RuleEvent::Finished { .. }), "{}", event);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn on_for_fires_once_per_on_period() {
+        let mut server = Server::new_async().await;
+        let control = server
+            .mock("PUT", "/v1/devices/control?")
+            .with_body(r#"{"code": 200, "message": "Success", "data": {}}"#)
+            .expect(2)
+            .create_async()
+            .await;
+        let client = GoveeClient::new(&server.url(), "key").unwrap();
+
+        let set = RuleSet::from_toml(
+            r#"
+            [[rules]]
+            name = "heater timeout"
+            when = { device = "heater", on_for = "0s" }
+            then = [{ set = "heater", power = "off" }]
+            "#,
+        )
+        .unwrap();
+        let devices = devices();
+        let rules = set.plan(&devices).unwrap();
+        let watcher = Watcher::new(16);
+        let mut engine = RuleEngine::new(client, devices.clone(), watcher.clone(), rules);
+
+        let cancel = CancellationToken::new();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let running = tokio::spawn({
+            let cancel = cancel.clone();
+            async move { engine.run(&cancel, |e| tx.send(e).unwrap()).await }
+        });
+        tokio::task::yield_now().await;
+
+        let heater = &devices.devices[1];
+        watcher.record(heater, power(heater, PowerState::On));
+        let fired = rx.recv().await.unwrap();
+        assert!(
+            matches!(&fired, RuleEvent::Fired { because, .. } if because == "heater was on for 0s"),
+            "{}",
+            fired
+        );
+        finished(&mut rx).await;
+
+        // still the same on period, however many ticks pass
+        tokio::time::sleep(TICK * 4).await;
+        assert!(rx.try_recv().is_err());
+
+        watcher.record(heater, power(heater, PowerState::Off));
+        watcher.record(heater, power(heater, PowerState::On));
+        let fired = rx.recv().await.unwrap();
+        assert!(matches!(fired, RuleEvent::Fired { .. }), "{}", fired);
+        finished(&mut rx).await;
+        control.assert_async().await;
+
+        cancel.cancel();
+        running.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reloads_and_keeps_old_rules_on_failure() {
+        let mut server = Server::new_async().await;
+        let control = server
+            .mock("PUT", "/v1/devices/control?")
+            .with_body(r#"{"code": 200, "message": "Success", "data": {}}"#)
+            .expect(2)
+            .create_async()
+            .await;
+        let client = GoveeClient::new(&server.url(), "key").unwrap();
+
+        let rule = |name: &str| {
+            format!(
+                "[[rules]]\nname = {:?}\nwhen = {{ device = \"desk lamp\", turns = \"on\" }}\nthen = [{{ set = \"heater\", power = \"off\" }}]\n",
+                name
+            )
+        };
+        let path = std::env::temp_dir().join(format!("govee-rules-{}.toml", std::process::id()));
+        std::fs::write(&path, rule("one")).unwrap();
+
+        let devices = devices();
+        let watcher = Watcher::new(16);
+        let mut engine =
+            RuleEngine::from_file(client, devices.clone(), watcher.clone(), &path).unwrap();
+
+        let cancel = CancellationToken::new();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let running = tokio::spawn({
+            let cancel = cancel.clone();
+            async move { engine.run(&cancel, |e| tx.send(e).unwrap()).await }
+        });
+        tokio::task::yield_now().await;
+
+        std::fs::write(&path, rule("one") + rule("two").as_str()).unwrap();
+        let reloaded = rx.recv().await.unwrap();
+        assert!(
+            matches!(reloaded, RuleEvent::Reloaded { rules: 2 }),
+            "{}",
+            reloaded
+        );
+
+        std::fs::write(&path, rule("one") + "[[rules]]\nname = \"broken\"\n").unwrap();
+        let failed = rx.recv().await.unwrap();
+        assert!(
+            matches!(failed, RuleEvent::ReloadFailed { .. }),
+            "{}",
+            failed
+        );
+
+        // the two rules from before the broken edit still fire
+        let lamp = &devices.devices[0];
+        watcher.record(lamp, power(lamp, PowerState::Off));
+        watcher.record(lamp, power(lamp, PowerState::On));
+        for expected in ["one", "two"] {
+            let fired = rx.recv().await.unwrap();
+            assert!(
+                matches!(&fired, RuleEvent::Fired { rule, .. } if rule == expected),
+                "{}",
+                fired
+            );
+        }
+        finished(&mut rx).await;
+        finished(&mut rx).await;
+
+        std::fs::remove_file(&path).unwrap();
+        cancel.cancel();
+        running.await.unwrap();
+        control.assert_async().await;
+    }
+}
@@ -0,0 +1,743 @@
+//! Running actions at fixed times and at sunrise, sunset and twilight.
+//!
+//! A [Schedule] is a list of jobs, usually written as a TOML or YAML file:
+//!
+//! ```toml
+//! timezone = "Europe/Amsterdam"
+//! location = { latitude = 52.37, longitude = 4.89 }
+//!
+//! [[jobs]]
+//! name = "porch on"
+//! at = "sunset+15m"
+//! set = "porch"
+//! power = "on"
+//! catch_up = "2h"
+//!
+//! [[jobs]]
+//! name = "porch off"
+//! at = "0 23 * * *"
+//! set = "porch"
+//! power = "off"
+//! ```
+//!
+//! `at` is either a five field [Cron] expression or a [SolarEvent] (`dawn`,
+//! `sunrise`, `sunset` or `dusk`) with an optional offset like `+15m` or
+//! `-1h 30m`. Solar events are computed offline from the `location`, and
+//! every time is read in the schedule's `timezone`, which defaults to UTC.
+//!
+//! Targets are resolved with anything implementing [Resolve], just like a
+//! [Sequence](crate::sequence::Sequence).
+//!
+//! # Missed runs
+//!
+//! A job can be missed when the machine sleeps through it or the scheduler
+//! is not running. A job is run late only if it has a `catch_up` window and
+//! is no later than that, and then only once however many times it was
+//! missed. Otherwise it is skipped until its next time. Catching up after a
+//! restart needs a state file, passed to [Timetable::run], recording when each
+//! job last ran.
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+
+#[cfg(feature = "config")]
+use crate::config::{self, ConfigError};
+use crate::{
+    client::{GoveeClient, GoveeError},
+    lookup::Resolve,
+    models::{color_or_hex, Color, DesiredState, Devices, PowerState},
+};
+
+mod cron;
+mod solar;
+
+pub use cron::{Cron, CronError};
+pub use solar::{Location, SolarEvent};
+
+/// How late a job can start and still count as on time, allowing for a busy
+/// machine.
+pub const ON_TIME: Duration = Duration::from_secs(60);
+
+/// The longest the scheduler sleeps before checking the clock again, so it
+/// notices when the machine wakes from sleep.
+const MAX_SLEEP: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Error)]
+pub enum ScheduleError {
+    #[cfg(feature = "config")]
+    #[error("failed to load schedule: {}", source)]
+    Config {
+        #[from]
+        source: ConfigError,
+    },
+    #[error("invalid schedule: {}", ProblemList(problems))]
+    Invalid { problems: Vec<ScheduleProblem> },
+    #[error("failed to write schedule state {}: {}", path.display(), source)]
+    State {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// A problem with a particular job, found by [Schedule::plan].
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+#[error("{}: {}", path, message)]
+pub struct ScheduleProblem {
+    /// Where the problem is, like `jobs[2]`.
+    pub path: String,
+    pub message: String,
+}
+
+struct ProblemList<'a>(&'a [ScheduleProblem]);
+
+impl<'a> fmt::Display for ProblemList<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, p) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}", p)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+pub enum TriggerError {
+    #[error(transparent)]
+    Cron(#[from] CronError),
+    #[error("invalid offset {:?}: expected something like +15m or -1h 30m", offset)]
+    Offset { offset: String },
+}
+
+/// When a job runs.
+///
+/// # Examples
+/// ```
+/// use govee_rs::schedule::{SolarEvent, Trigger};
+///
+/// let trigger: Trigger = "sunset+15m".parse().unwrap();
+/// assert_eq!(
+///     trigger,
+///     Trigger::Solar {
+///         event: SolarEvent::Sunset,
+///         offset: chrono::Duration::minutes(15),
+///     }
+/// );
+/// assert!("0 23 * * *".parse::<Trigger>().is_ok());
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Trigger {
+    Cron(Cron),
+    Solar {
+        event: SolarEvent,
+        offset: chrono::Duration,
+    },
+}
+
+impl FromStr for Trigger {
+    type Err = TriggerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s.find(['+', '-']).unwrap_or(s.len());
+        let Ok(event) = s[..split].parse::<SolarEvent>() else {
+            return Ok(Self::Cron(s.parse()?));
+        };
+
+        let offset = &s[split..];
+        let invalid = || TriggerError::Offset {
+            offset: offset.to_string(),
+        };
+        let offset = match offset.chars().next() {
+            None => chrono::Duration::zero(),
+            Some(sign) => {
+                let magnitude = humantime_serde::re::humantime::parse_duration(offset[1..].trim())
+                    .ok()
+                    .and_then(|d| chrono::Duration::from_std(d).ok())
+                    .ok_or_else(invalid)?;
+                if sign == '-' {
+                    -magnitude
+                } else {
+                    magnitude
+                }
+            }
+        };
+        Ok(Self::Solar { event, offset })
+    }
+}
+
+impl TryFrom<String> for Trigger {
+    type Error = TriggerError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cron(cron) => write!(f, "{}", cron),
+            Self::Solar { event, offset } if offset.is_zero() => write!(f, "{}", event),
+            Self::Solar { event, offset } => {
+                let sign = if *offset < chrono::Duration::zero() {
+                    '-'
+                } else {
+                    '+'
+                };
+                let magnitude = offset.abs().to_std().unwrap_or_default();
+                write!(
+                    f,
+                    "{}{}{}",
+                    event,
+                    sign,
+                    humantime_serde::re::humantime::format_duration(magnitude)
+                )
+            }
+        }
+    }
+}
+
+impl From<Trigger> for String {
+    fn from(value: Trigger) -> Self {
+        value.to_string()
+    }
+}
+
+impl Trigger {
+    /// The first time strictly after `after` this trigger fires.
+    ///
+    /// Solar triggers need a `location` and never fire without one.
+    pub fn next_after<Z: chrono::TimeZone>(
+        &self,
+        after: &DateTime<Z>,
+        location: Option<&Location>,
+    ) -> Option<DateTime<Z>> {
+        match self {
+            Self::Cron(cron) => cron.next_after(after),
+            Self::Solar { event, offset } => event.next_after(after, *offset, location?),
+        }
+    }
+}
+
+/// A job in a [Schedule].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    pub name: String,
+    pub at: Trigger,
+
+    /// The device, room or group to change.
+    pub set: String,
+    #[serde(default)]
+    pub power: Option<PowerState>,
+    #[serde(default)]
+    pub brightness: Option<u64>,
+    #[serde(default, deserialize_with = "color_or_hex")]
+    pub color: Option<Color>,
+    #[serde(default)]
+    pub color_temp: Option<u64>,
+
+    /// How late the job may still run after being missed.
+    #[serde(default, with = "humantime_serde")]
+    pub catch_up: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+    /// An IANA time zone name like `Europe/Amsterdam`, defaulting to UTC.
+    #[serde(default)]
+    pub timezone: Option<String>,
+
+    /// Needed by jobs that run at solar events.
+    #[serde(default)]
+    pub location: Option<Location>,
+    pub jobs: Vec<Job>,
+}
+
+impl Schedule {
+    /// Load a schedule from a `.toml`, `.yaml` or `.yml` file.
+    #[cfg(feature = "config")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ScheduleError> {
+        Ok(config::load(path.as_ref())?)
+    }
+
+    #[cfg(feature = "config")]
+    pub fn from_toml(raw: &str) -> Result<Self, ScheduleError> {
+        Ok(config::from_toml(raw)?)
+    }
+
+    #[cfg(feature = "config")]
+    pub fn from_yaml(raw: &str) -> Result<Self, ScheduleError> {
+        Ok(config::from_yaml(raw)?)
+    }
+
+    /// Validate the schedule and resolve its targets.
+    ///
+    /// Every problem found is reported, not just the first.
+    pub fn plan<R: Resolve + ?Sized>(&self, resolver: &R) -> Result<Timetable, ScheduleError> {
+        let mut problems = Vec::new();
+        let mut problem = |path: &str, message: String| {
+            problems.push(ScheduleProblem {
+                path: path.to_string(),
+                message,
+            })
+        };
+
+        let timezone = match self.timezone.as_deref().map(str::parse::<Tz>) {
+            None => Tz::UTC,
+            Some(Ok(tz)) => tz,
+            Some(Err(_)) => {
+                problem(
+                    "timezone",
+                    format!(
+                        "unknown time zone {:?}",
+                        self.timezone.as_deref().unwrap_or_default()
+                    ),
+                );
+                Tz::UTC
+            }
+        };
+
+        if self.jobs.is_empty() {
+            problem("jobs", "a schedule needs at least one job".into());
+        }
+
+        let mut jobs = Vec::new();
+        let mut names = HashMap::new();
+        for (i, job) in self.jobs.iter().enumerate() {
+            let path = format!("jobs[{}]", i);
+            if let Some(first) = names.insert(job.name.as_str(), i) {
+                problem(
+                    &path,
+                    format!("{:?} is also the name of jobs[{}]", job.name, first),
+                );
+            }
+            if matches!(job.at, Trigger::Solar { .. }) && self.location.is_none() {
+                problem(&path, format!("{} needs a location", job.at));
+            }
+
+            let desired = DesiredState {
+                power: job.power,
+                brightness: job.brightness,
+                color: job.color,
+                color_temp: job.color_temp,
+            };
+            let devices = match resolver.resolve(&job.set) {
+                Ok(devices) => devices,
+                Err(e) => {
+                    problem(&path, e.to_string());
+                    continue;
+                }
+            };
+            for device in devices.iter() {
+                if let Err(e) = desired.commands_for(device) {
+                    problem(&path, e.to_string());
+                }
+            }
+
+            jobs.push(PlannedJob {
+                name: job.name.clone(),
+                trigger: job.at.clone(),
+                devices,
+                desired,
+                catch_up: job.catch_up,
+            });
+        }
+
+        if !problems.is_empty() {
+            return Err(ScheduleError::Invalid { problems });
+        }
+
+        Ok(Timetable {
+            timezone,
+            location: self.location,
+            jobs,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PlannedJob {
+    name: String,
+    trigger: Trigger,
+    devices: Devices,
+    desired: DesiredState,
+    catch_up: Option<Duration>,
+}
+
+impl PlannedJob {
+    /// Send the job's commands to every device, carrying on past failures.
+    async fn run(&self, client: &GoveeClient) -> Result<(), GoveeError> {
+        let mut first_error = None;
+        for device in self.devices.iter() {
+            // checked when planning
+            let cmds = self.desired.commands_for(device).unwrap_or_default();
+            for cmd in cmds {
+                if let Err(e) = client.control(device, cmd).await {
+                    first_error.get_or_insert(e);
+                    break;
+                }
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// If a run due at `due` and noticed at `now` should happen.
+    fn runs_late(&self, due: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        let late = (now - due).to_std().unwrap_or_default();
+        late <= ON_TIME || self.catch_up.is_some_and(|window| late <= window)
+    }
+}
+
+/// A time a job is due.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Firing {
+    pub job: String,
+    pub at: DateTime<Tz>,
+}
+
+/// What happened to a job that came due.
+#[derive(Debug)]
+pub enum Report {
+    Ran {
+        firing: Firing,
+    },
+    /// Ran after being missed, within its catch up window.
+    CaughtUp {
+        firing: Firing,
+        late: Duration,
+    },
+    /// Missed by more than the job's catch up window.
+    Skipped {
+        firing: Firing,
+        late: Duration,
+    },
+    Failed {
+        firing: Firing,
+        error: GoveeError,
+    },
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let human = |d: &Duration| {
+            humantime_serde::re::humantime::format_duration(Duration::from_secs(d.as_secs()))
+        };
+        match self {
+            Self::Ran { firing } => write!(f, "{} ran at {}", firing.job, firing.at),
+            Self::CaughtUp { firing, late } => write!(
+                f,
+                "{} due at {} ran {} late",
+                firing.job,
+                firing.at,
+                human(late)
+            ),
+            Self::Skipped { firing, late } => write!(
+                f,
+                "{} due at {} was skipped, {} late",
+                firing.job,
+                firing.at,
+                human(late)
+            ),
+            Self::Failed { firing, error } => {
+                write!(f, "{} due at {} failed: {}", firing.job, firing.at, error)
+            }
+        }
+    }
+}
+
+/// When each job last came due, as saved in a state file.
+type LastRuns = HashMap<String, DateTime<Utc>>;
+
+fn load_state(path: &Path) -> LastRuns {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default()
+}
+
+fn save_state(path: &Path, last: &LastRuns) -> Result<(), ScheduleError> {
+    let raw = serde_json::to_string_pretty(last).unwrap_or_default();
+    std::fs::write(path, raw).map_err(|source| ScheduleError::State {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// A validated [Schedule] with its targets resolved.
+#[derive(Debug, Clone)]
+pub struct Timetable {
+    timezone: Tz,
+    location: Option<Location>,
+    jobs: Vec<PlannedJob>,
+}
+
+impl Timetable {
+    pub fn timezone(&self) -> Tz {
+        self.timezone
+    }
+
+    fn next(&self, job: &PlannedJob, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        job.trigger
+            .next_after(&after.with_timezone(&self.timezone), self.location.as_ref())
+            .map(|t| t.with_timezone(&Utc))
+    }
+
+    fn firing(&self, job: &PlannedJob, at: DateTime<Utc>) -> Firing {
+        Firing {
+            job: job.name.clone(),
+            at: at.with_timezone(&self.timezone),
+        }
+    }
+
+    /// The next `count` times any job is due after `after`, in order.
+    pub fn upcoming(&self, after: DateTime<Utc>, count: usize) -> Vec<Firing> {
+        let mut firings = Vec::new();
+        for job in &self.jobs {
+            let mut at = after;
+            for _ in 0..count {
+                let Some(next) = self.next(job, at) else {
+                    break;
+                };
+                firings.push(self.firing(job, next));
+                at = next;
+            }
+        }
+        firings.sort_by(|a, b| a.at.cmp(&b.at).then_with(|| a.job.cmp(&b.job)));
+        firings.truncate(count);
+        firings
+    }
+
+    /// Run jobs as they come due until `cancel` is triggered, telling
+    /// `report` about each one.
+    ///
+    /// If `state` is given, when each job last came due is kept there so that
+    /// jobs missed while the scheduler was not running can catch up.
+    pub async fn run(
+        &self,
+        client: &GoveeClient,
+        cancel: &CancellationToken,
+        state: Option<&Path>,
+        mut report: impl FnMut(Report),
+    ) -> Result<(), ScheduleError> {
+        let mut last = state.map(load_state).unwrap_or_default();
+        let started = Utc::now();
+
+        // when each job is next due, from when it last ran if that is known
+        let mut due: Vec<_> = self
+            .jobs
+            .iter()
+            .map(|job| {
+                let since = last.get(&job.name).copied().unwrap_or(started);
+                self.next(job, since.min(started))
+            })
+            .collect();
+
+        loop {
+            let Some(soonest) = due.iter().flatten().min().copied() else {
+                // nothing will ever be due again
+                cancel.cancelled().await;
+                return Ok(());
+            };
+
+            let wait = (soonest - Utc::now()).to_std().unwrap_or_default();
+            if !wait.is_zero() {
+                tokio::select! {
+                    _ = tokio::time::sleep(wait.min(MAX_SLEEP)) => continue,
+                    _ = cancel.cancelled() => return Ok(()),
+                }
+            }
+
+            let now = Utc::now();
+            for (job, next) in self.jobs.iter().zip(due.iter_mut()) {
+                let Some(at) = next.filter(|at| *at <= now) else {
+                    continue;
+                };
+                let firing = self.firing(job, at);
+                let late = (now - at).to_std().unwrap_or_default();
+
+                let outcome = if job.runs_late(at, now) {
+                    match job.run(client).await {
+                        Ok(()) if late <= ON_TIME => Report::Ran { firing },
+                        Ok(()) => Report::CaughtUp { firing, late },
+                        Err(error) => Report::Failed { firing, error },
+                    }
+                } else {
+                    Report::Skipped { firing, late }
+                };
+                report(outcome);
+
+                // however many times it was missed, it only runs once
+                *next = self.next(job, at.max(now));
+                last.insert(job.name.clone(), at);
+                if let Some(path) = state {
+                    save_state(path, &last)?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::models::{ControlCommand, Device, DeviceId};
+
+    fn porch() -> Devices {
+        Devices {
+            devices: vec![Device {
+                model: "H5081".into(),
+                name: "porch".into(),
+                controllable: true,
+                supported_commands: [ControlCommand::Turn].into(),
+                ..Device::new(DeviceId::parse("34:20:03:2e:30:2b").unwrap())
+            }],
+        }
+    }
+
+    #[test]
+    fn plans_and_lists_upcoming() {
+        let schedule: Schedule = serde_json::from_value(serde_json::json!({
+            "timezone": "Europe/Amsterdam",
+            "location": { "latitude": 52.37, "longitude": 4.89 },
+            "jobs": [
+                { "name": "porch on", "at": "sunset+15m", "set": "porch", "power": "on" },
+                { "name": "porch off", "at": "0 23 * * *", "set": "porch", "power": "off" },
+            ],
+        }))
+        .unwrap();
+        let timetable = schedule.plan(&porch()).unwrap();
+
+        let noon = Utc.with_ymd_and_hms(2024, 6, 21, 10, 0, 0).unwrap();
+        let upcoming = timetable.upcoming(noon, 3);
+        let summary: Vec<_> = upcoming
+            .iter()
+            .map(|f| format!("{} {}", f.at.format("%d %H:%M"), f.job))
+            .collect();
+        // sunset in Amsterdam is at 22:07 at midsummer
+        assert_eq!(
+            summary,
+            [
+                "21 22:22 porch on",
+                "21 23:00 porch off",
+                "22 22:22 porch on"
+            ]
+        );
+
+        let broken = Schedule {
+            jobs: vec![Job {
+                brightness: Some(50),
+                ..schedule.jobs[0].clone()
+            }],
+            ..Default::default()
+        };
+        let Err(ScheduleError::Invalid { problems }) = broken.plan(&porch()) else {
+            panic!("expected problems");
+        };
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+    }
+
+    #[test]
+    fn triggers_round_trip() {
+        let trigger: Trigger = "sunrise-1h 30m".parse().unwrap();
+        assert_eq!(
+            trigger,
+            Trigger::Solar {
+                event: SolarEvent::Sunrise,
+                offset: chrono::Duration::minutes(-90),
+            }
+        );
+        assert_eq!(trigger.to_string(), "sunrise-1h 30m");
+        assert_eq!("Dusk".parse::<Trigger>().unwrap().to_string(), "dusk");
+        assert_eq!(
+            "0 23 * * *".parse::<Trigger>().unwrap().to_string(),
+            "0 23 * * *"
+        );
+        assert_eq!(
+            "sunset+soon".parse::<Trigger>(),
+            Err(TriggerError::Offset {
+                offset: "+soon".into()
+            })
+        );
+
+        // solar triggers need somewhere to be
+        let noon = Utc.with_ymd_and_hms(2024, 6, 21, 12, 0, 0).unwrap();
+        assert_eq!(trigger.next_after(&noon, None), None);
+    }
+
+    #[tokio::test]
+    async fn missed_jobs_catch_up_once_or_skip() {
+        let mut server = mockito::Server::new_async().await;
+        let control = server
+            .mock("PUT", "/v1/devices/control?")
+            .with_status(200)
+            .with_body(r#"{"code": 200, "message": "Success", "data": {}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let client = GoveeClient::new(&server.url(), "key").unwrap();
+
+        let schedule: Schedule = serde_json::from_value(serde_json::json!({
+            "jobs": [
+                { "name": "late", "at": "* * * * *", "set": "porch", "power": "on", "catch_up": "2h" },
+                { "name": "missed", "at": "* * * * *", "set": "porch", "power": "off" },
+            ],
+        }))
+        .unwrap();
+        let timetable = schedule.plan(&porch()).unwrap();
+
+        // both jobs last ran half an hour ago, so each missed about 30 runs
+        let state =
+            std::env::temp_dir().join(format!("govee-schedule-{}.json", std::process::id()));
+        let half_hour_ago = Utc::now() - chrono::Duration::minutes(30);
+        let last = LastRuns::from([
+            ("late".to_string(), half_hour_ago),
+            ("missed".to_string(), half_hour_ago),
+        ]);
+        save_state(&state, &last).unwrap();
+
+        let cancel = CancellationToken::new();
+        let mut reports = Vec::new();
+        timetable
+            .run(&client, &cancel, Some(&state), |report| {
+                reports.push(report);
+                if reports.len() == 2 {
+                    cancel.cancel();
+                }
+            })
+            .await
+            .unwrap();
+        control.assert_async().await;
+
+        assert!(
+            matches!(&reports[0], Report::CaughtUp { firing, .. } if firing.job == "late"),
+            "{:?}",
+            reports
+        );
+        assert!(
+            matches!(&reports[1], Report::Skipped { firing, .. } if firing.job == "missed"),
+            "{:?}",
+            reports
+        );
+        let saved = load_state(&state);
+        std::fs::remove_file(&state).unwrap();
+        assert!(saved["late"] > half_hour_ago);
+    }
+}
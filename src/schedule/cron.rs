@@ -0,0 +1,321 @@
+//! Five field cron expressions.
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone};
+use thiserror::Error;
+
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+pub enum CronError {
+    #[error(
+        "a cron expression needs 5 fields (minute hour day month weekday), got {}",
+        count
+    )]
+    FieldCount { count: usize },
+    #[error("invalid {} field {:?}", field, value)]
+    Field { field: &'static str, value: String },
+}
+
+const MONTHS: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// How far ahead to look for a match. Long enough for `0 0 29 2 *` to find
+/// the next leap day.
+const SEARCH_DAYS: i64 = 366 * 8;
+
+/// A standard cron expression: `minute hour day-of-month month day-of-week`.
+///
+/// Fields accept `*`, numbers, ranges like `1-5`, steps like `*/15` or
+/// `8-18/2`, and comma separated lists of those. Months and weekdays may also
+/// be written as `jan` or `mon`, and Sunday as `0` or `7`. Weekday ranges may
+/// wrap around the end of the week, like `fri-mon`. The shorthands
+/// `@yearly`, `@monthly`, `@weekly`, `@daily` and `@hourly` are accepted too.
+///
+/// As in classic cron, when both the day of month and day of week are
+/// restricted a day matching either one matches. A field is only
+/// unrestricted when it is exactly `*`, so `*/2` restricts it.
+///
+/// # Examples
+/// ```
+/// use chrono::{TimeZone, Utc};
+/// use govee_rs::schedule::Cron;
+///
+/// let weekday_evenings: Cron = "30 18 * * mon-fri".parse().unwrap();
+/// let saturday = Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+/// assert_eq!(
+///     weekday_evenings.next_after(&saturday),
+///     Some(Utc.with_ymd_and_hms(2024, 6, 3, 18, 30, 0).unwrap()),
+/// );
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Cron {
+    source: String,
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// Parse one field into a bitset of the values it allows.
+///
+/// With a `period`, a range whose start is after its end wraps around, and
+/// values are taken modulo the period.
+fn field(
+    field: &'static str,
+    value: &str,
+    min: u32,
+    max: u32,
+    names: &[&str],
+    period: Option<u32>,
+) -> Result<u64, CronError> {
+    let invalid = || CronError::Field {
+        field,
+        value: value.to_string(),
+    };
+    let number = |s: &str| -> Result<u32, CronError> {
+        let lower = s.to_ascii_lowercase();
+        let n = match names.iter().position(|n| *n == lower) {
+            // names start at the field's minimum
+            Some(i) => i as u32 + min,
+            None => s.parse().map_err(|_| invalid())?,
+        };
+        (min..=max).contains(&n).then_some(n).ok_or_else(invalid)
+    };
+
+    let mut bits = 0;
+    for part in value.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(invalid());
+        }
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                // `5/10` means every 10 starting at 5
+                None if part.contains('/') => (number(range)?, max),
+                None => {
+                    let n = number(range)?;
+                    (n, n)
+                }
+            },
+        };
+        let end = match period {
+            Some(period) if start > end => end + period,
+            _ if start > end => return Err(invalid()),
+            _ => end,
+        };
+
+        for n in (start..=end).step_by(step as usize) {
+            bits |= 1 << period.map_or(n, |p| n % p);
+        }
+    }
+    Ok(bits)
+}
+
+impl FromStr for Cron {
+    type Err = CronError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expanded = match s.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+
+        let fields: Vec<_> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(CronError::FieldCount {
+                count: fields.len(),
+            });
+        };
+
+        // 7 is another way of writing Sunday, which the period folds onto 0
+        let weekdays = field("weekday", weekday, 0, 7, WEEKDAYS, Some(7))?;
+
+        Ok(Self {
+            source: s.trim().to_string(),
+            minutes: field("minute", minute, 0, 59, &[], None)?,
+            hours: field("hour", hour, 0, 23, &[], None)? as u32,
+            days: field("day", day, 1, 31, &[], None)? as u32,
+            months: field("month", month, 1, 12, MONTHS, None)? as u16,
+            weekdays: weekdays as u8,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Cron {
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        if self.months & (1 << date.month()) == 0 {
+            return false;
+        }
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        if self.any_day || self.any_weekday {
+            day && weekday
+        } else {
+            day || weekday
+        }
+    }
+
+    /// The first time strictly after `after` that matches, in `after`'s time
+    /// zone.
+    ///
+    /// Times skipped by a daylight saving change never match, and times
+    /// repeated by one match only the first time.
+    pub fn next_after<Tz: TimeZone>(&self, after: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let tz = after.timezone();
+        let local = after.naive_local();
+        let start = local.date();
+
+        for offset in 0..SEARCH_DAYS {
+            let date = start + Duration::days(offset);
+            if !self.day_matches(date) {
+                continue;
+            }
+
+            for hour in (0..24).filter(|h| self.hours & (1 << h) != 0) {
+                for minute in (0..60).filter(|m| self.minutes & (1 << m) != 0) {
+                    let Some(candidate) = date.and_hms_opt(hour, minute, 0) else {
+                        continue;
+                    };
+                    if offset == 0 && candidate <= local {
+                        continue;
+                    }
+                    if let Some(time) = tz.from_local_datetime(&candidate).earliest() {
+                        if time > *after {
+                            return Some(time);
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use chrono_tz::Europe::Amsterdam;
+
+    use super::*;
+
+    #[test]
+    fn parses_and_finds_next() {
+        let at = |y, mo, d, h, mi| Utc.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap();
+
+        let every_quarter: Cron = "*/15 * * * *".parse().unwrap();
+        assert_eq!(
+            every_quarter.next_after(&at(2024, 1, 1, 10, 0)),
+            Some(at(2024, 1, 1, 10, 15))
+        );
+        assert_eq!(
+            every_quarter.next_after(&at(2024, 1, 1, 10, 59)),
+            Some(at(2024, 1, 1, 11, 0))
+        );
+
+        let leap: Cron = "0 0 29 feb *".parse().unwrap();
+        assert_eq!(
+            leap.next_after(&at(2024, 3, 1, 0, 0)),
+            Some(at(2028, 2, 29, 0, 0))
+        );
+
+        // day of month or day of week
+        let either: Cron = "0 12 1 * 7".parse().unwrap();
+        assert_eq!(
+            either.next_after(&at(2024, 6, 1, 13, 0)),
+            Some(at(2024, 6, 2, 12, 0))
+        );
+
+        // 02:30 does not exist on the day clocks go forward
+        let early: Cron = "30 2 * * *".parse().unwrap();
+        let before = Amsterdam.with_ymd_and_hms(2024, 3, 30, 12, 0, 0).unwrap();
+        let next = early.next_after(&before).unwrap();
+        assert_eq!(
+            next,
+            Amsterdam.with_ymd_and_hms(2024, 4, 1, 2, 30, 0).unwrap()
+        );
+
+        assert_eq!(
+            "* * *".parse::<Cron>(),
+            Err(CronError::FieldCount { count: 3 })
+        );
+        assert!("61 * * * *".parse::<Cron>().is_err());
+        assert!("5-1 * * * *".parse::<Cron>().is_err());
+    }
+
+    #[test]
+    fn steps_restrict_day_fields() {
+        let at = |d, h| Utc.with_ymd_and_hms(2024, 6, d, h, 0, 0).unwrap();
+
+        // the 1st, 3rd, 5th and so on
+        let odd_days: Cron = "0 0 */2 * *".parse().unwrap();
+        assert_eq!(odd_days.next_after(&at(1, 12)), Some(at(3, 0)));
+
+        // Sunday, Tuesday, Thursday and Saturday; June 2nd 2024 is a Sunday
+        let alternate: Cron = "0 0 * * */2".parse().unwrap();
+        assert_eq!(alternate.next_after(&at(2, 12)), Some(at(4, 0)));
+
+        // restricted either way, so either field matching is enough
+        let either: Cron = "0 0 */10 * mon".parse().unwrap();
+        assert_eq!(either.next_after(&at(1, 12)), Some(at(3, 0)));
+        assert_eq!(either.next_after(&at(3, 12)), Some(at(10, 0)));
+        assert_eq!(either.next_after(&at(10, 12)), Some(at(11, 0)));
+    }
+
+    #[test]
+    fn weekday_ranges_wrap() {
+        let at = |d, h| Utc.with_ymd_and_hms(2024, 6, d, h, 0, 0).unwrap();
+
+        // June 3rd 2024 is a Monday
+        let weekend: Cron = "0 9 * * fri-sun".parse().unwrap();
+        assert_eq!(weekend.next_after(&at(3, 12)), Some(at(7, 9)));
+        assert_eq!(weekend.next_after(&at(8, 12)), Some(at(9, 9)));
+        assert_eq!(weekend.next_after(&at(9, 12)), Some(at(14, 9)));
+
+        let long_weekend: Cron = "0 9 * * 5-1".parse().unwrap();
+        assert_eq!(long_weekend.next_after(&at(9, 12)), Some(at(10, 9)));
+        assert_eq!(long_weekend.next_after(&at(10, 12)), Some(at(14, 9)));
+
+        assert_eq!(
+            "0 9 * * fri-7".parse::<Cron>().unwrap().weekdays,
+            weekend.weekdays
+        );
+        assert!("0 9 * * 8".parse::<Cron>().is_err());
+        // only weekdays wrap
+        assert!("0 9 * nov-feb *".parse::<Cron>().is_err());
+    }
+
+    #[test]
+    fn shorthands_and_names() {
+        assert_eq!(
+            "@weekly".parse::<Cron>().unwrap().weekdays,
+            "0 0 * * sun".parse::<Cron>().unwrap().weekdays
+        );
+        assert_eq!("@hourly".parse::<Cron>().unwrap().to_string(), "@hourly");
+        let named: Cron = "0 0 1 JAN,Jul *".parse().unwrap();
+        assert_eq!(named.months, 1 << 1 | 1 << 7);
+        assert!("0 0 1 foo *".parse::<Cron>().is_err());
+        assert!("*/0 * * * *".parse::<Cron>().is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Sunrise, sunset and twilight times, computed offline.
+//!
+//! This uses the sunrise equation as described by NOAA, which is accurate to
+//! about a minute away from the poles. Refraction is accounted for but
+//! elevation is not.
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Where on earth solar events are computed for.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    /// Degrees north of the equator, negative for south.
+    pub latitude: f64,
+
+    /// Degrees east of Greenwich, negative for west.
+    pub longitude: f64,
+}
+
+/// A moment in the sun's day.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SolarEvent {
+    /// The start of civil twilight, when the sun is 6° below the horizon.
+    Dawn,
+    Sunrise,
+    Sunset,
+    /// The end of civil twilight, when the sun is 6° below the horizon.
+    Dusk,
+}
+
+impl SolarEvent {
+    /// The altitude of the sun's center at the event, in degrees.
+    fn altitude(self) -> f64 {
+        match self {
+            // the sun's radius plus refraction at the horizon
+            Self::Sunrise | Self::Sunset => -0.833,
+            Self::Dawn | Self::Dusk => -6.0,
+        }
+    }
+
+    fn morning(self) -> bool {
+        matches!(self, Self::Dawn | Self::Sunrise)
+    }
+
+    /// When this event happens on `date` at `location`, or `None` if the sun
+    /// does not reach the event's altitude that day, as near the poles.
+    ///
+    /// # Examples
+    /// ```
+    /// use chrono::NaiveDate;
+    /// use govee_rs::schedule::{Location, SolarEvent};
+    ///
+    /// let london = Location { latitude: 51.5, longitude: -0.13 };
+    /// let midsummer = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+    /// let sunset = SolarEvent::Sunset.on(midsummer, &london).unwrap();
+    /// assert_eq!(sunset.format("%H:%M").to_string(), "20:22");
+    /// ```
+    pub fn on(self, date: NaiveDate, location: &Location) -> Option<DateTime<Utc>> {
+        const J2000: f64 = 2_451_545.0;
+        const UNIX_EPOCH_JD: f64 = 2_440_587.5;
+
+        let epoch = NaiveDate::from_ymd_opt(2000, 1, 1)?;
+        let days = (date - epoch).num_days() as f64 + 0.0008;
+
+        // mean solar noon at this longitude
+        let noon = days - location.longitude / 360.0;
+        let anomaly = (357.5291 + 0.985_600_28 * noon).rem_euclid(360.0);
+        let m = anomaly.to_radians();
+        let center = 1.9148 * m.sin() + 0.0200 * (2.0 * m).sin() + 0.0003 * (3.0 * m).sin();
+        let ecliptic = (anomaly + center + 180.0 + 102.9372)
+            .rem_euclid(360.0)
+            .to_radians();
+        let transit = J2000 + noon + 0.0053 * m.sin() - 0.0069 * (2.0 * ecliptic).sin();
+
+        let declination = (ecliptic.sin() * 23.4397_f64.to_radians().sin()).asin();
+        let latitude = location.latitude.to_radians();
+        let cos_hour_angle = (self.altitude().to_radians().sin()
+            - latitude.sin() * declination.sin())
+            / (latitude.cos() * declination.cos());
+        if !(-1.0..=1.0).contains(&cos_hour_angle) {
+            return None;
+        }
+
+        let hour_angle = cos_hour_angle.acos().to_degrees() / 360.0;
+        let julian = if self.morning() {
+            transit - hour_angle
+        } else {
+            transit + hour_angle
+        };
+
+        let seconds = (julian - UNIX_EPOCH_JD) * 86_400.0;
+        Utc.timestamp_opt(seconds.round() as i64, 0).single()
+    }
+
+    /// The first time this event happens strictly after `after` once
+    /// shifted by `offset`, looking up to a year ahead.
+    pub fn next_after<Tz: TimeZone>(
+        self,
+        after: &DateTime<Tz>,
+        offset: chrono::Duration,
+        location: &Location,
+    ) -> Option<DateTime<Tz>> {
+        let tz = after.timezone();
+        // start early enough for large negative offsets and far-off zones
+        let start = after.naive_utc().date() - chrono::Duration::days(2);
+
+        (0..370)
+            .filter_map(|day| {
+                let date = start + chrono::Duration::days(day);
+                self.on(date, location)
+            })
+            .map(|time| (time + offset).with_timezone(&tz))
+            .find(|time| time > after)
+    }
+}
+
+impl fmt::Display for SolarEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Dawn => "dawn",
+            Self::Sunrise => "sunrise",
+            Self::Sunset => "sunset",
+            Self::Dusk => "dusk",
+        })
+    }
+}
+
+impl FromStr for SolarEvent {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "dawn" => Ok(Self::Dawn),
+            "sunrise" => Ok(Self::Sunrise),
+            "sunset" => Ok(Self::Sunset),
+            "dusk" => Ok(Self::Dusk),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polar_night_and_ordering() {
+        let tromso = Location {
+            latitude: 69.65,
+            longitude: 18.96,
+        };
+        let midwinter = NaiveDate::from_ymd_opt(2024, 12, 21).unwrap();
+        assert_eq!(SolarEvent::Sunrise.on(midwinter, &tromso), None);
+        // civil twilight still happens
+        assert!(SolarEvent::Dawn.on(midwinter, &tromso).is_some());
+
+        let sydney = Location {
+            latitude: -33.87,
+            longitude: 151.21,
+        };
+        let day = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let times: Vec<_> = [
+            SolarEvent::Dawn,
+            SolarEvent::Sunrise,
+            SolarEvent::Sunset,
+            SolarEvent::Dusk,
+        ]
+        .iter()
+        .map(|e| e.on(day, &sydney).unwrap())
+        .collect();
+        assert!(times.windows(2).all(|w| w[0] < w[1]));
+        // 06:44 local (UTC+11) sunrise, give or take a couple of minutes
+        let sunrise = times[1].timestamp()
+            - Utc
+                .with_ymd_and_hms(2024, 2, 29, 19, 44, 0)
+                .unwrap()
+                .timestamp();
+        assert!(sunrise.abs() < 180, "sunrise off by {}s", sunrise);
+    }
+
+    #[test]
+    fn next_after_applies_the_offset() {
+        let london = Location {
+            latitude: 51.5,
+            longitude: -0.13,
+        };
+        let day = |d| NaiveDate::from_ymd_opt(2024, 6, d).unwrap();
+        let sunset = |d| SolarEvent::Sunset.on(day(d), &london).unwrap();
+        let early = chrono::Duration::minutes(-30);
+
+        let noon = Utc.with_ymd_and_hms(2024, 6, 21, 12, 0, 0).unwrap();
+        assert_eq!(
+            SolarEvent::Sunset.next_after(&noon, early, &london),
+            Some(sunset(21) + early)
+        );
+
+        // sunset itself is still ahead, but half an hour before it is not
+        let shortly_before = sunset(21) - chrono::Duration::minutes(10);
+        assert_eq!(
+            SolarEvent::Sunset.next_after(&shortly_before, early, &london),
+            Some(sunset(22) + early)
+        );
+
+        // a late offset can land after midnight
+        let late = chrono::Duration::hours(5);
+        assert_eq!(
+            SolarEvent::Sunset.next_after(&(sunset(21) + late), late, &london),
+            Some(sunset(22) + late)
+        );
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for event in [
+            SolarEvent::Dawn,
+            SolarEvent::Sunrise,
+            SolarEvent::Sunset,
+            SolarEvent::Dusk,
+        ] {
+            assert_eq!(event.to_string().parse(), Ok(event));
+        }
+        assert_eq!(" Sunset ".parse(), Ok(SolarEvent::Sunset));
+        assert_eq!("noon".parse::<SolarEvent>(), Err(()));
+    }
+}
@@ -0,0 +1,673 @@
+//! Automations written as [Rhai](https://rhai.rs) scripts.
+//!
+//! For automations too involved for [rules](crate::rules) or
+//! [sequences](crate::sequence), a [ScriptRunner] runs small scripts with
+//! these functions available:
+//!
+//! | Function | |
+//! |---|---|
+//! | `devices()` | every device, as maps with `id`, `name` and `model` |
+//! | `state(target)` | a device's state, as a map with `online`, `power`, `brightness`, `color` and `color_temp`, each `()` if not reported |
+//! | `on(target)`, `off(target)` | turn devices on or off |
+//! | `brightness(target, percent)` | set the brightness of devices |
+//! | `color(target, color)` | set the color of devices, as a hex string or a name like `"orange"` |
+//! | `color_temp(target, kelvin)` | set the color temperature of devices |
+//! | `sleep(ms)` | wait |
+//! | `rgb(r, g, b)`, `hsv(hue, saturation, value)` | make a hex color string |
+//! | `mix(from, to, amount)` | blend two colors |
+//!
+//! Targets are resolved like anywhere else, so they may be rooms or groups
+//! when the runner is given a [Home](crate::layout::Home).
+//!
+//! ```rhai
+//! for device in devices() {
+//!     if state(device.name).power == "on" {
+//!         print(`${device.name} was left on`);
+//!     }
+//! }
+//!
+//! for i in 0..6 {
+//!     color("office", hsv(i * 60.0, 1.0, 1.0));
+//!     sleep(2000);
+//! }
+//! off("office");
+//! ```
+//!
+//! Scripts are sandboxed: they cannot read or write files, `import` modules,
+//! `eval` code or reach the network other than through the functions
+//! above. Each run is limited in time, operations, call depth and the size of
+//! its strings and collections, as set by [Limits].
+use std::{
+    fmt,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rhai::{module_resolvers::DummyModuleResolver, Dynamic, Engine, EvalAltResult, Map, Position};
+use thiserror::Error;
+use tokio::runtime::Handle;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    client::GoveeClient,
+    lookup::Resolve,
+    models::{Color, DesiredState, Device, Devices, PowerState},
+};
+
+/// How often a sleeping script checks whether it should stop.
+const SLEEP_CHECK: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Error)]
+pub enum ScriptError {
+    #[error("failed to read script {}: {}", path.display(), source)]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("invalid script: {}", message)]
+    Compile { message: String },
+    #[error("script failed: {}", message)]
+    Runtime { message: String },
+    #[error("script ran for longer than {:?}", limit)]
+    Timeout { limit: Duration },
+    #[error("script ran more than {} operations", limit)]
+    TooManyOperations { limit: u64 },
+    #[error("script was cancelled")]
+    Cancelled,
+    #[error("script crashed: {}", source)]
+    Join {
+        #[from]
+        source: tokio::task::JoinError,
+    },
+}
+
+/// How much a script may do.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Limits {
+    /// How long a script may run, including time spent sleeping and waiting
+    /// for the API.
+    pub timeout: Duration,
+    pub max_operations: u64,
+    pub max_call_depth: usize,
+    /// In bytes.
+    pub max_string_size: usize,
+    /// The most items in an array or map.
+    pub max_collection_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(60),
+            max_operations: 1_000_000,
+            max_call_depth: 32,
+            max_string_size: 64 * 1024,
+            max_collection_size: 10_000,
+        }
+    }
+}
+
+/// What a script printed and returned.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ScriptOutput {
+    /// Every line passed to `print` or `debug`, in order.
+    pub printed: Vec<String>,
+
+    /// The value of the script's last expression, unless it was `()`.
+    pub value: Option<String>,
+}
+
+/// Why a script was stopped from outside, carried by Rhai's termination
+/// error.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Stop {
+    Timeout,
+    Cancelled,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Timeout => "timeout",
+            Self::Cancelled => "cancelled",
+        })
+    }
+}
+
+impl From<Stop> for Box<EvalAltResult> {
+    fn from(value: Stop) -> Self {
+        EvalAltResult::ErrorTerminated(value.to_string().into(), Position::NONE).into()
+    }
+}
+
+type RhaiResult<T> = Result<T, Box<EvalAltResult>>;
+
+type Printer = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// The lines a script printed.
+struct Printed {
+    lines: Mutex<Vec<String>>,
+    printer: Option<Printer>,
+}
+
+impl Printed {
+    fn push(&self, line: &str) {
+        if let Some(printer) = &self.printer {
+            printer(line);
+        }
+        self.lines
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(line.into());
+    }
+}
+
+/// What the script functions work with.
+struct Host<R> {
+    client: GoveeClient,
+    devices: Devices,
+    resolver: Arc<R>,
+    runtime: Handle,
+    deadline: Instant,
+    cancel: CancellationToken,
+}
+
+impl<R: Resolve> Host<R> {
+    fn stopped(&self) -> Option<Stop> {
+        if self.cancel.is_cancelled() {
+            Some(Stop::Cancelled)
+        } else if Instant::now() >= self.deadline {
+            Some(Stop::Timeout)
+        } else {
+            None
+        }
+    }
+
+    fn resolve(&self, target: &str) -> RhaiResult<Devices> {
+        self.resolver
+            .resolve(target)
+            .map_err(|e| e.to_string().into())
+    }
+
+    /// Wait for an API call, giving up when the script runs out of time or
+    /// is cancelled.
+    fn wait<T>(&self, call: impl Future<Output = T>) -> RhaiResult<T> {
+        let deadline = tokio::time::Instant::from_std(self.deadline);
+        let result = self.runtime.block_on(async {
+            tokio::select! {
+                result = tokio::time::timeout_at(deadline, call) => result.map_err(|_| Stop::Timeout),
+                _ = self.cancel.cancelled() => Err(Stop::Cancelled),
+            }
+        });
+        Ok(result?)
+    }
+
+    fn set(&self, target: &str, desired: DesiredState) -> RhaiResult<()> {
+        for device in self.resolve(target)?.iter() {
+            let cmds = desired.commands_for(device).map_err(|e| e.to_string())?;
+            for cmd in cmds {
+                self.wait(self.client.control(device, cmd))?
+                    .map_err(|e| format!("{}: {}", device.name, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn state(&self, target: &str) -> RhaiResult<Map> {
+        let devices = self.resolve(target)?;
+        let [device] = devices.devices.as_slice() else {
+            return Err(format!("{:?} is more than one device", target).into());
+        };
+        let state = self
+            .wait(self.client.state(device))?
+            .map_err(|e| format!("{}: {}", device.name, e))?
+            .normalized();
+
+        let mut map = Map::new();
+        let mut put = |key: &str, value: Option<Dynamic>| {
+            map.insert(key.into(), value.unwrap_or(Dynamic::UNIT));
+        };
+        put("online", state.online.map(Dynamic::from));
+        put(
+            "power",
+            state.power.map(|p| match p {
+                PowerState::On => "on".into(),
+                PowerState::Off => "off".into(),
+            }),
+        );
+        put("brightness", state.brightness.map(|b| (b as i64).into()));
+        put("color", state.color.map(|c| c.to_string().into()));
+        put("color_temp", state.color_temp.map(|t| (t as i64).into()));
+        Ok(map)
+    }
+
+    fn sleep(&self, ms: i64) -> RhaiResult<()> {
+        let until = Instant::now() + Duration::from_millis(ms.max(0) as u64);
+        loop {
+            if let Some(stop) = self.stopped() {
+                return Err(stop.into());
+            }
+            let now = Instant::now();
+            if now >= until {
+                return Ok(());
+            }
+            std::thread::sleep((until - now).min(SLEEP_CHECK));
+        }
+    }
+}
+
+fn device_map(device: &Device) -> Map {
+    let mut map = Map::new();
+    map.insert("id".into(), device.device.to_string().into());
+    map.insert("name".into(), device.name.clone().into());
+    map.insert("model".into(), device.model.to_string().into());
+    map
+}
+
+fn parse_color(s: &str) -> RhaiResult<Color> {
+    Color::parse(s)
+        .ok()
+        .or_else(|| Color::named(s))
+        .ok_or_else(|| format!("{:?} is not a hex color or a known color name", s).into())
+}
+
+fn percent(value: i64) -> RhaiResult<u64> {
+    u64::try_from(value).map_err(|_| format!("{} is not a valid brightness", value).into())
+}
+
+/// Runs scripts against a client's devices.
+///
+/// # Examples
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use govee_rs::{script::ScriptRunner, GoveeClient, DEFAULT_API_URL};
+/// use tokio_util::sync::CancellationToken;
+///
+/// let client = GoveeClient::new(DEFAULT_API_URL, "my-api-key")?;
+/// let devices = client.devices().await?;
+/// let runner = ScriptRunner::new(client, devices.clone(), devices);
+///
+/// let output = runner
+///     .run(r#"on("desk lamp"); color("desk lamp", "orange")"#, &CancellationToken::new())
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ScriptRunner<R> {
+    client: GoveeClient,
+    devices: Devices,
+    resolver: Arc<R>,
+    limits: Limits,
+    printer: Option<Printer>,
+}
+
+impl<R: Resolve + Send + Sync + 'static> ScriptRunner<R> {
+    /// Make a runner for `devices`, resolving targets with `resolver`.
+    pub fn new(client: GoveeClient, devices: Devices, resolver: R) -> Self {
+        Self {
+            client,
+            devices,
+            resolver: Arc::new(resolver),
+            limits: Limits::default(),
+            printer: None,
+        }
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Also pass every printed line to `printer` as soon as it is printed,
+    /// rather than only collecting it into the [ScriptOutput].
+    pub fn with_printer(mut self, printer: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.printer = Some(Arc::new(printer));
+        self
+    }
+
+    /// An engine with the sandbox and limits applied, but no functions.
+    fn sandbox(&self) -> Engine {
+        let mut engine = Engine::new();
+        engine
+            .set_module_resolver(DummyModuleResolver::new())
+            .disable_symbol("eval")
+            .set_max_operations(self.limits.max_operations)
+            .set_max_call_levels(self.limits.max_call_depth)
+            .set_max_string_size(self.limits.max_string_size)
+            .set_max_array_size(self.limits.max_collection_size)
+            .set_max_map_size(self.limits.max_collection_size);
+        engine
+    }
+
+    fn engine(&self, host: Arc<Host<R>>, printed: Arc<Printed>) -> Engine {
+        let mut engine = self.sandbox();
+
+        let progress = host.clone();
+        engine.on_progress(move |_| progress.stopped().map(|stop| stop.to_string().into()));
+
+        let lines = printed.clone();
+        engine.on_print(move |s| lines.push(s));
+        engine.on_debug(move |s, _, _| printed.push(s));
+
+        let h = host.clone();
+        engine.register_fn("devices", move || -> rhai::Array {
+            h.devices.iter().map(|d| device_map(d).into()).collect()
+        });
+        let h = host.clone();
+        engine.register_fn("state", move |target: &str| h.state(target));
+        let h = host.clone();
+        engine.register_fn("on", move |target: &str| {
+            h.set(
+                target,
+                DesiredState {
+                    power: Some(PowerState::On),
+                    ..Default::default()
+                },
+            )
+        });
+        let h = host.clone();
+        engine.register_fn("off", move |target: &str| {
+            h.set(
+                target,
+                DesiredState {
+                    power: Some(PowerState::Off),
+                    ..Default::default()
+                },
+            )
+        });
+        let h = host.clone();
+        engine.register_fn("brightness", move |target: &str, value: i64| {
+            h.set(
+                target,
+                DesiredState {
+                    brightness: Some(percent(value)?),
+                    ..Default::default()
+                },
+            )
+        });
+        let h = host.clone();
+        engine.register_fn("color", move |target: &str, color: &str| {
+            h.set(
+                target,
+                DesiredState {
+                    color: Some(parse_color(color)?),
+                    ..Default::default()
+                },
+            )
+        });
+        let h = host.clone();
+        engine.register_fn("color_temp", move |target: &str, kelvin: i64| {
+            h.set(
+                target,
+                DesiredState {
+                    color_temp: Some(kelvin.max(0) as u64),
+                    ..Default::default()
+                },
+            )
+        });
+        engine.register_fn("sleep", move |ms: i64| host.sleep(ms));
+
+        engine.register_fn("rgb", |r: i64, g: i64, b: i64| {
+            let channel = |n: i64| n.clamp(0, 255) as u8;
+            Color::from((channel(r), channel(g), channel(b))).to_string()
+        });
+        engine.register_fn("hsv", |h: f64, s: f64, v: f64| {
+            Color::from_hsv(h, s, v).to_string()
+        });
+        engine.register_fn(
+            "mix",
+            |from: &str, to: &str, amount: f64| -> RhaiResult<String> {
+                Ok(parse_color(from)?.mix(parse_color(to)?, amount).to_string())
+            },
+        );
+
+        engine
+    }
+
+    fn error(&self, error: Box<EvalAltResult>) -> ScriptError {
+        match error.unwrap_inner() {
+            EvalAltResult::ErrorTerminated(token, _)
+                if token.to_string() == Stop::Cancelled.to_string() =>
+            {
+                ScriptError::Cancelled
+            }
+            EvalAltResult::ErrorTerminated(..) => ScriptError::Timeout {
+                limit: self.limits.timeout,
+            },
+            EvalAltResult::ErrorTooManyOperations(_) => ScriptError::TooManyOperations {
+                limit: self.limits.max_operations,
+            },
+            _ => ScriptError::Runtime {
+                message: error.to_string(),
+            },
+        }
+    }
+
+    /// Check that `source` compiles, without running it.
+    pub fn check(&self, source: &str) -> Result<(), ScriptError> {
+        self.sandbox()
+            .compile(source)
+            .map(|_| ())
+            .map_err(|e| ScriptError::Compile {
+                message: e.to_string(),
+            })
+    }
+
+    /// Run `source` until it finishes, runs out of time, or `cancel` is
+    /// triggered.
+    ///
+    /// The script runs on a blocking thread, so it does not hold up the
+    /// runtime while it computes or sleeps.
+    pub async fn run(
+        &self,
+        source: &str,
+        cancel: &CancellationToken,
+    ) -> Result<ScriptOutput, ScriptError> {
+        let host = Arc::new(Host {
+            client: self.client.clone(),
+            devices: self.devices.clone(),
+            resolver: self.resolver.clone(),
+            runtime: Handle::current(),
+            deadline: Instant::now() + self.limits.timeout,
+            cancel: cancel.child_token(),
+        });
+        let printed = Arc::new(Printed {
+            lines: Mutex::default(),
+            printer: self.printer.clone(),
+        });
+        let engine = self.engine(host, printed.clone());
+        let source = source.to_string();
+
+        let result = tokio::task::spawn_blocking(move || {
+            let ast = engine.compile(&source).map_err(|e| Err(e.to_string()))?;
+            engine.eval_ast::<Dynamic>(&ast).map_err(Ok)
+        })
+        .await?;
+
+        let value = match result {
+            Ok(value) => value,
+            Err(Err(message)) => return Err(ScriptError::Compile { message }),
+            Err(Ok(error)) => return Err(self.error(error)),
+        };
+
+        let printed = std::mem::take(&mut *printed.lines.lock().unwrap_or_else(|e| e.into_inner()));
+        Ok(ScriptOutput {
+            printed,
+            value: (!value.is_unit()).then(|| value.to_string()),
+        })
+    }
+
+    /// Run the script in `path`, like [ScriptRunner::run].
+    pub async fn run_file(
+        &self,
+        path: impl AsRef<Path>,
+        cancel: &CancellationToken,
+    ) -> Result<ScriptOutput, ScriptError> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path).map_err(|source| ScriptError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        self.run(&source, cancel).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use mockito::Server;
+
+    use super::*;
+    use crate::models::{ControlCommand, DeviceId};
+
+    fn runner(url: &str) -> ScriptRunner<Devices> {
+        let devices = Devices {
+            devices: vec![Device {
+                model: "H6159".into(),
+                name: "desk lamp".into(),
+                controllable: true,
+                supported_commands: HashSet::from([ControlCommand::Turn, ControlCommand::Color]),
+                ..Device::new(DeviceId::parse("34:20:03:2e:30:2b").unwrap())
+            }],
+        };
+        let client = GoveeClient::new(url, "key").unwrap();
+        ScriptRunner::new(client, devices.clone(), devices)
+    }
+
+    #[tokio::test]
+    async fn runs_scripts_against_devices() {
+        let mut server = Server::new_async().await;
+        let control = server
+            .mock("PUT", "/v1/devices/control?")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "cmd": { "name": "color", "value": { "r": 255, "g": 0, "b": 0 } },
+            })))
+            .with_body(r#"{"code": 200, "message": "Success", "data": {}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let output = runner(&server.url())
+            .run(
+                r#"
+                for device in devices() {
+                    print(device.name);
+                }
+                color("desk lamp", hsv(360.0, 1.0, 1.0));
+                mix("black", rgb(255, 255, 255), 0.5)
+                "#,
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        control.assert_async().await;
+        assert_eq!(output.printed, ["desk lamp"]);
+        assert_eq!(output.value.as_deref(), Some("#808080"));
+
+        let unsupported = runner(&server.url())
+            .run(r#"brightness("desk lamp", 50)"#, &CancellationToken::new())
+            .await;
+        assert!(matches!(unsupported, Err(ScriptError::Runtime { .. })));
+    }
+
+    #[tokio::test]
+    async fn scripts_are_limited_and_sandboxed() {
+        let runner = runner("http://127.0.0.1:9").with_limits(Limits {
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        });
+        let cancel = CancellationToken::new();
+
+        let forever = runner.run("loop { sleep(10) }", &cancel).await;
+        assert!(
+            matches!(forever, Err(ScriptError::Timeout { .. })),
+            "{:?}",
+            forever
+        );
+        let busy = runner.run("let x = 0; loop { x += 1 }", &cancel).await;
+        assert!(matches!(
+            busy,
+            Err(ScriptError::Timeout { .. } | ScriptError::TooManyOperations { .. })
+        ));
+
+        assert!(runner
+            .run(r#"import "secrets" as s;"#, &cancel)
+            .await
+            .is_err());
+        assert!(matches!(
+            runner.check(r#"eval("1 + 1")"#),
+            Err(ScriptError::Compile { .. })
+        ));
+
+        cancel.cancel();
+        let cancelled = runner.run("sleep(10000)", &cancel).await;
+        assert!(
+            matches!(cancelled, Err(ScriptError::Cancelled)),
+            "{:?}",
+            cancelled
+        );
+    }
+
+    #[tokio::test]
+    async fn api_calls_stop_with_the_script() {
+        // accepts connections and never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                held.push(stream);
+            }
+        });
+
+        let runner = runner(&url).with_limits(Limits {
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        });
+        let started = Instant::now();
+        let timed_out = runner
+            .run(r#"on("desk lamp")"#, &CancellationToken::new())
+            .await;
+        assert!(
+            matches!(timed_out, Err(ScriptError::Timeout { .. })),
+            "{:?}",
+            timed_out
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let runner = runner.with_limits(Limits::default());
+        let cancel = CancellationToken::new();
+        let cancelling = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            cancelling.cancel();
+        });
+        let cancelled = runner.run(r#"state("desk lamp")"#, &cancel).await;
+        assert!(
+            matches!(cancelled, Err(ScriptError::Cancelled)),
+            "{:?}",
+            cancelled
+        );
+    }
+
+    #[test]
+    fn check_applies_the_limits() {
+        let runner = runner("http://127.0.0.1:9").with_limits(Limits {
+            max_string_size: 8,
+            ..Default::default()
+        });
+        assert!(runner.check(r#"let s = "short";"#).is_ok());
+        assert!(matches!(
+            runner.check(r#"let s = "rather too long";"#),
+            Err(ScriptError::Compile { .. })
+        ));
+    }
+}
@@ -0,0 +1,1077 @@
+//! Scripted light sequences.
+//!
+//! A [Sequence] is a list of steps, usually written as a TOML or YAML file:
+//!
+//! ```toml
+//! on_cancel = "restore"
+//!
+//! [[steps]]
+//! set = "office"
+//! power = "on"
+//! color = "#ff0000"
+//!
+//! [[steps]]
+//! wait = "2s"
+//!
+//! [[steps]]
+//! fade = "desk"
+//! brightness = 10
+//! over = "30s"
+//!
+//! [[steps]]
+//! repeat = 3
+//! steps = [
+//!     { set = "desk", power = "off" },
+//!     { wait = "500ms" },
+//!     { set = "desk", power = "on" },
+//!     { wait = "500ms" },
+//! ]
+//!
+//! [[steps]]
+//! restore = true
+//! ```
+//!
+//! Targets are resolved with anything implementing [Resolve], so they may be
+//! device names or, with a [Home](crate::layout::Home), rooms and groups.
+//!
+//! A sequence is checked and resolved with [Sequence::plan] before anything is
+//! sent. The resulting [Plan] can be inspected with [Plan::dry_run] or
+//! executed with [Plan::run], which can be cancelled at any point. When a run
+//! is cancelled or fails, the devices are left as described by
+//! [Sequence::on_cancel].
+use std::{collections::HashMap, fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+
+#[cfg(feature = "config")]
+use crate::config::{self, ConfigError};
+use crate::{
+    client::{GoveeClient, GoveeError},
+    lookup::Resolve,
+    models::{
+        color_or_hex, Color, ControlCmd, ControlCommand, DesiredState, DesiredStateError, Device,
+        DeviceId, Devices, NormalizedState, PowerState,
+    },
+};
+
+/// The most steps a fade is split into when not otherwise specified.
+pub const MAX_FADE_STEPS: u32 = 20;
+
+/// The shortest time between fade steps when not otherwise specified.
+pub const MIN_FADE_STEP: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Error)]
+pub enum SequenceError {
+    #[cfg(feature = "config")]
+    #[error("failed to load sequence: {}", source)]
+    Config {
+        #[from]
+        source: ConfigError,
+    },
+    #[error("invalid sequence: {}", ProblemList(problems))]
+    Invalid { problems: Vec<SequenceProblem> },
+    #[error("sequence failed: {}", source)]
+    Govee {
+        #[from]
+        source: GoveeError,
+    },
+}
+
+/// A problem with a particular step, found by [Sequence::plan].
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+#[error("{}: {}", path, message)]
+pub struct SequenceProblem {
+    /// Where the problem is, like `steps[2].steps[0]`.
+    pub path: String,
+    pub message: String,
+}
+
+struct ProblemList<'a>(&'a [SequenceProblem]);
+
+impl<'a> fmt::Display for ProblemList<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, p) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}", p)?;
+        }
+        Ok(())
+    }
+}
+
+/// What to leave devices as when a run is cancelled or fails.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnCancel {
+    /// Put every device the sequence touched back the way it was.
+    #[default]
+    Restore,
+    /// Turn off every device the sequence touched.
+    Off,
+    /// Leave devices however the sequence left them.
+    Leave,
+}
+
+/// Set one or more properties of a target.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetStep {
+    /// The device, room or group to change.
+    pub set: String,
+    #[serde(default)]
+    pub power: Option<PowerState>,
+    #[serde(default)]
+    pub brightness: Option<u64>,
+    #[serde(default, deserialize_with = "color_or_hex")]
+    pub color: Option<Color>,
+    #[serde(default)]
+    pub color_temp: Option<u64>,
+}
+
+impl SetStep {
+    /// The state this step puts its target in.
+    pub fn desired(&self) -> DesiredState {
+        DesiredState {
+            power: self.power,
+            brightness: self.brightness,
+            color: self.color,
+            color_temp: self.color_temp,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WaitStep {
+    #[serde(with = "humantime_serde")]
+    pub wait: Duration,
+}
+
+/// Gradually change the brightness of a target.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FadeStep {
+    /// The device, room or group to fade.
+    pub fade: String,
+
+    /// The starting brightness, defaulting to the current brightness.
+    #[serde(default)]
+    pub from: Option<u64>,
+
+    /// The final brightness.
+    pub brightness: u64,
+
+    #[serde(with = "humantime_serde")]
+    pub over: Duration,
+
+    /// How many brightness changes to make, defaulting to one every
+    /// [MIN_FADE_STEP], up to [MAX_FADE_STEPS].
+    #[serde(default)]
+    pub steps: Option<u32>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepeatStep {
+    pub repeat: u32,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RestoreTarget {
+    /// `true` restores everything the sequence touches.
+    All(bool),
+    /// Restore only the given device, room or group.
+    Target(String),
+}
+
+/// Put devices back the way they were when the sequence started.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RestoreStep {
+    pub restore: RestoreTarget,
+}
+
+/// A single step, identified by which of `set`, `wait`, `fade`, `repeat` or
+/// `restore` it contains.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Step {
+    Set(SetStep),
+    Wait(WaitStep),
+    Fade(FadeStep),
+    Repeat(RepeatStep),
+    Restore(RestoreStep),
+}
+
+impl<'de> Deserialize<'de> for Step {
+    // Dispatching on the identifying key (rather than `#[serde(untagged)]`)
+    // means mistakes are reported against the right kind of step.
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        const KINDS: [&str; 5] = ["set", "wait", "fade", "repeat", "restore"];
+
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let Some(map) = value.as_object() else {
+            return Err(D::Error::custom("a step must be a table"));
+        };
+
+        let kinds: Vec<_> = KINDS.iter().filter(|k| map.contains_key(**k)).collect();
+        let step = match kinds.as_slice() {
+            [&"set"] => serde_json::from_value(value).map(Step::Set),
+            [&"wait"] => serde_json::from_value(value).map(Step::Wait),
+            [&"fade"] => serde_json::from_value(value).map(Step::Fade),
+            [&"repeat"] => serde_json::from_value(value).map(Step::Repeat),
+            [&"restore"] => serde_json::from_value(value).map(Step::Restore),
+            [] => {
+                return Err(D::Error::custom(format!(
+                    "a step needs one of: {}",
+                    KINDS.join(", ")
+                )))
+            }
+            _ => {
+                return Err(D::Error::custom(format!(
+                    "a step can only be one of: {}",
+                    KINDS.join(", ")
+                )))
+            }
+        };
+
+        step.map_err(D::Error::custom)
+    }
+}
+
+/// A sequence of steps as written by a user.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sequence {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub on_cancel: OnCancel,
+    pub steps: Vec<Step>,
+}
+
+impl Sequence {
+    /// Load a sequence from a `.toml`, `.yaml` or `.yml` file.
+    #[cfg(feature = "config")]
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, SequenceError> {
+        Ok(config::load(path.as_ref())?)
+    }
+
+    #[cfg(feature = "config")]
+    pub fn from_toml(raw: &str) -> Result<Self, SequenceError> {
+        Ok(config::from_toml(raw)?)
+    }
+
+    #[cfg(feature = "config")]
+    pub fn from_yaml(raw: &str) -> Result<Self, SequenceError> {
+        Ok(config::from_yaml(raw)?)
+    }
+
+    /// Validate the sequence and resolve its targets.
+    ///
+    /// Every problem found is reported, not just the first.
+    pub fn plan<R: Resolve + ?Sized>(&self, resolver: &R) -> Result<Plan, SequenceError> {
+        let mut planner = Planner {
+            resolver,
+            problems: Vec::new(),
+            touched: Devices::default(),
+        };
+
+        let items = planner.steps(&self.steps, "steps");
+
+        if self.steps.is_empty() {
+            planner.problem("steps", "a sequence needs at least one step");
+        }
+
+        if !planner.problems.is_empty() {
+            return Err(SequenceError::Invalid {
+                problems: planner.problems,
+            });
+        }
+
+        Ok(Plan {
+            items,
+            touched: planner.touched,
+            on_cancel: self.on_cancel,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Control {
+        device: Device,
+        cmd: ControlCmd,
+    },
+    Wait(Duration),
+    Fade {
+        device: Device,
+        from: Option<u64>,
+        to: u64,
+        over: Duration,
+        steps: u32,
+    },
+    Repeat {
+        times: u32,
+        items: Vec<Item>,
+    },
+    /// `None` restores everything the sequence touches.
+    Restore(Option<Devices>),
+}
+
+struct Planner<'a, R: ?Sized> {
+    resolver: &'a R,
+    problems: Vec<SequenceProblem>,
+    touched: Devices,
+}
+
+impl<'a, R: Resolve + ?Sized> Planner<'a, R> {
+    fn problem(&mut self, path: &str, message: impl Into<String>) {
+        self.problems.push(SequenceProblem {
+            path: path.to_string(),
+            message: message.into(),
+        });
+    }
+
+    fn target(&mut self, path: &str, name: &str, needs: &[ControlCommand]) -> Devices {
+        let devices = match self.resolver.resolve(name) {
+            Ok(devices) => devices,
+            Err(e) => {
+                self.problem(path, e.to_string());
+                return Devices::default();
+            }
+        };
+
+        for device in devices.iter() {
+            if !device.controllable {
+                self.problem(path, format!("{:?} cannot be controlled", device.name));
+            }
+            for cmd in needs {
+                if !device.supports(cmd) {
+                    self.problem(
+                        path,
+                        format!("{:?} does not support {:?}", device.name, cmd),
+                    );
+                }
+            }
+        }
+
+        self.touched = self.touched.union(&devices);
+        devices
+    }
+
+    fn steps(&mut self, steps: &[Step], path: &str) -> Vec<Item> {
+        let mut items = Vec::new();
+        for (i, step) in steps.iter().enumerate() {
+            let path = format!("{}[{}]", path, i);
+            match step {
+                Step::Set(set) => self.set(set, &path, &mut items),
+                Step::Wait(wait) => items.push(Item::Wait(wait.wait)),
+                Step::Fade(fade) => self.fade(fade, &path, &mut items),
+                Step::Repeat(repeat) => {
+                    if repeat.repeat == 0 {
+                        self.problem(&path, "repeat must be at least 1");
+                    }
+                    if repeat.steps.is_empty() {
+                        self.problem(&path, "repeat needs at least one step");
+                    }
+                    let inner = self.steps(&repeat.steps, &format!("{}.steps", path));
+                    items.push(Item::Repeat {
+                        times: repeat.repeat,
+                        items: inner,
+                    });
+                }
+                Step::Restore(restore) => {
+                    let devices = match &restore.restore {
+                        RestoreTarget::All(true) => None,
+                        RestoreTarget::All(false) => {
+                            self.problem(&path, "restore must be true or a target");
+                            continue;
+                        }
+                        RestoreTarget::Target(name) => Some(self.target(&path, name, &[])),
+                    };
+                    items.push(Item::Restore(devices));
+                }
+            }
+        }
+
+        items
+    }
+
+    fn set(&mut self, set: &SetStep, path: &str, items: &mut Vec<Item>) {
+        let desired = set.desired();
+        if let Err(e) = desired.commands() {
+            self.problem(path, e.to_string());
+        }
+
+        let devices = self.target(path, &set.set, &[]);
+        for device in devices.iter() {
+            match desired.commands_for(device) {
+                Ok(cmds) => items.extend(cmds.into_iter().map(|cmd| Item::Control {
+                    device: device.clone(),
+                    cmd,
+                })),
+                Err(e @ DesiredStateError::Unsupported { .. }) => self.problem(path, e.to_string()),
+                // already reported above
+                Err(_) => {}
+            }
+        }
+    }
+
+    fn fade(&mut self, fade: &FadeStep, path: &str, items: &mut Vec<Item>) {
+        if fade.brightness > 100 || fade.from.is_some_and(|f| f > 100) {
+            self.problem(path, "brightness must be between 0 and 100");
+        }
+        if fade.over.is_zero() {
+            self.problem(path, "fades must take some time");
+        }
+        if fade.steps == Some(0) {
+            self.problem(path, "steps must be at least 1");
+        }
+
+        let steps = fade.steps.unwrap_or_else(|| {
+            let fit = fade.over.as_secs_f64() / MIN_FADE_STEP.as_secs_f64();
+            (fit as u32).clamp(1, MAX_FADE_STEPS)
+        });
+
+        let devices = self.target(path, &fade.fade, &[ControlCommand::Brightness]);
+        for device in devices.iter() {
+            if fade.from.is_none() && !device.retrievable {
+                self.problem(
+                    path,
+                    format!(
+                        "{:?} can't report its brightness, so the fade needs a `from`",
+                        device.name
+                    ),
+                );
+            }
+            items.push(Item::Fade {
+                device: device.clone(),
+                from: fade.from,
+                to: fade.brightness,
+                over: fade.over,
+                steps,
+            });
+        }
+    }
+}
+
+/// One entry in the output of [Plan::dry_run].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PlannedAction {
+    /// How long after the start of the run this happens.
+    pub at: Duration,
+    pub device: DeviceId,
+    pub name: String,
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Action {
+    Control(ControlCmd),
+    Fade {
+        from: Option<u64>,
+        to: u64,
+        over: Duration,
+        steps: u32,
+    },
+    Restore,
+}
+
+impl fmt::Display for PlannedAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>8.1}s  {}: ", self.at.as_secs_f64(), self.name)?;
+        match &self.action {
+            Action::Control(ControlCmd::Turn(p)) => write!(f, "turn {}", p),
+            Action::Control(ControlCmd::Brightness(b)) => write!(f, "brightness {}", b),
+            Action::Control(ControlCmd::Color(c)) => write!(f, "color {}", c),
+            Action::Control(ControlCmd::ColorTem(t)) => write!(f, "color temp {}K", t),
+            Action::Fade {
+                from,
+                to,
+                over,
+                steps,
+            } => {
+                let from = from.map_or_else(|| "current".to_string(), |b| b.to_string());
+                write!(
+                    f,
+                    "fade brightness {} -> {} over {}s in {} steps",
+                    from,
+                    to,
+                    over.as_secs_f64(),
+                    steps
+                )
+            }
+            Action::Restore => f.write_str("restore"),
+        }
+    }
+}
+
+/// How a run ended.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Outcome {
+    Completed,
+    Cancelled,
+}
+
+/// A validated [Sequence] with its targets resolved.
+#[derive(Debug, Clone)]
+pub struct Plan {
+    items: Vec<Item>,
+    touched: Devices,
+    on_cancel: OnCancel,
+}
+
+impl Plan {
+    /// Every device the sequence touches.
+    pub fn devices(&self) -> &Devices {
+        &self.touched
+    }
+
+    /// How long the sequence takes, ignoring request latency.
+    pub fn duration(&self) -> Duration {
+        fn total(items: &[Item]) -> Duration {
+            items
+                .iter()
+                .map(|item| match item {
+                    Item::Wait(d) => *d,
+                    Item::Fade { over, .. } => *over,
+                    Item::Repeat { times, items } => total(items) * *times,
+                    _ => Duration::ZERO,
+                })
+                .sum()
+        }
+        total(&self.items)
+    }
+
+    /// Describe everything the run would do, without sending anything.
+    ///
+    /// # Examples
+    /// ```
+    /// use govee_rs::{
+    ///     models::{ControlCmd, ControlCommand, Device, Devices, PowerState},
+    ///     sequence::{Action, Sequence},
+    /// };
+    ///
+    /// let devices = Devices {
+    ///     devices: vec![Device {
+    ///         name: "desk".into(),
+    ///         controllable: true,
+    ///         supported_commands: [ControlCommand::Turn].into(),
+    ///         ..Device::new("34:20:03:2e:30:2b".parse().unwrap())
+    ///     }],
+    /// };
+    ///
+    /// let sequence: Sequence = serde_json::from_str(r#"{
+    ///     "on_cancel": "leave",
+    ///     "steps": [
+    ///         {"set": "desk", "power": "on"},
+    ///         {"wait": "2s"},
+    ///         {"set": "desk", "power": "off"}
+    ///     ]
+    /// }"#).unwrap();
+    ///
+    /// let plan = sequence.plan(&devices).unwrap();
+    /// let actions = plan.dry_run();
+    /// assert_eq!(actions.len(), 2);
+    /// assert_eq!(actions[1].at.as_secs(), 2);
+    /// assert_eq!(actions[1].action, Action::Control(ControlCmd::Turn(PowerState::Off)));
+    /// ```
+    pub fn dry_run(&self) -> Vec<PlannedAction> {
+        fn walk(
+            items: &[Item],
+            touched: &Devices,
+            at: &mut Duration,
+            out: &mut Vec<PlannedAction>,
+        ) {
+            for item in items {
+                match item {
+                    Item::Control { device, cmd } => out.push(PlannedAction {
+                        at: *at,
+                        device: device.device.clone(),
+                        name: device.name.clone(),
+                        action: Action::Control(*cmd),
+                    }),
+                    Item::Wait(d) => *at += *d,
+                    Item::Fade {
+                        device,
+                        from,
+                        to,
+                        over,
+                        steps,
+                    } => {
+                        out.push(PlannedAction {
+                            at: *at,
+                            device: device.device.clone(),
+                            name: device.name.clone(),
+                            action: Action::Fade {
+                                from: *from,
+                                to: *to,
+                                over: *over,
+                                steps: *steps,
+                            },
+                        });
+                        *at += *over;
+                    }
+                    Item::Repeat { times, items } => {
+                        for _ in 0..*times {
+                            walk(items, touched, at, out);
+                        }
+                    }
+                    Item::Restore(devices) => {
+                        for device in devices.as_ref().unwrap_or(touched).iter() {
+                            out.push(PlannedAction {
+                                at: *at,
+                                device: device.device.clone(),
+                                name: device.name.clone(),
+                                action: Action::Restore,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut out = Vec::new();
+        let mut at = Duration::ZERO;
+        walk(&self.items, &self.touched, &mut at, &mut out);
+        out
+    }
+
+    /// Run the sequence until it completes or `cancel` is triggered.
+    ///
+    /// The state of every retrievable device the sequence touches is captured
+    /// before anything is sent, for use by restore steps and
+    /// [OnCancel::Restore]. If the run is cancelled or a request fails,
+    /// devices are left as described by the sequence's [OnCancel] before
+    /// returning.
+    pub async fn run(
+        &self,
+        client: &GoveeClient,
+        cancel: &CancellationToken,
+    ) -> Result<Outcome, SequenceError> {
+        let mut saved = HashMap::new();
+        for device in self.touched.retrievable().iter() {
+            saved.insert(
+                device.device.clone(),
+                client.state(device).await?.normalized(),
+            );
+        }
+
+        let runner = Runner {
+            client,
+            cancel,
+            saved: &saved,
+            touched: &self.touched,
+        };
+
+        let result = runner.items(&self.items).await;
+
+        match result {
+            Ok(Outcome::Completed) => Ok(Outcome::Completed),
+            other => {
+                // clean up with a fresh token so this isn't cancelled too
+                let cleanup = Runner {
+                    client,
+                    cancel: &CancellationToken::new(),
+                    saved: &saved,
+                    touched: &self.touched,
+                };
+                match self.on_cancel {
+                    OnCancel::Restore => cleanup.restore(&self.touched).await?,
+                    OnCancel::Off => {
+                        for device in self.touched.supporting(ControlCommand::Turn).iter() {
+                            client.turn(device, PowerState::Off).await?;
+                        }
+                    }
+                    OnCancel::Leave => {}
+                }
+                other
+            }
+        }
+    }
+}
+
+struct Runner<'a> {
+    client: &'a GoveeClient,
+    cancel: &'a CancellationToken,
+    saved: &'a HashMap<DeviceId, NormalizedState>,
+    touched: &'a Devices,
+}
+
+impl<'a> Runner<'a> {
+    /// Returns false if cancelled while waiting.
+    async fn wait(&self, duration: Duration) -> bool {
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => true,
+            _ = self.cancel.cancelled() => false,
+        }
+    }
+
+    async fn control(&self, device: &Device, cmd: ControlCmd) -> Result<(), SequenceError> {
+        match cmd {
+            ControlCmd::Turn(p) => self.client.turn(device, p).await?,
+            ControlCmd::Brightness(b) => self.client.brightness(device, b).await?,
+            ControlCmd::Color(c) => self.client.color(device, c).await?,
+            ControlCmd::ColorTem(t) => self.client.color_temp(device, t).await?,
+        }
+        Ok(())
+    }
+
+    async fn items(&self, items: &[Item]) -> Result<Outcome, SequenceError> {
+        for item in items {
+            if self.cancel.is_cancelled() {
+                return Ok(Outcome::Cancelled);
+            }
+
+            match item {
+                Item::Control { device, cmd } => self.control(device, *cmd).await?,
+                Item::Wait(d) => {
+                    if !self.wait(*d).await {
+                        return Ok(Outcome::Cancelled);
+                    }
+                }
+                Item::Fade {
+                    device,
+                    from,
+                    to,
+                    over,
+                    steps,
+                } => {
+                    let from = match from {
+                        Some(from) => *from,
+                        None => self
+                            .client
+                            .state(device)
+                            .await?
+                            .normalized()
+                            .brightness
+                            .unwrap_or(100),
+                    };
+
+                    let interval = *over / *steps;
+                    for step in 1..=*steps {
+                        let value = fade_value(from, *to, step, *steps);
+                        self.client.brightness(device, value).await?;
+                        if !self.wait(interval).await {
+                            return Ok(Outcome::Cancelled);
+                        }
+                    }
+                }
+                Item::Repeat { times, items } => {
+                    for _ in 0..*times {
+                        if Box::pin(self.items(items)).await? == Outcome::Cancelled {
+                            return Ok(Outcome::Cancelled);
+                        }
+                    }
+                }
+                Item::Restore(devices) => {
+                    self.restore(devices.as_ref().unwrap_or(self.touched))
+                        .await?
+                }
+            }
+        }
+
+        Ok(Outcome::Completed)
+    }
+
+    async fn restore(&self, devices: &Devices) -> Result<(), SequenceError> {
+        for device in devices.iter() {
+            let Some(state) = self.saved.get(&device.device) else {
+                continue;
+            };
+
+            if state.power == Some(PowerState::Off) {
+                self.control(device, ControlCmd::Turn(PowerState::Off))
+                    .await?;
+                continue;
+            }
+
+            if let Some(power) = state.power {
+                self.control(device, ControlCmd::Turn(power)).await?;
+            }
+            match (state.color_temp, state.color) {
+                (Some(temp), _) if temp > 0 && device.supports(&ControlCommand::ColorTem) => {
+                    self.control(device, ControlCmd::ColorTem(temp)).await?
+                }
+                (_, Some(color)) if device.supports(&ControlCommand::Color) => {
+                    self.control(device, ControlCmd::Color(color)).await?
+                }
+                _ => {}
+            }
+            if let Some(brightness) = state.brightness {
+                if device.supports(&ControlCommand::Brightness) {
+                    self.control(device, ControlCmd::Brightness(brightness))
+                        .await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The brightness after `step` of `steps` in a linear fade.
+fn fade_value(from: u64, to: u64, step: u32, steps: u32) -> u64 {
+    let progress = f64::from(step) / f64::from(steps.max(1));
+    let value = from as f64 + (to as f64 - from as f64) * progress;
+    value.round() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn devices() -> Devices {
+        use ControlCommand::*;
+
+        let device = |id: &str, name: &str, cmds: &[ControlCommand]| Device {
+            device: DeviceId::parse(id).unwrap(),
+            model: "H6159".into(),
+            name: name.into(),
+            controllable: true,
+            retrievable: true,
+            supported_commands: cmds.iter().copied().collect(),
+        };
+
+        Devices {
+            devices: vec![
+                device(
+                    "34:20:03:2e:30:01",
+                    "desk",
+                    &[Turn, Brightness, Color, ColorTem],
+                ),
+                device("34:20:03:2e:30:02", "plug", &[Turn]),
+            ],
+        }
+    }
+
+    fn sequence(json: &str) -> Sequence {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn step_parsing() {
+        let seq = sequence(
+            r##"{"steps": [
+                {"set": "desk", "color": "#ff0000", "power": "on"},
+                {"wait": "1m 30s"},
+                {"fade": "desk", "brightness": 10, "over": "30s"},
+                {"repeat": 2, "steps": [{"wait": "500ms"}]},
+                {"restore": true},
+                {"restore": "desk"}
+            ]}"##,
+        );
+
+        assert!(matches!(seq.steps[0], Step::Set(_)));
+        assert_eq!(
+            seq.steps[1],
+            Step::Wait(WaitStep {
+                wait: Duration::from_secs(90)
+            })
+        );
+        assert!(matches!(seq.steps[2], Step::Fade(_)));
+        assert!(matches!(seq.steps[3], Step::Repeat(_)));
+        assert!(matches!(seq.steps[5], Step::Restore(_)));
+
+        assert!(
+            serde_json::from_str::<Sequence>(r#"{"steps": [{"set": "x", "wait": "1s"}]}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn validation() {
+        let seq = sequence(
+            r##"{"steps": [
+                {"set": "plug", "color": "#ff0000"},
+                {"set": "desk"},
+                {"set": "garage", "power": "on"},
+                {"fade": "desk", "brightness": 200, "over": "0s"},
+                {"repeat": 0, "steps": [{"set": "desk", "color_temp": 100}]}
+            ]}"##,
+        );
+
+        match seq.plan(&devices()) {
+            Err(SequenceError::Invalid { problems }) => {
+                let paths: Vec<_> = problems.iter().map(|p| p.path.as_str()).collect();
+                assert_eq!(
+                    paths,
+                    vec![
+                        "steps[0]",
+                        "steps[1]",
+                        "steps[2]",
+                        "steps[3]",
+                        "steps[3]",
+                        "steps[4]",
+                        "steps[4].steps[0]",
+                    ]
+                );
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn dry_run() {
+        let seq = sequence(
+            r##"{"steps": [
+                {"set": "desk", "power": "off", "color": "#00ff00"},
+                {"repeat": 2, "steps": [
+                    {"set": "plug", "power": "on"},
+                    {"wait": "1s"}
+                ]},
+                {"fade": "desk", "from": 100, "brightness": 0, "over": "10s"},
+                {"restore": true}
+            ]}"##,
+        );
+
+        let plan = seq.plan(&devices()).unwrap();
+        assert_eq!(plan.duration(), Duration::from_secs(12));
+        assert_eq!(plan.devices().len(), 2);
+
+        let actions = plan.dry_run();
+        let summary: Vec<_> = actions
+            .iter()
+            .map(|a| (a.at.as_secs(), a.name.as_str(), a.action.clone()))
+            .collect();
+
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    0,
+                    "desk",
+                    Action::Control(ControlCmd::Color(Color { r: 0, g: 255, b: 0 }))
+                ),
+                (
+                    0,
+                    "desk",
+                    Action::Control(ControlCmd::Turn(PowerState::Off))
+                ),
+                (0, "plug", Action::Control(ControlCmd::Turn(PowerState::On))),
+                (1, "plug", Action::Control(ControlCmd::Turn(PowerState::On))),
+                (
+                    2,
+                    "desk",
+                    Action::Fade {
+                        from: Some(100),
+                        to: 0,
+                        over: Duration::from_secs(10),
+                        steps: 5
+                    }
+                ),
+                (12, "desk", Action::Restore),
+                (12, "plug", Action::Restore),
+            ]
+        );
+    }
+
+    #[test]
+    fn fade_values() {
+        let values: Vec<_> = (1..=4).map(|s| fade_value(100, 20, s, 4)).collect();
+        assert_eq!(values, vec![80, 60, 40, 20]);
+        assert_eq!(fade_value(10, 50, 1, 1), 50);
+    }
+
+    #[tokio::test]
+    async fn cancel_restores() {
+        let mut server = mockito::Server::new_async().await;
+        let client = GoveeClient::new(&server.url(), "foobarbaz").unwrap();
+
+        let state_mock = server
+            .mock("GET", "/v1/devices/state")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(
+                r#"{"data": {
+                    "device": "34:20:03:2e:30:01",
+                    "model": "H6159",
+                    "properties": [{"powerState": "off"}]
+                }}"#,
+            )
+            .create_async()
+            .await;
+
+        let on_mock = server
+            .mock("PUT", "/v1/devices/control?")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "cmd": {"name": "turn", "value": "on"}
+            })))
+            .with_status(200)
+            .with_body(r#"{"data": {}}"#)
+            .create_async()
+            .await;
+
+        let off_mock = server
+            .mock("PUT", "/v1/devices/control?")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "cmd": {"name": "turn", "value": "off"}
+            })))
+            .with_status(200)
+            .with_body(r#"{"data": {}}"#)
+            .create_async()
+            .await;
+
+        let mut devices = devices();
+        devices.devices.truncate(1);
+
+        let plan = sequence(
+            r#"{"steps": [
+                {"set": "desk", "power": "on"},
+                {"wait": "1h"},
+                {"set": "desk", "power": "on"}
+            ]}"#,
+        )
+        .plan(&devices)
+        .unwrap();
+
+        let cancel = CancellationToken::new();
+        let canceller = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            canceller.cancel();
+        });
+
+        let outcome = plan.run(&client, &cancel).await.unwrap();
+        assert_eq!(outcome, Outcome::Cancelled);
+
+        state_mock.assert_async().await;
+        on_mock.assert_async().await;
+        off_mock.assert_async().await;
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn toml() {
+        let seq = Sequence::from_toml(
+            r##"
+            on_cancel = "off"
+
+            [[steps]]
+            set = "desk"
+            color = "#ff0000"
+
+            [[steps]]
+            wait = "2s"
+
+            [[steps]]
+            repeat = 3
+            steps = [{ set = "desk", power = "off" }, { wait = "500ms" }]
+            "##,
+        )
+        .unwrap();
+
+        assert_eq!(seq.on_cancel, OnCancel::Off);
+        assert_eq!(seq.plan(&devices()).unwrap().dry_run().len(), 4);
+    }
+}
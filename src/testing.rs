@@ -0,0 +1,528 @@
+//! A fake Govee API for tests.
+//!
+//! [FakeServer] serves `v1/devices`, `v1/devices/state` and
+//! `v1/devices/control` on a local port from an in-memory set of devices, so
+//! control requests change the state later requests see. It can also take
+//! devices offline, fail requests, add latency, enforce rate limits, and
+//! records every request it receives for assertions.
+//!
+//! [lan] simulates devices on the local network in the same spirit.
+//!
+//! # Examples
+//! ```
+//! # #[tokio::main]
+//! # async fn main() {
+//! use govee_rs::{models::PowerState, testing::{self, FakeServer}};
+//!
+//! let server = FakeServer::start().await.unwrap();
+//! let lamp = server.add_device(testing::light("34:20:03:2e:30:2b", "lamp"));
+//!
+//! let client = server.client();
+//! client.turn(&lamp, PowerState::On).await.unwrap();
+//!
+//! let state = client.state(&lamp).await.unwrap().normalized();
+//! assert_eq!(state.power, Some(PowerState::On));
+//! assert_eq!(server.controls().len(), 1);
+//! # }
+//! ```
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime},
+};
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, HeaderValue, Method as AxumMethod, StatusCode as AxumStatus, Uri},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use http::{Method, StatusCode};
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, task::JoinHandle};
+
+use crate::{
+    models::{
+        Color, ControlCmd, ControlCommand, ControlRequest, Device, DeviceId, DeviceProperty,
+        DeviceState, Devices, NormalizedState, PowerState, COLOR_TEMP_RANGE,
+    },
+    monitor::DAILY_REQUEST_LIMIT,
+    GoveeClient,
+};
+
+pub mod lan;
+
+/// The API key [FakeServer::client] uses, and the server accepts by default.
+pub const FAKE_API_KEY: &str = "fake-api-key";
+
+/// A light supporting every command.
+pub fn light(id: &str, name: &str) -> Device {
+    Device {
+        device: DeviceId::parse(id).expect("a valid device id"),
+        model: "H6159".into(),
+        name: name.into(),
+        controllable: true,
+        retrievable: true,
+        supported_commands: HashSet::from([
+            ControlCommand::Turn,
+            ControlCommand::Brightness,
+            ControlCommand::Color,
+            ControlCommand::ColorTem,
+        ]),
+    }
+}
+
+/// A smart plug that can only be turned on and off.
+pub fn plug(id: &str, name: &str) -> Device {
+    Device {
+        device: DeviceId::parse(id).expect("a valid device id"),
+        model: "H5081".into(),
+        name: name.into(),
+        controllable: true,
+        retrievable: true,
+        supported_commands: HashSet::from([ControlCommand::Turn]),
+    }
+}
+
+/// A request received by a [FakeServer].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedRequest {
+    pub method: Method,
+    pub path: String,
+    pub query: Option<String>,
+
+    /// Whether the request had the expected API key.
+    pub authorized: bool,
+
+    /// The body, if it was JSON.
+    pub body: Option<Value>,
+}
+
+impl RecordedRequest {
+    /// The control request this was, if it was a valid one.
+    pub fn control(&self) -> Option<ControlRequest<'static>> {
+        if self.method != Method::PUT || self.path != "/v1/devices/control" {
+            return None;
+        }
+        serde_json::from_value(self.body.clone()?).ok()
+    }
+}
+
+struct Fake {
+    api_key: String,
+    devices: Vec<Device>,
+    states: HashMap<DeviceId, NormalizedState>,
+    failures: VecDeque<(StatusCode, String)>,
+    latency: Duration,
+    requests: Vec<RecordedRequest>,
+
+    daily_limit: u64,
+    daily_used: u64,
+    endpoint_limit: Option<u64>,
+    // per endpoint: when the current minute started and requests made in it
+    endpoint_windows: HashMap<String, (Instant, u64)>,
+}
+
+/// A stateful fake of the Govee API, listening on localhost.
+///
+/// The server stops when this is dropped.
+pub struct FakeServer {
+    addr: SocketAddr,
+    fake: Arc<Mutex<Fake>>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for FakeServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl FakeServer {
+    /// Start a server with no devices on a free port.
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let fake = Arc::new(Mutex::new(Fake {
+            api_key: FAKE_API_KEY.into(),
+            devices: Vec::new(),
+            states: HashMap::new(),
+            failures: VecDeque::new(),
+            latency: Duration::ZERO,
+            requests: Vec::new(),
+            daily_limit: u64::from(DAILY_REQUEST_LIMIT),
+            daily_used: 0,
+            endpoint_limit: None,
+            endpoint_windows: HashMap::new(),
+        }));
+
+        let app = Router::new().fallback(handle).with_state(fake.clone());
+        let task = tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+
+        Ok(Self { addr, fake, task })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Fake> {
+        self.fake.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The url to pass to [GoveeClient::new].
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// A client for this server using [FAKE_API_KEY].
+    pub fn client(&self) -> GoveeClient {
+        GoveeClient::new(&self.url(), FAKE_API_KEY).expect("a valid url")
+    }
+
+    /// Accept `api_key` instead of [FAKE_API_KEY].
+    pub fn set_api_key(&self, api_key: &str) {
+        self.lock().api_key = api_key.into();
+    }
+
+    /// Add a device, online and switched off, returning it for convenience.
+    pub fn add_device(&self, device: Device) -> Device {
+        let supports = |command| device.supported_commands.contains(&command);
+        let state = NormalizedState {
+            device: device.device.clone(),
+            model: device.model.clone(),
+            online: Some(true),
+            power: Some(PowerState::Off),
+            brightness: supports(ControlCommand::Brightness).then_some(100),
+            color: supports(ControlCommand::Color).then_some(Color {
+                r: 255,
+                g: 255,
+                b: 255,
+            }),
+            color_temp: None,
+        };
+
+        let mut fake = self.lock();
+        fake.devices.retain(|d| d.device != device.device);
+        fake.devices.push(device.clone());
+        fake.states.insert(device.device.clone(), state);
+        device
+    }
+
+    /// The current state of a device.
+    pub fn state(&self, device: &DeviceId) -> Option<NormalizedState> {
+        self.lock().states.get(device).cloned()
+    }
+
+    /// Replace the state of a device, as though it was changed by hand.
+    pub fn set_state(&self, state: NormalizedState) {
+        self.lock().states.insert(state.device.clone(), state);
+    }
+
+    /// Take a device offline, or bring it back. Offline devices report their
+    /// last state and refuse control requests.
+    pub fn set_online(&self, device: &DeviceId, online: bool) {
+        if let Some(state) = self.lock().states.get_mut(device) {
+            state.online = Some(online);
+        }
+    }
+
+    /// Answer the next request with an error envelope, after any failures
+    /// already queued.
+    pub fn fail_next(&self, status: StatusCode, message: &str) {
+        self.lock().failures.push_back((status, message.into()));
+    }
+
+    /// Wait this long before answering each request.
+    pub fn set_latency(&self, latency: Duration) {
+        self.lock().latency = latency;
+    }
+
+    /// Allow this many requests in total before answering `429`, resetting
+    /// the count.
+    pub fn set_daily_limit(&self, limit: u64) {
+        let mut fake = self.lock();
+        fake.daily_limit = limit;
+        fake.daily_used = 0;
+    }
+
+    /// Allow this many requests per minute to each endpoint, or any number
+    /// if `None`, which is the default.
+    pub fn set_endpoint_limit(&self, limit: Option<u64>) {
+        let mut fake = self.lock();
+        fake.endpoint_limit = limit;
+        fake.endpoint_windows.clear();
+    }
+
+    /// Every request received so far.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.lock().requests.clone()
+    }
+
+    /// Every valid control request received so far, including refused ones.
+    pub fn controls(&self) -> Vec<ControlRequest<'static>> {
+        self.lock()
+            .requests
+            .iter()
+            .filter_map(RecordedRequest::control)
+            .collect()
+    }
+
+    pub fn clear_requests(&self) {
+        self.lock().requests.clear();
+    }
+}
+
+/// A Govee error envelope.
+fn error(status: StatusCode, message: &str) -> (StatusCode, Value) {
+    (
+        status,
+        json!({ "code": status.as_u16(), "message": message, "data": {} }),
+    )
+}
+
+fn success(data: Value) -> (StatusCode, Value) {
+    (
+        StatusCode::OK,
+        json!({ "code": 200, "message": "Success", "data": data }),
+    )
+}
+
+async fn handle(
+    State(fake): State<Arc<Mutex<Fake>>>,
+    method: AxumMethod,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let latency = fake.lock().unwrap_or_else(|e| e.into_inner()).latency;
+    if !latency.is_zero() {
+        tokio::time::sleep(latency).await;
+    }
+
+    let mut fake = fake.lock().unwrap_or_else(|e| e.into_inner());
+    let mut request = RecordedRequest {
+        method: Method::from_bytes(method.as_str().as_bytes()).unwrap_or_default(),
+        path: uri.path().to_string(),
+        query: uri.query().map(String::from),
+        authorized: false,
+        body: serde_json::from_slice(&body).ok(),
+    };
+    request.authorized = headers
+        .get("Govee-API-Key")
+        .is_some_and(|key| key.as_bytes() == fake.api_key.as_bytes());
+    fake.requests.push(request.clone());
+
+    let mut quota = HeaderMap::new();
+    let (status, body) = fake.respond(&request, &mut quota);
+
+    let status = AxumStatus::from_u16(status.as_u16()).unwrap_or(AxumStatus::OK);
+    (status, quota, Json(body)).into_response()
+}
+
+impl Fake {
+    fn respond(&mut self, request: &RecordedRequest, quota: &mut HeaderMap) -> (StatusCode, Value) {
+        if !request.authorized {
+            return error(StatusCode::UNAUTHORIZED, "Invalid API Key");
+        }
+        if let Some((status, message)) = self.failures.pop_front() {
+            return error(status, &message);
+        }
+        if let Some(refused) = self.take_quota(&request.path, quota) {
+            return refused;
+        }
+
+        match (&request.method, request.path.as_str()) {
+            (&Method::GET, "/v1/devices") => success(json!(Devices {
+                devices: self.devices.clone()
+            })),
+            (&Method::GET, "/v1/devices/state") => self.device_state(request.query.as_deref()),
+            (&Method::PUT, "/v1/devices/control") => match request.control() {
+                Some(control) => self.control(&control),
+                None => error(StatusCode::BAD_REQUEST, "Invalid Parameter"),
+            },
+            _ => error(StatusCode::NOT_FOUND, "Not Found"),
+        }
+    }
+
+    /// Count a request against the quotas, setting the rate limit headers and
+    /// refusing it if either quota is used up.
+    fn take_quota(&mut self, path: &str, headers: &mut HeaderMap) -> Option<(StatusCode, Value)> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut set = |name: &'static str, value: u64| {
+            headers.insert(name, HeaderValue::from(value));
+        };
+
+        let daily_refused = self.daily_used >= self.daily_limit;
+        if !daily_refused {
+            self.daily_used += 1;
+        }
+        set("X-RateLimit-Limit", self.daily_limit);
+        set(
+            "X-RateLimit-Remaining",
+            self.daily_limit.saturating_sub(self.daily_used),
+        );
+        set("X-RateLimit-Reset", now - now % 86400 + 86400);
+
+        let mut endpoint_refused = false;
+        if let Some(limit) = self.endpoint_limit {
+            let window = self
+                .endpoint_windows
+                .entry(path.to_string())
+                .or_insert((Instant::now(), 0));
+            if window.0.elapsed() >= Duration::from_secs(60) {
+                *window = (Instant::now(), 0);
+            }
+            endpoint_refused = window.1 >= limit;
+            if !endpoint_refused {
+                window.1 += 1;
+            }
+            let reset = Duration::from_secs(60).saturating_sub(window.0.elapsed());
+            set("API-RateLimit-Limit", limit);
+            set("API-RateLimit-Remaining", limit.saturating_sub(window.1));
+            set("API-RateLimit-Reset", now + reset.as_secs());
+        }
+
+        (daily_refused || endpoint_refused)
+            .then(|| error(StatusCode::TOO_MANY_REQUESTS, "Too Many Requests"))
+    }
+
+    fn find(&self, device: &str, model: &str) -> Result<&Device, (StatusCode, Value)> {
+        self.devices
+            .iter()
+            .find(|d| d.device.as_str() == device && d.model.as_str() == model)
+            .ok_or_else(|| error(StatusCode::BAD_REQUEST, "Device Not Found"))
+    }
+
+    fn device_state(&self, query: Option<&str>) -> (StatusCode, Value) {
+        let params: HashMap<_, _> =
+            url::form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+                .into_owned()
+                .collect();
+        let (Some(id), Some(model)) = (params.get("device"), params.get("model")) else {
+            return error(StatusCode::BAD_REQUEST, "Missing Parameter");
+        };
+
+        let device = match self.find(id, model) {
+            Ok(device) => device,
+            Err(e) => return e,
+        };
+        if !device.retrievable {
+            return error(StatusCode::BAD_REQUEST, "Device Not Retrievable");
+        }
+
+        let state = &self.states[&device.device];
+        let properties = [
+            state.online.map(|online| DeviceProperty::Online { online }),
+            state
+                .power
+                .map(|power_state| DeviceProperty::PowerState { power_state }),
+            state
+                .brightness
+                .map(|brightness| DeviceProperty::Brightness { brightness }),
+            state.color.map(|color| DeviceProperty::Color { color }),
+            state
+                .color_temp
+                .map(|color_tem| DeviceProperty::ColorTem { color_tem }),
+        ];
+        success(json!(DeviceState {
+            device: device.device.clone(),
+            model: device.model.clone(),
+            properties: properties.into_iter().flatten().collect(),
+        }))
+    }
+
+    fn control(&mut self, control: &ControlRequest) -> (StatusCode, Value) {
+        let device = match self.find(control.device.as_str(), control.model.as_str()) {
+            Ok(device) => device,
+            Err(e) => return e,
+        };
+        if !device.controllable || !device.supported_commands.contains(&control.cmd.command()) {
+            return error(StatusCode::BAD_REQUEST, "Unsupported Cmd");
+        }
+
+        let id = device.device.clone();
+        let state = self.states.get_mut(&id).expect("every device has a state");
+        if state.online == Some(false) {
+            return error(StatusCode::BAD_REQUEST, "Device Offline");
+        }
+
+        match control.cmd {
+            ControlCmd::Turn(power) => state.power = Some(power),
+            ControlCmd::Brightness(brightness) if brightness <= 100 => {
+                state.brightness = Some(brightness)
+            }
+            ControlCmd::Color(color) => {
+                state.color = Some(color);
+                state.color_temp = None;
+            }
+            ControlCmd::ColorTem(temp)
+                if (COLOR_TEMP_RANGE.0..=COLOR_TEMP_RANGE.1).contains(&temp) =>
+            {
+                state.color_temp = Some(temp);
+                state.color = None;
+            }
+            _ => return error(StatusCode::BAD_REQUEST, "Parameter Value Out Of Range"),
+        }
+
+        success(json!({}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn control_changes_state() {
+        let server = FakeServer::start().await.unwrap();
+        let lamp = server.add_device(light("34:20:03:2e:30:2b", "lamp"));
+        let plug = server.add_device(plug("34:20:03:2e:30:2c", "plug"));
+        let client = server.client();
+
+        assert_eq!(client.devices().await.unwrap().len(), 2);
+
+        client.color_temp(&lamp, 3000).await.unwrap();
+        let state = client.state(&lamp).await.unwrap().normalized();
+        assert_eq!(state.color_temp, Some(3000));
+        assert_eq!(state.color, None);
+
+        assert!(client.brightness(&plug, 10).await.is_err());
+        assert_eq!(server.controls().len(), 2);
+
+        server.set_online(&lamp.device, false);
+        assert!(client.turn(&lamp, PowerState::On).await.is_err());
+        assert_eq!(
+            server.state(&lamp.device).unwrap().power,
+            Some(PowerState::Off)
+        );
+
+        let stranger = GoveeClient::new(&server.url(), "wrong").unwrap();
+        assert!(stranger.devices().await.is_err());
+        assert!(!server.requests().last().unwrap().authorized);
+    }
+
+    #[tokio::test]
+    async fn failures_and_rate_limits() {
+        let server = FakeServer::start().await.unwrap();
+        server.add_device(light("34:20:03:2e:30:2b", "lamp"));
+        let client = server.client();
+
+        server.fail_next(StatusCode::INTERNAL_SERVER_ERROR, "Internal Error");
+        assert!(client.devices().await.is_err());
+        assert!(client.devices().await.is_ok());
+
+        server.set_endpoint_limit(Some(1));
+        assert!(client.devices().await.is_ok());
+        assert!(client.devices().await.is_err());
+
+        server.set_endpoint_limit(None);
+        server.set_daily_limit(1);
+        assert!(client.devices().await.is_ok());
+        assert!(client.devices().await.is_err());
+    }
+}
@@ -0,0 +1,416 @@
+//! A simulator of devices speaking the Govee LAN protocol.
+//!
+//! Real devices listen for multicast `scan` requests on port 4001 and for
+//! commands on port 4003, and reply to port 4002. So several devices can run
+//! on one machine, a [LanSimulator] instead binds one local UDP socket for
+//! discovery and one per device, all on free ports, and replies to whichever
+//! address a request came from. Scan replies carry a `port` field beside the
+//! usual `ip` so clients can find each device's socket.
+//!
+//! Commands a device's [LanFeatures] do not include are ignored, as they are
+//! by real devices.
+//!
+//! # Examples
+//! ```
+//! # #[tokio::main]
+//! # async fn main() {
+//! use govee_rs::{
+//!     models::PowerState,
+//!     testing::lan::{LanMessage, LanSimulator, SimDevice},
+//! };
+//! use tokio::net::UdpSocket;
+//!
+//! let lamp = SimDevice::new("34:20:03:2e:30:2b".parse().unwrap(), "H6159".into());
+//! let sim = LanSimulator::start([lamp.clone()]).await.unwrap();
+//!
+//! let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+//! let addr = sim.device_addr(&lamp.device).unwrap();
+//! socket
+//!     .send_to(&LanMessage::Turn { value: 1 }.encode(), addr)
+//!     .await
+//!     .unwrap();
+//! socket
+//!     .send_to(&LanMessage::DevStatus(Default::default()).encode(), addr)
+//!     .await
+//!     .unwrap();
+//!
+//! let mut buf = [0; 1024];
+//! let (len, _) = socket.recv_from(&mut buf).await.unwrap();
+//! let LanMessage::DevStatus(status) = LanMessage::decode(&buf[..len]).unwrap() else {
+//!     panic!("expected a status");
+//! };
+//! assert_eq!(status.on_off, Some(1));
+//! assert_eq!(sim.state(&lamp.device).unwrap().power, Some(PowerState::On));
+//! # }
+//! ```
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use tokio::{net::UdpSocket, task::JoinHandle};
+
+pub use crate::lan::{DevStatus, LanMessage, ScanData};
+use crate::{
+    catalog::{Catalog, LanFeatures},
+    models::{Color, DeviceId, Model, NormalizedState, PowerState},
+};
+
+/// A device to simulate.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SimDevice {
+    pub device: DeviceId,
+    pub model: Model,
+    pub lan: LanFeatures,
+}
+
+impl SimDevice {
+    /// A device with the LAN features the built-in catalog lists for its
+    /// model, or every feature for unknown models.
+    pub fn new(device: DeviceId, model: Model) -> Self {
+        Self::in_catalog(device, model, &Catalog::builtin())
+    }
+
+    /// A device with the LAN features `catalog` lists for its model, or
+    /// every feature for unknown models.
+    pub fn in_catalog(device: DeviceId, model: Model, catalog: &Catalog) -> Self {
+        let lan = catalog
+            .lookup(&model)
+            .map(|info| info.lan)
+            .unwrap_or(LanFeatures::ALL);
+        Self { device, model, lan }
+    }
+
+    pub fn with_lan(mut self, lan: LanFeatures) -> Self {
+        self.lan = lan;
+        self
+    }
+}
+
+struct Simulated {
+    device: SimDevice,
+    addr: SocketAddr,
+    state: NormalizedState,
+}
+
+struct Sim {
+    devices: Vec<Simulated>,
+    loss: f64,
+    delay: Duration,
+    rng: u64,
+    received: Vec<(Option<DeviceId>, LanMessage)>,
+}
+
+impl Sim {
+    /// Decide if the next packet is lost, using xorshift so runs with the
+    /// same seed lose the same packets.
+    fn lose(&mut self) -> bool {
+        if self.loss <= 0.0 {
+            return false;
+        }
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng as f64 / u64::MAX as f64) < self.loss
+    }
+}
+
+/// Simulated LAN devices on local UDP sockets.
+///
+/// The sockets close when this is dropped.
+pub struct LanSimulator {
+    discovery: SocketAddr,
+    sim: Arc<Mutex<Sim>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for LanSimulator {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+const DEFAULT_SEED: u64 = 0x5eed_6013;
+
+impl LanSimulator {
+    /// Start simulating `devices`, each switched off.
+    pub async fn start(devices: impl IntoIterator<Item = SimDevice>) -> io::Result<Self> {
+        let mut sockets = Vec::new();
+        let mut simulated = Vec::new();
+        for device in devices {
+            let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+            simulated.push(Simulated {
+                addr: socket.local_addr()?,
+                state: NormalizedState {
+                    device: device.device.clone(),
+                    model: device.model.clone(),
+                    online: Some(true),
+                    power: Some(PowerState::Off),
+                    brightness: Some(100),
+                    color: Some(Color {
+                        r: 255,
+                        g: 255,
+                        b: 255,
+                    }),
+                    color_temp: None,
+                },
+                device,
+            });
+            sockets.push(socket);
+        }
+
+        let discovery = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+        let sim = Arc::new(Mutex::new(Sim {
+            devices: simulated,
+            loss: 0.0,
+            delay: Duration::ZERO,
+            rng: DEFAULT_SEED,
+            received: Vec::new(),
+        }));
+
+        let mut tasks = vec![tokio::spawn(serve(discovery.clone(), None, sim.clone()))];
+        for (i, socket) in sockets.into_iter().enumerate() {
+            tasks.push(tokio::spawn(serve(socket, Some(i), sim.clone())));
+        }
+
+        Ok(Self {
+            discovery: discovery.local_addr()?,
+            sim,
+            tasks,
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Sim> {
+        lock(&self.sim)
+    }
+
+    /// Where to send `scan` requests, in place of the multicast group.
+    pub fn discovery_addr(&self) -> SocketAddr {
+        self.discovery
+    }
+
+    /// Where to send commands for a device, in place of port 4003.
+    pub fn device_addr(&self, device: &DeviceId) -> Option<SocketAddr> {
+        self.lock()
+            .devices
+            .iter()
+            .find(|d| &d.device.device == device)
+            .map(|d| d.addr)
+    }
+
+    /// The current state of a device.
+    pub fn state(&self, device: &DeviceId) -> Option<NormalizedState> {
+        self.lock()
+            .devices
+            .iter()
+            .find(|d| &d.device.device == device)
+            .map(|d| d.state.clone())
+    }
+
+    /// Replace the state of a device, as though it was changed by hand.
+    pub fn set_state(&self, state: NormalizedState) {
+        let mut sim = self.lock();
+        if let Some(device) = sim
+            .devices
+            .iter_mut()
+            .find(|d| d.device.device == state.device)
+        {
+            device.state = state;
+        }
+    }
+
+    /// Drop this fraction of incoming packets, from `0.0` to `1.0`.
+    pub fn set_loss(&self, loss: f64) {
+        self.lock().loss = loss.clamp(0.0, 1.0);
+    }
+
+    /// Choose which packets are lost. Runs with the same seed lose the same
+    /// packets.
+    pub fn set_seed(&self, seed: u64) {
+        // xorshift is stuck at zero
+        self.lock().rng = seed.max(1);
+    }
+
+    /// Wait this long before sending each reply.
+    pub fn set_delay(&self, delay: Duration) {
+        self.lock().delay = delay;
+    }
+
+    /// Every message received and not lost, with the device it was sent to,
+    /// or `None` for the discovery socket.
+    pub fn received(&self) -> Vec<(Option<DeviceId>, LanMessage)> {
+        self.lock().received.clone()
+    }
+}
+
+fn lock(sim: &Mutex<Sim>) -> MutexGuard<'_, Sim> {
+    sim.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Answer packets on `socket`, which belongs to the device at `index` or is
+/// the discovery socket if `None`.
+async fn serve(socket: Arc<UdpSocket>, index: Option<usize>, sim: Arc<Mutex<Sim>>) {
+    let mut buf = vec![0; 4096];
+    loop {
+        let Ok((len, from)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+        let Ok(message) = LanMessage::decode(&buf[..len]) else {
+            continue;
+        };
+
+        let (replies, delay) = {
+            let mut sim = lock(&sim);
+            if sim.lose() {
+                continue;
+            }
+            let device = index.map(|i| sim.devices[i].device.device.clone());
+            sim.received.push((device, message.clone()));
+
+            let replies = match index {
+                Some(i) => handle(&mut sim.devices[i], message).into_iter().collect(),
+                None => scan(&mut sim, &message),
+            };
+            (replies, sim.delay)
+        };
+
+        for reply in replies {
+            let socket = socket.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                let _ = socket.send_to(&reply.encode(), from).await;
+            });
+        }
+    }
+}
+
+/// Every scannable device's reply to a scan, less any lost on the way.
+fn scan(sim: &mut Sim, message: &LanMessage) -> Vec<LanMessage> {
+    if !matches!(message, LanMessage::Scan(_)) {
+        return Vec::new();
+    }
+
+    let found: Vec<_> = sim
+        .devices
+        .iter()
+        .filter(|d| d.device.lan.scan)
+        .map(|d| {
+            LanMessage::Scan(ScanData {
+                ip: Some(d.addr.ip().to_string()),
+                port: Some(d.addr.port()),
+                device: Some(d.device.device.clone()),
+                sku: Some(d.device.model.clone()),
+                ..Default::default()
+            })
+        })
+        .collect();
+    found.into_iter().filter(|_| !sim.lose()).collect()
+}
+
+/// Apply a command to a device, returning its reply if it sends one.
+fn handle(device: &mut Simulated, message: LanMessage) -> Option<LanMessage> {
+    let lan = device.device.lan;
+    let state = &mut device.state;
+    match message {
+        LanMessage::Turn { value } if lan.turn => {
+            state.power = Some(if value == 0 {
+                PowerState::Off
+            } else {
+                PowerState::On
+            });
+        }
+        LanMessage::Brightness { value } if lan.brightness && (1..=100).contains(&value) => {
+            state.brightness = Some(value);
+        }
+        LanMessage::Colorwc { color_temp, .. } if color_temp > 0 && lan.color_temp => {
+            state.color_temp = Some(color_temp);
+            state.color = None;
+        }
+        LanMessage::Colorwc { color, color_temp } if color_temp == 0 && lan.color => {
+            state.color = Some(color);
+            state.color_temp = None;
+        }
+        LanMessage::DevStatus(_) if lan.status => {
+            return Some(LanMessage::DevStatus(DevStatus {
+                on_off: Some(u8::from(state.power == Some(PowerState::On))),
+                brightness: state.brightness,
+                color: Some(state.color.unwrap_or_default()),
+                color_temp: Some(state.color_temp.unwrap_or(0)),
+            }));
+        }
+        _ => {}
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn recv(socket: &UdpSocket) -> Option<LanMessage> {
+        let mut buf = [0; 4096];
+        let (len, _) = tokio::time::timeout(Duration::from_millis(200), socket.recv_from(&mut buf))
+            .await
+            .ok()?
+            .ok()?;
+        LanMessage::decode(&buf[..len]).ok()
+    }
+
+    #[tokio::test]
+    async fn scan_and_control() {
+        let lamp = SimDevice::new("34:20:03:2e:30:2b".parse().unwrap(), "H6159".into());
+        let hidden = SimDevice::new("34:20:03:2e:30:2c".parse().unwrap(), "H6003".into());
+        let sim = LanSimulator::start([lamp.clone(), hidden.clone()])
+            .await
+            .unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let request = LanMessage::Scan(ScanData {
+            account_topic: Some("reserve".into()),
+            ..Default::default()
+        });
+        socket
+            .send_to(&request.encode(), sim.discovery_addr())
+            .await
+            .unwrap();
+        let Some(LanMessage::Scan(found)) = recv(&socket).await else {
+            panic!("expected a scan reply");
+        };
+        assert_eq!(found.device, Some(lamp.device.clone()));
+        assert_eq!(found.port, sim.device_addr(&lamp.device).map(|a| a.port()));
+        // the H6003 has no LAN control
+        assert_eq!(recv(&socket).await, None);
+
+        let addr = sim.device_addr(&lamp.device).unwrap();
+        let colorwc = LanMessage::Colorwc {
+            color: Color::default(),
+            color_temp: 2700,
+        };
+        socket.send_to(&colorwc.encode(), addr).await.unwrap();
+        socket
+            .send_to(&LanMessage::DevStatus(DevStatus::default()).encode(), addr)
+            .await
+            .unwrap();
+        let Some(LanMessage::DevStatus(status)) = recv(&socket).await else {
+            panic!("expected a status reply");
+        };
+        assert_eq!(status.color_temp, Some(2700));
+        assert_eq!(sim.state(&lamp.device).unwrap().color, None);
+
+        sim.set_loss(1.0);
+        socket
+            .send_to(&LanMessage::Turn { value: 1 }.encode(), addr)
+            .await
+            .unwrap();
+        assert_eq!(recv(&socket).await, None);
+        assert_eq!(
+            sim.state(&lamp.device).unwrap().power,
+            Some(PowerState::Off)
+        );
+        assert_eq!(sim.received().len(), 3);
+    }
+}
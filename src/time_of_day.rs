@@ -0,0 +1,37 @@
+//! Times of day as `HH:MM` or `HH:MM:SS`, for `#[serde(with = "...")]`.
+use chrono::NaiveTime;
+use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+pub fn serialize<S: Serializer>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&time.format("%H:%M:%S"))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+    let raw = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&raw, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(&raw, "%H:%M"))
+        .map_err(|_| D::Error::custom(format!("invalid time of day {:?}", raw)))
+}
+
+/// The same for optional times, which need `#[serde(default)]` too.
+#[cfg(feature = "rules")]
+pub mod option {
+    use chrono::NaiveTime;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        time: &Option<NaiveTime>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match time {
+            Some(time) => super::serialize(time, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<NaiveTime>, D::Error> {
+        super::deserialize(deserializer).map(Some)
+    }
+}
@@ -0,0 +1,429 @@
+//! Pushing device state changes to WebSocket clients.
+//!
+//! [router] serves a WebSocket on `/ws`. Clients subscribe to devices and
+//! are sent a `state` message whenever a [Watcher] sees one change, and can
+//! control devices over the same connection. Every message is a JSON object
+//! tagged by its `type`.
+//!
+//! ```text
+//! > {"type": "subscribe", "devices": ["34:20:03:2e:30:2b"]}
+//! < {"type": "state", "device": "34:20:03:2e:30:2b", "name": "lamp", "changed": ["power"], ...}
+//! > {"type": "control", "id": 1, "device": "34:20:03:2e:30:2b", "state": {"brightness": 40}}
+//! < {"type": "ack", "id": 1}
+//! < {"type": "state", "device": "34:20:03:2e:30:2b", "name": "lamp", "changed": ["brightness"], ...}
+//! ```
+//!
+//! Subscribing to an empty list of devices subscribes to all of them, and
+//! the current state of each newly subscribed device is sent straight away.
+//!
+//! # Examples
+//! ```no_run
+//! # #[tokio::main]
+//! # async fn main() {
+//! use govee_rs::{events::Watcher, monitor::Monitor, websocket, GoveeClient, DEFAULT_API_URL};
+//!
+//! let client = GoveeClient::new(DEFAULT_API_URL, "my-api-key").unwrap();
+//! let devices = client.devices().await.unwrap();
+//!
+//! let watcher = Watcher::new(64);
+//! let monitor = Monitor::new(client.clone(), &devices);
+//! let poller = watcher.clone();
+//! tokio::spawn(async move { poller.run(&monitor).await });
+//!
+//! let listener = tokio::net::TcpListener::bind("127.0.0.1:8090").await.unwrap();
+//! axum::serve(listener, websocket::router(client, devices, watcher))
+//!     .await
+//!     .unwrap();
+//! # }
+//! ```
+use std::{collections::HashSet, sync::Arc, time::SystemTime};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        State, WebSocketUpgrade,
+    },
+    response::Response,
+    routing::get,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    events::{self, StateEvent, Watcher},
+    models::{DesiredState, Device, DeviceId, Devices, NormalizedState},
+    GoveeClient,
+};
+
+/// A message from a client.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Receive changes to these devices, or to every device if empty.
+    Subscribe {
+        #[serde(default)]
+        devices: Vec<DeviceId>,
+    },
+    /// Stop receiving changes to these devices, or to every device if empty.
+    Unsubscribe {
+        #[serde(default)]
+        devices: Vec<DeviceId>,
+    },
+    /// Bring a device into a state. `id` is echoed back in the reply.
+    Control {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+        device: DeviceId,
+        state: DesiredState,
+    },
+}
+
+/// A message to a client.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    State(StateEvent),
+    /// A control message succeeded.
+    Ack {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+    },
+    /// A message failed. `id` is set if it was a control message with one.
+    Error {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+        message: String,
+    },
+}
+
+impl ServerMessage {
+    fn error(id: Option<u64>, message: impl ToString) -> Self {
+        Self::Error {
+            id,
+            message: message.to_string(),
+        }
+    }
+}
+
+struct Hub {
+    client: GoveeClient,
+    devices: Devices,
+    watcher: Watcher,
+}
+
+/// The devices a connection is subscribed to.
+#[derive(Default)]
+enum Subscription {
+    #[default]
+    None,
+    All,
+    Some(HashSet<DeviceId>),
+}
+
+impl Subscription {
+    fn contains(&self, device: &DeviceId) -> bool {
+        match self {
+            Self::None => false,
+            Self::All => true,
+            Self::Some(devices) => devices.contains(device),
+        }
+    }
+}
+
+/// A router serving the WebSocket on `/ws`.
+///
+/// Only `devices` can be subscribed to and controlled. States come from
+/// `watcher`, which is also told about successful control messages.
+pub fn router(client: GoveeClient, devices: Devices, watcher: Watcher) -> Router {
+    let hub = Arc::new(Hub {
+        client,
+        devices,
+        watcher,
+    });
+
+    Router::new().route("/ws", get(upgrade)).with_state(hub)
+}
+
+async fn upgrade(State(hub): State<Arc<Hub>>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| connection(hub, socket))
+}
+
+async fn connection(hub: Arc<Hub>, mut socket: WebSocket) {
+    let mut events = hub.watcher.subscribe();
+    let mut subscription = Subscription::None;
+
+    loop {
+        let replies = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => hub.handle(&text, &mut subscription).await,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // pings are answered for us
+                Some(Ok(_)) => continue,
+            },
+            event = events.recv() => match event {
+                Ok(event) if subscription.contains(&event.device) => {
+                    vec![ServerMessage::State(event)]
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => vec![ServerMessage::error(
+                    None,
+                    format!("too slow, missed {} state changes", missed),
+                )],
+                Err(RecvError::Closed) => break,
+            },
+        };
+
+        for reply in replies {
+            let Ok(text) = serde_json::to_string(&reply) else {
+                continue;
+            };
+            if socket.send(Message::Text(text)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Hub {
+    fn device(&self, id: &DeviceId) -> Result<&Device, String> {
+        self.devices
+            .iter()
+            .find(|d| &d.device == id)
+            .ok_or_else(|| format!("unknown device {}", id))
+    }
+
+    /// The last known state of `device` as though every property changed.
+    fn current(&self, device: &Device) -> Option<StateEvent> {
+        let state = self.watcher.state(&device.device)?;
+        Some(StateEvent {
+            device: device.device.clone(),
+            name: device.name.clone(),
+            changed: events::diff(&NormalizedState::new(state.device.clone()), &state),
+            state,
+            at: SystemTime::now(),
+        })
+    }
+
+    async fn handle(&self, text: &str, subscription: &mut Subscription) -> Vec<ServerMessage> {
+        let message: ClientMessage = match serde_json::from_str(text) {
+            Ok(message) => message,
+            Err(e) => {
+                return vec![ServerMessage::error(
+                    None,
+                    format!("invalid message: {}", e),
+                )]
+            }
+        };
+
+        match message {
+            ClientMessage::Subscribe { devices } => self.subscribe(devices, subscription),
+            ClientMessage::Unsubscribe { devices } => {
+                match subscription {
+                    _ if devices.is_empty() => *subscription = Subscription::None,
+                    Subscription::All => {
+                        let remaining = self
+                            .devices
+                            .iter()
+                            .map(|d| d.device.clone())
+                            .filter(|id| !devices.contains(id))
+                            .collect();
+                        *subscription = Subscription::Some(remaining);
+                    }
+                    Subscription::Some(subscribed) => {
+                        subscribed.retain(|id| !devices.contains(id));
+                    }
+                    Subscription::None => {}
+                }
+                Vec::new()
+            }
+            ClientMessage::Control { id, device, state } => {
+                match self.control(&device, &state).await {
+                    Ok(()) => vec![ServerMessage::Ack { id }],
+                    Err(e) => vec![ServerMessage::error(id, e)],
+                }
+            }
+        }
+    }
+
+    fn subscribe(
+        &self,
+        devices: Vec<DeviceId>,
+        subscription: &mut Subscription,
+    ) -> Vec<ServerMessage> {
+        let mut replies = Vec::new();
+        let mut added = Vec::new();
+
+        if devices.is_empty() {
+            added.extend(
+                self.devices
+                    .iter()
+                    .filter(|d| !subscription.contains(&d.device)),
+            );
+            *subscription = Subscription::All;
+        } else {
+            for id in devices {
+                let device = match self.device(&id) {
+                    Ok(device) => device,
+                    Err(e) => {
+                        replies.push(ServerMessage::error(None, e));
+                        continue;
+                    }
+                };
+                match subscription {
+                    Subscription::All => {}
+                    Subscription::Some(subscribed) => {
+                        if subscribed.insert(id) {
+                            added.push(device);
+                        }
+                    }
+                    Subscription::None => {
+                        *subscription = Subscription::Some(HashSet::from([id]));
+                        added.push(device);
+                    }
+                }
+            }
+        }
+
+        replies.extend(
+            added
+                .into_iter()
+                .filter_map(|d| self.current(d))
+                .map(ServerMessage::State),
+        );
+        replies
+    }
+
+    async fn control(&self, id: &DeviceId, desired: &DesiredState) -> Result<(), String> {
+        let device = self.device(id)?;
+        let cmds = desired.commands_for(device).map_err(|e| e.to_string())?;
+        for cmd in cmds {
+            self.client
+                .control(device, cmd)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+
+        // tell subscribers now, as the API takes a while to catch up
+        let mut state = self
+            .watcher
+            .state(&device.device)
+            .unwrap_or_else(|| NormalizedState {
+                model: device.model.clone(),
+                ..NormalizedState::new(device.device.clone())
+            });
+        state.apply(desired);
+        self.watcher.record(device, state);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::{SinkExt, StreamExt};
+    use mockito::Server;
+    use serde_json::json;
+    use tokio_tungstenite::tungstenite;
+
+    use super::*;
+    use crate::models::{ControlCommand, PowerState};
+
+    async fn recv(
+        ws: &mut tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+    ) -> ServerMessage {
+        loop {
+            if let tungstenite::Message::Text(text) = ws.next().await.unwrap().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribe_and_control() {
+        let mut server = Server::new_async().await;
+        let control = server
+            .mock("PUT", "/v1/devices/control?")
+            .with_status(200)
+            .with_body(r#"{"data": {}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let lamp = Device {
+            model: "H6159".into(),
+            name: "lamp".into(),
+            controllable: true,
+            supported_commands: HashSet::from([ControlCommand::Turn, ControlCommand::Brightness]),
+            ..Device::new(DeviceId::parse("34:20:03:2e:30:2b").unwrap())
+        };
+        let devices = Devices {
+            devices: vec![lamp.clone()],
+        };
+        let watcher = Watcher::new(16);
+        watcher.record(
+            &lamp,
+            NormalizedState {
+                power: Some(PowerState::On),
+                ..NormalizedState::new(lamp.device.clone())
+            },
+        );
+
+        let client = GoveeClient::new(&server.url(), "key").unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router(client, devices, watcher.clone()))
+                .await
+                .unwrap()
+        });
+
+        let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+            .await
+            .unwrap();
+        let send = |message: serde_json::Value| tungstenite::Message::Text(message.to_string());
+
+        ws.send(send(
+            json!({"type": "subscribe", "devices": ["34:20:03:2e:30:2b"]}),
+        ))
+        .await
+        .unwrap();
+        let ServerMessage::State(current) = recv(&mut ws).await else {
+            panic!("expected the current state");
+        };
+        assert_eq!(current.state.power, Some(PowerState::On));
+
+        ws.send(send(json!({
+            "type": "control",
+            "id": 7,
+            "device": "34:20:03:2e:30:2b",
+            "state": {"brightness": 40},
+        })))
+        .await
+        .unwrap();
+
+        // the optimistic change follows the ack
+        assert_eq!(recv(&mut ws).await, ServerMessage::Ack { id: Some(7) });
+        let ServerMessage::State(changed) = recv(&mut ws).await else {
+            panic!("expected a state change");
+        };
+        assert_eq!(changed.changed, vec![events::Field::Brightness]);
+        assert_eq!(changed.state.brightness, Some(40));
+
+        ws.send(send(json!({
+            "type": "control",
+            "id": 8,
+            "device": "34:20:03:2e:30:2b",
+            "state": {"color_temp": 3000},
+        })))
+        .await
+        .unwrap();
+        assert!(matches!(
+            recv(&mut ws).await,
+            ServerMessage::Error { id: Some(8), .. }
+        ));
+
+        control.assert_async().await;
+    }
+}